@@ -3,7 +3,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use prost_build::Config;
-use std::{env, path::PathBuf};
+use prost_wkt_build::{FileDescriptorSet, Message};
+use std::{env, fs, path::PathBuf};
 
 fn main() {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
@@ -14,13 +15,55 @@ fn main() {
     config.type_attribute(".", "#[allow(clippy::enum_variant_names)]");
     config.type_attribute(".", "#[allow(missing_docs)]");
 
+    // Let request definitions loaded from config files omit fields
+    config.message_attribute(".sf.firehose.v2.Request", "#[serde(default)]");
+    config.message_attribute(".sf.firehose.v2.SingleBlockRequest", "#[serde(default)]");
+
+    // Implement `prost::Name` so messages can be looked up by their full name
+    config.enable_type_names();
+    config.type_name_domain(["."], "type.googleapis.com");
+
+    // Render raw byte payloads as 0x-prefixed hex rather than integer lists
+    config.field_attribute(
+        ".sf.bstream.v1.Block.payload_buffer",
+        "#[serde(with = \"crate::hex_bytes\")]",
+    );
+
+    // Render Ethereum filter addresses and signatures as hex in config files
+    for field in [
+        "LogFilter.addresses",
+        "LogFilter.event_signatures",
+        "CallToFilter.addresses",
+        "CallToFilter.signatures",
+    ] {
+        config.field_attribute(
+            format!(".sf.ethereum.transform.v1.{field}"),
+            "#[serde(with = \"crate::hex_bytes::repeated\")]",
+        );
+    }
+
     // Map Google protobuf types to prost_wkt_types
     config.extern_path(".google.protobuf.Any", "::prost_wkt_types::Any");
     config.extern_path(".google.protobuf.Timestamp", "::prost_wkt_types::Timestamp");
 
+    let mut protos = vec![
+        "protos/firehose.proto",
+        "protos/bstream.proto",
+        "protos/ethereum_transforms.proto",
+    ];
+    if env::var_os("CARGO_FEATURE_V1").is_some() {
+        protos.push("protos/firehose_v1.proto");
+    }
+
+    let descriptors = out_dir.join("descriptors.bin");
     tonic_prost_build::configure()
         .build_client(true)
-        .file_descriptor_set_path(out_dir.join("descriptors.bin"))
-        .compile_with_config(config, &["protos/firehose.proto"], &["protos/"])
+        .file_descriptor_set_path(&descriptors)
+        .compile_with_config(config, &protos, &["protos/"])
         .unwrap();
+
+    // Register the messages with `prost-wkt`, so `Any` transforms in config
+    // files (de)serialize as their message rather than raw bytes
+    let descriptors = FileDescriptorSet::decode(fs::read(descriptors).unwrap().as_slice()).unwrap();
+    prost_wkt_build::add_serde(out_dir, descriptors);
 }
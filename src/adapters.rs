@@ -0,0 +1,153 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Seams that keep downstream crates off this crate's exact `tonic` and
+//! `prost` versions: channels behind a trait, and payloads as bytes.
+
+use std::{fmt::Display, future::Future};
+
+use tonic::{codegen::Bytes, transport::Channel};
+
+#[cfg(feature = "runtime")]
+use crate::FirehoseEndpoint;
+use crate::{FirehoseError, Response};
+
+/// Something that opens gRPC channels to a Firehose endpoint.
+///
+/// Code generic over a `ChannelSource` builds clients without constructing
+/// `tonic` endpoints itself, so it only names the channel type this crate
+/// re-exports through [`tonic`](crate::tonic) and keeps compiling when this
+/// crate moves to a new `tonic` version. Implemented by
+/// [`FirehoseEndpoint`], and by [`Channel`] for channels opened elsewhere
+/// and shared.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "runtime")]
+/// # mod example {
+/// use firehose_rs::{ChannelSource, FirehoseEndpoint, FirehoseError, StreamClient};
+///
+/// async fn client(source: &impl ChannelSource) -> Result<(), FirehoseError> {
+///     let channel = source.channel().await?;
+///     let client = StreamClient::new(channel);
+///     // ...
+///     Ok(())
+/// }
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// client(&FirehoseEndpoint::from_env()?).await?;
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+pub trait ChannelSource {
+    /// Open a channel, connected once this resolves.
+    fn channel(&self) -> impl Future<Output = Result<Channel, FirehoseError>> + Send;
+
+    /// Open a channel connecting on first use.
+    fn channel_lazy(&self) -> Result<Channel, FirehoseError>;
+}
+
+#[cfg(feature = "runtime")]
+impl ChannelSource for FirehoseEndpoint {
+    fn channel(&self) -> impl Future<Output = Result<Channel, FirehoseError>> + Send {
+        self.connect()
+    }
+
+    fn channel_lazy(&self) -> Result<Channel, FirehoseError> {
+        self.connect_lazy()
+    }
+}
+
+/// Clones of the channel, which share its connection.
+impl ChannelSource for Channel {
+    fn channel(&self) -> impl Future<Output = Result<Channel, FirehoseError>> + Send {
+        std::future::ready(Ok(self.clone()))
+    }
+
+    fn channel_lazy(&self) -> Result<Channel, FirehoseError> {
+        Ok(self.clone())
+    }
+}
+
+/// A chain block decoded from its encoded bytes, with whatever decoder and
+/// `prost` version the implementing crate uses.
+///
+/// Unlike [`FromResponse`](crate::FromResponse), implementations never see
+/// this crate's message types, only the type URL and payload bytes, so they
+/// do not change when this crate upgrades `prost`. Decode responses with
+/// [`Response::decode_block`].
+///
+/// # Example
+///
+/// ```rust
+/// use firehose_rs::DecodeBlock;
+///
+/// struct BlockSize(usize);
+///
+/// impl DecodeBlock for BlockSize {
+///     type Error = String;
+///
+///     fn decode_block(type_url: &str, bytes: &[u8]) -> Result<Self, String> {
+///         if type_url.ends_with("sf.ethereum.type.v2.Block") {
+///             Ok(BlockSize(bytes.len()))
+///         } else {
+///             Err(format!("unexpected block type {type_url}"))
+///         }
+///     }
+/// }
+/// ```
+pub trait DecodeBlock: Sized {
+    /// Why a block cannot be decoded.
+    type Error: Display + Send;
+
+    /// Decode the block of type `type_url` encoded in `bytes`.
+    fn decode_block(type_url: &str, bytes: &[u8]) -> Result<Self, Self::Error>;
+}
+
+/// Why [`Response::decode_block`] failed.
+#[derive(Debug)]
+pub enum DecodeBlockError<E> {
+    /// The response carries no block.
+    MissingBlock,
+    /// The decoder rejected the block.
+    Decode(E),
+}
+
+impl<E: Display> Display for DecodeBlockError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeBlockError::MissingBlock => write!(f, "response carries no block"),
+            DecodeBlockError::Decode(e) => write!(f, "failed to decode block: {e}"),
+        }
+    }
+}
+
+impl<E: std::fmt::Debug + Display> std::error::Error for DecodeBlockError<E> {}
+
+impl Response {
+    /// Type URL of the block, such as
+    /// `type.googleapis.com/sf.ethereum.type.v2.Block`.
+    pub fn block_type_url(&self) -> Option<&str> {
+        self.block.as_ref().map(|block| block.type_url.as_str())
+    }
+
+    /// Encoded bytes of the block, for decoders of any `prost` version.
+    pub fn block_bytes(&self) -> Option<&[u8]> {
+        self.block.as_ref().map(|block| block.value.as_slice())
+    }
+
+    /// Decode the block as a `T`.
+    pub fn decode_block<T: DecodeBlock>(&self) -> Result<T, DecodeBlockError<T::Error>> {
+        let block = self.block.as_ref().ok_or(DecodeBlockError::MissingBlock)?;
+        T::decode_block(&block.type_url, &block.value).map_err(DecodeBlockError::Decode)
+    }
+}
+
+/// `value`, such as a `Vec<u8>`, a `&'static [u8]` or [`Bytes`], as the
+/// bytes of a message.
+pub(crate) fn payload(value: impl Into<Bytes>) -> Vec<u8> {
+    Vec::from(value.into())
+}
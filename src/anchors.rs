@@ -0,0 +1,221 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::{BlockMetadata, FirehoseError, ForkStep, Response};
+
+/// Known `(number, hash)` checkpoints of a chain, that streamed and fetched
+/// blocks must match.
+///
+/// A block at an anchored height with another hash means the endpoint serves
+/// a wrong fork or corrupted data; [`ResilientStream::with_anchors`],
+/// [`FetchRange::with_anchors`] and [`Handoff::with_anchors`] then fail with
+/// [`FirehoseError::AnchorMismatch`] instead of carrying on for hours. Only
+/// final blocks are checked, see [`verify`](Anchors::verify). Hashes
+/// compare without their `0x` prefix and case-insensitively. Clones share the
+/// checkpoints.
+///
+/// [`ResilientStream::with_anchors`]: crate::ResilientStream::with_anchors
+/// [`FetchRange::with_anchors`]: crate::FetchRange::with_anchors
+/// [`Handoff::with_anchors`]: crate::Handoff::with_anchors
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "runtime")]
+/// # mod example {
+/// use firehose_rs::{Anchors, EndpointPool, FirehoseEndpoint, Request, ResilientStream};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// // Ethereum mainnet genesis.
+/// let anchors = Anchors::new().with_anchor(
+///     0,
+///     "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3",
+/// );
+///
+/// let pool = EndpointPool::new([FirehoseEndpoint::from_env()?])?;
+/// let request = Request {
+///     start_block_num: 0,
+///     stop_block_num: 1_000_000,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+/// let mut stream = ResilientStream::new(pool, request).with_anchors(anchors);
+/// while let Some(response) = stream.message().await? {
+///     // ...
+/// }
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Anchors {
+    hashes: Arc<BTreeMap<u64, String>>,
+}
+
+impl Anchors {
+    /// No checkpoints.
+    pub fn new() -> Self {
+        Anchors::default()
+    }
+
+    /// Add the checkpoint that block `number` has hash `hash`.
+    pub fn with_anchor(mut self, number: u64, hash: impl Into<String>) -> Self {
+        self.insert(number, hash);
+        self
+    }
+
+    /// Add the checkpoint that block `number` has hash `hash`, replacing any
+    /// previous one for that block.
+    pub fn insert(&mut self, number: u64, hash: impl Into<String>) {
+        Arc::make_mut(&mut self.hashes).insert(number, hash.into());
+    }
+
+    /// The expected hash of block `number`, if it is anchored.
+    pub fn get(&self, number: u64) -> Option<&str> {
+        self.hashes.get(&number).map(String::as_str)
+    }
+
+    /// Number of checkpoints.
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Whether there are no checkpoints.
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Check `response` against the checkpoints.
+    ///
+    /// Only final blocks are checked: those sent as [`ForkStep::StepFinal`],
+    /// and new blocks at or below the last irreversible block of their
+    /// metadata. A non-final block at an anchored height may be on a fork
+    /// the chain abandons and the stream undoes, so it is not checked;
+    /// anchors are meant for heights long final, which streams send as
+    /// final blocks. Undo steps are not checked.
+    ///
+    /// Responses without metadata fail with [`FirehoseError::Decode`], since
+    /// their height cannot be told, unless there are no checkpoints.
+    pub fn verify(&self, response: &Response) -> Result<(), FirehoseError> {
+        let step = response.step();
+        if step == ForkStep::StepUndo {
+            return Ok(());
+        }
+        let metadata = self.metadata(response.metadata.as_ref())?;
+        if step == ForkStep::StepFinal || metadata.is_some_and(|m| m.num <= m.lib_num) {
+            return metadata.map_or(Ok(()), |metadata| self.verify_metadata(metadata));
+        }
+        Ok(())
+    }
+
+    /// The `metadata` of a streamed or fetched response, failing without it
+    /// unless there are no checkpoints.
+    pub(crate) fn metadata<'a>(
+        &self,
+        metadata: Option<&'a BlockMetadata>,
+    ) -> Result<Option<&'a BlockMetadata>, FirehoseError> {
+        match metadata {
+            None if !self.is_empty() => Err(FirehoseError::Decode(
+                "response carries no block metadata to check against the anchors".to_string(),
+            )),
+            metadata => Ok(metadata),
+        }
+    }
+
+    pub(crate) fn verify_metadata(&self, metadata: &BlockMetadata) -> Result<(), FirehoseError> {
+        let Some(expected) = self.get(metadata.num) else {
+            return Ok(());
+        };
+        if normalize(expected).eq_ignore_ascii_case(normalize(&metadata.id)) {
+            return Ok(());
+        }
+        Err(FirehoseError::AnchorMismatch {
+            block: metadata.num,
+            expected: expected.to_string(),
+            actual: metadata.id.clone(),
+        })
+    }
+}
+
+impl<S: Into<String>> FromIterator<(u64, S)> for Anchors {
+    fn from_iter<I: IntoIterator<Item = (u64, S)>>(iter: I) -> Self {
+        Anchors {
+            hashes: Arc::new(
+                iter.into_iter()
+                    .map(|(number, hash)| (number, hash.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn normalize(hash: &str) -> &str {
+    hash.trim_start_matches("0x")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+
+    /// Block 100 with ID `id`, at a last irreversible block of `lib_num`.
+    fn response(step: ForkStep, id: &str, lib_num: u64) -> Response {
+        Response {
+            step: step.into(),
+            metadata: Some(BlockMetadata {
+                num: 100,
+                id: id.to_string(),
+                lib_num,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn checks_final_blocks() {
+        let anchors = Anchors::new().with_anchor(100, format!("0x{}", HASH.to_uppercase()));
+
+        anchors
+            .verify(&response(ForkStep::StepFinal, HASH, 100))
+            .unwrap();
+        assert!(matches!(
+            anchors.verify(&response(ForkStep::StepFinal, "00", 100)),
+            Err(FirehoseError::AnchorMismatch { block: 100, .. })
+        ));
+        // At or below the last irreversible block, new blocks are final too.
+        assert!(anchors
+            .verify(&response(ForkStep::StepNew, "00", 100))
+            .is_err());
+    }
+
+    #[test]
+    fn skips_blocks_that_may_be_undone() {
+        let anchors = Anchors::new().with_anchor(100, HASH);
+
+        anchors
+            .verify(&response(ForkStep::StepNew, "00", 90))
+            .unwrap();
+        anchors
+            .verify(&response(ForkStep::StepUndo, "00", 100))
+            .unwrap();
+    }
+
+    #[test]
+    fn fails_without_metadata() {
+        let response = Response {
+            step: ForkStep::StepFinal.into(),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            Anchors::new().with_anchor(100, HASH).verify(&response),
+            Err(FirehoseError::Decode(_))
+        ));
+        Anchors::new().verify(&response).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use prost::Message;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::bstream::Block;
+
+#[cfg(feature = "zstd")]
+use super::is_compressed;
+use super::{dbin_files, open_bundle};
+
+/// Where a block is stored in a `dbin` archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockLocation {
+    /// The block number.
+    pub number: u64,
+    /// The block ID.
+    pub id: String,
+    /// File name, relative to the archive directory.
+    pub file: String,
+    /// Offset of the encoded block in the file, after its length prefix. For
+    /// `.dbin.zst` files, the offset in the decompressed file.
+    pub offset: u64,
+    /// Length of the encoded block.
+    pub length: u64,
+}
+
+/// An embedded SQLite index of a `dbin` archive.
+///
+/// Maps block numbers to their file and offset, and block IDs to numbers, so
+/// single blocks can be read from merged-blocks files without scanning whole
+/// bundles. [`DbinSink::with_index`](crate::sink::DbinSink::with_index)
+/// maintains it while writing, and [`ArchiveIndex::build`] indexes an existing
+/// archive.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::archive::ArchiveIndex;
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let index = ArchiveIndex::open("archive/index.sqlite")?;
+/// if let Some(location) = index.by_number(17_000_042)? {
+///     let block = index.read_block("archive", &location)?;
+///     assert_eq!(block.number, 17_000_042);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ArchiveIndex {
+    connection: Connection,
+}
+
+impl ArchiveIndex {
+    /// Open the index at `path`, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS blocks (
+                 number INTEGER PRIMARY KEY,
+                 id TEXT NOT NULL,
+                 file TEXT NOT NULL,
+                 offset INTEGER NOT NULL,
+                 length INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS blocks_by_id ON blocks (id);",
+        )?;
+        Ok(ArchiveIndex { connection })
+    }
+
+    /// Index every block of the `dbin` files in `dir`.
+    pub fn build(&self, dir: impl AsRef<Path>) -> io::Result<u64> {
+        let mut blocks = 0;
+        for path in dbin_files(dir.as_ref())? {
+            let file = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            let mut reader = open_bundle(&path)?;
+            while let Some((offset, length, block)) = reader.next_block_at()? {
+                self.insert(&BlockLocation {
+                    number: block.number,
+                    id: block.id,
+                    file: file.clone(),
+                    offset,
+                    length,
+                })
+                .map_err(io::Error::other)?;
+                blocks += 1;
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Record where a block is stored, replacing any previous location.
+    pub fn insert(&self, location: &BlockLocation) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO blocks (number, id, file, offset, length)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                location.number as i64,
+                location.id,
+                location.file,
+                location.offset as i64,
+                location.length as i64,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Where the block numbered `number` is stored.
+    pub fn by_number(&self, number: u64) -> rusqlite::Result<Option<BlockLocation>> {
+        self.query("WHERE number = ?1", params![number as i64])
+    }
+
+    /// Where the block with ID `id` is stored.
+    pub fn by_id(&self, id: &str) -> rusqlite::Result<Option<BlockLocation>> {
+        self.query("WHERE id = ?1", params![id])
+    }
+
+    /// Read the block at `location` from the archive in `dir`.
+    pub fn read_block(&self, dir: impl AsRef<Path>, location: &BlockLocation) -> io::Result<Block> {
+        let path = dir.as_ref().join(&location.file);
+        let mut file = File::open(&path)?;
+        let mut message = vec![0; location.length as usize];
+
+        #[cfg(feature = "zstd")]
+        if is_compressed(&path) {
+            // Offsets are in the decompressed file, so decompress up to them.
+            let mut decoder = zstd::Decoder::new(file)?;
+            io::copy(&mut (&mut decoder).take(location.offset), &mut io::sink())?;
+            decoder.read_exact(&mut message)?;
+            return Block::decode(message.as_slice())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+
+        file.seek(SeekFrom::Start(location.offset))?;
+        file.read_exact(&mut message)?;
+        Block::decode(message.as_slice()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn query(
+        &self,
+        filter: &str,
+        params: impl rusqlite::Params,
+    ) -> rusqlite::Result<Option<BlockLocation>> {
+        self.connection
+            .query_row(
+                &format!("SELECT number, id, file, offset, length FROM blocks {filter}"),
+                params,
+                |row| {
+                    Ok(BlockLocation {
+                        number: row.get::<_, i64>(0)? as u64,
+                        id: row.get(1)?,
+                        file: row.get(2)?,
+                        offset: row.get::<_, i64>(3)? as u64,
+                        length: row.get::<_, i64>(4)? as u64,
+                    })
+                },
+            )
+            .optional()
+    }
+}
@@ -0,0 +1,303 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reading and verifying local archives of merged-blocks (`dbin`) files, such
+//! as those written by [`DbinSink`](crate::sink::DbinSink).
+
+#[cfg(feature = "sqlite-index")]
+mod index;
+
+#[cfg(feature = "sqlite-index")]
+pub use index::{ArchiveIndex, BlockLocation};
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use prost::Message;
+
+use crate::{
+    bstream::Block, sink::ExportManifest, BlockRange, EndpointPool, FirehoseError,
+    SingleBlockRequest,
+};
+
+/// Reads the blocks of one `dbin` file.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::archive::DbinReader;
+///
+/// # fn example() -> std::io::Result<()> {
+/// let reader = DbinReader::open("archive/0017000000.dbin")?;
+/// for block in reader {
+///     let block = block?;
+///     println!("{} {}", block.number, block.id);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DbinReader<R> {
+    reader: R,
+    content_type: String,
+    position: u64,
+}
+
+impl DbinReader<BufReader<File>> {
+    /// Open the `dbin` file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        DbinReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl DbinReader<zstd::Decoder<'static, BufReader<File>>> {
+    /// Open the zstd-compressed `dbin` file at `path`, such as a `.dbin.zst`
+    /// bundle written by a [compressing](crate::sink::DbinSink::with_compression)
+    /// sink.
+    pub fn open_zstd(path: impl AsRef<Path>) -> io::Result<Self> {
+        DbinReader::new(zstd::Decoder::new(File::open(path)?)?)
+    }
+}
+
+impl<R: Read> DbinReader<R> {
+    /// Read the header from `reader`, positioned at the start of a `dbin`
+    /// file.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 5];
+        reader.read_exact(&mut magic)?;
+        if &magic[..4] != b"dbin" || magic[4] != 1 {
+            return Err(invalid_data("not a version 1 dbin file"));
+        }
+
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let mut content_type = vec![0; u16::from_be_bytes(length) as usize];
+        reader.read_exact(&mut content_type)?;
+        let content_type = String::from_utf8(content_type)
+            .map_err(|_| invalid_data("dbin content type is not UTF-8"))?;
+
+        Ok(DbinReader {
+            position: 7 + content_type.len() as u64,
+            reader,
+            content_type,
+        })
+    }
+
+    /// Type URL of the chain blocks in the file, from its header.
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Read the next block, or `None` at the end of the file.
+    pub fn next_block(&mut self) -> io::Result<Option<Block>> {
+        Ok(self.next_block_at()?.map(|(_, _, block)| block))
+    }
+
+    /// Read the next block with the offset and length of its encoding in the
+    /// file, or `None` at the end of the file.
+    pub fn next_block_at(&mut self) -> io::Result<Option<(u64, u64, Block)>> {
+        let mut length = [0; 4];
+        match self.reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let length = u32::from_be_bytes(length) as u64;
+        let offset = self.position + 4;
+        let mut message = vec![0; length as usize];
+        self.reader.read_exact(&mut message)?;
+        self.position = offset + length;
+
+        let block = Block::decode(message.as_slice()).map_err(|e| invalid_data(&e.to_string()))?;
+        Ok(Some((offset, length, block)))
+    }
+}
+
+impl<R: Read> Iterator for DbinReader<R> {
+    type Item = io::Result<Block>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_block().transpose()
+    }
+}
+
+/// Which blocks [`verify`] re-fetches from the endpoint.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sampling {
+    /// Every block.
+    #[default]
+    All,
+    /// Every block whose number is a multiple of `n`.
+    EveryNth(u64),
+}
+
+impl Sampling {
+    fn includes(&self, number: u64) -> bool {
+        match self {
+            Sampling::All => true,
+            Sampling::EveryNth(n) => number % (*n).max(1) == 0,
+        }
+    }
+}
+
+/// A local block whose hash differs from the endpoint's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashMismatch {
+    /// The block number.
+    pub number: u64,
+    /// File holding the local block.
+    pub file: PathBuf,
+    /// Block ID in the local file.
+    pub local_id: String,
+    /// Block ID returned by the endpoint.
+    pub remote_id: String,
+}
+
+/// Outcome of [`verify`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// `dbin` files read.
+    pub files: usize,
+    /// Blocks read from them.
+    pub blocks: u64,
+    /// Blocks compared against the endpoint.
+    pub checked: u64,
+    /// Compared blocks whose hash differs from the endpoint's.
+    pub mismatches: Vec<HashMismatch>,
+    /// Gaps between the first and last local block.
+    pub missing: Vec<BlockRange>,
+    /// Files listed in the directory's `manifest.json` that are missing or do
+    /// not match their checksum.
+    pub corrupt_files: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Whether the archive is complete and matches the endpoint.
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty() && self.missing.is_empty() && self.corrupt_files.is_empty()
+    }
+}
+
+/// Check the `dbin` archive in `dir` against the blocks served by `pool`.
+///
+/// Reads every `*.dbin` file in block order, reports gaps in the block
+/// numbers, and re-fetches the blocks selected by `sampling` to compare
+/// their hashes. When the directory holds a `manifest.json`, the files it
+/// lists are also checked against their recorded checksums.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{archive::{verify, Sampling}, EndpointPool, FirehoseEndpoint};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = EndpointPool::new([FirehoseEndpoint::from_env()?])?;
+///
+/// // Spot-check one block in a thousand
+/// let report = verify("archive", &pool, Sampling::EveryNth(1000)).await?;
+/// assert!(report.is_ok(), "{report:?}");
+/// # Ok(())
+/// # }
+/// ```
+pub async fn verify(
+    dir: impl AsRef<Path>,
+    pool: &EndpointPool,
+    sampling: Sampling,
+) -> Result<VerifyReport, FirehoseError> {
+    let dir = dir.as_ref();
+    let mut report = VerifyReport::default();
+
+    if let Some(manifest) = ExportManifest::load(dir.join("manifest.json"))? {
+        report.corrupt_files = manifest
+            .verify(dir)?
+            .into_iter()
+            .map(|part| part.file.clone())
+            .collect();
+    }
+
+    let mut next_expected: Option<u64> = None;
+    for path in dbin_files(dir)? {
+        report.files += 1;
+
+        for block in open_bundle(&path)? {
+            let block = block?;
+            report.blocks += 1;
+
+            match next_expected {
+                Some(expected) if block.number > expected => report
+                    .missing
+                    .push(BlockRange::new(expected, block.number - 1)),
+                _ => {}
+            }
+            next_expected = Some(
+                next_expected.map_or(block.number + 1, |expected| expected.max(block.number + 1)),
+            );
+
+            if !sampling.includes(block.number) {
+                continue;
+            }
+
+            let response = pool
+                .fetch(SingleBlockRequest::new_by_block_number(block.number))
+                .await?;
+            report.checked += 1;
+
+            let remote_id = response.metadata.map(|metadata| metadata.id);
+            if let Some(remote_id) = remote_id.filter(|id| *id != block.id) {
+                report.mismatches.push(HashMismatch {
+                    number: block.number,
+                    file: path.clone(),
+                    local_id: block.id,
+                    remote_id,
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// The `*.dbin` files of `dir`, sorted by name, hence by first block.
+pub(crate) fn dbin_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| {
+            path.as_ref().map_or(true, |path| {
+                path.extension().is_some_and(|ext| ext == "dbin") || is_compressed(path)
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    files.sort();
+    Ok(files)
+}
+
+/// Open the `dbin` file at `path`, decompressing it if it is a `.dbin.zst`
+/// file.
+pub(crate) fn open_bundle(path: &Path) -> io::Result<DbinReader<Box<dyn Read + Send>>> {
+    let file = BufReader::new(File::open(path)?);
+    #[cfg(feature = "zstd")]
+    if is_compressed(path) {
+        return DbinReader::new(Box::new(zstd::Decoder::with_buffer(file)?));
+    }
+    DbinReader::new(Box::new(file))
+}
+
+/// Whether `path` is a zstd-compressed `dbin` file, which can only be read
+/// with the `zstd` feature.
+pub(crate) fn is_compressed(path: &Path) -> bool {
+    cfg!(feature = "zstd")
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(".dbin.zst"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
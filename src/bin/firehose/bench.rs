@@ -0,0 +1,106 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use clap::Args;
+use firehose_rs::{FirehoseEndpoint, Request};
+use prost::Message;
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Block to start streaming from (negative values are relative to head).
+    #[arg(long, allow_hyphen_values = true)]
+    start: i64,
+
+    /// Number of blocks to receive before stopping.
+    #[arg(long, default_value_t = 1000)]
+    count: u64,
+
+    /// Only stream final blocks.
+    #[arg(long)]
+    final_blocks_only: bool,
+}
+
+pub async fn run(
+    endpoint: &FirehoseEndpoint,
+    request: Request,
+    args: BenchArgs,
+) -> Result<(), Box<dyn Error>> {
+    let stop_block_num = match u64::try_from(args.start) {
+        Ok(start) if args.count > 0 => start + args.count - 1,
+        _ => 0,
+    };
+    let request = Request {
+        start_block_num: args.start,
+        stop_block_num,
+        final_blocks_only: args.final_blocks_only || request.final_blocks_only,
+        ..request
+    };
+
+    let mut client = endpoint.stream_client().await?;
+
+    let started = Instant::now();
+    let mut stream = client.blocks(request).await?.into_inner();
+
+    let mut time_to_first_block = None;
+    let mut last_arrival: Option<Instant> = None;
+    let mut gaps = Vec::with_capacity(args.count as usize);
+    let mut blocks = 0u64;
+    let mut bytes = 0u64;
+
+    while blocks < args.count {
+        let Some(response) = stream.message().await? else {
+            break;
+        };
+
+        let now = Instant::now();
+        match last_arrival.replace(now) {
+            Some(previous) => gaps.push(now - previous),
+            None => time_to_first_block = Some(now - started),
+        }
+
+        blocks += 1;
+        bytes += response.encoded_len() as u64;
+    }
+
+    let elapsed = started.elapsed();
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    gaps.sort_unstable();
+
+    println!("blocks          {blocks}");
+    println!("bytes           {bytes}");
+    println!("elapsed         {:.2}s", elapsed.as_secs_f64());
+    if let Some(first) = time_to_first_block {
+        println!("first block     {:.2}s", first.as_secs_f64());
+    }
+    println!(
+        "throughput      {:.1} blocks/s, {:.2} MB/s",
+        blocks as f64 / secs,
+        bytes as f64 / 1_000_000.0 / secs
+    );
+    if !gaps.is_empty() {
+        println!(
+            "inter-block     p50 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+            millis(percentile(&gaps, 50.0)),
+            millis(percentile(&gaps, 99.0)),
+            millis(gaps[gaps.len() - 1]),
+        );
+    }
+
+    Ok(())
+}
+
+/// Nearest-rank percentile of an ascending, non-empty slice.
+fn percentile(sorted: &[Duration], pct: f64) -> Duration {
+    let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
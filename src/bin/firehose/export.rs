@@ -0,0 +1,122 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{error::Error, fs, path::PathBuf};
+
+use clap::{Args, ValueEnum};
+#[cfg(feature = "parquet")]
+use firehose_rs::{sink::ParquetSink, DynamicDecoder, Flattener};
+use firehose_rs::{
+    sink::{export, DbinSink, NdjsonSink, DEFAULT_BUNDLE_SIZE},
+    FileCursorStore, FirehoseEndpoint, Request,
+};
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = Format::Ndjson)]
+    format: Format,
+
+    /// Directory receiving the exported files and the cursor file.
+    #[arg(long, short = 'o')]
+    output: PathBuf,
+
+    /// First block to export (negative values are relative to head).
+    #[arg(long, allow_hyphen_values = true)]
+    start: i64,
+
+    /// Last block to export, inclusive. Streams forever when omitted.
+    #[arg(long)]
+    stop: Option<u64>,
+
+    /// Blocks per file for the `dbin` and `parquet` formats.
+    #[arg(long, default_value_t = DEFAULT_BUNDLE_SIZE)]
+    bundle_size: u64,
+
+    /// Flatten blocks into the tables of this chain, with the `parquet`
+    /// format.
+    #[cfg(feature = "parquet")]
+    #[arg(long, value_enum, requires = "descriptor_set")]
+    flatten: Option<Chain>,
+
+    /// File descriptor set (`.binpb`) of the chain's block type, to decode
+    /// blocks for `--flatten`.
+    #[cfg(feature = "parquet")]
+    #[arg(long)]
+    descriptor_set: Option<PathBuf>,
+
+    /// Cursor file used to resume interrupted exports [default: <output>/cursor]
+    #[arg(long)]
+    cursor_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// One JSON-encoded response per line, in `blocks.ndjson`.
+    Ndjson,
+    /// Merged-blocks bundles, as written by `firehose-core`, listed with their
+    /// checksums in `manifest.json`.
+    Dbin,
+    /// A directory of Parquet files per table: `blocks`, plus the tables of
+    /// `--flatten`.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+#[cfg(feature = "parquet")]
+#[derive(Clone, Copy, ValueEnum)]
+enum Chain {
+    /// `eth_blocks`, `eth_transactions` and `eth_logs`.
+    Ethereum,
+    /// `sol_blocks`, `sol_transactions` and `sol_instructions`.
+    Solana,
+}
+
+pub async fn run(
+    endpoint: &FirehoseEndpoint,
+    request: Request,
+    args: ExportArgs,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(&args.output)?;
+
+    let request = Request {
+        start_block_num: args.start,
+        stop_block_num: args.stop.unwrap_or_default(),
+        final_blocks_only: true,
+        ..request
+    };
+    let mut cursors = FileCursorStore::new(
+        args.cursor_file
+            .unwrap_or_else(|| args.output.join("cursor")),
+    );
+
+    let blocks = match args.format {
+        Format::Ndjson => {
+            let mut sink = NdjsonSink::create(args.output.join("blocks.ndjson"))?;
+            export(endpoint, request, &mut sink, &mut cursors).await?
+        }
+        Format::Dbin => {
+            let mut sink = DbinSink::with_bundle_size(&args.output, args.bundle_size)?
+                .with_manifest(args.output.join("manifest.json"))?;
+            export(endpoint, request, &mut sink, &mut cursors).await?
+        }
+        #[cfg(feature = "parquet")]
+        Format::Parquet => {
+            let mut sink = ParquetSink::new(&args.output)?.with_blocks_per_file(args.bundle_size);
+            if let (Some(chain), Some(path)) = (args.flatten, &args.descriptor_set) {
+                let mut decoder = DynamicDecoder::new()?;
+                decoder.add_file_descriptor_set(&fs::read(path)?)?;
+                let flattener = match chain {
+                    Chain::Ethereum => Flattener::ethereum(decoder)?,
+                    Chain::Solana => Flattener::solana(decoder)?,
+                };
+                sink = sink.with_flattener(flattener);
+            }
+            export(endpoint, request, &mut sink, &mut cursors).await?
+        }
+    };
+
+    eprintln!("exported {blocks} blocks to {}", args.output.display());
+    Ok(())
+}
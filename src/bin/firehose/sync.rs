@@ -0,0 +1,50 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use firehose_rs::{
+    sink::{sync, DbinSink, DEFAULT_BUNDLE_SIZE},
+    BlockRange, FirehoseEndpoint,
+};
+
+#[derive(Args)]
+pub struct SyncArgs {
+    /// Directory of the `dbin` archive and its `manifest.json`.
+    #[arg(long, short = 'o')]
+    output: PathBuf,
+
+    /// First block to mirror, defaults to the endpoint's first streamable
+    /// block.
+    #[arg(long)]
+    start: Option<u64>,
+
+    /// Last block to mirror, inclusive, defaults to the last final block.
+    #[arg(long)]
+    stop: Option<u64>,
+
+    /// Blocks per file, as the archive was written with.
+    #[arg(long, default_value_t = DEFAULT_BUNDLE_SIZE)]
+    bundle_size: u64,
+}
+
+pub async fn run(endpoint: &FirehoseEndpoint, args: SyncArgs) -> Result<(), Box<dyn Error>> {
+    let range = BlockRange::new(args.start.unwrap_or(0), args.stop.unwrap_or(u64::MAX));
+    let mut sink = DbinSink::with_bundle_size(&args.output, args.bundle_size)?
+        .with_manifest(args.output.join("manifest.json"))?;
+    let report = sync(endpoint, &mut sink, Some(range)).await?;
+
+    for span in &report.fetched {
+        println!("fetched {span}");
+    }
+    eprintln!(
+        "synced {} to {}: {} blocks in {} spans",
+        args.output.display(),
+        report.available,
+        report.blocks,
+        report.fetched.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    error::Error,
+    io::{self, IsTerminal},
+    time::SystemTime,
+};
+
+use clap::Args;
+use firehose_rs::{FirehoseEndpoint, ForkStep, Request, Response};
+
+const RED: &str = "\x1b[31m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+#[derive(Args)]
+pub struct TailArgs {
+    /// Block to start from (negative values are relative to head).
+    #[arg(long, allow_hyphen_values = true, default_value_t = -1)]
+    start: i64,
+
+    /// Only follow final blocks.
+    #[arg(long)]
+    final_blocks_only: bool,
+
+    /// Disable colored output.
+    #[arg(long)]
+    no_color: bool,
+}
+
+pub async fn run(
+    endpoint: &FirehoseEndpoint,
+    request: Request,
+    args: TailArgs,
+) -> Result<(), Box<dyn Error>> {
+    let color = !args.no_color && io::stdout().is_terminal();
+    let request = Request {
+        start_block_num: args.start,
+        final_blocks_only: args.final_blocks_only || request.final_blocks_only,
+        ..request
+    };
+
+    let mut client = endpoint.stream_client().await?;
+    let mut stream = client.blocks(request).await?.into_inner();
+
+    while let Some(response) = stream.message().await? {
+        println!("{}", summarize(&response, color));
+    }
+
+    Ok(())
+}
+
+/// One line per block: the response summary and the lag behind the chain.
+fn summarize(response: &Response, color: bool) -> String {
+    let step = ForkStep::try_from(response.step).unwrap_or(ForkStep::StepUnset);
+    // Only blocks from the past have a lag.
+    let lag = response
+        .timestamp()
+        .and_then(|produced| SystemTime::now().duration_since(produced).ok())
+        .map(|lag| format!("{:.1}s", lag.as_secs_f64()))
+        .unwrap_or_else(|| "-".to_string());
+
+    let line = format!("{}  lag {lag}", response.summary());
+
+    match (color, step) {
+        (true, ForkStep::StepUndo) => format!("{RED}{line}{RESET}"),
+        (true, ForkStep::StepUnset) => format!("{DIM}{line}{RESET}"),
+        _ => line,
+    }
+}
@@ -0,0 +1,200 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::HashMap, error::Error};
+#[cfg(feature = "dynamic")]
+use std::{fs, path::PathBuf};
+
+use clap::Args;
+#[cfg(feature = "dynamic")]
+use firehose_rs::{verify_ethereum_roots, DynamicDecoder};
+use firehose_rs::{FirehoseEndpoint, Request};
+use serde_json::{json, Value};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// Ethereum JSON-RPC endpoint to check block hashes against.
+    #[arg(long)]
+    rpc_url: String,
+
+    /// First block to verify.
+    #[arg(long)]
+    start: u64,
+
+    /// Last block to verify, inclusive.
+    #[arg(long)]
+    stop: u64,
+
+    /// Blocks per JSON-RPC batch request.
+    #[arg(long, default_value_t = 50)]
+    batch_size: usize,
+
+    /// Also recompute the transactions and receipts roots of every block
+    /// from its contents and compare them with its header.
+    #[cfg(feature = "dynamic")]
+    #[arg(long, requires = "descriptor_set")]
+    roots: bool,
+
+    /// File descriptor set (`.binpb`) of `sf.ethereum.type.v2.Block`, to
+    /// decode blocks for `--roots`.
+    #[cfg(feature = "dynamic")]
+    #[arg(long)]
+    descriptor_set: Option<PathBuf>,
+
+    /// Chain ID of the blocks, to re-encode typed transactions for
+    /// `--roots`.
+    #[cfg(feature = "dynamic")]
+    #[arg(long, default_value_t = 1)]
+    chain_id: u64,
+}
+
+pub async fn run(
+    endpoint: &FirehoseEndpoint,
+    request: Request,
+    args: VerifyArgs,
+) -> Result<(), Box<dyn Error>> {
+    let request = Request {
+        start_block_num: i64::try_from(args.start)?,
+        stop_block_num: args.stop,
+        final_blocks_only: true,
+        ..request
+    };
+
+    #[cfg(feature = "dynamic")]
+    let decoder = match &args.descriptor_set {
+        Some(path) if args.roots => {
+            let mut decoder = DynamicDecoder::new()?;
+            decoder.add_file_descriptor_set(&fs::read(path)?)?;
+            Some(decoder)
+        }
+        _ => None,
+    };
+
+    let rpc = reqwest::Client::new();
+    let mut client = endpoint.stream_client().await?;
+    let mut stream = client.blocks(request).await?.into_inner();
+
+    let mut report = Report::default();
+    let mut batch = Vec::with_capacity(args.batch_size);
+
+    while let Some(response) = stream.message().await? {
+        let Some(metadata) = &response.metadata else {
+            return Err("firehose response has no block metadata".into());
+        };
+
+        #[cfg(feature = "dynamic")]
+        if let Some(decoder) = &decoder {
+            if let Some(block) = decoder.decode_response_block(&response)? {
+                report.roots_checked = true;
+                if let Err(e) = verify_ethereum_roots(&block, args.chain_id) {
+                    report.roots_failed += 1;
+                    println!("roots    #{}: {e}", metadata.num);
+                }
+            }
+        }
+
+        batch.push((metadata.num, metadata.id.clone()));
+
+        if batch.len() >= args.batch_size.max(1) {
+            check_batch(&rpc, &args.rpc_url, &batch, &mut report).await?;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        check_batch(&rpc, &args.rpc_url, &batch, &mut report).await?;
+    }
+
+    print!(
+        "checked {} blocks: {} mismatched, {} missing from RPC",
+        report.checked, report.mismatched, report.missing
+    );
+    if report.roots_checked {
+        print!(", {} failed root verification", report.roots_failed);
+    }
+    println!();
+
+    if report.mismatched > 0 || report.missing > 0 || report.roots_failed > 0 {
+        return Err("verification failed".into());
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Report {
+    checked: u64,
+    mismatched: u64,
+    missing: u64,
+    /// Whether any block was checked against its roots.
+    roots_checked: bool,
+    roots_failed: u64,
+}
+
+/// Fetch the canonical hashes of `batch` in one JSON-RPC batch call and
+/// compare them with the Firehose block IDs.
+async fn check_batch(
+    rpc: &reqwest::Client,
+    url: &str,
+    batch: &[(u64, String)],
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    let calls: Vec<Value> = batch
+        .iter()
+        .map(|(num, _)| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": num,
+                "method": "eth_getBlockByNumber",
+                "params": [format!("0x{num:x}"), false],
+            })
+        })
+        .collect();
+
+    let replies: Vec<Value> = rpc
+        .post(url)
+        .json(&calls)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let mut hashes = HashMap::with_capacity(replies.len());
+    for reply in replies {
+        if let Some(error) = reply.get("error") {
+            return Err(format!("JSON-RPC error: {error}").into());
+        }
+        let Some(id) = reply.get("id").and_then(Value::as_u64) else {
+            continue;
+        };
+        let hash = reply
+            .get("result")
+            .and_then(|block| block.get("hash"))
+            .and_then(Value::as_str)
+            .map(normalize);
+        hashes.insert(id, hash);
+    }
+
+    for (num, id) in batch {
+        report.checked += 1;
+        match hashes.get(num).cloned().flatten() {
+            Some(hash) if hash == normalize(id) => {}
+            Some(hash) => {
+                report.mismatched += 1;
+                println!("mismatch #{num}: firehose {id}, rpc 0x{hash}");
+            }
+            None => {
+                report.missing += 1;
+                println!("missing  #{num}: not returned by RPC");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lowercase hex without the `0x` prefix, so both sides compare equal
+/// regardless of how the chain encodes its block IDs.
+fn normalize(hash: &str) -> String {
+    hash.trim_start_matches("0x").to_ascii_lowercase()
+}
@@ -0,0 +1,123 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::Display;
+
+use crate::{
+    request_id, BlockRange, FetchClient, FirehoseChannel, FirehoseError, FromResponse, Response,
+    SingleBlockRequest,
+};
+
+/// The first block where a predicate flips, found by [`bisect`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Flip<T> {
+    /// Number of the first block whose predicate differs from the first
+    /// block of the range.
+    pub block: u64,
+    /// That block, decoded.
+    pub value: T,
+    /// The predicate of the blocks before the flip.
+    pub before: bool,
+    /// Blocks fetched by the search.
+    pub fetches: u32,
+}
+
+/// Find the first block of `range` where `predicate` over blocks decoded as
+/// `T` flips, fetching about `log2(range.len())` blocks with `client`.
+///
+/// The predicate of the first block of the range is taken as the expected
+/// one, so the search finds where downstream state starts to diverge from
+/// expectations, whichever way the predicate is phrased. Returns `None` when
+/// the last block agrees with the first, which, for a predicate that flips at
+/// most once, means it never flips. A predicate flipping back and forth
+/// yields one of its flips.
+///
+/// Every block of the range must be fetchable: on chains with skipped slots,
+/// give a range of existing blocks, or a failed fetch ends the search.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{bisect, BlockRange, FirehoseEndpoint, Response};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut client = FirehoseEndpoint::from_env()?.fetch_client().await?;
+///
+/// // First block whose payload exceeds 1 MB.
+/// let flip = bisect(
+///     &mut client,
+///     BlockRange::new(17_000_000, 17_100_000),
+///     |block: &Response| block.block_bytes().is_some_and(|bytes| bytes.len() > 1 << 20),
+/// )
+/// .await?;
+/// if let Some(flip) = flip {
+///     println!("flipped at block {} after {} fetches", flip.block, flip.fetches);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn bisect<T>(
+    client: &mut FetchClient<FirehoseChannel>,
+    range: BlockRange,
+    mut predicate: impl FnMut(&T) -> bool,
+) -> Result<Option<Flip<T>>, FirehoseError>
+where
+    T: FromResponse,
+    T::Error: Display + Send,
+{
+    if range.is_empty() {
+        return Ok(None);
+    }
+
+    let before = predicate(&fetch_decoded(client, range.start).await?);
+    if range.len() == 1 {
+        return Ok(None);
+    }
+    let last = fetch_decoded(client, range.stop).await?;
+    let mut fetches = 2;
+    if predicate(&last) == before {
+        return Ok(None);
+    }
+
+    // `low` agrees with the first block, `high` does not.
+    let (mut low, mut high, mut value) = (range.start, range.stop, last);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        let decoded = fetch_decoded(client, mid).await?;
+        fetches += 1;
+        if predicate(&decoded) == before {
+            low = mid;
+        } else {
+            high = mid;
+            value = decoded;
+        }
+    }
+
+    Ok(Some(Flip {
+        block: high,
+        value,
+        before,
+        fetches,
+    }))
+}
+
+async fn fetch_decoded<T>(
+    client: &mut FetchClient<FirehoseChannel>,
+    block: u64,
+) -> Result<T, FirehoseError>
+where
+    T: FromResponse,
+    T::Error: Display + Send,
+{
+    let mut request = tonic::Request::new(SingleBlockRequest::new_by_block_number(block));
+    let id = request_id::ensure(request.metadata_mut());
+    let response = client
+        .block(request)
+        .await
+        .map_err(|status| request_id::tag(status, &id))?
+        .into_inner();
+
+    T::from_response(Response::from(response))
+        .map_err(|e| FirehoseError::Decode(format!("block {block}: {e}")))
+}
@@ -0,0 +1,175 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversions between `sf.bstream.v1.Block` and the Firehose v2 response
+//! envelope.
+
+use std::fmt::{self, Display};
+
+use prost_wkt_types::Any;
+
+use crate::firehose_v2::{
+    summary::{write_block, write_size},
+    BlockMetadata, ForkStep, Response,
+};
+
+use super::{Block, Protocol};
+
+impl Protocol {
+    /// Type URL of the chain block carried in a legacy `payload_buffer`
+    /// written with the given `payload_version`.
+    ///
+    /// Ethereum archives of version 1 hold `sf.ethereum.codec.v1.Block`,
+    /// versions 2 and 3 the current `sf.ethereum.type.v2.Block`. Other chains
+    /// only ever wrote version 1. Returns `None` for [`Protocol::Unknown`] and
+    /// for versions whose block type is not known.
+    pub fn block_type_url(&self, payload_version: i32) -> Option<&'static str> {
+        match (self, payload_version) {
+            (Protocol::Eth, 1) => Some("type.googleapis.com/sf.ethereum.codec.v1.Block"),
+            (Protocol::Eth, 2 | 3) => Some("type.googleapis.com/sf.ethereum.type.v2.Block"),
+            (Protocol::Eos, 1) => Some("type.googleapis.com/sf.antelope.type.v1.Block"),
+            (Protocol::Solana, 1) => Some("type.googleapis.com/sf.solana.type.v1.Block"),
+            (Protocol::Near, 1) => Some("type.googleapis.com/sf.near.type.v1.Block"),
+            (Protocol::Cosmos, 1) => Some("type.googleapis.com/sf.cosmos.type.v1.Block"),
+            _ => None,
+        }
+    }
+}
+
+impl Block {
+    /// The chain block carried by this envelope.
+    ///
+    /// Prefers `payload`, falling back to the deprecated `payload_kind`,
+    /// `payload_version` and `payload_buffer` found in older archives.
+    #[allow(deprecated)]
+    pub fn payload_any(&self) -> Option<Any> {
+        if let Some(payload) = &self.payload {
+            return Some(payload.clone());
+        }
+
+        let type_url = Protocol::try_from(self.payload_kind)
+            .ok()
+            .and_then(|protocol| protocol.block_type_url(self.payload_version))?;
+
+        Some(Any {
+            type_url: type_url.to_string(),
+            value: self.payload_buffer.clone(),
+        })
+    }
+
+    /// The [`BlockMetadata`] described by this envelope.
+    pub fn metadata(&self) -> BlockMetadata {
+        BlockMetadata {
+            num: self.number,
+            id: self.id.clone(),
+            parent_num: self.parent_num,
+            parent_id: self.parent_id.clone(),
+            lib_num: self.lib_num,
+            time: self.timestamp,
+        }
+    }
+
+    /// Wrap this block in a v2 [`Response`] with the given fork step.
+    ///
+    /// The response has no cursor, since flat files do not carry one.
+    pub fn into_response(self, step: ForkStep) -> Response {
+        Response {
+            block: self.payload_any(),
+            step: step.into(),
+            cursor: String::new(),
+            metadata: Some(self.metadata()),
+        }
+    }
+}
+
+/// Block number, hash prefix and payload size, e.g.
+/// `#17000000 1a2b3c4d… 152.3 KB`.
+impl Display for Block {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_block(f, Some(&self.metadata()))?;
+        let size = self
+            .payload
+            .as_ref()
+            .map_or(self.payload_buffer.len(), |payload| payload.value.len());
+        write!(f, " ")?;
+        write_size(f, size)
+    }
+}
+
+/// Blocks read from merged-block files are final, so the response is marked
+/// `STEP_FINAL`.
+impl From<Block> for Response {
+    fn from(block: Block) -> Self {
+        block.into_response(ForkStep::StepFinal)
+    }
+}
+
+impl TryFrom<Response> for Block {
+    type Error = MissingMetadataError;
+
+    /// Build a bstream [`Block`] from a v2 [`Response`].
+    ///
+    /// The envelope fields come from the response metadata, which older
+    /// Firehose servers do not send.
+    fn try_from(response: Response) -> Result<Self, Self::Error> {
+        let metadata = response.metadata.ok_or(MissingMetadataError)?;
+
+        Ok(Block {
+            number: metadata.num,
+            id: metadata.id,
+            parent_id: metadata.parent_id,
+            timestamp: metadata.time,
+            lib_num: metadata.lib_num,
+            parent_num: metadata.parent_num,
+            payload: response.block,
+            ..Default::default()
+        })
+    }
+}
+
+/// Returned when a [`Response`] without block metadata is converted into a
+/// bstream [`Block`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MissingMetadataError;
+
+impl Display for MissingMetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "response has no block metadata")
+    }
+}
+
+impl std::error::Error for MissingMetadataError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(deprecated)]
+    fn legacy(protocol: Protocol, payload_version: i32) -> Block {
+        Block {
+            payload_kind: protocol.into(),
+            payload_version,
+            payload_buffer: vec![0x08, 0x01],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn types_legacy_payloads_by_version() {
+        let type_url = |block: Block| block.payload_any().map(|any| any.type_url);
+
+        assert_eq!(
+            type_url(legacy(Protocol::Eth, 1)).as_deref(),
+            Some("type.googleapis.com/sf.ethereum.codec.v1.Block")
+        );
+        assert_eq!(
+            type_url(legacy(Protocol::Eth, 3)).as_deref(),
+            Some("type.googleapis.com/sf.ethereum.type.v2.Block")
+        );
+        assert_eq!(type_url(legacy(Protocol::Eth, 0)), None);
+        assert_eq!(type_url(legacy(Protocol::Near, 2)), None);
+        assert_eq!(type_url(legacy(Protocol::Unknown, 1)), None);
+    }
+}
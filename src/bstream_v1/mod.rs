@@ -0,0 +1,7 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod envelope;
+
+tonic::include_proto!("sf.bstream.v1");
@@ -0,0 +1,1007 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
+};
+
+use prost::Message;
+use tokio::{task::JoinHandle, time::Instant};
+
+#[cfg(feature = "zstd")]
+use crate::Zstd;
+use crate::{
+    firehose_v2::single_block_request::{BlockHashAndNumber, Reference},
+    BlockMetadata, FetchClient, FirehoseChannel, FirehoseError, Request, Response,
+    SingleBlockRequest, SingleBlockResponse,
+};
+
+/// A memory-bounded, least-recently-used cache of single-block fetches.
+///
+/// Hot paths that fetch the same few blocks again and again, such as parent
+/// lookups while handling reorgs, can answer from memory instead of the
+/// network. Entries are keyed by the whole request, transforms included, and
+/// the least recently used ones are evicted once the encoded size of the
+/// cached responses exceeds the budget.
+///
+/// Fetches by hash or cursor always identify the same block and are always
+/// cached. A fetch by number alone is only cached once its block is final,
+/// since the canonical block at a height may still change before that.
+///
+/// Clones share the same entries, so one cache can serve an
+/// [`EndpointPool`](crate::EndpointPool) and individual clients at once.
+///
+/// [Keyed by block hash](CacheKey::BlockHash), the cache instead holds every
+/// block it sees under its hash, forked ones included.
+///
+/// Besides the size budget, entries can expire after a
+/// [maximum age](FetchCache::with_max_age), and caches that should only keep
+/// [final blocks](FetchCache::with_finalized_only) drop the others. Expired
+/// entries are never returned, and a [janitor](FetchCache::spawn_janitor)
+/// frees their memory in the background, so a cache can run unattended.
+///
+/// A [disk tier](FetchCache::with_disk) keeps responses in files as well,
+/// under its own size budget and the same expiry, so they survive restarts
+/// and outlive their eviction from memory.
+///
+/// With the `zstd` feature, [compressed](FetchCache::with_compression)
+/// entries fit several times more blocks into the same budget, at the cost
+/// of decompressing them on every hit.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{FetchCache, FirehoseEndpoint, SingleBlockRequest};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let cache = FetchCache::new(64 * 1024 * 1024);
+/// let mut client = FirehoseEndpoint::from_env()?.fetch_client().await?;
+///
+/// let request =
+///     SingleBlockRequest::new_by_block_hash_and_number("0xabc123...".to_string(), 12345);
+/// let first = cache.block(&mut client, request.clone()).await?;
+/// let again = cache.block(&mut client, request).await?; // served from memory
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FetchCache {
+    inner: Arc<Mutex<Lru>>,
+    key: CacheKey,
+    #[cfg(feature = "zstd")]
+    compression: Option<Zstd>,
+}
+
+/// How a [`FetchCache`] keys its entries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheKey {
+    /// By request: fetches by hash or cursor, and fetches by number of final
+    /// blocks, are answered from the cache when the same request is made
+    /// again.
+    #[default]
+    Request,
+    /// By block hash and transforms: every block with
+    /// [metadata](SingleBlockResponse::metadata) is cached under its hash,
+    /// final or not, so the blocks of abandoned forks are kept apart from the
+    /// canonical ones and reorg handling can look up exactly the version it
+    /// saw. Only fetches by hash are answered from the cache; fetches by
+    /// number or cursor still fill it.
+    BlockHash,
+}
+
+#[derive(Debug)]
+struct Lru {
+    max_bytes: usize,
+    expiry: Expiry,
+    bytes: usize,
+    clock: u64,
+    entries: HashMap<Vec<u8>, Entry>,
+    /// Keys by last use, oldest first.
+    order: BTreeMap<u64, Vec<u8>>,
+    disk: Option<Disk>,
+}
+
+/// When entries must not be returned anymore.
+#[derive(Clone, Copy, Debug, Default)]
+struct Expiry {
+    max_age: Option<Duration>,
+    finalized_only: bool,
+    /// Highest final block seen in cached responses.
+    lib: u64,
+}
+
+impl Expiry {
+    /// Whether an entry cached `age` ago for block `number` has expired.
+    fn is_expired(&self, age: Duration, number: Option<u64>) -> bool {
+        let too_old = self.max_age.is_some_and(|max_age| age > max_age);
+        let not_final = self.finalized_only && number.is_none_or(|number| number > self.lib);
+        too_old || not_final
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: Cached,
+    size: usize,
+    used: u64,
+    inserted: Instant,
+    /// Number of the cached block, if its response carried metadata.
+    number: Option<u64>,
+}
+
+/// A cached response, as stored.
+#[derive(Clone, Debug)]
+enum Cached {
+    Response(SingleBlockResponse),
+    #[cfg(feature = "zstd")]
+    Compressed(Vec<u8>),
+}
+
+impl Cached {
+    fn size(&self) -> usize {
+        match self {
+            Cached::Response(response) => response.encoded_len(),
+            #[cfg(feature = "zstd")]
+            Cached::Compressed(bytes) => bytes.len(),
+        }
+    }
+
+    /// A response read back from a file of the disk tier, `None` if it does
+    /// not decode.
+    fn from_file(payload: Vec<u8>, compressed: bool) -> Option<Cached> {
+        match compressed {
+            false => SingleBlockResponse::decode(payload.as_slice())
+                .ok()
+                .map(Cached::Response),
+            #[cfg(feature = "zstd")]
+            true => Some(Cached::Compressed(payload)),
+            // Written by a build with the `zstd` feature.
+            #[cfg(not(feature = "zstd"))]
+            true => None,
+        }
+    }
+}
+
+impl Lru {
+    fn touch(&mut self, key: &[u8]) -> Option<Cached> {
+        let now = Instant::now();
+        if self.entries.get(key).is_some_and(|entry| {
+            self.expiry
+                .is_expired(now.saturating_duration_since(entry.inserted), entry.number)
+        }) {
+            self.remove(key);
+        }
+
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            let key = self.order.remove(&entry.used).expect("entry is ordered");
+            entry.used = self.clock;
+            self.order.insert(self.clock, key);
+            return Some(entry.response.clone());
+        }
+        self.touch_disk(key)
+    }
+
+    /// Load the entry for `key` from the disk tier back into memory, if it is
+    /// there and has not expired.
+    fn touch_disk(&mut self, key: &[u8]) -> Option<Cached> {
+        let expiry = self.expiry;
+        let disk = self.disk.as_mut()?;
+        let (response, number, age) = disk.get(key)?;
+        if expiry.is_expired(age, number) {
+            disk.remove(file_id(key));
+            return None;
+        }
+
+        self.insert_memory(key.to_vec(), response.clone(), number);
+        Some(response)
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.used);
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn insert(&mut self, key: Vec<u8>, response: Cached, metadata: Option<&BlockMetadata>) {
+        if let Some(metadata) = metadata {
+            self.expiry.lib = self.expiry.lib.max(metadata.lib_num);
+        }
+        let number = metadata.map(|metadata| metadata.num);
+        if let Some(disk) = &mut self.disk {
+            // A failed write only costs the disk tier a hit later.
+            let _ = disk.insert(&key, &response, number, self.expiry.lib);
+        }
+        self.insert_memory(key, response, number);
+    }
+
+    fn insert_memory(&mut self, key: Vec<u8>, response: Cached, number: Option<u64>) {
+        let size = key.len() + response.size();
+        if size > self.max_bytes {
+            return;
+        }
+
+        self.remove(&key);
+        while self.bytes + size > self.max_bytes {
+            let (_, oldest) = self.order.pop_first().expect("cache is not empty");
+            let entry = self.entries.remove(&oldest).expect("ordered key is cached");
+            self.bytes -= entry.size;
+        }
+
+        self.clock += 1;
+        self.bytes += size;
+        self.order.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                response,
+                size,
+                used: self.clock,
+                inserted: Instant::now(),
+                number,
+            },
+        );
+    }
+
+    /// Remove the expired entries, from memory and disk, returning how many
+    /// there were.
+    fn evict_expired(&mut self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                self.expiry
+                    .is_expired(now.saturating_duration_since(entry.inserted), entry.number)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &expired {
+            self.remove(key);
+        }
+        let on_disk = match &mut self.disk {
+            Some(disk) => disk.evict_expired(self.expiry),
+            None => 0,
+        };
+        expired.len() + on_disk
+    }
+}
+
+/// Extension of the files of the disk tier.
+const FILE_EXTENSION: &str = "block";
+
+/// Flag of compressed responses in a file of the disk tier.
+const FILE_COMPRESSED: u8 = 1;
+
+/// Flag of files recording their block number.
+const FILE_NUMBERED: u8 = 2;
+
+/// The disk tier of a [`FetchCache`]: one file per response in a directory,
+/// named after a hash of its key.
+///
+/// Each file holds the length of the key, the key, flags, the block number
+/// and the last irreversible block known when it was written, and then the
+/// response. The key is checked on every read, so responses whose keys hash
+/// alike are told apart.
+#[derive(Debug)]
+struct Disk {
+    dir: PathBuf,
+    max_bytes: u64,
+    bytes: u64,
+    files: HashMap<u64, DiskEntry>,
+    /// Files by when they were written, oldest first.
+    order: BTreeSet<(SystemTime, u64)>,
+    /// Highest last irreversible block recorded in the files found when
+    /// opening the tier.
+    lib: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct DiskEntry {
+    size: u64,
+    written: SystemTime,
+    number: Option<u64>,
+}
+
+impl DiskEntry {
+    fn age(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.written).unwrap_or_default()
+    }
+}
+
+impl Disk {
+    /// Open the tier in `dir`, creating it if needed and indexing the files
+    /// left by earlier runs.
+    fn open(dir: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut disk = Disk {
+            dir,
+            max_bytes,
+            bytes: 0,
+            files: HashMap::new(),
+            order: BTreeSet::new(),
+            lib: 0,
+        };
+
+        for entry in fs::read_dir(&disk.dir)? {
+            let path = entry?.path();
+            let Some(id) = path_id(&path) else {
+                // Interrupted writes.
+                if path.extension().is_some_and(|ext| ext == "tmp") {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            };
+
+            let header = fs::metadata(&path).and_then(|metadata| {
+                let file = File::open(&path)?;
+                let (_, _, number, lib) = read_header(&mut BufReader::new(file))?;
+                Ok((metadata, number, lib))
+            });
+            match header {
+                Ok((metadata, number, lib)) => {
+                    disk.lib = disk.lib.max(lib);
+                    disk.track(
+                        id,
+                        DiskEntry {
+                            size: metadata.len(),
+                            written: metadata.modified()?,
+                            number,
+                        },
+                    );
+                }
+                // Torn files are dropped.
+                Err(_) => {
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        disk.shrink();
+        Ok(disk)
+    }
+
+    /// The response stored for `key`, with its block number and age.
+    fn get(&mut self, key: &[u8]) -> Option<(Cached, Option<u64>, Duration)> {
+        let id = file_id(key);
+        let entry = *self.files.get(&id)?;
+
+        let read = File::open(self.path(id)).and_then(|file| {
+            let mut reader = BufReader::new(file);
+            let (stored, flags, _, _) = read_header(&mut reader)?;
+            let mut payload = Vec::new();
+            reader.read_to_end(&mut payload)?;
+            Ok((stored, flags, payload))
+        });
+        let (stored, flags, payload) = match read {
+            Ok(read) => read,
+            Err(_) => {
+                self.remove(id);
+                return None;
+            }
+        };
+        // Another key with the same hash.
+        if stored != key {
+            return None;
+        }
+
+        let Some(response) = Cached::from_file(payload, flags & FILE_COMPRESSED != 0) else {
+            self.remove(id);
+            return None;
+        };
+        Some((response, entry.number, entry.age(SystemTime::now())))
+    }
+
+    /// Write `response` under `key`, then delete the oldest files over the
+    /// budget.
+    fn insert(
+        &mut self,
+        key: &[u8],
+        response: &Cached,
+        number: Option<u64>,
+        lib: u64,
+    ) -> io::Result<()> {
+        let encoded;
+        let (payload, mut flags) = match response {
+            Cached::Response(response) => {
+                encoded = response.encode_to_vec();
+                (encoded.as_slice(), 0)
+            }
+            #[cfg(feature = "zstd")]
+            Cached::Compressed(bytes) => (bytes.as_slice(), FILE_COMPRESSED),
+        };
+        if number.is_some() {
+            flags |= FILE_NUMBERED;
+        }
+
+        let size = (FILE_HEADER + key.len() + payload.len()) as u64;
+        if size > self.max_bytes {
+            return Ok(());
+        }
+        let key_len = u32::try_from(key.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "cache key too long"))?;
+
+        let id = file_id(key);
+        let path = self.path(id);
+        let tmp = path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        file.write_all(&key_len.to_le_bytes())?;
+        file.write_all(key)?;
+        file.write_all(&[flags])?;
+        file.write_all(&number.unwrap_or_default().to_le_bytes())?;
+        file.write_all(&lib.to_le_bytes())?;
+        file.write_all(payload)?;
+        file.flush()?;
+        drop(file);
+        fs::rename(&tmp, &path)?;
+
+        self.track(
+            id,
+            DiskEntry {
+                size,
+                written: SystemTime::now(),
+                number,
+            },
+        );
+        self.shrink();
+        Ok(())
+    }
+
+    /// Delete the expired files, returning how many there were.
+    fn evict_expired(&mut self, expiry: Expiry) -> usize {
+        let now = SystemTime::now();
+        let expired: Vec<u64> = self
+            .files
+            .iter()
+            .filter(|(_, entry)| expiry.is_expired(entry.age(now), entry.number))
+            .map(|(&id, _)| id)
+            .collect();
+
+        for &id in &expired {
+            self.remove(id);
+        }
+        expired.len()
+    }
+
+    /// Delete every file.
+    fn clear(&mut self) {
+        let ids: Vec<u64> = self.files.keys().copied().collect();
+        for id in ids {
+            self.remove(id);
+        }
+    }
+
+    /// Delete the oldest files until the rest fit the budget.
+    fn shrink(&mut self) {
+        while self.bytes > self.max_bytes {
+            let Some(&(_, id)) = self.order.first() else {
+                break;
+            };
+            self.remove(id);
+        }
+    }
+
+    fn track(&mut self, id: u64, entry: DiskEntry) {
+        self.untrack(id);
+        self.bytes += entry.size;
+        self.order.insert((entry.written, id));
+        self.files.insert(id, entry);
+    }
+
+    fn untrack(&mut self, id: u64) -> Option<DiskEntry> {
+        let entry = self.files.remove(&id)?;
+        self.order.remove(&(entry.written, id));
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn remove(&mut self, id: u64) {
+        if self.untrack(id).is_some() {
+            // Already gone is as good as deleted.
+            let _ = fs::remove_file(self.path(id));
+        }
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{id:016x}.{FILE_EXTENSION}"))
+    }
+}
+
+/// Size of a file of the disk tier besides its key and response: the key
+/// length, flags, block number and last irreversible block.
+const FILE_HEADER: usize = 4 + 1 + 8 + 8;
+
+/// Read the header of a file of the disk tier, returning its key, flags,
+/// block number and last irreversible block.
+fn read_header(reader: &mut impl Read) -> io::Result<(Vec<u8>, u8, Option<u64>, u64)> {
+    let mut word = [0; 4];
+    reader.read_exact(&mut word)?;
+    let mut key = vec![0; u32::from_le_bytes(word) as usize];
+    reader.read_exact(&mut key)?;
+
+    let mut fields = [0; FILE_HEADER - 4];
+    reader.read_exact(&mut fields)?;
+    let flags = fields[0];
+    let number = u64::from_le_bytes(fields[1..9].try_into().expect("8 bytes"));
+    let lib = u64::from_le_bytes(fields[9..17].try_into().expect("8 bytes"));
+    let number = (flags & FILE_NUMBERED != 0).then_some(number);
+    Ok((key, flags, number, lib))
+}
+
+/// Hash of a cache key naming its file: 64-bit FNV-1a, which unlike the
+/// standard library's hashers is stable across releases.
+fn file_id(key: &[u8]) -> u64 {
+    key.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The id of the file at `path`, if it belongs to the disk tier.
+fn path_id(path: &Path) -> Option<u64> {
+    if path.extension()? != FILE_EXTENSION {
+        return None;
+    }
+    u64::from_str_radix(path.file_stem()?.to_str()?, 16).ok()
+}
+
+impl FetchCache {
+    /// Create a cache holding at most `max_bytes` of encoded responses.
+    pub fn new(max_bytes: usize) -> Self {
+        FetchCache {
+            inner: Arc::new(Mutex::new(Lru {
+                max_bytes,
+                expiry: Expiry::default(),
+                bytes: 0,
+                clock: 0,
+                entries: HashMap::new(),
+                order: BTreeMap::new(),
+                disk: None,
+            })),
+            key: CacheKey::Request,
+            #[cfg(feature = "zstd")]
+            compression: None,
+        }
+    }
+
+    /// Key entries according to `key`.
+    ///
+    /// Clones made before this call keep sharing the entries but not the
+    /// keying, so set it before sharing the cache.
+    pub fn with_key(mut self, key: CacheKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Expire entries `max_age` after they were cached.
+    ///
+    /// Applies to all clones of the cache.
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        self.lock().expiry.max_age = Some(max_age);
+        self
+    }
+
+    /// Only keep blocks known to be final: at or below the highest last
+    /// irreversible block seen in cached responses.
+    ///
+    /// Blocks that were not final yet when cached expire until a later
+    /// response shows them final, so caches [keyed by hash](CacheKey::BlockHash)
+    /// stop holding on to the blocks of recent forks. Blocks of forks below
+    /// the last irreversible block are not told apart from canonical ones.
+    /// Applies to all clones of the cache.
+    pub fn with_finalized_only(self) -> Self {
+        self.lock().expiry.finalized_only = true;
+        self
+    }
+
+    /// Also keep cached responses in files under `dir`, up to `max_bytes` of
+    /// them, answering fetches evicted from memory or cached before a
+    /// restart.
+    ///
+    /// Files are loaded back into memory on a hit. Once over the budget, the
+    /// oldest files are deleted first; the files of earlier runs count
+    /// towards it. The [maximum age](FetchCache::with_max_age) and
+    /// [finality](FetchCache::with_finalized_only) settings, and the
+    /// [janitor](FetchCache::spawn_janitor), apply to the files too. Compressed
+    /// caches store their files compressed.
+    ///
+    /// Files are read and written while the cache is locked, so put the
+    /// directory on local storage. Applies to all clones of the cache.
+    pub fn with_disk(self, dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let disk = Disk::open(dir.into(), max_bytes)?;
+        let mut lru = self.lock();
+        lru.expiry.lib = lru.expiry.lib.max(disk.lib);
+        lru.disk = Some(disk);
+        drop(lru);
+        Ok(self)
+    }
+
+    /// Store responses compressed with `zstd`, counting their compressed
+    /// size against the budget.
+    #[cfg(feature = "zstd")]
+    pub fn with_compression(mut self, zstd: Zstd) -> Self {
+        self.compression = Some(zstd);
+        self
+    }
+
+    /// The cached response to `request`, marking it as recently used.
+    pub fn get(&self, request: &SingleBlockRequest) -> Option<SingleBlockResponse> {
+        let key = match self.key {
+            CacheKey::Request => request.encode_to_vec(),
+            CacheKey::BlockHash => match &request.reference {
+                Some(Reference::BlockHashAndNumber(block)) => {
+                    hash_key(request, &block.hash, block.num)
+                }
+                _ => return None,
+            },
+        };
+
+        let cached = self.lock().touch(&key)?;
+        match cached {
+            Cached::Response(response) => Some(response),
+            #[cfg(feature = "zstd")]
+            Cached::Compressed(bytes) => {
+                let bytes = self.compression.as_ref()?.decompress(&bytes).ok()?;
+                SingleBlockResponse::decode(bytes.as_slice()).ok()
+            }
+        }
+    }
+
+    /// Cache `response` as the answer to `request`, if it may be reused.
+    pub fn insert(&self, request: &SingleBlockRequest, response: &SingleBlockResponse) {
+        let key = match (self.key, &response.metadata) {
+            (CacheKey::Request, _) if is_cacheable(request, response) => request.encode_to_vec(),
+            (CacheKey::BlockHash, Some(metadata)) => hash_key(request, &metadata.id, metadata.num),
+            _ => return,
+        };
+
+        #[cfg(feature = "zstd")]
+        if let Some(zstd) = &self.compression {
+            if let Ok(bytes) = zstd.compress(&response.encode_to_vec()) {
+                self.lock()
+                    .insert(key, Cached::Compressed(bytes), response.metadata.as_ref());
+            }
+            return;
+        }
+        self.lock().insert(
+            key,
+            Cached::Response(response.clone()),
+            response.metadata.as_ref(),
+        );
+    }
+
+    /// Cache a block received on a stream of `request`, as the answer to a
+    /// fetch of its hash with the same transforms.
+    ///
+    /// Streamed blocks without [metadata](Response::metadata) are ignored.
+    pub fn insert_streamed(&self, request: &Request, response: &Response) {
+        let Some(metadata) = &response.metadata else {
+            return;
+        };
+
+        let fetch = SingleBlockRequest {
+            reference: Some(Reference::BlockHashAndNumber(BlockHashAndNumber {
+                hash: metadata.id.clone(),
+                num: metadata.num,
+            })),
+            transforms: request.transforms.clone(),
+        };
+        let block = SingleBlockResponse {
+            block: response.block.clone(),
+            metadata: response.metadata.clone(),
+        };
+        self.insert(&fetch, &block);
+    }
+
+    /// Fetch `request` through `client`, unless the response is cached.
+    pub async fn block(
+        &self,
+        client: &mut FetchClient<FirehoseChannel>,
+        request: SingleBlockRequest,
+    ) -> Result<SingleBlockResponse, FirehoseError> {
+        if let Some(response) = self.get(&request) {
+            return Ok(response);
+        }
+
+        let response = client.block(request.clone()).await?.into_inner();
+        self.insert(&request, &response);
+        Ok(response)
+    }
+
+    /// Number of cached responses.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    /// Encoded size of the cached requests and responses, in bytes, after
+    /// compression if enabled.
+    pub fn size_bytes(&self) -> usize {
+        self.lock().bytes
+    }
+
+    /// Size of the files of the [disk tier](FetchCache::with_disk), in bytes,
+    /// or zero without one.
+    pub fn disk_size_bytes(&self) -> u64 {
+        self.lock().disk.as_ref().map_or(0, |disk| disk.bytes)
+    }
+
+    /// Remove the entries past their [maximum age](FetchCache::with_max_age)
+    /// or [not final](FetchCache::with_finalized_only), in memory and on
+    /// disk, returning how many were removed.
+    pub fn evict_expired(&self) -> usize {
+        self.lock().evict_expired()
+    }
+
+    /// Run [`FetchCache::evict_expired`] every `interval` on a background
+    /// task.
+    ///
+    /// The task ends once every clone of the cache is dropped.
+    pub fn spawn_janitor(&self, interval: Duration) -> JoinHandle<()> {
+        let inner = Arc::downgrade(&self.inner);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval.max(Duration::from_millis(1)));
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let Some(inner) = Weak::upgrade(&inner) else {
+                    return;
+                };
+                inner
+                    .lock()
+                    .expect("fetch cache lock poisoned")
+                    .evict_expired();
+            }
+        })
+    }
+
+    /// Remove all entries, deleting the files of the disk tier.
+    pub fn clear(&self) {
+        let mut lru = self.lock();
+        lru.entries.clear();
+        lru.order.clear();
+        lru.bytes = 0;
+        if let Some(disk) = &mut lru.disk {
+            disk.clear();
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.inner.lock().expect("fetch cache lock poisoned")
+    }
+}
+
+/// Key of the block `hash` at `num` fetched with the transforms of `request`,
+/// whatever the case and `0x` prefix of the hash.
+fn hash_key(request: &SingleBlockRequest, hash: &str, num: u64) -> Vec<u8> {
+    SingleBlockRequest {
+        reference: Some(Reference::BlockHashAndNumber(BlockHashAndNumber {
+            hash: hash.trim_start_matches("0x").to_ascii_lowercase(),
+            num,
+        })),
+        transforms: request.transforms.clone(),
+    }
+    .encode_to_vec()
+}
+
+fn is_cacheable(request: &SingleBlockRequest, response: &SingleBlockResponse) -> bool {
+    match request.reference {
+        Some(Reference::BlockNumber(_)) => response
+            .metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.num <= metadata.lib_num),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use prost_wkt_types::Any;
+
+    use super::*;
+
+    /// Block `num` with a 100-byte payload, at a last irreversible block of
+    /// `lib_num`.
+    fn response(num: u64, lib_num: u64) -> SingleBlockResponse {
+        SingleBlockResponse {
+            block: Some(Any {
+                type_url: "type.googleapis.com/test.Block".to_string(),
+                value: vec![0; 100],
+            }),
+            metadata: Some(BlockMetadata {
+                num,
+                id: format!("{num:064x}"),
+                lib_num,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Fetch of block `num` by hash, which is always cacheable.
+    fn by_hash(num: u64) -> SingleBlockRequest {
+        SingleBlockRequest::new_by_block_hash_and_number(format!("{num:064x}"), num)
+    }
+
+    /// An empty directory for the disk tier of one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("firehose-cache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let entry = by_hash(1).encoded_len() + response(1, 0).encoded_len();
+        let cache = FetchCache::new(2 * entry);
+        cache.insert(&by_hash(1), &response(1, 0));
+        cache.insert(&by_hash(2), &response(2, 0));
+
+        // A hit makes block 1 the most recently used, a miss changes nothing.
+        assert_eq!(cache.get(&by_hash(1)), Some(response(1, 0)));
+        assert_eq!(cache.get(&by_hash(9)), None);
+        cache.insert(&by_hash(3), &response(3, 0));
+
+        assert_eq!(cache.get(&by_hash(2)), None);
+        assert_eq!(cache.get(&by_hash(1)), Some(response(1, 0)));
+        assert_eq!(cache.get(&by_hash(3)), Some(response(3, 0)));
+    }
+
+    #[test]
+    fn evicts_the_oldest_entries_over_its_budget() {
+        let entry = by_hash(1).encoded_len() + response(1, 0).encoded_len();
+        let cache = FetchCache::new(3 * entry);
+        for num in 1..=5 {
+            cache.insert(&by_hash(num), &response(num, 0));
+            assert!(cache.size_bytes() <= 3 * entry);
+        }
+
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.size_bytes(), 3 * entry);
+        assert_eq!(cache.get(&by_hash(1)), None);
+        assert_eq!(cache.get(&by_hash(2)), None);
+        for num in 3..=5 {
+            assert_eq!(cache.get(&by_hash(num)), Some(response(num, 0)));
+        }
+
+        // Entries larger than the whole budget are not cached.
+        let small = FetchCache::new(entry - 1);
+        small.insert(&by_hash(1), &response(1, 0));
+        assert!(small.is_empty());
+    }
+
+    #[test]
+    fn hash_lookups_return_blocks_fetched_by_number() {
+        let cache = FetchCache::new(1 << 20).with_key(CacheKey::BlockHash);
+        let by_number = SingleBlockRequest::new_by_block_number(0xab);
+        // Not final, so only cached because the cache is keyed by hash.
+        cache.insert(&by_number, &response(0xab, 0));
+
+        assert_eq!(cache.get(&by_hash(0xab)), Some(response(0xab, 0)));
+        // Whatever the case and prefix of the hash.
+        let upper =
+            SingleBlockRequest::new_by_block_hash_and_number(format!("0x{:064X}", 0xab), 0xab);
+        assert_eq!(cache.get(&upper), Some(response(0xab, 0)));
+        // Only fetches by hash are answered.
+        assert_eq!(cache.get(&by_number), None);
+
+        // A forked block at the same height is kept apart.
+        let mut fork = response(0xab, 0);
+        let fork_hash = format!("{:064x}", 0xf0_0000_u64);
+        fork.metadata.as_mut().unwrap().id.clone_from(&fork_hash);
+        cache.insert(&by_number, &fork);
+
+        assert_eq!(cache.get(&by_hash(0xab)), Some(response(0xab, 0)));
+        let fork_request = SingleBlockRequest::new_by_block_hash_and_number(fork_hash, 0xab);
+        assert_eq!(cache.get(&fork_request), Some(fork));
+    }
+
+    #[test]
+    fn expires_entries_after_their_max_age() {
+        let cache = FetchCache::new(1 << 20).with_max_age(Duration::from_millis(50));
+        cache.insert(&by_hash(1), &response(1, 0));
+        assert_eq!(cache.get(&by_hash(1)), Some(response(1, 0)));
+
+        sleep(Duration::from_millis(100));
+        assert_eq!(cache.get(&by_hash(1)), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.size_bytes(), 0);
+    }
+
+    #[test]
+    fn keeps_only_final_blocks() {
+        let cache = FetchCache::new(1 << 20).with_finalized_only();
+        cache.insert(&by_hash(10), &response(10, 5));
+        cache.insert(&by_hash(20), &response(20, 15));
+
+        // Block 10 became final with block 20, which is not final itself.
+        assert_eq!(cache.get(&by_hash(10)), Some(response(10, 5)));
+        assert_eq!(cache.get(&by_hash(20)), None);
+    }
+
+    #[test]
+    fn evicts_expired_entries_in_memory_and_on_disk() {
+        let dir = scratch_dir("expired");
+        let cache = FetchCache::new(1 << 20)
+            .with_max_age(Duration::from_millis(50))
+            .with_disk(&dir, 1 << 20)
+            .unwrap();
+        cache.insert(&by_hash(1), &response(1, 0));
+        assert!(cache.disk_size_bytes() > 0);
+
+        assert_eq!(cache.evict_expired(), 0);
+        sleep(Duration::from_millis(100));
+        assert_eq!(cache.evict_expired(), 2);
+        assert!(cache.is_empty());
+        assert_eq!(cache.disk_size_bytes(), 0);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn serves_evicted_entries_from_disk_across_restarts() {
+        let dir = scratch_dir("restart");
+        let one_entry = by_hash(1).encoded_len() + response(1, 0).encoded_len();
+        let cache = FetchCache::new(one_entry).with_disk(&dir, 1 << 20).unwrap();
+        cache.insert(&by_hash(1), &response(1, 0));
+        cache.insert(&by_hash(2), &response(2, 0));
+        assert_eq!(cache.len(), 1);
+
+        // Evicted from memory, still on disk.
+        assert_eq!(cache.get(&by_hash(1)), Some(response(1, 0)));
+        drop(cache);
+
+        let cache = FetchCache::new(1 << 20).with_disk(&dir, 1 << 20).unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&by_hash(2)), Some(response(2, 0)));
+        assert_eq!(cache.get(&by_hash(1)), Some(response(1, 0)));
+        // Loaded back into memory.
+        assert_eq!(cache.len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_the_oldest_files_over_the_disk_budget() {
+        let dir = scratch_dir("budget");
+        let file_size =
+            (FILE_HEADER + by_hash(1).encoded_len() + response(1, 0).encoded_len()) as u64;
+        // Nothing fits in memory, so every hit comes from disk.
+        let cache = FetchCache::new(0).with_disk(&dir, 2 * file_size).unwrap();
+
+        for num in 1..=3 {
+            cache.insert(&by_hash(num), &response(num, 0));
+            // Distinct write times.
+            sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(cache.disk_size_bytes(), 2 * file_size);
+        assert_eq!(cache.get(&by_hash(1)), None);
+        assert_eq!(cache.get(&by_hash(2)), Some(response(2, 0)));
+        assert_eq!(cache.get(&by_hash(3)), Some(response(3, 0)));
+
+        // Files left by an earlier run count towards the budget.
+        drop(cache);
+        let cache = FetchCache::new(0).with_disk(&dir, file_size).unwrap();
+        assert_eq!(cache.disk_size_bytes(), file_size);
+        assert_eq!(cache.get(&by_hash(2)), None);
+        assert_eq!(cache.get(&by_hash(3)), Some(response(3, 0)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
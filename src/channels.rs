@@ -0,0 +1,119 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use tonic::transport::Channel;
+
+/// How an [`EndpointPool`](crate::EndpointPool) with several channels per
+/// endpoint assigns streams to them, see
+/// [`with_channels`](crate::EndpointPool::with_channels).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChannelAssignment {
+    /// Take channels in turn.
+    #[default]
+    RoundRobin,
+    /// Take the channel carrying the fewest streams, the first of them on
+    /// ties.
+    LeastLoaded,
+}
+
+/// The channels of one endpoint, and how many streams each carries.
+#[derive(Debug)]
+pub(crate) struct ChannelSet {
+    channels: Vec<Channel>,
+    streams: Vec<AtomicUsize>,
+    next: AtomicUsize,
+    assignment: ChannelAssignment,
+}
+
+impl ChannelSet {
+    /// `channels`, of which there must be at least one.
+    pub(crate) fn new(channels: Vec<Channel>, assignment: ChannelAssignment) -> Self {
+        assert!(!channels.is_empty(), "channel set needs a channel");
+        ChannelSet {
+            streams: channels.iter().map(|_| AtomicUsize::new(0)).collect(),
+            channels,
+            next: AtomicUsize::new(0),
+            assignment,
+        }
+    }
+
+    /// A channel for a call that does not hold a lease, such as a fetch.
+    pub(crate) fn channel(&self) -> Channel {
+        self.channels[self.pick()].clone()
+    }
+
+    /// Lease a channel for a stream, until the lease is dropped.
+    pub(crate) fn lease(self: &Arc<Self>, endpoint: usize) -> ChannelLease {
+        let slot = self.pick();
+        self.streams[slot].fetch_add(1, Ordering::Relaxed);
+        ChannelLease {
+            set: self.clone(),
+            endpoint,
+            slot,
+        }
+    }
+
+    /// Streams carried by each channel.
+    pub(crate) fn streams(&self) -> Vec<usize> {
+        self.streams
+            .iter()
+            .map(|streams| streams.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    fn pick(&self) -> usize {
+        match self.assignment {
+            ChannelAssignment::RoundRobin => {
+                self.next.fetch_add(1, Ordering::Relaxed) % self.channels.len()
+            }
+            ChannelAssignment::LeastLoaded => self
+                .streams
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, streams)| streams.load(Ordering::Relaxed))
+                .map_or(0, |(slot, _)| slot),
+        }
+    }
+}
+
+/// A channel assigned to one stream, from
+/// [`EndpointPool::lease_at`](crate::EndpointPool::lease_at).
+///
+/// The stream counts towards the channel's load until the lease is dropped.
+/// Keeping the lease across reconnects keeps the stream on the same
+/// channel.
+#[derive(Debug)]
+pub struct ChannelLease {
+    set: Arc<ChannelSet>,
+    endpoint: usize,
+    slot: usize,
+}
+
+impl ChannelLease {
+    /// The leased channel.
+    pub fn channel(&self) -> Channel {
+        self.set.channels[self.slot].clone()
+    }
+
+    /// Index of the endpoint in its pool.
+    pub fn endpoint(&self) -> usize {
+        self.endpoint
+    }
+
+    /// Index of the channel among the endpoint's channels.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+}
+
+impl Drop for ChannelLease {
+    fn drop(&mut self) {
+        self.set.streams[self.slot].fetch_sub(1, Ordering::Relaxed);
+    }
+}
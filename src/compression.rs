@@ -0,0 +1,101 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    io::{self, Read},
+    sync::Arc,
+};
+
+/// Zstd settings for blocks stored locally, by the
+/// [`FetchCache`](crate::FetchCache) and the [spill queue](crate::spill_queue).
+///
+/// Blocks of one chain share most of their structure, so a dictionary
+/// [trained](Zstd::train) on a sample of them compresses each block much
+/// better than it compresses on its own. Data compressed with a dictionary
+/// can only be decompressed with the same dictionary.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "runtime")]
+/// # mod example {
+/// use firehose_rs::{FetchCache, Zstd};
+///
+/// # fn example(samples: Vec<Vec<u8>>) -> std::io::Result<()> {
+/// let dictionary = Zstd::train(&samples, 112 * 1024)?;
+/// let cache = FetchCache::new(256 * 1024 * 1024)
+///     .with_compression(Zstd::default().with_dictionary(dictionary));
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Zstd {
+    level: i32,
+    dictionary: Option<Arc<[u8]>>,
+}
+
+impl Zstd {
+    /// Level used by [`Zstd::default`], zstd's own default.
+    pub const DEFAULT_LEVEL: i32 = 3;
+
+    /// Compress at `level`, from 1 (fastest) to 22 (smallest).
+    pub fn new(level: i32) -> Self {
+        Zstd {
+            level,
+            dictionary: None,
+        }
+    }
+
+    /// Compress and decompress with `dictionary`, as returned by
+    /// [`Zstd::train`].
+    pub fn with_dictionary(mut self, dictionary: impl Into<Arc<[u8]>>) -> Self {
+        self.dictionary = Some(dictionary.into());
+        self
+    }
+
+    /// Train a dictionary of at most `max_size` bytes on `samples`, such as
+    /// encoded blocks of the chain it will compress.
+    pub fn train(samples: &[impl AsRef<[u8]>], max_size: usize) -> io::Result<Vec<u8>> {
+        zstd::dict::from_samples(samples, max_size)
+    }
+
+    /// The compression level.
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    /// The dictionary, if any.
+    pub fn dictionary(&self) -> Option<&[u8]> {
+        self.dictionary.as_deref()
+    }
+
+    /// Compress `data` into one zstd frame.
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match &self.dictionary {
+            Some(dictionary) => {
+                zstd::bulk::Compressor::with_dictionary(self.level, dictionary)?.compress(data)
+            }
+            None => zstd::bulk::compress(data, self.level),
+        }
+    }
+
+    /// Decompress `data`, as returned by [`Zstd::compress`] with the same
+    /// dictionary.
+    pub fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(dictionary) = &self.dictionary else {
+            return zstd::stream::decode_all(data);
+        };
+
+        let mut decompressed = Vec::new();
+        zstd::stream::Decoder::with_dictionary(data, dictionary)?.read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+}
+
+impl Default for Zstd {
+    fn default() -> Self {
+        Zstd::new(Zstd::DEFAULT_LEVEL)
+    }
+}
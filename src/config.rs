@@ -0,0 +1,356 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Loading request and endpoint definitions from JSON, TOML, or YAML files.
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Request, SingleBlockRequest};
+
+/// File formats understood by the config loaders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    /// JSON (`.json`).
+    Json,
+    /// TOML (`.toml`).
+    Toml,
+    /// YAML (`.yaml` or `.yml`).
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Infer the format from a file extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Deserialize a value from `input` in this format.
+    pub fn parse<T: DeserializeOwned>(self, input: &str) -> Result<T, ConfigError> {
+        match self {
+            ConfigFormat::Json => serde_json::from_str(input).map_err(|e| ConfigError::Parse {
+                format: self,
+                message: e.to_string(),
+            }),
+            ConfigFormat::Toml => toml::from_str(input).map_err(|e| ConfigError::Parse {
+                format: self,
+                message: e.to_string(),
+            }),
+            ConfigFormat::Yaml => serde_norway::from_str(input).map_err(|e| ConfigError::Parse {
+                format: self,
+                message: e.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Json => write!(f, "JSON"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+            ConfigFormat::Yaml => write!(f, "YAML"),
+        }
+    }
+}
+
+/// Read and deserialize a config file, picking the format from its extension.
+pub(crate) fn load<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    let format =
+        ConfigFormat::from_path(path).ok_or_else(|| ConfigError::UnknownFormat(path.into()))?;
+    let input = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.into(),
+        source,
+    })?;
+    format.parse(&input)
+}
+
+impl Request {
+    /// Load a streaming [`Request`] from a JSON, TOML, or YAML file.
+    ///
+    /// The format is picked from the file extension. Missing fields take their
+    /// protobuf defaults, and unknown keys are ignored, so the same file can
+    /// also hold settings for other parts of a job.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// # backfill.toml
+    /// start_block_num = 17000000
+    /// stop_block_num = 17100000
+    /// final_blocks_only = true
+    /// ```
+    ///
+    /// ```rust,no_run
+    /// use firehose_rs::Request;
+    ///
+    /// let request = Request::from_config_file("backfill.toml").unwrap();
+    /// assert!(request.final_blocks_only);
+    /// ```
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        load(path.as_ref())
+    }
+
+    /// Parse a streaming [`Request`] from a string in the given format.
+    pub fn from_config_str(input: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        format.parse(input)
+    }
+}
+
+impl SingleBlockRequest {
+    /// Load a [`SingleBlockRequest`] from a JSON, TOML, or YAML file.
+    ///
+    /// See [`Request::from_config_file`] for how the file is interpreted.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        load(path.as_ref())
+    }
+
+    /// Parse a [`SingleBlockRequest`] from a string in the given format.
+    ///
+    /// ```rust
+    /// use firehose_rs::{ConfigFormat, SingleBlockRequest};
+    ///
+    /// let yaml = "reference:\n  BlockNumber:\n    num: 12345\n";
+    /// let request = SingleBlockRequest::from_config_str(yaml, ConfigFormat::Yaml).unwrap();
+    /// assert_eq!(request, SingleBlockRequest::new_by_block_number(12345));
+    /// ```
+    pub fn from_config_str(input: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        format.parse(input)
+    }
+}
+
+/// Connection settings from the `endpoint` section of a config file.
+///
+/// Lives next to the [`Request`] fields of the same file, which ignore it, so
+/// one file describes a whole job. All settings are optional.
+///
+/// ```toml
+/// start_block_num = 17000000
+/// final_blocks_only = true
+///
+/// [endpoint]
+/// uri = "mainnet.eth.streamingfast.io:443"
+/// api_key = "..."
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EndpointConfig {
+    /// Endpoint URI, e.g. `mainnet.eth.streamingfast.io:443`.
+    pub uri: Option<String>,
+    /// API key sent as `x-api-key`.
+    pub api_key: Option<String>,
+    /// Connect without TLS.
+    pub insecure: bool,
+    /// Proxy to connect through, e.g. `socks5://proxy:1080`.
+    pub proxy: Option<String>,
+}
+
+/// The part of a config file holding the [`EndpointConfig`].
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct EndpointSection {
+    endpoint: EndpointConfig,
+}
+
+impl EndpointConfig {
+    /// Load the `endpoint` section of a JSON, TOML, or YAML file, empty when
+    /// the file has none.
+    pub fn from_config_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        load::<EndpointSection>(path.as_ref()).map(|section| section.endpoint)
+    }
+
+    /// Parse the `endpoint` section of a string in the given format.
+    ///
+    /// ```rust
+    /// use firehose_rs::{ConfigFormat, EndpointConfig};
+    ///
+    /// let yaml = "start_block_num: 100\nendpoint:\n  uri: localhost:10015\n  insecure: true\n";
+    /// let endpoint = EndpointConfig::from_config_str(yaml, ConfigFormat::Yaml).unwrap();
+    /// assert_eq!(endpoint.uri.as_deref(), Some("localhost:10015"));
+    /// assert!(endpoint.insecure);
+    /// ```
+    pub fn from_config_str(input: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        format
+            .parse::<EndpointSection>(input)
+            .map(|section| section.endpoint)
+    }
+}
+
+/// Errors returned while loading a config file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file extension does not map to a known [`ConfigFormat`].
+    UnknownFormat(PathBuf),
+    /// The file could not be read.
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+        /// Underlying I/O error.
+        source: std::io::Error,
+    },
+    /// The contents could not be deserialized.
+    Parse {
+        /// Format the contents were parsed as.
+        format: ConfigFormat,
+        /// Description of the parse failure.
+        message: String,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownFormat(path) => write!(
+                f,
+                "cannot infer config format of `{}`, expected .json, .toml, .yaml or .yml",
+                path.display()
+            ),
+            ConfigError::Io { path, source } => {
+                write!(f, "failed to read `{}`: {source}", path.display())
+            }
+            ConfigError::Parse { format, message } => {
+                write!(f, "invalid {format} config: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ethereum::EthereumFilter;
+
+    /// A request with an Ethereum filter transform, packed as an `Any`.
+    fn filtered_request() -> Request {
+        EthereumFilter::new()
+            .logs([[0xaa; 20]], [[0xbb; 32]])
+            .calls([[0xcc; 20]], [[0xdd; 4]])
+            .with_final_blocks_only()
+            .apply(Request {
+                start_block_num: 17_000_000,
+                stop_block_num: 17_100_000,
+                ..Default::default()
+            })
+            .unwrap()
+    }
+
+    fn assert_round_trips(format: ConfigFormat, serialized: String) {
+        assert!(
+            serialized.contains("sf.ethereum.transform.v1.CombinedFilter"),
+            "{format}: {serialized}"
+        );
+        let request = Request::from_config_str(&serialized, format).unwrap();
+        assert_eq!(request, filtered_request(), "{format}: {serialized}");
+    }
+
+    #[test]
+    fn requests_with_transforms_round_trip_through_json() {
+        let request = filtered_request();
+        assert_eq!(request.transforms.len(), 1);
+        assert_round_trips(ConfigFormat::Json, serde_json::to_string(&request).unwrap());
+    }
+
+    #[test]
+    fn requests_with_transforms_round_trip_through_toml() {
+        assert_round_trips(
+            ConfigFormat::Toml,
+            toml::to_string(&filtered_request()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn requests_with_transforms_round_trip_through_yaml() {
+        assert_round_trips(
+            ConfigFormat::Yaml,
+            serde_norway::to_string(&filtered_request()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn endpoint_section_sits_next_to_the_request() {
+        let toml = r#"
+            start_block_num = 100
+            final_blocks_only = true
+
+            [endpoint]
+            uri = "localhost:10015"
+            api_key = "secret"
+            insecure = true
+        "#;
+
+        let request = Request::from_config_str(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(request.start_block_num, 100);
+        assert!(request.final_blocks_only);
+
+        let endpoint = EndpointConfig::from_config_str(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(
+            endpoint,
+            EndpointConfig {
+                uri: Some("localhost:10015".to_string()),
+                api_key: Some("secret".to_string()),
+                insecure: true,
+                proxy: None,
+            }
+        );
+    }
+
+    #[test]
+    fn missing_endpoint_section_is_empty() {
+        let endpoint =
+            EndpointConfig::from_config_str(r#"{"start_block_num": 1}"#, ConfigFormat::Json)
+                .unwrap();
+        assert_eq!(endpoint, EndpointConfig::default());
+    }
+
+    #[test]
+    fn config_files_are_read_by_extension() {
+        let dir = std::env::temp_dir().join(format!("firehose-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("job.yaml");
+        fs::write(
+            &path,
+            serde_norway::to_string(&filtered_request()).unwrap()
+                + "endpoint:\n  uri: localhost:10015\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Request::from_config_file(&path).unwrap(),
+            filtered_request()
+        );
+        assert_eq!(
+            EndpointConfig::from_config_file(&path)
+                .unwrap()
+                .uri
+                .as_deref(),
+            Some("localhost:10015")
+        );
+        assert!(matches!(
+            Request::from_config_file(dir.join("job.ini")),
+            Err(ConfigError::UnknownFormat(_))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::VecDeque;
+
+use crate::{AckHandle, FirehoseError, ForkStep, ResilientStream, Response};
+
+/// A stream emitting only blocks that are `depth` blocks deep, for consumers
+/// that cannot handle reorgs but do not want to wait for finality.
+///
+/// Blocks are held back until the stream's head is `depth` blocks past them,
+/// or until they are final. Undo steps remove held-back blocks, so emitted
+/// blocks are never undone unless a reorg is deeper than `depth`, which fails
+/// the stream with [`FirehoseError::DeepReorg`].
+///
+/// The wrapped stream runs [with acknowledgements](ResilientStream::with_acks):
+/// a block's cursor becomes eligible for checkpointing once it has been
+/// emitted and [`ConfirmedStream::message`] is called again, so held-back
+/// blocks are replayed after a crash instead of skipped.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{ConfirmedStream, FirehoseEndpoint, Request, ResilientStream};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+/// let stream = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?;
+///
+/// let mut confirmed = ConfirmedStream::new(stream, 12);
+/// while let Some(response) = confirmed.message().await? {
+///     println!("confirmed block at cursor {}", response.cursor);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ConfirmedStream {
+    stream: ResilientStream,
+    acks: AckHandle,
+    depth: u64,
+    /// Received blocks that are not confirmed yet, oldest first.
+    pending: VecDeque<Response>,
+    /// Number of the newest block received.
+    head: u64,
+    /// Last irreversible block reported by the stream.
+    lib: u64,
+    /// Cursor of the last block emitted.
+    emitted: Option<String>,
+}
+
+impl ConfirmedStream {
+    /// Emit the blocks of `stream` once they are `depth` blocks deep.
+    pub fn new(stream: ResilientStream, depth: u64) -> Self {
+        let stream = stream.with_acks();
+        let acks = stream.ack_handle().expect("stream has acknowledgements");
+
+        ConfirmedStream {
+            stream,
+            acks,
+            depth,
+            pending: VecDeque::new(),
+            head: 0,
+            lib: 0,
+            emitted: None,
+        }
+    }
+
+    /// The wrapped stream.
+    pub fn stream(&self) -> &ResilientStream {
+        &self.stream
+    }
+
+    /// Number of blocks held back until they are confirmed.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Receive the next confirmed block.
+    ///
+    /// Returns `Ok(None)` once a bounded stream has completed; blocks still
+    /// unconfirmed at that point are not emitted.
+    pub async fn message(&mut self) -> Result<Option<Response>, FirehoseError> {
+        // The previous block has been processed.
+        if let Some(cursor) = self.emitted.take() {
+            self.acks.ack(cursor);
+        }
+
+        loop {
+            if let Some(response) = self.pop_confirmed() {
+                return Ok(Some(response));
+            }
+
+            let Some(response) = self.stream.message().await? else {
+                return Ok(None);
+            };
+            let Some(metadata) = &response.metadata else {
+                // Without a block number, nothing can be held back.
+                self.emitted = Some(response.cursor.clone());
+                return Ok(Some(response));
+            };
+            let number = metadata.num;
+            self.lib = self.lib.max(metadata.lib_num);
+
+            match response.step() {
+                ForkStep::StepUndo => {
+                    if self.pending.back().and_then(Response::block_number) != Some(number) {
+                        return Err(FirehoseError::DeepReorg {
+                            block: number,
+                            depth: self.depth,
+                        });
+                    }
+                    self.pending.pop_back();
+                    self.head = number.saturating_sub(1);
+                }
+                ForkStep::StepFinal => {
+                    self.lib = self.lib.max(number);
+                    self.head = self.head.max(number);
+                    self.pending.push_back(response);
+                }
+                _ => {
+                    self.head = number;
+                    self.pending.push_back(response);
+                }
+            }
+        }
+    }
+
+    fn pop_confirmed(&mut self) -> Option<Response> {
+        let number = self.pending.front().and_then(Response::block_number)?;
+        if number > self.lib && self.head < number.saturating_add(self.depth) {
+            return None;
+        }
+
+        let response = self.pending.pop_front()?;
+        self.emitted = Some(response.cursor.clone());
+        Some(response)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::{
+        testing::{ethereum_block, Replay},
+        CheckpointInterval, CursorStore, EndpointPool, Request,
+    };
+
+    /// Blocks 1 to `last` of a synthetic chain, none of them final.
+    fn chain(last: u64) -> Vec<Response> {
+        (1..=last)
+            .map(|number| ethereum_block().number(number).lib(0).build())
+            .collect()
+    }
+
+    async fn stream(replay: Replay, stop: u64) -> ResilientStream {
+        let request = Request {
+            start_block_num: 1,
+            stop_block_num: stop,
+            ..Default::default()
+        };
+        let pool = EndpointPool::new([replay.serve().await]).unwrap();
+        ResilientStream::new(pool, request)
+    }
+
+    /// A cursor store recording every commit, readable while the stream owns
+    /// it.
+    #[derive(Clone, Default)]
+    struct Commits(Arc<Mutex<Vec<String>>>);
+
+    impl Commits {
+        fn last(&self) -> Option<String> {
+            self.0.lock().unwrap().last().cloned()
+        }
+    }
+
+    impl CursorStore for Commits {
+        fn load(&self) -> io::Result<Option<String>> {
+            Ok(self.last())
+        }
+
+        fn store(&mut self, cursor: &str) -> io::Result<()> {
+            self.0.lock().unwrap().push(cursor.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn emits_blocks_once_they_are_deep_enough() {
+        let mut confirmed = ConfirmedStream::new(stream(Replay::new(chain(10)), 10).await, 3);
+
+        let first = confirmed.message().await.unwrap().unwrap();
+        assert_eq!(first.block_number(), Some(1));
+        // Blocks 2 to 4 are still held back.
+        assert_eq!(confirmed.stream().summary().blocks_received, 4);
+        assert_eq!(confirmed.pending(), 3);
+
+        let mut numbers = vec![1];
+        while let Some(response) = confirmed.message().await.unwrap() {
+            numbers.extend(response.block_number());
+        }
+        // The last three blocks never got three confirmations.
+        assert_eq!(numbers, (1..=7).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn drops_blocks_undone_inside_the_window() {
+        let blocks = chain(10);
+        let replay = Replay::new(blocks.clone()).with_reorg(8, 2);
+        let mut confirmed = ConfirmedStream::new(stream(replay, 10).await, 3);
+
+        let mut cursors = Vec::new();
+        while let Some(response) = confirmed.message().await.unwrap() {
+            cursors.push(response.cursor);
+        }
+
+        // The orphans of blocks 8 and 9 were undone before getting three
+        // confirmations.
+        let canonical: Vec<String> = blocks[..7]
+            .iter()
+            .map(|block| block.cursor.clone())
+            .collect();
+        assert_eq!(cursors, canonical);
+    }
+
+    #[tokio::test]
+    async fn fails_when_an_emitted_block_is_undone() {
+        let replay = Replay::new(chain(10)).with_reorg(5, 3);
+        let mut confirmed = ConfirmedStream::new(stream(replay, 10).await, 1);
+
+        let error = loop {
+            match confirmed.message().await {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("stream completed without a deep reorg"),
+                Err(e) => break e,
+            }
+        };
+        // The orphan of block 6 was emitted once the orphan of block 7
+        // confirmed it.
+        assert!(matches!(
+            error,
+            FirehoseError::DeepReorg { block: 6, depth: 1 }
+        ));
+    }
+
+    #[tokio::test]
+    async fn releases_final_blocks_right_away() {
+        let blocks: Vec<Response> = (1..=5)
+            .map(|number| {
+                ethereum_block()
+                    .number(number)
+                    .lib(0)
+                    .step(ForkStep::StepFinal)
+                    .build()
+            })
+            .collect();
+        let mut confirmed = ConfirmedStream::new(stream(Replay::new(blocks), 5).await, 100);
+
+        for number in 1..=5 {
+            let response = confirmed.message().await.unwrap().unwrap();
+            assert_eq!(response.block_number(), Some(number));
+            assert_eq!(confirmed.stream().summary().blocks_received, number);
+        }
+        assert_eq!(confirmed.message().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn acknowledges_blocks_without_metadata() {
+        let bare = Response {
+            cursor: "bare".to_string(),
+            ..Default::default()
+        };
+        let blocks = [
+            ethereum_block().number(1).build(),
+            bare,
+            ethereum_block().number(2).build(),
+        ];
+        let commits = Commits::default();
+        let stream = stream(Replay::new(blocks), 2)
+            .await
+            .with_cursor_store(commits.clone(), CheckpointInterval::default())
+            .unwrap();
+        let mut confirmed = ConfirmedStream::new(stream, 0);
+
+        let first = confirmed.message().await.unwrap().unwrap();
+        assert_eq!(first.block_number(), Some(1));
+        let bare = confirmed.message().await.unwrap().unwrap();
+        assert!(bare.metadata.is_none());
+        let last = confirmed.message().await.unwrap().unwrap();
+        assert_eq!(last.block_number(), Some(2));
+
+        // Receiving block 2 committed the block before it.
+        assert_eq!(commits.last().as_deref(), Some("bare"));
+    }
+}
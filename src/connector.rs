@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fmt,
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tonic::transport::Uri;
+use tower::{BoxError, Service};
+
+/// A byte stream returned by a [`Connector`].
+pub trait Io: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Io for T {}
+
+/// Stream handed to tonic by a [`Connector`].
+pub type Connection = TokioIo<Box<dyn Io>>;
+
+/// Future returned by [`Connector`].
+type ConnectFuture = Pin<Box<dyn Future<Output = Result<Connection, BoxError>> + Send>>;
+
+/// How a [`FirehoseEndpoint`](crate::FirehoseEndpoint) opens the byte
+/// stream its connections run over, set with
+/// [`FirehoseEndpoint::with_connector`](crate::FirehoseEndpoint::with_connector).
+///
+/// By default the endpoint dials TCP. A connector replaces only that step,
+/// for transports such as userspace WireGuard tunnels, Unix sockets or
+/// in-memory duplex streams in tests: TLS (unless the endpoint is
+/// [insecure](crate::FirehoseEndpoint::with_insecure)), credentials,
+/// compression and retries still apply on top.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{Connector, FirehoseEndpoint};
+/// use tokio::net::UnixStream;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// // The URI is still used for the `:authority` header and TLS, but the
+/// // connection goes over a Unix socket
+/// let endpoint = FirehoseEndpoint::new("http://firehose.local")
+///     .with_insecure(true)
+///     .with_connector(Connector::from_fn(|_uri| {
+///         UnixStream::connect("/var/run/firehose.sock")
+///     }));
+///
+/// let mut client = endpoint.stream_client().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Connector {
+    connect: Arc<dyn Fn(Uri) -> ConnectFuture + Send + Sync>,
+}
+
+impl Connector {
+    /// Open connections with `service`, called with the endpoint URI.
+    ///
+    /// The service is cloned for each connection.
+    pub fn new<S>(service: S) -> Self
+    where
+        S: Service<Uri> + Clone + Send + Sync + 'static,
+        S::Response: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        Connector {
+            connect: Arc::new(move |uri| {
+                let mut service = service.clone();
+                Box::pin(async move {
+                    poll_fn(|cx| service.poll_ready(cx))
+                        .await
+                        .map_err(Into::into)?;
+                    let io = service.call(uri).await.map_err(Into::into)?;
+                    Ok(TokioIo::new(Box::new(io) as Box<dyn Io>))
+                })
+            }),
+        }
+    }
+
+    /// Open connections with `connect`, called with the endpoint URI.
+    pub fn from_fn<F, Fut, T, E>(connect: F) -> Self
+    where
+        F: Fn(Uri) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        E: Into<BoxError>,
+    {
+        Connector {
+            connect: Arc::new(move |uri| {
+                let connecting = connect(uri);
+                Box::pin(async move {
+                    let io = connecting.await.map_err(Into::into)?;
+                    Ok(TokioIo::new(Box::new(io) as Box<dyn Io>))
+                })
+            }),
+        }
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = Connection;
+    type Error = BoxError;
+    type Future = ConnectFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        (self.connect)(uri)
+    }
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connector").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,211 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use tonic::Code;
+
+use crate::{
+    request_id, BlockRange, ErrorClass, FetchClient, FirehoseChannel, FirehoseEndpoint,
+    FirehoseError, InfoRequest, SingleBlockRequest,
+};
+
+/// Blocks fetched at evenly spaced points of a probed range, before the
+/// boundaries between them are searched.
+const PROBE_SAMPLES: u64 = 16;
+
+/// Which parts of a block range an endpoint can serve, from
+/// [`FirehoseEndpoint::probe_range`].
+///
+/// Its [`Display`](fmt::Display) output is a one-line summary, e.g.
+/// `0-99999: available 50000-99999, missing 0-49999 (21 fetches)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Coverage {
+    /// The probed range.
+    pub range: BlockRange,
+    /// The first block the endpoint can stream, as reported by `Info`.
+    /// `None` for servers without the `EndpointInfo` service.
+    pub first_streamable_block: Option<u64>,
+    /// Spans of the range the endpoint serves, in order.
+    pub available: Vec<BlockRange>,
+    /// Spans of the range the endpoint does not serve, in order: before its
+    /// first streamable block, pruned, or beyond its head.
+    pub missing: Vec<BlockRange>,
+    /// Blocks fetched to probe the range.
+    pub fetches: u32,
+}
+
+impl Coverage {
+    /// Whether the endpoint serves the whole range.
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+
+    /// Number of blocks of the range the endpoint serves.
+    pub fn available_blocks(&self) -> u64 {
+        self.available.iter().map(BlockRange::len).sum()
+    }
+
+    fn push(&mut self, span: BlockRange, available: bool) {
+        let spans = if available {
+            &mut self.available
+        } else {
+            &mut self.missing
+        };
+        match spans.last_mut() {
+            Some(last) if last.stop.checked_add(1) == Some(span.start) => last.stop = span.stop,
+            _ => spans.push(span),
+        }
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let spans = |spans: &[BlockRange]| {
+            spans
+                .iter()
+                .map(BlockRange::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        write!(f, "{}: ", self.range)?;
+        match (self.available.is_empty(), self.missing.is_empty()) {
+            (false, true) => write!(f, "available")?,
+            (true, false) => write!(f, "missing")?,
+            (true, true) => write!(f, "empty")?,
+            (false, false) => write!(
+                f,
+                "available {}, missing {}",
+                spans(&self.available),
+                spans(&self.missing)
+            )?,
+        }
+        write!(f, " ({} fetches)", self.fetches)
+    }
+}
+
+impl FirehoseEndpoint {
+    /// Find which parts of the blocks `start` to `stop` (both included) the
+    /// endpoint can serve, before starting a job over them.
+    ///
+    /// Blocks before the first streamable block reported by `Info` are
+    /// missing. The rest of the range is sampled with fetches at evenly
+    /// spaced blocks, and every boundary between a served and a missing
+    /// sample is located exactly by binary search, so a report costs a few
+    /// dozen fetches however long the range. Pruned spans falling entirely
+    /// between two samples go undetected.
+    ///
+    /// A fetch counts as missing when the server answers that the block is
+    /// not found or out of range; any other failure is returned.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use firehose_rs::FirehoseEndpoint;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let coverage = FirehoseEndpoint::from_env()?
+    ///     .probe_range(17_000_000, 17_999_999)
+    ///     .await?;
+    /// if !coverage.is_complete() {
+    ///     eprintln!("endpoint cannot serve the whole backfill: {coverage}");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn probe_range(&self, start: u64, stop: u64) -> Result<Coverage, FirehoseError> {
+        let channel = self.connect().await?;
+        let first_streamable_block = match self
+            .info_client_with_channel(channel.clone())?
+            .info(InfoRequest {})
+            .await
+        {
+            Ok(response) => Some(response.into_inner().first_streamable_block_num),
+            Err(status) if status.code() == Code::Unimplemented => None,
+            Err(status) => return Err(status.into()),
+        };
+
+        let range = BlockRange::new(start, stop);
+        let mut coverage = Coverage {
+            range,
+            first_streamable_block,
+            available: Vec::new(),
+            missing: Vec::new(),
+            fetches: 0,
+        };
+        if range.is_empty() {
+            return Ok(coverage);
+        }
+
+        let from = first_streamable_block.map_or(start, |first| first.max(start));
+        if from > start {
+            coverage.push(BlockRange::new(start, (from - 1).min(stop)), false);
+        }
+        if from > stop {
+            return Ok(coverage);
+        }
+
+        let mut prober = Prober {
+            client: self.fetch_client_with_channel(channel)?,
+            fetches: 0,
+        };
+        let span = stop - from;
+        let samples = PROBE_SAMPLES.min(span.saturating_add(1));
+        let mut previous = (from, prober.serves(from).await?);
+        let mut run_start = from;
+        for i in 1..samples {
+            // Widened so that long ranges do not overflow.
+            let block = from + (u128::from(span) * u128::from(i) / u128::from(samples - 1)) as u64;
+            if block == previous.0 {
+                continue;
+            }
+
+            let served = prober.serves(block).await?;
+            if served != previous.1 {
+                // `low` is served like the previous sample, `high` is not.
+                let (mut low, mut high) = (previous.0, block);
+                while high - low > 1 {
+                    let mid = low + (high - low) / 2;
+                    if prober.serves(mid).await? == previous.1 {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                coverage.push(BlockRange::new(run_start, high - 1), previous.1);
+                run_start = high;
+            }
+            previous = (block, served);
+        }
+        coverage.push(BlockRange::new(run_start, stop), previous.1);
+
+        coverage.fetches = prober.fetches;
+        Ok(coverage)
+    }
+}
+
+struct Prober {
+    client: FetchClient<FirehoseChannel>,
+    fetches: u32,
+}
+
+impl Prober {
+    /// Whether the endpoint serves `block`.
+    async fn serves(&mut self, block: u64) -> Result<bool, FirehoseError> {
+        self.fetches += 1;
+        let mut request = tonic::Request::new(SingleBlockRequest::new_by_block_number(block));
+        let id = request_id::ensure(request.metadata_mut());
+        match self.client.block(request).await {
+            Ok(_) => Ok(true),
+            Err(status) => {
+                let error = FirehoseError::from(request_id::tag(status, &id));
+                match &error {
+                    FirehoseError::Status(status) if status.code() == Code::NotFound => Ok(false),
+                    _ if error.classification() == ErrorClass::OutOfRange => Ok(false),
+                    _ => Err(error),
+                }
+            }
+        }
+    }
+}
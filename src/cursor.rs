@@ -0,0 +1,103 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Persist the cursor of the last processed block so streams can resume.
+///
+/// Cursors are opaque strings handed out in [`Response::cursor`](crate::Response::cursor);
+/// passing one back in [`Request::cursor`](crate::Request::cursor) resumes the
+/// stream right after that block.
+pub trait CursorStore {
+    /// Load the last stored cursor, if any.
+    fn load(&self) -> io::Result<Option<String>>;
+
+    /// Replace the stored cursor.
+    fn store(&mut self, cursor: &str) -> io::Result<()>;
+}
+
+/// Number of the block a cursor points at, when the cursor embeds it in
+/// plain text.
+///
+/// Firehose cursors are opaque, but the plain form of bstream cursors,
+/// `c<version>:<step>:<block number>:<block ID>:...`, carries the block
+/// number. Returns `None` for any other cursor, including obfuscated ones.
+///
+/// # Example
+///
+/// ```rust
+/// use firehose_rs::cursor_block_num;
+///
+/// let cursor = "c3:1:17000000:a1b2:17000064:c3d4:16999936:e5f6";
+/// assert_eq!(cursor_block_num(cursor), Some(17_000_000));
+/// assert_eq!(cursor_block_num("Ka8bGLTkvHc="), None);
+/// ```
+pub fn cursor_block_num(cursor: &str) -> Option<u64> {
+    let mut fields = cursor.split(':');
+    let version = fields.next()?.strip_prefix('c')?;
+    if version.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    fields.nth(1)?.parse().ok()
+}
+
+/// A [`CursorStore`] keeping the cursor in a single file.
+///
+/// Writes go to a temporary file that is renamed over the cursor file, so a
+/// crash never leaves a truncated cursor behind.
+#[derive(Clone, Debug)]
+pub struct FileCursorStore {
+    path: PathBuf,
+}
+
+impl FileCursorStore {
+    /// Store the cursor at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileCursorStore { path: path.into() }
+    }
+
+    /// The path of the cursor file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl CursorStore for FileCursorStore {
+    fn load(&self) -> io::Result<Option<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(cursor) if cursor.trim().is_empty() => Ok(None),
+            Ok(cursor) => Ok(Some(cursor.trim().to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn store(&mut self, cursor: &str) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        fs::write(&tmp, cursor)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// A [`CursorStore`] that only keeps the cursor in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryCursorStore {
+    cursor: Option<String>,
+}
+
+impl CursorStore for MemoryCursorStore {
+    fn load(&self) -> io::Result<Option<String>> {
+        Ok(self.cursor.clone())
+    }
+
+    fn store(&mut self, cursor: &str) -> io::Result<()> {
+        self.cursor = Some(cursor.to_string());
+        Ok(())
+    }
+}
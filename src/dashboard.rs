@@ -0,0 +1,561 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Terminal dashboard for long backfills.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    io,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    backend::Backend,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    symbols,
+    widgets::{Axis, Block, Chart, Dataset, Gauge, GraphType, Row, Table},
+    Frame, Terminal,
+};
+use tokio::sync::watch;
+
+use crate::{
+    CallMetrics, Handoff, HealthReporter, RangePlanner, StreamHealth, StreamMetrics, UnitProgress,
+};
+
+/// How often the dashboard samples its sources and redraws by default.
+const DEFAULT_REFRESH: Duration = Duration::from_secs(1);
+
+/// Throughput samples kept for the chart by default, five minutes at the
+/// default refresh.
+const DEFAULT_HISTORY: usize = 300;
+
+/// Width of the progress bars of running units, in characters.
+const BAR_WIDTH: usize = 20;
+
+/// A full-screen terminal dashboard of a backfill, with the progress of each
+/// running unit, a throughput chart, per-stream reconnects and lag, and the
+/// health of streams and sinks.
+///
+/// The dashboard only reads the crate's own stats: the units of a
+/// [`RangePlanner`], either given directly or followed through
+/// [`Handoff::planner`], the [stream metrics](CallMetrics::streams) recorded
+/// by [`ResilientStream::with_metrics`](crate::ResilientStream::with_metrics)
+/// or [`Handoff::with_metrics`], and the [report](HealthReporter::report) of a
+/// [`HealthReporter`], whose sinks are watched with
+/// [`HealthReporter::watch_sink`] for their lag. Throughput counts the blocks
+/// recorded in the metrics, or, without metrics, the blocks the planner's
+/// units processed.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{CallMetrics, Dashboard, EndpointPool, FirehoseEndpoint, Handoff, Request};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = EndpointPool::new([FirehoseEndpoint::from_env()?])?;
+/// let request = Request {
+///     start_block_num: 0,
+///     ..Default::default()
+/// };
+///
+/// let metrics = CallMetrics::new();
+/// let handoff = Handoff::new(pool, request)
+///     .with_workers(8, 100_000)
+///     .with_metrics(metrics.clone(), "mainnet");
+/// let dashboard = Dashboard::new("mainnet backfill")
+///     .with_backfill(&handoff)
+///     .with_metrics(metrics);
+///
+/// let (task, mut blocks) = handoff.spawn();
+/// dashboard
+///     .run(async move {
+///         while let Some((phase, response)) = blocks.recv().await {
+///             // ... write the block ...
+///         }
+///     })
+///     .await?;
+/// task.await??;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Dashboard {
+    title: String,
+    planner: Option<watch::Receiver<Option<RangePlanner>>>,
+    metrics: Option<CallMetrics>,
+    health: Option<HealthReporter>,
+    refresh: Duration,
+    history: usize,
+    started: Option<Instant>,
+    /// When the last sample was taken, with the total and per-stream block
+    /// counts at that time.
+    last: Option<(Instant, u64, BTreeMap<String, u64>)>,
+    /// Seconds since the first sample and blocks per second, oldest first.
+    throughput: VecDeque<(f64, f64)>,
+    /// Blocks per second of each stream over the last sample.
+    rates: BTreeMap<String, f64>,
+    units: Vec<UnitProgress>,
+    streams: BTreeMap<String, StreamMetrics>,
+    health_report: Vec<StreamHealth>,
+}
+
+impl Dashboard {
+    /// An empty dashboard titled `title`.
+    pub fn new(title: impl Into<String>) -> Self {
+        Dashboard {
+            title: title.into(),
+            planner: None,
+            metrics: None,
+            health: None,
+            refresh: DEFAULT_REFRESH,
+            history: DEFAULT_HISTORY,
+            started: None,
+            last: None,
+            throughput: VecDeque::new(),
+            rates: BTreeMap::new(),
+            units: Vec::new(),
+            streams: BTreeMap::new(),
+            health_report: Vec::new(),
+        }
+    }
+
+    /// Show the progress of the units of `planner`.
+    pub fn with_planner(mut self, planner: RangePlanner) -> Self {
+        self.planner = Some(watch::channel(Some(planner)).1);
+        self
+    }
+
+    /// Show the progress of the backfill of `handoff`, once it starts.
+    pub fn with_backfill(mut self, handoff: &Handoff) -> Self {
+        self.planner = Some(handoff.planner());
+        self
+    }
+
+    /// Show the throughput, reconnects and lag of the streams recorded in
+    /// `metrics`.
+    pub fn with_metrics(mut self, metrics: CallMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Show the health of the streams and sinks reported to `health`.
+    pub fn with_health(mut self, health: HealthReporter) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Sample and redraw every `refresh`, one second by default.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh.max(Duration::from_millis(50));
+        self
+    }
+
+    /// Chart the last `samples` throughput samples, 300 by default.
+    pub fn with_history(mut self, samples: usize) -> Self {
+        self.history = samples.max(2);
+        self
+    }
+
+    /// Take over the terminal and redraw the dashboard until `until`
+    /// completes.
+    ///
+    /// Pressing `q`, `Esc` or `Ctrl-C` returns early, dropping `until`; keys
+    /// are read at each refresh. The terminal is restored on return, and by
+    /// the panic hook if the task panics.
+    pub async fn run(mut self, until: impl Future<Output = ()>) -> io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.run_on(&mut terminal, until).await;
+        ratatui::restore();
+        result
+    }
+
+    async fn run_on<B: Backend>(
+        &mut self,
+        terminal: &mut Terminal<B>,
+        until: impl Future<Output = ()>,
+    ) -> io::Result<()> {
+        tokio::pin!(until);
+        let mut ticks = tokio::time::interval(self.refresh);
+        loop {
+            tokio::select! {
+                _ = &mut until => return Ok(()),
+                _ = ticks.tick() => {}
+            }
+            self.sample(Instant::now());
+            terminal.draw(|frame| self.render(frame))?;
+            if quit_requested()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read the sources and record the throughput since the last sample.
+    fn sample(&mut self, now: Instant) {
+        self.units = self
+            .planner
+            .as_ref()
+            .and_then(|planner| planner.borrow().as_ref().map(RangePlanner::units))
+            .unwrap_or_default();
+        self.streams = self
+            .metrics
+            .as_ref()
+            .map(CallMetrics::streams)
+            .unwrap_or_default();
+        self.health_report = self
+            .health
+            .as_ref()
+            .map(HealthReporter::report)
+            .unwrap_or_default();
+
+        let counts: BTreeMap<String, u64> = self
+            .streams
+            .iter()
+            .map(|(label, stream)| (label.clone(), stream.block_size.count))
+            .collect();
+        let total = if counts.is_empty() {
+            self.units.iter().map(UnitProgress::blocks_done).sum()
+        } else {
+            counts.values().sum()
+        };
+
+        let started = *self.started.get_or_insert(now);
+        if let Some((at, last_total, last_counts)) = &self.last {
+            let seconds = now.duration_since(*at).as_secs_f64();
+            if seconds > 0.0 {
+                self.rates = counts
+                    .iter()
+                    .map(|(label, count)| {
+                        let before = last_counts.get(label).copied().unwrap_or(0);
+                        (label.clone(), count.saturating_sub(before) as f64 / seconds)
+                    })
+                    .collect();
+                self.throughput.push_back((
+                    now.duration_since(started).as_secs_f64(),
+                    total.saturating_sub(*last_total) as f64 / seconds,
+                ));
+                while self.throughput.len() > self.history {
+                    self.throughput.pop_front();
+                }
+            }
+        }
+        self.last = Some((now, total, counts));
+    }
+
+    fn render(&self, frame: &mut Frame<'_>) {
+        let [header, units, chart, bottom] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(5),
+            Constraint::Length(12),
+            Constraint::Length(8),
+        ])
+        .areas(frame.area());
+        let [streams, health] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(bottom);
+
+        self.render_progress(frame, header);
+        self.render_units(frame, units);
+        self.render_throughput(frame, chart);
+        self.render_streams(frame, streams);
+        self.render_health(frame, health);
+    }
+
+    fn render_progress(&self, frame: &mut Frame<'_>, area: Rect) {
+        let total: u64 = self.units.iter().map(|unit| unit.range.len()).sum();
+        let done: u64 = self.units.iter().map(UnitProgress::blocks_done).sum();
+        let ratio = if total == 0 {
+            0.0
+        } else {
+            done as f64 / total as f64
+        };
+
+        let mut label = if self.units.is_empty() {
+            "no backfill".to_string()
+        } else {
+            format!("{done} / {total} blocks ({:.1}%)", ratio * 100.0)
+        };
+        let rate = self.throughput.back().map_or(0.0, |(_, rate)| *rate);
+        if rate > 0.0 {
+            label.push_str(&format!(", {rate:.0} blocks/s"));
+            if done < total {
+                let eta = Duration::from_secs_f64((total - done) as f64 / rate);
+                label.push_str(&format!(", ETA {}", format_duration(eta)));
+            }
+        }
+
+        let gauge = Gauge::default()
+            .block(Block::bordered().title(self.title.as_str()))
+            .gauge_style(Style::new().fg(Color::Green))
+            .ratio(ratio.clamp(0.0, 1.0))
+            .label(label);
+        frame.render_widget(gauge, area);
+    }
+
+    fn render_units(&self, frame: &mut Frame<'_>, area: Rect) {
+        let done = self.units.iter().filter(|unit| unit.done).count();
+        let running: Vec<&UnitProgress> = self
+            .units
+            .iter()
+            .filter(|unit| unit.worker.is_some())
+            .collect();
+        let pending = self.units.len() - done - running.len();
+
+        let rows = running.iter().map(|unit| {
+            let ratio = unit.blocks_done() as f64 / unit.range.len().max(1) as f64;
+            Row::new([
+                unit.range.to_string(),
+                unit.worker
+                    .map_or_else(String::new, |worker| worker.to_string()),
+                unit.processed
+                    .map_or_else(|| "-".to_string(), |block| block.to_string()),
+                format!("{} {:>3.0}%", bar(ratio, BAR_WIDTH), ratio * 100.0),
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(25),
+                Constraint::Length(8),
+                Constraint::Length(12),
+                Constraint::Min(BAR_WIDTH as u16 + 5),
+            ],
+        )
+        .header(Row::new(["Unit", "Worker", "Processed", "Progress"]).style(Style::new().bold()))
+        .block(Block::bordered().title(format!(
+            "Units: {} running, {pending} pending, {done} done",
+            running.len()
+        )));
+        frame.render_widget(table, area);
+    }
+
+    fn render_throughput(&self, frame: &mut Frame<'_>, area: Rect) {
+        let points: Vec<(f64, f64)> = self.throughput.iter().copied().collect();
+        let (first, last) = match (points.first(), points.last()) {
+            (Some((first, _)), Some((last, _))) => (*first, last.max(first + 1.0)),
+            _ => (0.0, 1.0),
+        };
+        let peak = points
+            .iter()
+            .map(|(_, rate)| *rate)
+            .fold(0.0, f64::max)
+            .max(1.0);
+
+        let dataset = Dataset::default()
+            .marker(symbols::Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::new().fg(Color::Cyan))
+            .data(&points);
+        let chart = Chart::new(vec![dataset])
+            .block(Block::bordered().title("Throughput (blocks/s)"))
+            .x_axis(Axis::default().bounds([first, last]).labels([
+                format!(
+                    "-{}",
+                    format_duration(Duration::from_secs_f64(last - first))
+                ),
+                "now".to_string(),
+            ]))
+            .y_axis(
+                Axis::default()
+                    .bounds([0.0, peak * 1.1])
+                    .labels(["0".to_string(), format!("{peak:.0}")]),
+            );
+        frame.render_widget(chart, area);
+    }
+
+    fn render_streams(&self, frame: &mut Frame<'_>, area: Rect) {
+        let rows = self.streams.iter().map(|(label, stream)| {
+            let rate = self.rates.get(label).copied().unwrap_or(0.0);
+            let lag = match (stream.lag_blocks, stream.lag) {
+                (Some(blocks), _) => format!("{blocks} blocks"),
+                (None, Some(delay)) => format_duration(delay),
+                (None, None) => "-".to_string(),
+            };
+            Row::new([
+                label.clone(),
+                stream.block_size.count.to_string(),
+                format!("{rate:.1}"),
+                stream.reconnects.to_string(),
+                lag,
+            ])
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(12),
+                Constraint::Length(10),
+                Constraint::Length(9),
+                Constraint::Length(10),
+                Constraint::Length(12),
+            ],
+        )
+        .header(
+            Row::new(["Stream", "Blocks", "Blocks/s", "Reconnects", "Lag"])
+                .style(Style::new().bold()),
+        )
+        .block(Block::bordered().title("Streams"));
+        frame.render_widget(table, area);
+    }
+
+    fn render_health(&self, frame: &mut Frame<'_>, area: Rect) {
+        let rows = self.health_report.iter().map(|stream| {
+            let (status, color) = if stream.ready {
+                ("ready", Color::Green)
+            } else if stream.healthy {
+                ("healthy", Color::Yellow)
+            } else {
+                ("unhealthy", Color::Red)
+            };
+            Row::new([
+                stream.name.clone(),
+                status.to_string(),
+                stream
+                    .sink_lag
+                    .map_or_else(|| "-".to_string(), |blocks| blocks.to_string()),
+                stream.reason.clone().unwrap_or_default(),
+            ])
+            .style(Style::new().fg(color))
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Min(12),
+                Constraint::Length(9),
+                Constraint::Length(9),
+                Constraint::Min(12),
+            ],
+        )
+        .header(Row::new(["Stream", "Status", "Sink lag", "Reason"]).style(Style::new().bold()))
+        .block(Block::bordered().title("Health"));
+        frame.render_widget(table, area);
+    }
+}
+
+/// Whether a quit key was pressed since the last check, without waiting.
+fn quit_requested() -> io::Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        if let Event::Key(key) = event::read()? {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.kind == KeyEventKind::Press
+                && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+            {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// A `width` characters wide bar filled up to `ratio`.
+fn bar(ratio: f64, width: usize) -> String {
+    let filled = ((ratio.clamp(0.0, 1.0) * width as f64).round() as usize).min(width);
+    format!("{}{}", "█".repeat(filled), "░".repeat(width - filled))
+}
+
+/// `1h 02m`, `3m 05s` or `12s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{seconds}s"),
+        (0, minutes, seconds) => format!("{minutes}m {seconds:02}s"),
+        (hours, minutes, _) => format!("{hours}h {minutes:02}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+
+    use super::*;
+    use crate::BlockRange;
+
+    fn draw(dashboard: &Dashboard) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|frame| dashboard.render(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn shows_running_units_and_overall_progress() {
+        let planner = RangePlanner::new(BlockRange::new(0, 999), 100);
+        let first = planner.next(0).unwrap();
+        planner.progress(&first, 0, 49);
+        let second = planner.next(1).unwrap();
+        planner.complete(&second).unwrap();
+        planner.next(2).unwrap();
+
+        let mut dashboard = Dashboard::new("backfill").with_planner(planner);
+        dashboard.sample(Instant::now());
+        let screen = draw(&dashboard);
+
+        assert!(screen.contains("150 / 1000 blocks (15.0%)"), "{screen}");
+        assert!(
+            screen.contains("Units: 2 running, 7 pending, 1 done"),
+            "{screen}"
+        );
+        assert!(screen.contains("0-99"), "{screen}");
+        assert!(screen.contains(" 50%"), "{screen}");
+        assert!(screen.contains("200-299"), "{screen}");
+        assert!(!screen.contains("100-199"), "{screen}");
+    }
+
+    #[test]
+    fn samples_throughput_reconnects_and_sink_lag() {
+        let metrics = CallMetrics::new();
+        let health = HealthReporter::new(Duration::from_secs(60));
+        for _ in 0..3 {
+            metrics.record_block("mainnet/live", 1_000);
+        }
+        metrics.record_reconnect("mainnet/live");
+        health.record_block("sink");
+        health.record_sink_write("sink");
+        health.record_sink_write("sink");
+
+        let mut dashboard = Dashboard::new("live")
+            .with_metrics(metrics.clone())
+            .with_health(health);
+        let start = Instant::now();
+        dashboard.sample(start);
+        for _ in 0..5 {
+            metrics.record_block("mainnet/live", 1_000);
+        }
+        dashboard.sample(start + Duration::from_secs(2));
+
+        assert_eq!(dashboard.rates["mainnet/live"], 2.5);
+        assert_eq!(dashboard.throughput.back(), Some(&(2.0, 2.5)));
+
+        // Streams on the left half of the screen, health on the right.
+        let screen = draw(&dashboard);
+        let row = screen
+            .lines()
+            .find(|line| line.contains("mainnet/live"))
+            .unwrap();
+        let (stream, sink): (String, String) = (
+            row.chars().take(60).collect(),
+            row.chars().skip(60).collect(),
+        );
+        assert!(stream.contains(" 8 "), "{stream}");
+        assert!(stream.contains(" 2.5 "), "{stream}");
+        assert!(stream.contains(" 1 "), "{stream}");
+        assert!(sink.contains("sink") && sink.contains("ready"), "{sink}");
+        assert!(sink.contains(" 2 "), "{sink}");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_secs(12)), "12s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m 05s");
+        assert_eq!(format_duration(Duration::from_secs(3_725)), "1h 02m");
+        assert_eq!(bar(0.5, 4), "██░░");
+    }
+}
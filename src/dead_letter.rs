@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{fmt::Display, io};
+#[cfg(feature = "sink")]
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::Response;
+
+/// A block that could not be decoded, with the reason.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Number of the block, if the response carried its metadata.
+    pub block: Option<u64>,
+    /// Cursor of the block, to replay it once the decoder is fixed.
+    pub cursor: String,
+    /// Why decoding failed.
+    pub error: String,
+    /// The raw response.
+    pub response: Response,
+}
+
+impl DeadLetter {
+    /// Record that `response` failed with `error`.
+    pub fn new(response: Response, error: impl Display) -> Self {
+        DeadLetter {
+            block: response.block_number(),
+            cursor: response.cursor.clone(),
+            error: error.to_string(),
+            response,
+        }
+    }
+}
+
+/// A destination for blocks that failed to decode.
+///
+/// Given to [`ResilientStream::with_dead_letters`](crate::ResilientStream::with_dead_letters),
+/// it receives every block [`FromResponse`](crate::FromResponse) rejects, and
+/// the stream continues with the next block instead of failing.
+pub trait DeadLetterSink: Send {
+    /// Store one dead letter.
+    fn send(&mut self, letter: DeadLetter) -> io::Result<()>;
+}
+
+/// Hands dead letters to another task.
+impl DeadLetterSink for mpsc::UnboundedSender<DeadLetter> {
+    fn send(&mut self, letter: DeadLetter) -> io::Result<()> {
+        mpsc::UnboundedSender::send(self, letter)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "dead letter receiver dropped"))
+    }
+}
+
+/// A [`DeadLetterSink`] appending one JSON-encoded [`DeadLetter`] per line to
+/// a file.
+#[cfg(feature = "sink")]
+#[derive(Debug)]
+pub struct DeadLetterFile {
+    writer: BufWriter<File>,
+}
+
+#[cfg(feature = "sink")]
+impl DeadLetterFile {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(DeadLetterFile {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+#[cfg(feature = "sink")]
+impl DeadLetterSink for DeadLetterFile {
+    fn send(&mut self, letter: DeadLetter) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &letter)?;
+        self.writer.write_all(b"\n")?;
+        // Dead letters are rare; keep each one even if the process dies.
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::HashSet, net::SocketAddr, time::Duration};
+
+use tokio::{net::lookup_host, sync::mpsc::Sender, task::JoinHandle, time::MissedTickBehavior};
+use tonic::transport::{channel::Change, Channel, Endpoint, Uri};
+
+use crate::{FirehoseEndpoint, FirehoseError};
+
+/// Number of pending backend changes buffered between the resolver task and
+/// the balanced channel.
+const CHANGE_BUFFER: usize = 64;
+
+impl FirehoseEndpoint {
+    /// Connect to every address the endpoint's host name resolves to, and keep
+    /// following DNS changes.
+    ///
+    /// The returned [`Channel`] balances calls across all resolved backends.
+    /// A background task re-resolves the host name every `interval`, adding
+    /// new addresses and removing vanished ones; this is what a headless
+    /// Kubernetes service needs, where each pod has its own A record. TLS
+    /// still verifies certificates against the host name, not the addresses.
+    ///
+    /// Failed or empty lookups keep the current backends. The task stops once
+    /// the channel and all its clones are dropped.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    ///
+    /// use firehose_rs::FirehoseEndpoint;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let endpoint = FirehoseEndpoint::new("firehose-headless.indexing.svc.cluster.local:10015")
+    ///     .with_insecure(true);
+    ///
+    /// let (channel, _resolver) = endpoint
+    ///     .connect_with_dns_discovery(Duration::from_secs(30))
+    ///     .await?;
+    /// let mut client = endpoint.stream_client_with_channel(channel)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with_dns_discovery(
+        &self,
+        interval: Duration,
+    ) -> Result<(Channel, JoinHandle<()>), FirehoseError> {
+        #[cfg(feature = "proxy")]
+        if self.connector().is_some() {
+            return Err(FirehoseError::Config(
+                "DNS discovery cannot be combined with a proxy or custom connector".to_string(),
+            ));
+        }
+
+        let authority = self.authority()?;
+        let addresses: HashSet<SocketAddr> = lookup_host(&authority).await?.collect();
+        if addresses.is_empty() {
+            return Err(FirehoseError::Config(format!(
+                "`{authority}` did not resolve to any address"
+            )));
+        }
+
+        let (channel, changes) = Channel::balance_channel(CHANGE_BUFFER);
+        for address in &addresses {
+            let endpoint = self.endpoint_for_address(*address)?;
+            // The receiver lives in `channel`, which we still hold
+            let _ = changes.send(Change::Insert(*address, endpoint)).await;
+        }
+
+        let resolver = tokio::spawn(follow_dns(
+            self.clone(),
+            authority,
+            interval,
+            changes,
+            addresses,
+        ));
+
+        Ok((channel, resolver))
+    }
+
+    /// The `host:port` part of the URI, with the scheme's default port filled
+    /// in.
+    fn authority(&self) -> Result<String, FirehoseError> {
+        let uri: Uri = self.uri().parse().map_err(|e| {
+            FirehoseError::Config(format!("invalid endpoint `{}`: {e}", self.uri()))
+        })?;
+        let host = uri
+            .host()
+            .ok_or_else(|| FirehoseError::Config(format!("endpoint `{uri}` has no host")))?;
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme_str() == Some("http") {
+                80
+            } else {
+                443
+            });
+
+        Ok(format!("{host}:{port}"))
+    }
+
+    /// A tonic [`Endpoint`] dialing `address` directly, while presenting the
+    /// configured host name for TLS and the `:authority` header.
+    fn endpoint_for_address(&self, address: SocketAddr) -> Result<Endpoint, FirehoseError> {
+        let origin: Uri = self.uri().parse().map_err(|e| {
+            FirehoseError::Config(format!("invalid endpoint `{}`: {e}", self.uri()))
+        })?;
+        let scheme = origin.scheme_str().unwrap_or("https");
+
+        let endpoint = self.build_endpoint(format!("{scheme}://{address}"), origin.host())?;
+        Ok(endpoint.origin(origin))
+    }
+}
+
+/// Re-resolve `authority` every `interval` and forward the differences.
+async fn follow_dns(
+    endpoint: FirehoseEndpoint,
+    authority: String,
+    interval: Duration,
+    changes: Sender<Change<SocketAddr, Endpoint>>,
+    mut known: HashSet<SocketAddr>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick completes immediately, and we just resolved
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let current: HashSet<SocketAddr> = match lookup_host(&authority).await {
+            Ok(addresses) => addresses.collect(),
+            Err(_) => continue,
+        };
+        if current.is_empty() {
+            continue;
+        }
+
+        for removed in known.difference(&current) {
+            if changes.send(Change::Remove(*removed)).await.is_err() {
+                return;
+            }
+        }
+        for added in current.difference(&known) {
+            let Ok(backend) = endpoint.endpoint_for_address(*added) else {
+                continue;
+            };
+            if changes.send(Change::Insert(*added, backend)).await.is_err() {
+                return;
+            }
+        }
+
+        known = current;
+    }
+}
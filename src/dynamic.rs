@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runtime decoding of block payloads for chains without dedicated bindings.
+
+use std::fmt::{self, Display};
+
+use prost_reflect::{DescriptorError, DescriptorPool, DynamicMessage, MessageDescriptor};
+use prost_wkt_types::Any;
+
+use crate::{
+    firehose_v2::FILE_DESCRIPTOR_SET,
+    schema::{message_json_schema, TableSchema},
+    Response, RootError, SingleBlockResponse,
+};
+
+/// Decode [`Any`] block payloads into [`DynamicMessage`]s using descriptors
+/// registered at runtime.
+///
+/// The decoder starts out knowing the Firehose protos bundled with this crate.
+/// Register the descriptors of the chain you are streaming (for example, a
+/// `FileDescriptorSet` produced by `buf build -o blocks.binpb`) with
+/// [`add_file_descriptor_set`](DynamicDecoder::add_file_descriptor_set) before
+/// decoding its blocks.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{DynamicDecoder, Response};
+///
+/// # fn example(response: Response) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decoder = DynamicDecoder::new()?;
+/// decoder.add_file_descriptor_set(std::fs::read("blocks.binpb")?.as_slice())?;
+///
+/// if let Some(json) = decoder.response_block_to_json(&response)? {
+///     println!("{json}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct DynamicDecoder {
+    pool: DescriptorPool,
+}
+
+impl DynamicDecoder {
+    /// Create a decoder preloaded with the Firehose protos.
+    pub fn new() -> Result<Self, DynamicDecodeError> {
+        let pool = DescriptorPool::decode(FILE_DESCRIPTOR_SET)?;
+        Ok(Self { pool })
+    }
+
+    /// Create a decoder around an existing [`DescriptorPool`].
+    pub fn with_pool(pool: DescriptorPool) -> Self {
+        Self { pool }
+    }
+
+    /// Register the messages described by an encoded `FileDescriptorSet`.
+    pub fn add_file_descriptor_set(&mut self, bytes: &[u8]) -> Result<(), DynamicDecodeError> {
+        self.pool.decode_file_descriptor_set(bytes)?;
+        Ok(())
+    }
+
+    /// The descriptor pool used to resolve type URLs.
+    pub fn pool(&self) -> &DescriptorPool {
+        &self.pool
+    }
+
+    /// Decode an [`Any`] into a [`DynamicMessage`] of the type named by its
+    /// type URL.
+    pub fn decode(&self, any: &Any) -> Result<DynamicMessage, DynamicDecodeError> {
+        let descriptor = self.message_descriptor(&any.type_url)?;
+        Ok(DynamicMessage::decode(descriptor, any.value.as_slice())?)
+    }
+
+    /// Decode the block carried by a streaming [`Response`], if any.
+    pub fn decode_response_block(
+        &self,
+        response: &Response,
+    ) -> Result<Option<DynamicMessage>, DynamicDecodeError> {
+        response
+            .block
+            .as_ref()
+            .map(|any| self.decode(any))
+            .transpose()
+    }
+
+    /// Decode the block carried by a [`SingleBlockResponse`], if any.
+    pub fn decode_single_block(
+        &self,
+        response: &SingleBlockResponse,
+    ) -> Result<Option<DynamicMessage>, DynamicDecodeError> {
+        response
+            .block
+            .as_ref()
+            .map(|any| self.decode(any))
+            .transpose()
+    }
+
+    /// Decode an [`Any`] and render it as a JSON value.
+    pub fn to_json(&self, any: &Any) -> Result<serde_json::Value, DynamicDecodeError> {
+        let message = self.decode(any)?;
+        Ok(serde_json::to_value(&message)?)
+    }
+
+    /// Decode the block carried by a streaming [`Response`] and render it as
+    /// a JSON value.
+    pub fn response_block_to_json(
+        &self,
+        response: &Response,
+    ) -> Result<Option<serde_json::Value>, DynamicDecodeError> {
+        response
+            .block
+            .as_ref()
+            .map(|any| self.to_json(any))
+            .transpose()
+    }
+
+    /// The descriptor of the message type named by `type_url`, which may
+    /// also be a bare full name such as `sf.ethereum.type.v2.Block`.
+    pub fn message_descriptor(
+        &self,
+        type_url: &str,
+    ) -> Result<MessageDescriptor, DynamicDecodeError> {
+        self.pool
+            .get_message_by_name(message_name(type_url))
+            .ok_or_else(|| DynamicDecodeError::UnknownType(type_url.to_string()))
+    }
+
+    /// The [`TableSchema`] named `table` of the message type named by
+    /// `type_url`, flattened into columns.
+    pub fn table_schema(
+        &self,
+        table: &str,
+        type_url: &str,
+    ) -> Result<TableSchema, DynamicDecodeError> {
+        Ok(TableSchema::from_message(
+            table,
+            &self.message_descriptor(type_url)?,
+        ))
+    }
+
+    /// The JSON Schema of the message type named by `type_url`, as rendered
+    /// by [`to_json`](DynamicDecoder::to_json).
+    pub fn json_schema(&self, type_url: &str) -> Result<serde_json::Value, DynamicDecodeError> {
+        Ok(message_json_schema(&self.message_descriptor(type_url)?))
+    }
+}
+
+/// Strip the `type.googleapis.com/` style prefix from a type URL.
+pub(crate) fn message_name(type_url: &str) -> &str {
+    type_url.rsplit_once('/').map_or(type_url, |(_, name)| name)
+}
+
+/// Errors returned by [`DynamicDecoder`].
+#[derive(Debug)]
+pub enum DynamicDecodeError {
+    /// The descriptor set could not be parsed or conflicts with the pool.
+    Descriptor(DescriptorError),
+    /// No descriptor is registered for the payload's type URL.
+    UnknownType(String),
+    /// A dotted field path does not lead to a repeated message field.
+    InvalidPath(String),
+    /// The payload bytes do not match the registered descriptor.
+    Decode(prost::DecodeError),
+    /// The decoded message could not be rendered as JSON.
+    Json(serde_json::Error),
+    /// The block's transactions or receipts do not match its header, see
+    /// [`Flattener::with_root_verification`](crate::Flattener::with_root_verification).
+    Roots(RootError),
+}
+
+impl Display for DynamicDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DynamicDecodeError::Descriptor(e) => write!(f, "invalid descriptor set: {e}"),
+            DynamicDecodeError::UnknownType(type_url) => {
+                write!(f, "no descriptor registered for type `{type_url}`")
+            }
+            DynamicDecodeError::InvalidPath(path) => {
+                write!(f, "`{path}` is not a path to a repeated message field")
+            }
+            DynamicDecodeError::Decode(e) => write!(f, "failed to decode payload: {e}"),
+            DynamicDecodeError::Json(e) => write!(f, "failed to render payload as JSON: {e}"),
+            DynamicDecodeError::Roots(e) => write!(f, "block failed root verification: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DynamicDecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DynamicDecodeError::Descriptor(e) => Some(e),
+            DynamicDecodeError::UnknownType(_) | DynamicDecodeError::InvalidPath(_) => None,
+            DynamicDecodeError::Decode(e) => Some(e),
+            DynamicDecodeError::Json(e) => Some(e),
+            DynamicDecodeError::Roots(e) => Some(e),
+        }
+    }
+}
+
+impl From<DescriptorError> for DynamicDecodeError {
+    fn from(e: DescriptorError) -> Self {
+        DynamicDecodeError::Descriptor(e)
+    }
+}
+
+impl From<prost::DecodeError> for DynamicDecodeError {
+    fn from(e: prost::DecodeError) -> Self {
+        DynamicDecodeError::Decode(e)
+    }
+}
+
+impl From<serde_json::Error> for DynamicDecodeError {
+    fn from(e: serde_json::Error) -> Self {
+        DynamicDecodeError::Json(e)
+    }
+}
@@ -0,0 +1,1195 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    env, fmt,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tls")]
+use tonic::transport::ClientTlsConfig;
+use tonic::{
+    client::GrpcService,
+    codec::CompressionEncoding,
+    codegen::{Body, Bytes, StdError},
+    metadata::{Ascii, MetadataKey, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor, InterceptorLayer},
+    transport::{Channel, Endpoint},
+    Code, Status,
+};
+use tower::Layer;
+
+use crate::{
+    request_id, CodecClient, EndpointInfoClient, FetchClient, FetchService, FirehoseError,
+    InfoRequest, InfoResponse, RetryLayer, RetryService, StreamClient,
+};
+#[cfg(feature = "proxy")]
+use crate::{Connector, Proxy};
+
+/// Environment variable holding the endpoint URI.
+pub const ENV_ENDPOINT: &str = "FIREHOSE_ENDPOINT";
+/// Environment variable holding the API key sent with every call.
+pub const ENV_API_KEY: &str = "FIREHOSE_API_KEY";
+/// Environment variable disabling TLS when set to a truthy value.
+pub const ENV_INSECURE: &str = "FIREHOSE_INSECURE";
+/// Environment variable selecting message compression (`gzip`, `zstd`, `none`).
+pub const ENV_COMPRESSION: &str = "FIREHOSE_COMPRESSION";
+/// Environment variable holding the per-request timeout, in seconds.
+pub const ENV_TIMEOUT_SECS: &str = "FIREHOSE_TIMEOUT_SECS";
+/// Environment variable holding the connection timeout, in seconds.
+pub const ENV_CONNECT_TIMEOUT_SECS: &str = "FIREHOSE_CONNECT_TIMEOUT_SECS";
+/// Environment variable holding the URI of a proxy to connect through.
+pub const ENV_PROXY: &str = "FIREHOSE_PROXY";
+
+/// Channel type used by clients built from a [`FirehoseEndpoint`].
+pub type FirehoseChannel = InterceptedService<Channel, AuthInterceptor>;
+
+/// Connection settings for a Firehose endpoint.
+///
+/// Build one explicitly with [`FirehoseEndpoint::new`] and the `with_*`
+/// methods, or read the standard `FIREHOSE_*` environment variables with
+/// [`FirehoseEndpoint::from_env`], then create authenticated clients with
+/// [`stream_client`](FirehoseEndpoint::stream_client) and
+/// [`fetch_client`](FirehoseEndpoint::fetch_client).
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use firehose_rs::{FirehoseEndpoint, SingleBlockRequest};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let endpoint = FirehoseEndpoint::new("mainnet.eth.streamingfast.io:443")
+///     .with_api_key("my-api-key")
+///     .with_connect_timeout(Duration::from_secs(10));
+///
+/// let mut client = endpoint.fetch_client().await?;
+/// let response = client
+///     .block(SingleBlockRequest::new_by_block_number(12345))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct FirehoseEndpoint {
+    uri: String,
+    api_key: Option<String>,
+    bearer_token: Option<BearerToken>,
+    insecure: bool,
+    compression: Option<CompressionEncoding>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    max_decoding_message_size: Option<usize>,
+    #[cfg(feature = "proxy")]
+    proxy: Option<Proxy>,
+    #[cfg(feature = "proxy")]
+    connector: Option<Connector>,
+    metadata: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+}
+
+impl FirehoseEndpoint {
+    /// Create settings for the endpoint at `uri`.
+    ///
+    /// The scheme may be omitted (`host:port`), in which case `https://` is
+    /// assumed unless the endpoint is marked [insecure](Self::with_insecure).
+    pub fn new(uri: impl Into<String>) -> Self {
+        FirehoseEndpoint {
+            uri: uri.into(),
+            api_key: None,
+            bearer_token: None,
+            insecure: false,
+            compression: None,
+            timeout: None,
+            connect_timeout: None,
+            max_decoding_message_size: None,
+            #[cfg(feature = "proxy")]
+            proxy: None,
+            #[cfg(feature = "proxy")]
+            connector: None,
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Read the endpoint settings from the environment.
+    ///
+    /// | Variable | Meaning |
+    /// |----------|---------|
+    /// | `FIREHOSE_ENDPOINT` | Endpoint URI (required) |
+    /// | `FIREHOSE_API_KEY` | API key sent as `x-api-key` |
+    /// | `FIREHOSE_INSECURE` | `true`/`1`/`yes` to connect without TLS |
+    /// | `FIREHOSE_COMPRESSION` | `gzip`, `zstd`, or `none` |
+    /// | `FIREHOSE_TIMEOUT_SECS` | Per-request timeout |
+    /// | `FIREHOSE_CONNECT_TIMEOUT_SECS` | Connection timeout |
+    /// | `FIREHOSE_PROXY` | Proxy URI, see `Proxy::parse` |
+    ///
+    /// Unset or empty optional variables keep their defaults. `gzip` and
+    /// `zstd` need the `compression` feature, and a proxy the `proxy`
+    /// feature; without them, setting these variables is an error rather
+    /// than silently ignored.
+    pub fn from_env() -> Result<Self, FirehoseError> {
+        let uri = var(ENV_ENDPOINT)?
+            .ok_or_else(|| FirehoseError::Config(format!("{ENV_ENDPOINT} is not set")))?;
+
+        let mut endpoint = FirehoseEndpoint::new(uri);
+        endpoint.api_key = var(ENV_API_KEY)?;
+
+        if let Some(value) = var(ENV_INSECURE)? {
+            endpoint.insecure = parse_bool(ENV_INSECURE, &value)?;
+        }
+        if let Some(value) = var(ENV_COMPRESSION)? {
+            endpoint.compression = parse_compression(&value)?;
+        }
+        if let Some(value) = var(ENV_TIMEOUT_SECS)? {
+            endpoint.timeout = Some(parse_secs(ENV_TIMEOUT_SECS, &value)?);
+        }
+        if let Some(value) = var(ENV_CONNECT_TIMEOUT_SECS)? {
+            endpoint.connect_timeout = Some(parse_secs(ENV_CONNECT_TIMEOUT_SECS, &value)?);
+        }
+        if let Some(value) = var(ENV_PROXY)? {
+            #[cfg(feature = "proxy")]
+            {
+                endpoint.proxy = Some(Proxy::parse(&value)?);
+            }
+            #[cfg(not(feature = "proxy"))]
+            return Err(FirehoseError::Config(format!(
+                "{ENV_PROXY}={value} needs the `proxy` feature"
+            )));
+        }
+
+        Ok(endpoint)
+    }
+
+    /// Send `api_key` in the `x-api-key` header of every call.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Send the current value of `token` as `authorization: Bearer` with
+    /// every call.
+    ///
+    /// Clients read the token on each call, so refreshing it, for example
+    /// with `StreamingFastAuth` from the `streamingfast-auth` feature, takes
+    /// effect without rebuilding them.
+    pub fn with_bearer_token(mut self, token: BearerToken) -> Self {
+        self.bearer_token = Some(token);
+        self
+    }
+
+    /// Send `key: value` metadata with every call, such as a tenant or
+    /// tracing header expected by the provider.
+    ///
+    /// Every call also carries a unique `x-request-id`, unless one is set
+    /// here or on the call itself.
+    ///
+    /// # Panics
+    ///
+    /// Here rather than on each call, if `key` is not a valid ASCII metadata
+    /// key: lowercase, and not ending in `-bin`.
+    pub fn with_metadata(mut self, key: &'static str, value: MetadataValue<Ascii>) -> Self {
+        self.metadata.push((MetadataKey::from_static(key), value));
+        self
+    }
+
+    /// Connect over plaintext HTTP/2 instead of TLS.
+    pub fn with_insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Compress requests and accept compressed responses with `encoding`,
+    /// available with the `compression` feature.
+    pub fn with_compression(mut self, encoding: CompressionEncoding) -> Self {
+        self.compression = Some(encoding);
+        self
+    }
+
+    /// Fail requests that take longer than `timeout` to produce a response.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fail connection attempts that take longer than `timeout`.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Raise the maximum size of a decoded message.
+    ///
+    /// Blocks of busy chains regularly exceed tonic's 4 MiB default.
+    pub fn with_max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
+        self
+    }
+
+    /// Tunnel connections through `proxy`, for networks that only allow
+    /// egress through one.
+    ///
+    /// TLS is still negotiated with the endpoint itself. Not supported by
+    /// [`connect_with_dns_discovery`](Self::connect_with_dns_discovery),
+    /// since the proxy resolves host names.
+    #[cfg(feature = "proxy")]
+    pub fn with_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// The proxy connections are tunnelled through, if any.
+    #[cfg(feature = "proxy")]
+    pub fn proxy(&self) -> Option<&Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Open connections with `connector` instead of dialing TCP.
+    ///
+    /// TLS, credentials, compression and message size limits still apply on
+    /// top of the connector's streams. Takes precedence over a
+    /// [proxy](Self::with_proxy), and is not supported by
+    /// [`connect_with_dns_discovery`](Self::connect_with_dns_discovery).
+    #[cfg(feature = "proxy")]
+    pub fn with_connector(mut self, connector: Connector) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    /// The connector that replaces dialing TCP: the one set with
+    /// [`with_connector`](Self::with_connector), or the
+    /// [proxy](Self::with_proxy)'s.
+    #[cfg(feature = "proxy")]
+    pub fn connector(&self) -> Option<Connector> {
+        self.connector
+            .clone()
+            .or_else(|| self.proxy.as_ref().map(Proxy::connector))
+    }
+
+    /// The endpoint URI, including the scheme.
+    pub fn uri(&self) -> String {
+        if self.uri.contains("://") {
+            self.uri.clone()
+        } else if self.insecure {
+            format!("http://{}", self.uri)
+        } else {
+            format!("https://{}", self.uri)
+        }
+    }
+
+    /// Build the tonic [`Endpoint`] described by these settings.
+    pub fn endpoint(&self) -> Result<Endpoint, FirehoseError> {
+        self.build_endpoint(self.uri(), None)
+    }
+
+    /// Build a tonic [`Endpoint`] dialing `uri` with these settings' timeouts
+    /// and TLS configuration, verifying certificates against `tls_domain` when
+    /// given instead of the host in `uri`.
+    pub(crate) fn build_endpoint(
+        &self,
+        uri: String,
+        tls_domain: Option<&str>,
+    ) -> Result<Endpoint, FirehoseError> {
+        let mut endpoint = Endpoint::from_shared(uri.clone())
+            .map_err(|e| FirehoseError::Config(format!("invalid endpoint `{uri}`: {e}")))?;
+
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if !self.insecure && uri.starts_with("https://") {
+            #[cfg(feature = "tls")]
+            {
+                let mut tls = ClientTlsConfig::new().with_native_roots();
+                if let Some(domain) = tls_domain {
+                    tls = tls.domain_name(domain);
+                }
+                endpoint = endpoint.tls_config(tls)?;
+            }
+            #[cfg(not(feature = "tls"))]
+            {
+                let _ = tls_domain;
+                return Err(FirehoseError::Config(format!(
+                    "`{uri}` needs the `tls` feature, or an insecure endpoint"
+                )));
+            }
+        }
+
+        Ok(endpoint)
+    }
+
+    /// Establish a connection to the endpoint.
+    pub async fn connect(&self) -> Result<Channel, FirehoseError> {
+        self.dial(self.endpoint()?).await
+    }
+
+    /// Connect to `endpoint`, with the connector if any.
+    async fn dial(&self, endpoint: Endpoint) -> Result<Channel, FirehoseError> {
+        #[cfg(feature = "proxy")]
+        if let Some(connector) = self.connector() {
+            return Ok(endpoint.connect_with_connector(connector).await?);
+        }
+        Ok(endpoint.connect().await?)
+    }
+
+    /// Create a channel that connects on its first call instead of now.
+    ///
+    /// No I/O happens here, so clients can be built before the endpoint is
+    /// reachable, for example while containers come up out of order. A call
+    /// made while the endpoint is down fails with a retryable `UNAVAILABLE`
+    /// status, and the channel connects again on the next call, so a
+    /// [`RetryLayer`](crate::RetryLayer) or [`ResilientStream`] on top
+    /// rides out the wait. Must be called from within a Tokio runtime.
+    ///
+    /// [`ResilientStream`]: crate::ResilientStream
+    pub fn connect_lazy(&self) -> Result<Channel, FirehoseError> {
+        let endpoint = self.endpoint()?;
+        #[cfg(feature = "proxy")]
+        if let Some(connector) = self.connector() {
+            return Ok(endpoint.connect_with_connector_lazy(connector));
+        }
+        Ok(endpoint.connect_lazy())
+    }
+
+    /// Establish a connection and call `Info` on it, failing if either does
+    /// not complete within `timeout`.
+    ///
+    /// Services can call this at startup to fail fast on a wrong URI, an
+    /// unreachable host, a TLS misconfiguration or rejected credentials,
+    /// instead of on the first block request. The returned channel is ready
+    /// for [`stream_client_with_channel`](Self::stream_client_with_channel)
+    /// and [`fetch_client_with_channel`](Self::fetch_client_with_channel).
+    ///
+    /// Servers that predate the `EndpointInfo` service answer `UNIMPLEMENTED`,
+    /// which is not an error: [`ConnectionDiagnostics::info`] is `None`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    ///
+    /// use firehose_rs::FirehoseEndpoint;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let endpoint = FirehoseEndpoint::from_env()?;
+    /// let (channel, diagnostics) = endpoint.connect_ready(Duration::from_secs(10)).await?;
+    /// println!("{diagnostics}");
+    ///
+    /// let mut client = endpoint.stream_client_with_channel(channel)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_ready(
+        &self,
+        timeout: Duration,
+    ) -> Result<(Channel, ConnectionDiagnostics), FirehoseError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let timed_out = |step: &str| {
+            FirehoseError::Status(Status::deadline_exceeded(format!(
+                "{step} {} did not complete within {timeout:?}",
+                self.uri()
+            )))
+        };
+
+        let endpoint = self.endpoint()?;
+        let requested_tls = endpoint.uri().scheme_str() == Some("https");
+
+        let started = Instant::now();
+        let channel = tokio::time::timeout_at(deadline, self.dial(endpoint))
+            .await
+            .map_err(|_| timed_out("connecting to"))??;
+        let connect_latency = started.elapsed();
+
+        let mut client = self.info_client_with_channel(channel.clone())?;
+        let started = Instant::now();
+        let info = match tokio::time::timeout_at(deadline, client.info(InfoRequest {}))
+            .await
+            .map_err(|_| timed_out("calling Info on"))?
+        {
+            Ok(response) => Some(response.into_inner()),
+            Err(status) if status.code() == Code::Unimplemented => None,
+            Err(status) => return Err(status.into()),
+        };
+        let info_latency = started.elapsed();
+
+        let diagnostics = ConnectionDiagnostics {
+            uri: self.uri(),
+            requested_tls,
+            info,
+            connect_latency,
+            info_latency,
+        };
+        Ok((channel, diagnostics))
+    }
+
+    /// Layer attaching these settings' credentials to each call, for
+    /// [`stream_client_with_layer`](Self::stream_client_with_layer) and
+    /// [`fetch_client_with_layer`](Self::fetch_client_with_layer).
+    pub fn auth_layer(&self) -> Result<InterceptorLayer<AuthInterceptor>, FirehoseError> {
+        Ok(InterceptorLayer::new(self.interceptor()?))
+    }
+
+    /// The interceptor attaching these settings' credentials, metadata and a
+    /// request ID to each call.
+    pub fn interceptor(&self) -> Result<AuthInterceptor, FirehoseError> {
+        let mut interceptor = AuthInterceptor::new(self.api_key.as_deref())?;
+        if let Some(token) = &self.bearer_token {
+            interceptor = interceptor.with_bearer_token(token.clone());
+        }
+        for (key, value) in &self.metadata {
+            interceptor.metadata.push((key.clone(), value.clone()));
+        }
+        Ok(interceptor)
+    }
+
+    /// Connect and create a [`StreamClient`].
+    pub async fn stream_client(&self) -> Result<StreamClient<FirehoseChannel>, FirehoseError> {
+        let channel = self.connect().await?;
+        self.stream_client_with_channel(channel)
+    }
+
+    /// Create a [`StreamClient`] on a [lazy](Self::connect_lazy) channel.
+    pub fn stream_client_lazy(&self) -> Result<StreamClient<FirehoseChannel>, FirehoseError> {
+        self.stream_client_with_channel(self.connect_lazy()?)
+    }
+
+    /// Create a [`StreamClient`] on an existing channel.
+    pub fn stream_client_with_channel(
+        &self,
+        channel: Channel,
+    ) -> Result<StreamClient<FirehoseChannel>, FirehoseError> {
+        Ok(self.stream_client_with_layer(channel, self.auth_layer()?))
+    }
+
+    /// Create a [`StreamClient`] on `channel` wrapped in `layer`.
+    ///
+    /// The layer, typically a stack built with [`tower::ServiceBuilder`],
+    /// replaces the default middleware entirely: include
+    /// [`auth_layer`](Self::auth_layer) to send credentials, and combine it
+    /// with [`ObserveLayer`](crate::ObserveLayer)s for logging or metrics in
+    /// any order. Compression and message size limits still apply.
+    pub fn stream_client_with_layer<L>(
+        &self,
+        channel: Channel,
+        layer: L,
+    ) -> StreamClient<L::Service>
+    where
+        L: Layer<Channel>,
+        L::Service: GrpcService<tonic::body::Body>,
+        <L::Service as GrpcService<tonic::body::Body>>::Error: Into<StdError>,
+        <L::Service as GrpcService<tonic::body::Body>>::ResponseBody:
+            Body<Data = Bytes> + Send + 'static,
+        <<L::Service as GrpcService<tonic::body::Body>>::ResponseBody as Body>::Error:
+            Into<StdError> + Send,
+    {
+        let mut client = StreamClient::new(layer.layer(channel));
+        if let Some(encoding) = self.compression {
+            client = client.send_compressed(encoding).accept_compressed(encoding);
+        }
+        if let Some(limit) = self.max_decoding_message_size {
+            client = client.max_decoding_message_size(limit);
+        }
+        client
+    }
+
+    /// Connect and create a [`FetchClient`].
+    pub async fn fetch_client(&self) -> Result<FetchClient<FirehoseChannel>, FirehoseError> {
+        let channel = self.connect().await?;
+        self.fetch_client_with_channel(channel)
+    }
+
+    /// Create a [`FetchClient`] on a [lazy](Self::connect_lazy) channel.
+    pub fn fetch_client_lazy(&self) -> Result<FetchClient<FirehoseChannel>, FirehoseError> {
+        self.fetch_client_with_channel(self.connect_lazy()?)
+    }
+
+    /// Create a [`FetchService`] on a [lazy](Self::connect_lazy) channel,
+    /// retrying fetches with `retry` while the endpoint is unreachable.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use std::future::poll_fn;
+    ///
+    /// use firehose_rs::{Backoff, FirehoseEndpoint, RetryLayer, SingleBlockRequest};
+    /// use tower::Service;
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// // Succeeds even if the endpoint is not up yet
+    /// let mut service = FirehoseEndpoint::from_env()?
+    ///     .fetch_service_lazy(RetryLayer::new(Backoff::default()))?;
+    ///
+    /// poll_fn(|cx| service.poll_ready(cx)).await?;
+    /// let response = service
+    ///     .call(SingleBlockRequest::new_by_block_number(12345))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn fetch_service_lazy(
+        &self,
+        retry: RetryLayer,
+    ) -> Result<RetryService<FetchService>, FirehoseError> {
+        Ok(retry.layer(FetchService::new(self.fetch_client_lazy()?)))
+    }
+
+    /// Create a [`FetchClient`] on an existing channel.
+    pub fn fetch_client_with_channel(
+        &self,
+        channel: Channel,
+    ) -> Result<FetchClient<FirehoseChannel>, FirehoseError> {
+        Ok(self.fetch_client_with_layer(channel, self.auth_layer()?))
+    }
+
+    /// Create a [`FetchClient`] on `channel` wrapped in `layer`.
+    ///
+    /// See [`stream_client_with_layer`](Self::stream_client_with_layer).
+    pub fn fetch_client_with_layer<L>(&self, channel: Channel, layer: L) -> FetchClient<L::Service>
+    where
+        L: Layer<Channel>,
+        L::Service: GrpcService<tonic::body::Body>,
+        <L::Service as GrpcService<tonic::body::Body>>::Error: Into<StdError>,
+        <L::Service as GrpcService<tonic::body::Body>>::ResponseBody:
+            Body<Data = Bytes> + Send + 'static,
+        <<L::Service as GrpcService<tonic::body::Body>>::ResponseBody as Body>::Error:
+            Into<StdError> + Send,
+    {
+        let mut client = FetchClient::new(layer.layer(channel));
+        if let Some(encoding) = self.compression {
+            client = client.send_compressed(encoding).accept_compressed(encoding);
+        }
+        if let Some(limit) = self.max_decoding_message_size {
+            client = client.max_decoding_message_size(limit);
+        }
+        client
+    }
+
+    /// Connect and create a [`CodecClient`], exchanging message types of
+    /// the caller's choosing.
+    pub async fn codec_client(&self) -> Result<CodecClient<FirehoseChannel>, FirehoseError> {
+        let channel = self.connect().await?;
+        self.codec_client_with_channel(channel)
+    }
+
+    /// Create a [`CodecClient`] on a [lazy](Self::connect_lazy) channel.
+    pub fn codec_client_lazy(&self) -> Result<CodecClient<FirehoseChannel>, FirehoseError> {
+        self.codec_client_with_channel(self.connect_lazy()?)
+    }
+
+    /// Create a [`CodecClient`] on an existing channel.
+    pub fn codec_client_with_channel(
+        &self,
+        channel: Channel,
+    ) -> Result<CodecClient<FirehoseChannel>, FirehoseError> {
+        Ok(self.codec_client_with_layer(channel, self.auth_layer()?))
+    }
+
+    /// Create a [`CodecClient`] on `channel` wrapped in `layer`.
+    ///
+    /// See [`stream_client_with_layer`](Self::stream_client_with_layer).
+    pub fn codec_client_with_layer<L>(&self, channel: Channel, layer: L) -> CodecClient<L::Service>
+    where
+        L: Layer<Channel>,
+        L::Service: GrpcService<tonic::body::Body>,
+        <L::Service as GrpcService<tonic::body::Body>>::Error: Into<StdError>,
+        <L::Service as GrpcService<tonic::body::Body>>::ResponseBody:
+            Body<Data = Bytes> + Send + 'static,
+        <<L::Service as GrpcService<tonic::body::Body>>::ResponseBody as Body>::Error:
+            Into<StdError> + Send,
+    {
+        let mut client = CodecClient::new(layer.layer(channel));
+        if let Some(encoding) = self.compression {
+            client = client.send_compressed(encoding).accept_compressed(encoding);
+        }
+        if let Some(limit) = self.max_decoding_message_size {
+            client = client.max_decoding_message_size(limit);
+        }
+        client
+    }
+
+    /// Connect and create an [`EndpointInfoClient`].
+    pub async fn info_client(&self) -> Result<EndpointInfoClient<FirehoseChannel>, FirehoseError> {
+        let channel = self.connect().await?;
+        self.info_client_with_channel(channel)
+    }
+
+    /// Create an [`EndpointInfoClient`] on a [lazy](Self::connect_lazy)
+    /// channel.
+    pub fn info_client_lazy(&self) -> Result<EndpointInfoClient<FirehoseChannel>, FirehoseError> {
+        self.info_client_with_channel(self.connect_lazy()?)
+    }
+
+    /// Create an [`EndpointInfoClient`] on an existing channel.
+    pub fn info_client_with_channel(
+        &self,
+        channel: Channel,
+    ) -> Result<EndpointInfoClient<FirehoseChannel>, FirehoseError> {
+        let mut client = EndpointInfoClient::with_interceptor(channel, self.interceptor()?);
+        if let Some(encoding) = self.compression {
+            client = client.send_compressed(encoding).accept_compressed(encoding);
+        }
+        Ok(client)
+    }
+}
+
+impl fmt::Debug for FirehoseEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("FirehoseEndpoint");
+        debug
+            .field("uri", &self.uri)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("bearer_token", &self.bearer_token)
+            .field("insecure", &self.insecure)
+            .field("compression", &self.compression)
+            .field("timeout", &self.timeout)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("max_decoding_message_size", &self.max_decoding_message_size);
+        #[cfg(feature = "proxy")]
+        debug
+            .field("proxy", &self.proxy)
+            .field("connector", &self.connector);
+        debug.field("metadata", &self.metadata).finish()
+    }
+}
+
+/// What [`FirehoseEndpoint::connect_ready`] learned about the endpoint.
+///
+/// Its [`Display`](fmt::Display) output is a one-line summary for startup
+/// logs, e.g. `https://mainnet.eth.streamingfast.io:443 (requested TLS) chain
+/// eth from #0, connected in 84ms, info in 31ms`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionDiagnostics {
+    /// The endpoint URI, including the scheme.
+    pub uri: String,
+    /// Whether TLS was requested, from the URI scheme. The transport does not
+    /// report the negotiated protocol, so this is what the channel was
+    /// configured with, not an observation of the handshake.
+    pub requested_tls: bool,
+    /// The server's answer to `Info`: the chain it serves, its first
+    /// streamable block and the features of its blocks. `None` for servers
+    /// without the `EndpointInfo` service.
+    pub info: Option<InfoResponse>,
+    /// Time taken to establish the connection, including the TLS handshake.
+    pub connect_latency: Duration,
+    /// Round-trip time of the `Info` call.
+    pub info_latency: Duration,
+}
+
+impl ConnectionDiagnostics {
+    /// The chain the server serves, if it reported one.
+    pub fn chain_name(&self) -> Option<&str> {
+        self.info
+            .as_ref()
+            .map(|info| info.chain_name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// The first block the server can stream, if it reported its info.
+    pub fn first_streamable_block(&self) -> Option<u64> {
+        self.info
+            .as_ref()
+            .map(|info| info.first_streamable_block_num)
+    }
+}
+
+impl fmt::Display for ConnectionDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({})",
+            self.uri,
+            if self.requested_tls {
+                "requested TLS"
+            } else {
+                "plaintext"
+            }
+        )?;
+        match (&self.info, self.chain_name()) {
+            (Some(info), Some(chain)) => write!(
+                f,
+                " chain {chain} from #{}",
+                info.first_streamable_block_num
+            )?,
+            (Some(info), None) => write!(f, " from #{}", info.first_streamable_block_num)?,
+            (None, _) => write!(f, " without endpoint info")?,
+        }
+        write!(
+            f,
+            ", connected in {:?}, info in {:?}",
+            self.connect_latency, self.info_latency
+        )
+    }
+}
+
+/// A bearer token shared between clients and whatever refreshes it.
+///
+/// Clones share the same token. Until a token is [set](BearerToken::set),
+/// no `authorization` header is sent.
+#[derive(Clone, Default)]
+pub struct BearerToken {
+    value: Arc<RwLock<Option<MetadataValue<Ascii>>>>,
+}
+
+impl BearerToken {
+    /// Create an empty token.
+    pub fn new() -> Self {
+        BearerToken::default()
+    }
+
+    /// Replace the token, for example with a freshly issued JWT.
+    pub fn set(&self, token: &str) -> Result<(), FirehoseError> {
+        let mut value = MetadataValue::try_from(format!("Bearer {token}")).map_err(|_| {
+            FirehoseError::Config("bearer token is not valid ASCII metadata".to_string())
+        })?;
+        value.set_sensitive(true);
+        *self.value.write().expect("bearer token lock poisoned") = Some(value);
+        Ok(())
+    }
+
+    /// Whether a token has been set.
+    pub fn is_set(&self) -> bool {
+        self.value
+            .read()
+            .expect("bearer token lock poisoned")
+            .is_some()
+    }
+
+    fn header(&self) -> Option<MetadataValue<Ascii>> {
+        self.value
+            .read()
+            .expect("bearer token lock poisoned")
+            .clone()
+    }
+}
+
+impl fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BearerToken")
+            .field(&if self.is_set() {
+                "<redacted>"
+            } else {
+                "<unset>"
+            })
+            .finish()
+    }
+}
+
+/// Interceptor attaching API key and bearer token credentials to outgoing
+/// calls, along with extra metadata and a unique `x-request-id` unless the
+/// call already has one.
+#[derive(Clone, Default)]
+pub struct AuthInterceptor {
+    api_key: Option<MetadataValue<Ascii>>,
+    bearer_token: Option<BearerToken>,
+    metadata: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+}
+
+impl AuthInterceptor {
+    /// Create an interceptor sending `api_key` as `x-api-key`, if given.
+    pub fn new(api_key: Option<&str>) -> Result<Self, FirehoseError> {
+        let api_key = api_key
+            .map(|key| {
+                let mut value = MetadataValue::try_from(key).map_err(|_| {
+                    FirehoseError::Config("API key is not valid ASCII metadata".to_string())
+                })?;
+                value.set_sensitive(true);
+                Ok::<_, FirehoseError>(value)
+            })
+            .transpose()?;
+
+        Ok(AuthInterceptor {
+            api_key,
+            bearer_token: None,
+            metadata: Vec::new(),
+        })
+    }
+
+    /// Also send the current value of `token` as `authorization: Bearer`.
+    pub fn with_bearer_token(mut self, token: BearerToken) -> Self {
+        self.bearer_token = Some(token);
+        self
+    }
+
+    /// Also send `key: value` with every call.
+    ///
+    /// # Panics
+    ///
+    /// Here rather than on each call, if `key` is not a valid ASCII metadata
+    /// key.
+    pub fn with_metadata(mut self, key: &'static str, value: MetadataValue<Ascii>) -> Self {
+        self.metadata.push((MetadataKey::from_static(key), value));
+        self
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, Status> {
+        if let Some(api_key) = &self.api_key {
+            request.metadata_mut().insert("x-api-key", api_key.clone());
+        }
+        if let Some(header) = self.bearer_token.as_ref().and_then(BearerToken::header) {
+            request.metadata_mut().insert("authorization", header);
+        }
+        for (key, value) in &self.metadata {
+            request.metadata_mut().insert(key.clone(), value.clone());
+        }
+        request_id::ensure(request.metadata_mut());
+        Ok(request)
+    }
+}
+
+impl fmt::Debug for AuthInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthInterceptor")
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("bearer_token", &self.bearer_token)
+            .field("metadata", &self.metadata)
+            .finish()
+    }
+}
+
+/// Read an environment variable, treating empty values as unset.
+fn var(name: &str) -> Result<Option<String>, FirehoseError> {
+    match env::var(name) {
+        Ok(value) if value.trim().is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value.trim().to_string())),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(_)) => Err(FirehoseError::Config(format!(
+            "{name} is not valid unicode"
+        ))),
+    }
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, FirehoseError> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(FirehoseError::Config(format!(
+            "{name} must be a boolean, got `{value}`"
+        ))),
+    }
+}
+
+fn parse_compression(value: &str) -> Result<Option<CompressionEncoding>, FirehoseError> {
+    match value.to_ascii_lowercase().as_str() {
+        "none" => Ok(None),
+        #[cfg(feature = "compression")]
+        "gzip" => Ok(Some(CompressionEncoding::Gzip)),
+        #[cfg(feature = "compression")]
+        "zstd" => Ok(Some(CompressionEncoding::Zstd)),
+        #[cfg(not(feature = "compression"))]
+        "gzip" | "zstd" => Err(FirehoseError::Config(format!(
+            "{ENV_COMPRESSION}={value} needs the `compression` feature"
+        ))),
+        _ => Err(FirehoseError::Config(format!(
+            "{ENV_COMPRESSION} must be one of gzip, zstd, none, got `{value}`"
+        ))),
+    }
+}
+
+fn parse_secs(name: &str, value: &str) -> Result<Duration, FirehoseError> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| {
+            FirehoseError::Config(format!(
+                "{name} must be a non-negative number of seconds, got `{value}`"
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Serializes the tests changing the process-wide environment.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    const VARS: [&str; 7] = [
+        ENV_ENDPOINT,
+        ENV_API_KEY,
+        ENV_INSECURE,
+        ENV_COMPRESSION,
+        ENV_TIMEOUT_SECS,
+        ENV_CONNECT_TIMEOUT_SECS,
+        ENV_PROXY,
+    ];
+
+    /// [`FirehoseEndpoint::from_env`] with only `vars` of the `FIREHOSE_*`
+    /// variables set.
+    fn from_env(vars: &[(&str, &str)]) -> Result<FirehoseEndpoint, FirehoseError> {
+        let _guard = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for name in VARS {
+            env::remove_var(name);
+        }
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        let endpoint = FirehoseEndpoint::from_env();
+        for name in VARS {
+            env::remove_var(name);
+        }
+        endpoint
+    }
+
+    fn config_error<T: fmt::Debug>(result: Result<T, FirehoseError>) -> String {
+        match result {
+            Err(FirehoseError::Config(message)) => message,
+            other => panic!("expected a config error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn reads_every_variable() {
+        let endpoint = from_env(&[
+            (ENV_ENDPOINT, " localhost:10015 "),
+            (ENV_API_KEY, "secret"),
+            (ENV_INSECURE, "true"),
+            (ENV_TIMEOUT_SECS, "30"),
+            (ENV_CONNECT_TIMEOUT_SECS, "2.5"),
+        ])
+        .unwrap();
+
+        assert_eq!(endpoint.uri, "localhost:10015");
+        assert_eq!(endpoint.api_key.as_deref(), Some("secret"));
+        assert!(endpoint.insecure);
+        assert_eq!(endpoint.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(endpoint.connect_timeout, Some(Duration::from_millis(2500)));
+    }
+
+    #[cfg(feature = "proxy")]
+    #[test]
+    fn reads_the_proxy() {
+        let endpoint = from_env(&[
+            (ENV_ENDPOINT, "localhost:10015"),
+            (ENV_PROXY, "socks5://proxy:1080"),
+        ]);
+        assert!(endpoint.unwrap().proxy.is_some());
+    }
+
+    #[cfg(not(feature = "proxy"))]
+    #[test]
+    fn proxy_needs_its_feature() {
+        assert_eq!(
+            config_error(from_env(&[
+                (ENV_ENDPOINT, "localhost:10015"),
+                (ENV_PROXY, "socks5://proxy:1080"),
+            ])),
+            "FIREHOSE_PROXY=socks5://proxy:1080 needs the `proxy` feature"
+        );
+    }
+
+    #[test]
+    fn missing_or_empty_endpoint_is_an_error() {
+        assert_eq!(config_error(from_env(&[])), "FIREHOSE_ENDPOINT is not set");
+        assert_eq!(
+            config_error(from_env(&[(ENV_ENDPOINT, "  ")])),
+            "FIREHOSE_ENDPOINT is not set"
+        );
+    }
+
+    #[test]
+    fn empty_variables_keep_the_defaults() {
+        let endpoint = from_env(&[
+            (ENV_ENDPOINT, "localhost:10015"),
+            (ENV_API_KEY, ""),
+            (ENV_INSECURE, ""),
+            (ENV_COMPRESSION, " "),
+            (ENV_TIMEOUT_SECS, ""),
+            (ENV_CONNECT_TIMEOUT_SECS, ""),
+            (ENV_PROXY, ""),
+        ])
+        .unwrap();
+
+        assert_eq!(endpoint.api_key, None);
+        assert!(!endpoint.insecure);
+        assert_eq!(endpoint.compression, None);
+        assert_eq!(endpoint.timeout, None);
+        assert_eq!(endpoint.connect_timeout, None);
+        #[cfg(feature = "proxy")]
+        assert!(endpoint.proxy.is_none());
+    }
+
+    #[test]
+    fn insecure_accepts_truthy_and_falsy_values() {
+        for value in ["1", "true", "TRUE", "yes", "On"] {
+            let endpoint = from_env(&[(ENV_ENDPOINT, "localhost:10015"), (ENV_INSECURE, value)]);
+            assert!(endpoint.unwrap().insecure, "{value}");
+        }
+        for value in ["0", "false", "No", "off"] {
+            let endpoint = from_env(&[(ENV_ENDPOINT, "localhost:10015"), (ENV_INSECURE, value)]);
+            assert!(!endpoint.unwrap().insecure, "{value}");
+        }
+        assert_eq!(
+            config_error(from_env(&[
+                (ENV_ENDPOINT, "localhost:10015"),
+                (ENV_INSECURE, "maybe"),
+            ])),
+            "FIREHOSE_INSECURE must be a boolean, got `maybe`"
+        );
+    }
+
+    #[test]
+    fn unknown_compression_is_an_error() {
+        assert_eq!(
+            config_error(from_env(&[
+                (ENV_ENDPOINT, "localhost:10015"),
+                (ENV_COMPRESSION, "brotli"),
+            ])),
+            "FIREHOSE_COMPRESSION must be one of gzip, zstd, none, got `brotli`"
+        );
+    }
+
+    #[test]
+    fn invalid_timeouts_are_errors() {
+        assert_eq!(
+            config_error(from_env(&[
+                (ENV_ENDPOINT, "localhost:10015"),
+                (ENV_TIMEOUT_SECS, "-1"),
+            ])),
+            "FIREHOSE_TIMEOUT_SECS must be a non-negative number of seconds, got `-1`"
+        );
+        assert_eq!(
+            config_error(from_env(&[
+                (ENV_ENDPOINT, "localhost:10015"),
+                (ENV_CONNECT_TIMEOUT_SECS, "ten"),
+            ])),
+            "FIREHOSE_CONNECT_TIMEOUT_SECS must be a non-negative number of seconds, got `ten`"
+        );
+    }
+
+    #[test]
+    fn parses_booleans() {
+        for (value, expected) in [
+            ("1", true),
+            ("true", true),
+            ("Yes", true),
+            ("ON", true),
+            ("0", false),
+            ("False", false),
+            ("no", false),
+            ("off", false),
+        ] {
+            assert_eq!(parse_bool("VAR", value).unwrap(), expected, "{value}");
+        }
+        for value in ["", "2", "y", "enabled"] {
+            assert_eq!(
+                config_error(parse_bool("VAR", value)),
+                format!("VAR must be a boolean, got `{value}`")
+            );
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn parses_compression() {
+        let endpoint = from_env(&[(ENV_ENDPOINT, "localhost:10015"), (ENV_COMPRESSION, "zstd")]);
+        assert_eq!(
+            endpoint.unwrap().compression,
+            Some(CompressionEncoding::Zstd)
+        );
+
+        assert_eq!(parse_compression("none").unwrap(), None);
+        assert_eq!(
+            parse_compression("GZIP").unwrap(),
+            Some(CompressionEncoding::Gzip)
+        );
+        assert_eq!(
+            parse_compression("zstd").unwrap(),
+            Some(CompressionEncoding::Zstd)
+        );
+        for value in ["", "deflate", "gzip,zstd"] {
+            assert!(parse_compression(value).is_err(), "{value}");
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn compression_needs_its_feature() {
+        assert_eq!(parse_compression("none").unwrap(), None);
+        for value in ["gzip", "zstd"] {
+            assert_eq!(
+                config_error(parse_compression(value)),
+                format!("FIREHOSE_COMPRESSION={value} needs the `compression` feature")
+            );
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    #[test]
+    fn https_needs_the_tls_feature() {
+        assert_eq!(
+            config_error(FirehoseEndpoint::new("https://localhost:10015").endpoint()),
+            "`https://localhost:10015` needs the `tls` feature, or an insecure endpoint"
+        );
+    }
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse_secs("VAR", "0").unwrap(), Duration::ZERO);
+        assert_eq!(
+            parse_secs("VAR", "1.5").unwrap(),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(parse_secs("VAR", "60").unwrap(), Duration::from_secs(60));
+        for value in ["-0.5", "-1", "ten", "", "NaN", "inf", "1e400"] {
+            assert_eq!(
+                config_error(parse_secs("VAR", value)),
+                format!("VAR must be a non-negative number of seconds, got `{value}`")
+            );
+        }
+    }
+
+    fn diagnostics() -> ConnectionDiagnostics {
+        ConnectionDiagnostics {
+            uri: "https://mainnet.eth.streamingfast.io:443".to_string(),
+            requested_tls: true,
+            info: Some(InfoResponse {
+                chain_name: "eth".to_string(),
+                first_streamable_block_num: 0,
+                ..Default::default()
+            }),
+            connect_latency: Duration::from_millis(84),
+            info_latency: Duration::from_millis(31),
+        }
+    }
+
+    #[test]
+    fn diagnostics_display_the_requested_scheme_and_info() {
+        assert_eq!(
+            diagnostics().to_string(),
+            "https://mainnet.eth.streamingfast.io:443 (requested TLS) chain eth from #0, \
+             connected in 84ms, info in 31ms"
+        );
+
+        let plaintext = ConnectionDiagnostics {
+            uri: "http://localhost:10015".to_string(),
+            requested_tls: false,
+            info: Some(InfoResponse {
+                first_streamable_block_num: 12,
+                ..Default::default()
+            }),
+            ..diagnostics()
+        };
+        assert_eq!(
+            plaintext.to_string(),
+            "http://localhost:10015 (plaintext) from #12, connected in 84ms, info in 31ms"
+        );
+
+        let without_info = ConnectionDiagnostics {
+            info: None,
+            ..diagnostics()
+        };
+        assert_eq!(
+            without_info.to_string(),
+            "https://mainnet.eth.streamingfast.io:443 (requested TLS) without endpoint info, \
+             connected in 84ms, info in 31ms"
+        );
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{self, Display};
+#[cfg(feature = "runtime")]
+use std::time::Duration;
+
+use tonic::Code;
+#[cfg(feature = "runtime")]
+use tonic_types::{ErrorDetails, StatusExt};
+
+/// Errors returned when configuring, connecting to, or calling a Firehose
+/// endpoint.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FirehoseError {
+    /// The client configuration is invalid, for example a malformed
+    /// environment variable or endpoint URI.
+    Config(String),
+    /// The connection to the endpoint could not be established or was lost.
+    Transport(tonic::transport::Error),
+    /// The endpoint answered with a gRPC error status.
+    Status(tonic::Status),
+    /// Reading or writing local state, such as a cursor file, failed.
+    Io(std::io::Error),
+    /// A block could not be converted with
+    /// [`FromResponse`](crate::FromResponse).
+    Decode(String),
+    /// The function passed to
+    /// [`ResilientStream::process_ordered`](crate::ResilientStream::process_ordered)
+    /// failed or panicked.
+    Processing(String),
+    /// A reorg undid a block that a
+    /// [`ConfirmedStream`](crate::ConfirmedStream) had already emitted.
+    DeepReorg {
+        /// The undone block.
+        block: u64,
+        /// Confirmations the block had been emitted with.
+        depth: u64,
+    },
+    /// A block did not match the hash of its height in the
+    /// [`Anchors`](crate::Anchors), so the endpoint serves a wrong fork or
+    /// corrupted data.
+    AnchorMismatch {
+        /// The anchored block.
+        block: u64,
+        /// Hash of the checkpoint.
+        expected: String,
+        /// Hash of the block received.
+        actual: String,
+    },
+    /// A [`Sink`](crate::sink::Sink) failed to write or flush blocks.
+    #[cfg(feature = "sink")]
+    Sink(crate::sink::SinkError),
+    /// Exchanging an API key for a token failed.
+    #[cfg(feature = "streamingfast-auth")]
+    Auth(String),
+}
+
+/// How a caller should react to a [`FirehoseError`], as returned by
+/// [`FirehoseError::classification`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// A transient failure; the same call may succeed if retried later.
+    Retryable,
+    /// Retrying will not help without changing the request or configuration.
+    Fatal,
+    /// The credentials were rejected or have expired and must be refreshed.
+    AuthExpired,
+    /// The requested block is outside the range the endpoint serves, for
+    /// example beyond the chain head or before the first streamable block.
+    OutOfRange,
+}
+
+impl ErrorClass {
+    /// Whether retrying the call as is may succeed.
+    pub fn is_retryable(self) -> bool {
+        self == ErrorClass::Retryable
+    }
+}
+
+impl FirehoseError {
+    /// Classify the error so retry policies can react to it.
+    ///
+    /// Connection failures are retryable. gRPC statuses are classified by
+    /// code, refined by the messages Firehose servers return for missing
+    /// blocks, invalid cursors, exhausted quotas and expired tokens. Local
+    /// errors (configuration, I/O, decoding, processing, sinks) are fatal.
+    pub fn classification(&self) -> ErrorClass {
+        match self {
+            FirehoseError::Transport(_) => ErrorClass::Retryable,
+            FirehoseError::Status(status) => classify_status(status),
+            _ => ErrorClass::Fatal,
+        }
+    }
+
+    /// The `google.rpc.Status` details attached to a gRPC error, such as
+    /// retry info, quota failures and bad-request field violations.
+    ///
+    /// Returns `None` for errors that are not gRPC statuses, or whose details
+    /// are missing or cannot be decoded.
+    #[cfg(feature = "runtime")]
+    pub fn details(&self) -> Option<ErrorDetails> {
+        match self {
+            FirehoseError::Status(status) if !status.details().is_empty() => {
+                status.check_error_details().ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// The `x-request-id` of the failed call, to reference it with the
+    /// provider.
+    ///
+    /// Returns `None` for errors that are not gRPC statuses, or for calls
+    /// that carried no ID.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            FirehoseError::Status(status) => crate::request_id(status.metadata()),
+            _ => None,
+        }
+    }
+
+    /// How long the server asked the client to wait before retrying, from the
+    /// `RetryInfo` status detail.
+    #[cfg(feature = "runtime")]
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.details()?.retry_info()?.retry_delay
+    }
+
+    /// Whether the server rejected the request's cursor, for example because
+    /// the history it points into was pruned or it comes from another
+    /// provider.
+    pub fn is_cursor_invalid(&self) -> bool {
+        match self {
+            FirehoseError::Status(status) => is_cursor_invalid(status),
+            _ => false,
+        }
+    }
+}
+
+fn is_cursor_invalid(status: &tonic::Status) -> bool {
+    let message = status.message().to_ascii_lowercase();
+    ["cursor invalid", "invalid cursor"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+fn classify_status(status: &tonic::Status) -> ErrorClass {
+    let message = status.message().to_ascii_lowercase();
+    let mentions = |patterns: &[&str]| patterns.iter().any(|p| message.contains(p));
+
+    if is_cursor_invalid(status) {
+        return ErrorClass::Fatal;
+    }
+    if mentions(&[
+        "block not found",
+        "not yet available",
+        "below the first streamable",
+    ]) {
+        return ErrorClass::OutOfRange;
+    }
+    if mentions(&["token is expired", "token expired", "jwt expired"]) {
+        return ErrorClass::AuthExpired;
+    }
+
+    match status.code() {
+        Code::Unauthenticated => ErrorClass::AuthExpired,
+        Code::OutOfRange => ErrorClass::OutOfRange,
+        Code::ResourceExhausted if mentions(&["quota"]) => ErrorClass::Fatal,
+        Code::Unavailable
+        | Code::Unknown
+        | Code::Internal
+        | Code::Aborted
+        | Code::Cancelled
+        | Code::DeadlineExceeded
+        | Code::ResourceExhausted => ErrorClass::Retryable,
+        _ => ErrorClass::Fatal,
+    }
+}
+
+impl Display for FirehoseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FirehoseError::Config(message) => write!(f, "invalid configuration: {message}"),
+            FirehoseError::Transport(e) => write!(f, "transport error: {e}"),
+            FirehoseError::Status(status) => {
+                write!(
+                    f,
+                    "firehose returned {:?}: {}",
+                    status.code(),
+                    status.message()
+                )?;
+                match self.request_id() {
+                    Some(id) => write!(f, " (request {id})"),
+                    None => Ok(()),
+                }
+            }
+            FirehoseError::Io(e) => write!(f, "I/O error: {e}"),
+            FirehoseError::Decode(message) => write!(f, "failed to decode block: {message}"),
+            FirehoseError::Processing(message) => write!(f, "failed to process block: {message}"),
+            FirehoseError::DeepReorg { block, depth } => write!(
+                f,
+                "reorg undid block {block}, emitted after {depth} confirmations"
+            ),
+            FirehoseError::AnchorMismatch {
+                block,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "block {block} has hash {actual}, but its anchor is {expected}"
+            ),
+            #[cfg(feature = "sink")]
+            FirehoseError::Sink(e) => write!(f, "sink error: {e}"),
+            #[cfg(feature = "streamingfast-auth")]
+            FirehoseError::Auth(message) => write!(f, "authentication failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for FirehoseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FirehoseError::Config(_) => None,
+            FirehoseError::Transport(e) => Some(e),
+            FirehoseError::Status(status) => Some(status),
+            FirehoseError::Io(e) => Some(e),
+            FirehoseError::Decode(_)
+            | FirehoseError::Processing(_)
+            | FirehoseError::DeepReorg { .. }
+            | FirehoseError::AnchorMismatch { .. } => None,
+            #[cfg(feature = "sink")]
+            FirehoseError::Sink(e) => Some(e.as_ref()),
+            #[cfg(feature = "streamingfast-auth")]
+            FirehoseError::Auth(_) => None,
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for FirehoseError {
+    fn from(e: tonic::transport::Error) -> Self {
+        FirehoseError::Transport(e)
+    }
+}
+
+impl From<tonic::Status> for FirehoseError {
+    fn from(status: tonic::Status) -> Self {
+        FirehoseError::Status(status)
+    }
+}
+
+impl From<std::io::Error> for FirehoseError {
+    fn from(e: std::io::Error) -> Self {
+        FirehoseError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Status;
+
+    use super::*;
+
+    #[test]
+    fn classifies_statuses_by_message_then_code() {
+        let cases = [
+            // Rejected cursors, whatever the code and the rest of the message.
+            (
+                Code::InvalidArgument,
+                "cursor invalid: block not found",
+                ErrorClass::Fatal,
+            ),
+            (Code::Unavailable, "Invalid cursor", ErrorClass::Fatal),
+            // Blocks outside the served range.
+            (Code::NotFound, "block not found", ErrorClass::OutOfRange),
+            (
+                Code::Unavailable,
+                "block 42 is not yet available",
+                ErrorClass::OutOfRange,
+            ),
+            (
+                Code::InvalidArgument,
+                "start block is below the first streamable block",
+                ErrorClass::OutOfRange,
+            ),
+            (
+                Code::OutOfRange,
+                "past the chain head",
+                ErrorClass::OutOfRange,
+            ),
+            // Expired or rejected credentials.
+            (
+                Code::PermissionDenied,
+                "token is expired",
+                ErrorClass::AuthExpired,
+            ),
+            (Code::Unknown, "JWT expired", ErrorClass::AuthExpired),
+            (
+                Code::Unauthenticated,
+                "invalid API key",
+                ErrorClass::AuthExpired,
+            ),
+            // Exhausted quotas last until the next billing period, rate
+            // limits pass.
+            (
+                Code::ResourceExhausted,
+                "monthly quota exceeded",
+                ErrorClass::Fatal,
+            ),
+            (
+                Code::ResourceExhausted,
+                "rate limit exceeded",
+                ErrorClass::Retryable,
+            ),
+            // Otherwise by code.
+            (Code::Unavailable, "connection reset", ErrorClass::Retryable),
+            (Code::Unknown, "", ErrorClass::Retryable),
+            (Code::Internal, "", ErrorClass::Retryable),
+            (Code::Aborted, "", ErrorClass::Retryable),
+            (Code::Cancelled, "", ErrorClass::Retryable),
+            (Code::DeadlineExceeded, "", ErrorClass::Retryable),
+            (
+                Code::InvalidArgument,
+                "unknown transform",
+                ErrorClass::Fatal,
+            ),
+            (Code::PermissionDenied, "", ErrorClass::Fatal),
+            (Code::NotFound, "", ErrorClass::Fatal),
+            (Code::Unimplemented, "", ErrorClass::Fatal),
+            (Code::FailedPrecondition, "", ErrorClass::Fatal),
+        ];
+
+        for (code, message, class) in cases {
+            let error = FirehoseError::from(Status::new(code, message));
+            assert_eq!(error.classification(), class, "{code:?}: {message:?}");
+        }
+    }
+
+    #[test]
+    fn classifies_local_errors_as_fatal() {
+        let errors = [
+            FirehoseError::Config("missing endpoint".to_string()),
+            FirehoseError::Io(std::io::Error::other("disk full")),
+            FirehoseError::Decode("truncated block".to_string()),
+        ];
+
+        for error in errors {
+            assert_eq!(error.classification(), ErrorClass::Fatal, "{error}");
+        }
+    }
+
+    #[test]
+    fn detects_rejected_cursors() {
+        let rejected = FirehoseError::from(Status::invalid_argument("Cursor invalid"));
+        assert!(rejected.is_cursor_invalid());
+
+        let other = FirehoseError::from(Status::invalid_argument("invalid start block"));
+        assert!(!other.is_cursor_invalid());
+        assert!(!FirehoseError::Config("cursor invalid".to_string()).is_cursor_invalid());
+    }
+}
@@ -0,0 +1,1110 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transactions and receipts roots of Ethereum blocks, recomputed from their
+//! decoded contents.
+
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+};
+
+use prost_reflect::{DynamicMessage, ReflectMessage, Value};
+
+use crate::{ethereum_transform_v1::abi::keccak256, hex_bytes};
+
+/// `TransactionTraceStatus.SUCCEEDED`.
+const STATUS_SUCCEEDED: i32 = 1;
+
+/// A trie whose root an Ethereum block header commits to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrieRoot {
+    /// `transactions_root`, over the signed transactions.
+    Transactions,
+    /// `receipt_root`, over the transaction receipts.
+    Receipts,
+}
+
+impl Display for TrieRoot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrieRoot::Transactions => write!(f, "transactions root"),
+            TrieRoot::Receipts => write!(f, "receipts root"),
+        }
+    }
+}
+
+/// Why [`verify_ethereum_roots`] rejected a block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RootError {
+    /// The block lacks a field needed to re-encode it, or the field has an
+    /// unexpected type, as with descriptors of another chain.
+    Field(String),
+    /// A transaction has a type that cannot be re-encoded, such as the
+    /// deposit transactions of rollups.
+    UnsupportedType {
+        /// Position of the transaction in the block.
+        index: usize,
+        /// The transaction type.
+        tx_type: i32,
+    },
+    /// A root recomputed from the block's contents differs from its header.
+    Mismatch {
+        /// The root that differs.
+        root: TrieRoot,
+        /// The root in the header, as `0x`-prefixed hex.
+        header: String,
+        /// The root recomputed from the contents, as `0x`-prefixed hex.
+        computed: String,
+    },
+}
+
+impl Display for RootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootError::Field(field) => write!(f, "block has no usable field `{field}`"),
+            RootError::UnsupportedType { index, tx_type } => write!(
+                f,
+                "transaction {index} has type {tx_type}, which cannot be re-encoded"
+            ),
+            RootError::Mismatch {
+                root,
+                header,
+                computed,
+            } => write!(
+                f,
+                "{root} of the contents is {computed}, but the header has {header}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RootError {}
+
+/// Recompute the transactions and receipts roots of an Ethereum block,
+/// decoded as `sf.ethereum.type.v2.Block`, and compare them with its header.
+///
+/// Matching roots prove that the transactions and receipts, logs included,
+/// are exactly those the header commits to: none were altered, dropped or
+/// added by the provider. `chain_id` is needed to re-encode typed
+/// transactions, since Firehose does not record it per transaction.
+///
+/// Legacy, access list, dynamic fee, blob and set code transactions are
+/// supported; other types fail with [`RootError::UnsupportedType`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{verify_ethereum_roots, DynamicDecoder, Response};
+///
+/// # fn example(response: Response) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decoder = DynamicDecoder::new()?;
+/// decoder.add_file_descriptor_set(std::fs::read("ethereum.binpb")?.as_slice())?;
+///
+/// if let Some(block) = decoder.decode_response_block(&response)? {
+///     verify_ethereum_roots(&block, 1)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn verify_ethereum_roots(block: &DynamicMessage, chain_id: u64) -> Result<(), RootError> {
+    let header = message(block, "header")?;
+    let traces = messages(block, "transaction_traces")?;
+
+    let mut transactions = Vec::with_capacity(traces.len());
+    let mut receipts = Vec::with_capacity(traces.len());
+    for (index, trace) in traces.iter().enumerate() {
+        let tx_type = enum_number(trace, "type")?;
+        if !(0..=4).contains(&tx_type) {
+            return Err(RootError::UnsupportedType { index, tx_type });
+        }
+        transactions.push(encode_transaction(trace, tx_type as u8, chain_id)?);
+        receipts.push(encode_receipt(trace, tx_type as u8)?);
+    }
+
+    check(
+        &header,
+        "transactions_root",
+        TrieRoot::Transactions,
+        transactions,
+    )?;
+    check(&header, "receipt_root", TrieRoot::Receipts, receipts)
+}
+
+fn check(
+    header: &DynamicMessage,
+    field: &str,
+    root: TrieRoot,
+    values: Vec<Vec<u8>>,
+) -> Result<(), RootError> {
+    let expected = bytes(header, field)?;
+    let computed = trie_root(values);
+    if expected.as_slice() == computed {
+        return Ok(());
+    }
+    Err(RootError::Mismatch {
+        root,
+        header: hex_bytes::encode(&expected),
+        computed: hex_bytes::encode(&computed),
+    })
+}
+
+/// The transaction as signed, in its typed envelope.
+fn encode_transaction(
+    trace: &DynamicMessage,
+    tx_type: u8,
+    chain_id: u64,
+) -> Result<Vec<u8>, RootError> {
+    let mut fields = Vec::new();
+    if tx_type != 0 {
+        rlp_uint(&mut fields, &chain_id.to_be_bytes());
+    }
+    rlp_uint(&mut fields, &integer(trace, "nonce")?);
+    match tx_type {
+        0 | 1 => rlp_uint(&mut fields, &integer(trace, "gas_price")?),
+        _ => {
+            rlp_uint(&mut fields, &integer(trace, "max_priority_fee_per_gas")?);
+            rlp_uint(&mut fields, &integer(trace, "max_fee_per_gas")?);
+        }
+    }
+    rlp_uint(&mut fields, &integer(trace, "gas_limit")?);
+    rlp_bytes(&mut fields, &bytes(trace, "to")?);
+    rlp_uint(&mut fields, &integer(trace, "value")?);
+    rlp_bytes(&mut fields, &bytes(trace, "input")?);
+    if tx_type != 0 {
+        encode_access_list(&mut fields, trace)?;
+    }
+    if tx_type == 3 {
+        rlp_uint(&mut fields, &integer(trace, "blob_gas_fee_cap")?);
+        let mut hashes = Vec::new();
+        for hash in byte_list(trace, "blob_hashes")? {
+            rlp_bytes(&mut hashes, &hash);
+        }
+        rlp_list(&mut fields, &hashes);
+    }
+    if tx_type == 4 {
+        let mut authorizations = Vec::new();
+        for authorization in messages(trace, "set_code_authorizations")? {
+            let mut tuple = Vec::new();
+            rlp_uint(&mut tuple, &integer(&authorization, "chain_id")?);
+            rlp_bytes(&mut tuple, &bytes(&authorization, "address")?);
+            rlp_uint(&mut tuple, &integer(&authorization, "nonce")?);
+            rlp_uint(&mut tuple, &integer(&authorization, "v")?);
+            rlp_uint(&mut tuple, &integer(&authorization, "r")?);
+            rlp_uint(&mut tuple, &integer(&authorization, "s")?);
+            rlp_list(&mut authorizations, &tuple);
+        }
+        rlp_list(&mut fields, &authorizations);
+    }
+    rlp_uint(&mut fields, &integer(trace, "v")?);
+    rlp_uint(&mut fields, &integer(trace, "r")?);
+    rlp_uint(&mut fields, &integer(trace, "s")?);
+
+    Ok(envelope(tx_type, &fields))
+}
+
+fn encode_access_list(out: &mut Vec<u8>, trace: &DynamicMessage) -> Result<(), RootError> {
+    let mut list = Vec::new();
+    for tuple in messages(trace, "access_list")? {
+        let mut keys = Vec::new();
+        for key in byte_list(&tuple, "storage_keys")? {
+            rlp_bytes(&mut keys, &key);
+        }
+        let mut entry = Vec::new();
+        rlp_bytes(&mut entry, &bytes(&tuple, "address")?);
+        rlp_list(&mut entry, &keys);
+        rlp_list(&mut list, &entry);
+    }
+    rlp_list(out, &list);
+    Ok(())
+}
+
+/// The consensus receipt: post-state or status, cumulative gas, bloom and
+/// logs, in the transaction's typed envelope.
+fn encode_receipt(trace: &DynamicMessage, tx_type: u8) -> Result<Vec<u8>, RootError> {
+    let receipt = message(trace, "receipt")?;
+
+    let mut fields = Vec::new();
+    // Receipts before Byzantium carry the post-transaction state root
+    // instead of a status.
+    let state_root = bytes(&receipt, "state_root")?;
+    if state_root.is_empty() {
+        let succeeded = enum_number(trace, "status")? == STATUS_SUCCEEDED;
+        rlp_uint(&mut fields, &[u8::from(succeeded)]);
+    } else {
+        rlp_bytes(&mut fields, &state_root);
+    }
+    rlp_uint(&mut fields, &integer(&receipt, "cumulative_gas_used")?);
+    rlp_bytes(&mut fields, &bytes(&receipt, "logs_bloom")?);
+
+    let mut logs = Vec::new();
+    for log in messages(&receipt, "logs")? {
+        let mut topics = Vec::new();
+        for topic in byte_list(&log, "topics")? {
+            rlp_bytes(&mut topics, &topic);
+        }
+        let mut entry = Vec::new();
+        rlp_bytes(&mut entry, &bytes(&log, "address")?);
+        rlp_list(&mut entry, &topics);
+        rlp_bytes(&mut entry, &bytes(&log, "data")?);
+        rlp_list(&mut logs, &entry);
+    }
+    rlp_list(&mut fields, &logs);
+
+    Ok(envelope(tx_type, &fields))
+}
+
+/// `fields` as an RLP list, prefixed with the type of typed transactions.
+fn envelope(tx_type: u8, fields: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(fields.len() + 10);
+    if tx_type != 0 {
+        encoded.push(tx_type);
+    }
+    rlp_list(&mut encoded, fields);
+    encoded
+}
+
+fn field<'a>(message: &'a DynamicMessage, name: &str) -> Result<Cow<'a, Value>, RootError> {
+    message
+        .get_field_by_name(name)
+        .ok_or_else(|| missing(message, name))
+}
+
+fn missing(message: &DynamicMessage, name: &str) -> RootError {
+    RootError::Field(format!("{}.{name}", message.descriptor().full_name()))
+}
+
+fn message(message: &DynamicMessage, name: &str) -> Result<DynamicMessage, RootError> {
+    match field(message, name)?.as_ref() {
+        Value::Message(inner) => Ok(inner.clone()),
+        _ => Err(missing(message, name)),
+    }
+}
+
+fn messages(message: &DynamicMessage, name: &str) -> Result<Vec<DynamicMessage>, RootError> {
+    match field(message, name)?.as_ref() {
+        Value::List(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::Message(inner) => Ok(inner.clone()),
+                _ => Err(missing(message, name)),
+            })
+            .collect(),
+        _ => Err(missing(message, name)),
+    }
+}
+
+fn bytes(message: &DynamicMessage, name: &str) -> Result<Vec<u8>, RootError> {
+    match field(message, name)?.as_ref() {
+        Value::Bytes(bytes) => Ok(bytes.to_vec()),
+        _ => Err(missing(message, name)),
+    }
+}
+
+fn byte_list(message: &DynamicMessage, name: &str) -> Result<Vec<Vec<u8>>, RootError> {
+    match field(message, name)?.as_ref() {
+        Value::List(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::Bytes(bytes) => Ok(bytes.to_vec()),
+                _ => Err(missing(message, name)),
+            })
+            .collect(),
+        _ => Err(missing(message, name)),
+    }
+}
+
+fn enum_number(message: &DynamicMessage, name: &str) -> Result<i32, RootError> {
+    match field(message, name)?.as_ref() {
+        Value::EnumNumber(number) => Ok(*number),
+        _ => Err(missing(message, name)),
+    }
+}
+
+/// An unsigned integer as big-endian bytes, whether stored as a number, as
+/// bytes, or as a `BigInt` message.
+fn integer(message: &DynamicMessage, name: &str) -> Result<Vec<u8>, RootError> {
+    match field(message, name)?.as_ref() {
+        Value::U64(number) => Ok(number.to_be_bytes().to_vec()),
+        Value::U32(number) => Ok(number.to_be_bytes().to_vec()),
+        Value::Bytes(bytes) => Ok(bytes.to_vec()),
+        Value::Message(big_int) => bytes(big_int, "bytes"),
+        _ => Err(missing(message, name)),
+    }
+}
+
+fn rlp_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    if let [byte @ 0..=0x7f] = bytes {
+        out.push(*byte);
+    } else {
+        rlp_header(out, 0x80, bytes.len());
+        out.extend_from_slice(bytes);
+    }
+}
+
+/// A big-endian unsigned integer, without its leading zeros.
+fn rlp_uint(out: &mut Vec<u8>, bytes: &[u8]) {
+    let start = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len());
+    rlp_bytes(out, &bytes[start..]);
+}
+
+fn rlp_list(out: &mut Vec<u8>, payload: &[u8]) {
+    rlp_header(out, 0xc0, payload.len());
+    out.extend_from_slice(payload);
+}
+
+fn rlp_header(out: &mut Vec<u8>, offset: u8, len: usize) {
+    if len < 56 {
+        out.push(offset + len as u8);
+    } else {
+        let len = (len as u64).to_be_bytes();
+        let start = len.iter().position(|byte| *byte != 0).unwrap_or(7);
+        out.push(offset + 55 + (len.len() - start) as u8);
+        out.extend_from_slice(&len[start..]);
+    }
+}
+
+/// Root of the Merkle Patricia trie mapping the RLP-encoded index of each of
+/// `values` to it.
+fn trie_root(values: Vec<Vec<u8>>) -> [u8; 32] {
+    let mut items: Vec<(Vec<u8>, Vec<u8>)> = values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let mut key = Vec::new();
+            rlp_uint(&mut key, &(index as u64).to_be_bytes());
+            (nibbles(&key), value)
+        })
+        .collect();
+    items.sort();
+    keccak256(&trie_node(&items, 0))
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// The RLP encoding of the node holding `items`, sorted by key, whose keys
+/// share their first `depth` nibbles.
+fn trie_node(items: &[(Vec<u8>, Vec<u8>)], depth: usize) -> Vec<u8> {
+    let mut payload = Vec::new();
+    match items {
+        [] => return vec![0x80],
+        [(key, value)] => {
+            rlp_bytes(&mut payload, &compact(&key[depth..], true));
+            rlp_bytes(&mut payload, value);
+        }
+        [(first, _), .., (last, _)] => {
+            // Sorted keys share the prefix of the first and last ones.
+            let shared = first[depth..]
+                .iter()
+                .zip(&last[depth..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if shared > 0 {
+                rlp_bytes(&mut payload, &compact(&first[depth..depth + shared], false));
+                child(&mut payload, trie_node(items, depth + shared));
+            } else {
+                let (value, mut rest) = match items {
+                    [(key, value), rest @ ..] if key.len() == depth => (value.as_slice(), rest),
+                    _ => (&[][..], items),
+                };
+                for nibble in 0..16 {
+                    let len = rest
+                        .iter()
+                        .take_while(|(key, _)| key[depth] == nibble)
+                        .count();
+                    let (group, tail) = rest.split_at(len);
+                    if group.is_empty() {
+                        payload.push(0x80);
+                    } else {
+                        child(&mut payload, trie_node(group, depth + 1));
+                    }
+                    rest = tail;
+                }
+                rlp_bytes(&mut payload, value);
+            }
+        }
+    }
+
+    let mut node = Vec::new();
+    rlp_list(&mut node, &payload);
+    node
+}
+
+/// A reference to `node` from its parent: inline when shorter than a hash.
+fn child(out: &mut Vec<u8>, node: Vec<u8>) {
+    if node.len() < 32 {
+        out.extend_from_slice(&node);
+    } else {
+        rlp_bytes(out, &keccak256(&node));
+    }
+}
+
+/// The hex-prefix encoding of a key path, flagging leaves.
+fn compact(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut encoded = Vec::with_capacity(path.len() / 2 + 1);
+    let rest = if path.len() % 2 == 1 {
+        encoded.push(((flag + 1) << 4) | path[0]);
+        &path[1..]
+    } else {
+        encoded.push(flag << 4);
+        path
+    };
+    encoded.extend(rest.chunks_exact(2).map(|pair| (pair[0] << 4) | pair[1]));
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::{bytes::Bytes, Message};
+    use prost_reflect::{DescriptorPool, DynamicMessage, Value};
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, FileDescriptorSet,
+    };
+
+    use super::*;
+
+    const PACKAGE: &str = "sf.ethereum.type.v2";
+
+    /// The subset of `sf/ethereum/type/v2/type.proto` the roots are computed
+    /// from, with the upstream field names and types.
+    fn pool() -> DescriptorPool {
+        let big_int = |name: &str| field_descriptor(name, Type::Message, Some("BigInt"), false);
+        let bytes = |name: &str| field_descriptor(name, Type::Bytes, None, false);
+        let uint64 = |name: &str| field_descriptor(name, Type::Uint64, None, false);
+
+        let file = FileDescriptorProto {
+            name: Some("sf/ethereum/type/v2/type.proto".to_string()),
+            package: Some(PACKAGE.to_string()),
+            syntax: Some("proto3".to_string()),
+            message_type: vec![
+                descriptor("BigInt", vec![bytes("bytes")]),
+                descriptor(
+                    "Block",
+                    vec![
+                        field_descriptor("header", Type::Message, Some("BlockHeader"), false),
+                        field_descriptor(
+                            "transaction_traces",
+                            Type::Message,
+                            Some("TransactionTrace"),
+                            true,
+                        ),
+                    ],
+                ),
+                descriptor(
+                    "BlockHeader",
+                    vec![bytes("transactions_root"), bytes("receipt_root")],
+                ),
+                descriptor(
+                    "TransactionTrace",
+                    vec![
+                        bytes("to"),
+                        uint64("nonce"),
+                        big_int("gas_price"),
+                        uint64("gas_limit"),
+                        big_int("value"),
+                        bytes("input"),
+                        bytes("v"),
+                        bytes("r"),
+                        bytes("s"),
+                        field_descriptor("type", Type::Enum, Some("TransactionType"), false),
+                        field_descriptor("access_list", Type::Message, Some("AccessTuple"), true),
+                        big_int("max_fee_per_gas"),
+                        big_int("max_priority_fee_per_gas"),
+                        field_descriptor(
+                            "status",
+                            Type::Enum,
+                            Some("TransactionTraceStatus"),
+                            false,
+                        ),
+                        field_descriptor(
+                            "receipt",
+                            Type::Message,
+                            Some("TransactionReceipt"),
+                            false,
+                        ),
+                        big_int("blob_gas_fee_cap"),
+                        field_descriptor("blob_hashes", Type::Bytes, None, true),
+                    ],
+                ),
+                descriptor(
+                    "AccessTuple",
+                    vec![
+                        bytes("address"),
+                        field_descriptor("storage_keys", Type::Bytes, None, true),
+                    ],
+                ),
+                descriptor(
+                    "TransactionReceipt",
+                    vec![
+                        bytes("state_root"),
+                        uint64("cumulative_gas_used"),
+                        bytes("logs_bloom"),
+                        field_descriptor("logs", Type::Message, Some("Log"), true),
+                    ],
+                ),
+                descriptor(
+                    "Log",
+                    vec![
+                        bytes("address"),
+                        field_descriptor("topics", Type::Bytes, None, true),
+                        bytes("data"),
+                    ],
+                ),
+            ],
+            enum_type: vec![
+                enumeration(
+                    "TransactionType",
+                    &[
+                        "TRX_TYPE_LEGACY",
+                        "TRX_TYPE_ACCESS_LIST",
+                        "TRX_TYPE_DYNAMIC_FEE",
+                        "TRX_TYPE_BLOB",
+                    ],
+                ),
+                enumeration(
+                    "TransactionTraceStatus",
+                    &["UNKNOWN", "SUCCEEDED", "FAILED", "REVERTED"],
+                ),
+            ],
+            ..Default::default()
+        };
+
+        let set = FileDescriptorSet { file: vec![file] };
+        DescriptorPool::decode(set.encode_to_vec().as_slice()).unwrap()
+    }
+
+    fn descriptor(name: &str, mut fields: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        for (number, field) in fields.iter_mut().enumerate() {
+            field.number = Some(number as i32 + 1);
+        }
+        DescriptorProto {
+            name: Some(name.to_string()),
+            field: fields,
+            ..Default::default()
+        }
+    }
+
+    fn field_descriptor(
+        name: &str,
+        field_type: Type,
+        type_name: Option<&str>,
+        repeated: bool,
+    ) -> FieldDescriptorProto {
+        let label = if repeated {
+            Label::Repeated
+        } else {
+            Label::Optional
+        };
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            label: Some(label as i32),
+            r#type: Some(field_type as i32),
+            type_name: type_name.map(|name| format!(".{PACKAGE}.{name}")),
+            ..Default::default()
+        }
+    }
+
+    fn enumeration(name: &str, values: &[&str]) -> EnumDescriptorProto {
+        EnumDescriptorProto {
+            name: Some(name.to_string()),
+            value: values
+                .iter()
+                .enumerate()
+                .map(|(number, value)| EnumValueDescriptorProto {
+                    name: Some(value.to_string()),
+                    number: Some(number as i32),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// A message of type `name` with `fields` set.
+    fn build(pool: &DescriptorPool, name: &str, fields: Vec<(&str, Value)>) -> DynamicMessage {
+        let descriptor = pool
+            .get_message_by_name(&format!("{PACKAGE}.{name}"))
+            .unwrap();
+        let mut message = DynamicMessage::new(descriptor);
+        for (field, value) in fields {
+            message.set_field_by_name(field, value);
+        }
+        message
+    }
+
+    fn bytes_value(bytes: impl Into<Vec<u8>>) -> Value {
+        Value::Bytes(Bytes::from(bytes.into()))
+    }
+
+    fn hex_value(hex: &str) -> Value {
+        bytes_value(hex_bytes::decode(hex).unwrap())
+    }
+
+    fn big(pool: &DescriptorPool, value: u64) -> Value {
+        let bytes = value.to_be_bytes();
+        let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(8);
+        Value::Message(build(
+            pool,
+            "BigInt",
+            vec![("bytes", bytes_value(&bytes[start..]))],
+        ))
+    }
+
+    fn list(values: Vec<Value>) -> Value {
+        Value::List(values)
+    }
+
+    fn receipt(
+        pool: &DescriptorPool,
+        cumulative_gas_used: u64,
+        logs_bloom: Vec<u8>,
+        logs: Vec<Value>,
+    ) -> Value {
+        Value::Message(build(
+            pool,
+            "TransactionReceipt",
+            vec![
+                ("cumulative_gas_used", Value::U64(cumulative_gas_used)),
+                ("logs_bloom", bytes_value(logs_bloom)),
+                ("logs", list(logs)),
+            ],
+        ))
+    }
+
+    fn block(
+        pool: &DescriptorPool,
+        traces: Vec<DynamicMessage>,
+        roots: (&str, &str),
+    ) -> DynamicMessage {
+        let header = build(
+            pool,
+            "BlockHeader",
+            vec![
+                ("transactions_root", hex_value(roots.0)),
+                ("receipt_root", hex_value(roots.1)),
+            ],
+        );
+        build(
+            pool,
+            "Block",
+            vec![
+                ("header", Value::Message(header)),
+                (
+                    "transaction_traces",
+                    list(traces.into_iter().map(Value::Message).collect()),
+                ),
+            ],
+        )
+    }
+
+    /// The signed transaction of the EIP-155 example, with chain ID 1.
+    fn eip155_transaction(pool: &DescriptorPool) -> DynamicMessage {
+        build(
+            pool,
+            "TransactionTrace",
+            vec![
+                ("type", Value::EnumNumber(0)),
+                ("nonce", Value::U64(9)),
+                ("gas_price", big(pool, 20_000_000_000)),
+                ("gas_limit", Value::U64(21_000)),
+                ("to", bytes_value([0x35; 20])),
+                ("value", big(pool, 1_000_000_000_000_000_000)),
+                ("v", bytes_value([37])),
+                (
+                    "r",
+                    hex_value("28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276"),
+                ),
+                (
+                    "s",
+                    hex_value("67cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"),
+                ),
+                ("status", Value::EnumNumber(STATUS_SUCCEEDED)),
+                ("receipt", receipt(pool, 21_000, vec![0; 256], Vec::new())),
+            ],
+        )
+    }
+
+    /// The signed access list transaction of go-ethereum's
+    /// `TestEIP2718TransactionEncode`.
+    fn access_list_transaction(pool: &DescriptorPool) -> DynamicMessage {
+        build(
+            pool,
+            "TransactionTrace",
+            vec![
+                ("type", Value::EnumNumber(1)),
+                ("nonce", Value::U64(3)),
+                ("gas_price", big(pool, 1)),
+                ("gas_limit", Value::U64(25_000)),
+                ("to", hex_value("b94f5374fce5edbc8e2a8697c15331677e6ebf0b")),
+                ("value", big(pool, 10)),
+                ("input", hex_value("5544")),
+                ("v", bytes_value([1])),
+                (
+                    "r",
+                    hex_value("c9519f4f2b30335884581971573fadf60c6204f59a911df35ee8a540456b2660"),
+                ),
+                (
+                    "s",
+                    hex_value("32f1e8e2c5dd761f9e4f88f41c8310aeaba26a8bfcdacfedfa12ec3862d37521"),
+                ),
+                ("status", Value::EnumNumber(STATUS_SUCCEEDED)),
+                ("receipt", receipt(pool, 46_000, vec![0; 256], Vec::new())),
+            ],
+        )
+    }
+
+    fn signature() -> (Vec<u8>, Vec<u8>) {
+        ((1..=32).collect(), (33..=64).collect())
+    }
+
+    fn dynamic_fee_transaction(pool: &DescriptorPool) -> DynamicMessage {
+        let (r, s) = signature();
+        let access = build(
+            pool,
+            "AccessTuple",
+            vec![
+                ("address", bytes_value([0x22; 20])),
+                (
+                    "storage_keys",
+                    list(vec![hex_value(
+                        "0000000000000000000000000000000000000000000000000000000000000001",
+                    )]),
+                ),
+            ],
+        );
+        let transfer = build(
+            pool,
+            "Log",
+            vec![
+                ("address", bytes_value([0x11; 20])),
+                (
+                    "topics",
+                    list(vec![
+                        // Transfer(address,address,uint256)
+                        hex_value(
+                            "ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
+                        ),
+                        hex_value(
+                            "0000000000000000000000002222222222222222222222222222222222222222",
+                        ),
+                    ]),
+                ),
+                (
+                    "data",
+                    hex_value("0000000000000000000000000000000000000000000000000000000000000005"),
+                ),
+            ],
+        );
+        let empty = build(pool, "Log", vec![("address", bytes_value([0x11; 20]))]);
+        let bloom = (0..256).map(|i| (i % 7) as u8).collect();
+
+        build(
+            pool,
+            "TransactionTrace",
+            vec![
+                ("type", Value::EnumNumber(2)),
+                ("nonce", Value::U64(7)),
+                ("max_priority_fee_per_gas", big(pool, 2_000_000_000)),
+                ("max_fee_per_gas", big(pool, 30_000_000_000)),
+                ("gas_limit", Value::U64(60_000)),
+                ("to", bytes_value([0x11; 20])),
+                ("input", hex_value("a9059cbb")),
+                ("access_list", list(vec![Value::Message(access)])),
+                ("v", bytes_value([0])),
+                ("r", bytes_value(r)),
+                ("s", bytes_value(s)),
+                ("status", Value::EnumNumber(STATUS_SUCCEEDED)),
+                (
+                    "receipt",
+                    receipt(
+                        pool,
+                        100_000,
+                        bloom,
+                        vec![Value::Message(transfer), Value::Message(empty)],
+                    ),
+                ),
+            ],
+        )
+    }
+
+    fn blob_transaction(pool: &DescriptorPool) -> DynamicMessage {
+        let (r, s) = signature();
+        let mut blob_hash = vec![0x01];
+        blob_hash.extend([0xaa; 31]);
+
+        build(
+            pool,
+            "TransactionTrace",
+            vec![
+                ("type", Value::EnumNumber(3)),
+                ("nonce", Value::U64(8)),
+                ("max_priority_fee_per_gas", big(pool, 1_000_000_000)),
+                ("max_fee_per_gas", big(pool, 40_000_000_000)),
+                ("gas_limit", Value::U64(21_000)),
+                ("to", bytes_value([0x33; 20])),
+                ("value", big(pool, 1)),
+                ("blob_gas_fee_cap", big(pool, 3)),
+                ("blob_hashes", list(vec![bytes_value(blob_hash)])),
+                ("v", bytes_value([1])),
+                ("r", bytes_value(r)),
+                ("s", bytes_value(s)),
+                // Reverted.
+                ("status", Value::EnumNumber(3)),
+                ("receipt", receipt(pool, 121_000, vec![0; 256], Vec::new())),
+            ],
+        )
+    }
+
+    fn encoded_transaction(trace: &DynamicMessage) -> String {
+        let tx_type = enum_number(trace, "type").unwrap() as u8;
+        hex_bytes::encode(&encode_transaction(trace, tx_type, 1).unwrap())
+    }
+
+    fn encoded_receipt(trace: &DynamicMessage) -> String {
+        let tx_type = enum_number(trace, "type").unwrap() as u8;
+        hex_bytes::encode(&encode_receipt(trace, tx_type).unwrap())
+    }
+
+    #[test]
+    fn empty_trie_root() {
+        assert_eq!(
+            hex_bytes::encode(&trie_root(Vec::new())),
+            "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+        );
+    }
+
+    /// The `puppy` case of the Ethereum trie tests, with arbitrary keys.
+    #[test]
+    fn trie_root_of_test_vector() {
+        let mut items: Vec<(Vec<u8>, Vec<u8>)> = [
+            ("do", "verb"),
+            ("dog", "puppy"),
+            ("doge", "coin"),
+            ("horse", "stallion"),
+        ]
+        .iter()
+        .map(|(key, value)| (nibbles(key.as_bytes()), value.as_bytes().to_vec()))
+        .collect();
+        items.sort();
+
+        assert_eq!(
+            hex_bytes::encode(&keccak256(&trie_node(&items, 0))),
+            "0x5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"
+        );
+    }
+
+    /// The first transaction of Ethereum mainnet, the only one of block
+    /// 46147, re-encoded to its published hash.
+    #[test]
+    fn first_mainnet_transaction() {
+        let pool = pool();
+        let trace = build(
+            &pool,
+            "TransactionTrace",
+            vec![
+                ("type", Value::EnumNumber(0)),
+                ("nonce", Value::U64(0)),
+                ("gas_price", big(&pool, 50_000_000_000_000)),
+                ("gas_limit", Value::U64(21_000)),
+                ("to", hex_value("5df9b87991262f6ba471f09758cde1c0fc1de734")),
+                ("value", big(&pool, 31_337)),
+                ("v", bytes_value([0x1c])),
+                (
+                    "r",
+                    hex_value("88ff6cf0fefd94db46111149ae4bfc179e9b94721fffd821d38d16464b3f71d0"),
+                ),
+                (
+                    "s",
+                    hex_value("45e0aff800961cfce805daef7016b9b675c137a6a41a548f7b60a3484c06a33a"),
+                ),
+            ],
+        );
+
+        let encoded = encode_transaction(&trace, 0, 1).unwrap();
+        assert_eq!(
+            hex_bytes::encode(&keccak256(&encoded)),
+            "0x5c504ed432cb51138bcf09aa5e8a410dd4a1e204ef84bfed1be16dfba1b22060"
+        );
+        assert_eq!(
+            hex_bytes::encode(&trie_root(vec![encoded])),
+            "0x4513310fcb9f6f616972a3b948dc5d547f280849a87ebb5af0191f98b87be598"
+        );
+    }
+
+    #[test]
+    fn legacy_transaction() {
+        assert_eq!(
+            encoded_transaction(&eip155_transaction(&pool())),
+            "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f\
+             761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+    }
+
+    #[test]
+    fn access_list_transaction_encoding() {
+        assert_eq!(
+            encoded_transaction(&access_list_transaction(&pool())),
+            "0x01f8630103018261a894b94f5374fce5edbc8e2a8697c15331677e6ebf0b0a825544c001a0c9519f4f\
+             2b30335884581971573fadf60c6204f59a911df35ee8a540456b2660a032f1e8e2c5dd761f9e4f88f41c\
+             8310aeaba26a8bfcdacfedfa12ec3862d37521"
+        );
+    }
+
+    /// Fields in the order of EIP-1559: chain ID, nonce, priority fee, fee
+    /// cap, gas, destination, value, data, access list, y parity, r and s.
+    #[test]
+    fn dynamic_fee_transaction_encoding() {
+        assert_eq!(
+            encoded_transaction(&dynamic_fee_transaction(&pool())),
+            "0x02f8a8010784773594008506fc23ac0082ea60941111111111111111111111111111111111111111\
+             8084a9059cbbf838f7942222222222222222222222222222222222222222e1a00000000000000000000000\
+             00000000000000000000000000000000000000000180a00102030405060708090a0b0c0d0e0f1011121314\
+             15161718191a1b1c1d1e1f20a02122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e\
+             3f40"
+        );
+    }
+
+    /// Fields in the order of EIP-4844: those of EIP-1559, then the blob fee
+    /// cap and versioned hashes before the signature.
+    #[test]
+    fn blob_transaction_encoding() {
+        assert_eq!(
+            encoded_transaction(&blob_transaction(&pool())),
+            "0x03f88e0108843b9aca008509502f9000825208943333333333333333333333333333333333333333\
+             0180c003e1a001aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa01a0010203\
+             0405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20a02122232425262728292a2b2c2d\
+             2e2f303132333435363738393a3b3c3d3e3f40"
+        );
+    }
+
+    #[test]
+    fn receipt_with_logs() {
+        let bloom = "00010203040506".repeat(37);
+        let expected = format!(
+            "0x02f9019e01830186a0b90100{}f894f87a941111111111111111111111111111111111111111f842a0\
+             ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3efa0000000000000000000\
+             0000002222222222222222222222222222222222222222a000000000000000000000000000000000000000\
+             00000000000000000000000005d7941111111111111111111111111111111111111111c080",
+            &bloom[..512]
+        );
+        assert_eq!(encoded_receipt(&dynamic_fee_transaction(&pool())), expected);
+    }
+
+    #[test]
+    fn pre_byzantium_receipt_carries_the_state_root() {
+        let pool = pool();
+        let receipt = build(
+            &pool,
+            "TransactionReceipt",
+            vec![
+                ("state_root", bytes_value([0x5a; 32])),
+                ("cumulative_gas_used", Value::U64(21_000)),
+                ("logs_bloom", bytes_value(vec![0; 256])),
+            ],
+        );
+        let trace = build(
+            &pool,
+            "TransactionTrace",
+            vec![
+                ("status", Value::EnumNumber(STATUS_SUCCEEDED)),
+                ("receipt", Value::Message(receipt)),
+            ],
+        );
+
+        let encoded = encode_receipt(&trace, 0).unwrap();
+        assert_eq!(
+            hex_bytes::encode(&trie_root(vec![encoded])),
+            "0xa0046b5bc552e6a752954bd60fc8d476c7e7ebe6e136ee0d125e6c4cd512b655"
+        );
+    }
+
+    #[test]
+    fn verifies_a_block_of_every_transaction_type() {
+        let pool = pool();
+        let traces = vec![
+            eip155_transaction(&pool),
+            access_list_transaction(&pool),
+            dynamic_fee_transaction(&pool),
+            blob_transaction(&pool),
+        ];
+        let roots = (
+            "e87a28704cdc22135e4ed16f2068bd099bab478887455b4a4d807a5ca4ebbba8",
+            "63a39ee0ddc5e2959235297a6338ab47e61ff687e3d4d011e6c929b1d0fc4d65",
+        );
+
+        verify_ethereum_roots(&block(&pool, traces.clone(), roots), 1).unwrap();
+
+        // Another chain ID changes the typed transactions.
+        assert!(matches!(
+            verify_ethereum_roots(&block(&pool, traces.clone(), roots), 5),
+            Err(RootError::Mismatch {
+                root: TrieRoot::Transactions,
+                ..
+            })
+        ));
+
+        // So does a receipt altered by the provider.
+        let mut traces = traces;
+        let mut receipt = super::message(&traces[3], "receipt").unwrap();
+        receipt.set_field_by_name("cumulative_gas_used", Value::U64(121_001));
+        traces[3].set_field_by_name("receipt", Value::Message(receipt));
+        let error = verify_ethereum_roots(&block(&pool, traces, roots), 1).unwrap_err();
+        assert!(matches!(
+            error,
+            RootError::Mismatch {
+                root: TrieRoot::Receipts,
+                ref header,
+                ..
+            } if *header == format!("0x{}", roots.1)
+        ));
+    }
+
+    /// More than 128 transactions, whose keys span one and two bytes.
+    #[test]
+    fn verifies_a_large_block() {
+        let pool = pool();
+        let (r, s) = signature();
+        let traces = (0..130)
+            .map(|i| {
+                build(
+                    &pool,
+                    "TransactionTrace",
+                    vec![
+                        ("type", Value::EnumNumber(0)),
+                        ("nonce", Value::U64(i)),
+                        ("gas_price", big(&pool, 1)),
+                        ("gas_limit", Value::U64(21_000)),
+                        ("to", bytes_value([0x44; 20])),
+                        ("value", big(&pool, i)),
+                        ("v", bytes_value([27])),
+                        ("r", bytes_value(r.clone())),
+                        ("s", bytes_value(s.clone())),
+                        ("status", Value::EnumNumber(STATUS_SUCCEEDED)),
+                        (
+                            "receipt",
+                            receipt(&pool, 21_000 * (i + 1), vec![0; 256], Vec::new()),
+                        ),
+                    ],
+                )
+            })
+            .collect();
+
+        let roots = (
+            "4bf27ae4b944cac588ac2c80a0c270d54f74b6a8faa35ce35d278dcc866011f9",
+            "cd04375e5321aae8c188ebaaf8806acf92c85ef34b8e159c87da2d6cb17de9f3",
+        );
+        verify_ethereum_roots(&block(&pool, traces, roots), 1).unwrap();
+    }
+}
@@ -0,0 +1,460 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Call traces and receipts of decoded Ethereum blocks, flattened out of
+//! their transactions.
+
+use std::collections::HashMap;
+
+use prost_reflect::{DynamicMessage, Value};
+
+/// `TransactionTraceStatus.SUCCEEDED`.
+const STATUS_SUCCEEDED: i32 = 1;
+
+/// A call of an Ethereum transaction, with its place in the call tree
+/// resolved. See [`ethereum_calls`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EthereumCall {
+    /// Position of the transaction in the block.
+    pub transaction_index: usize,
+    /// Hash of the transaction.
+    pub transaction_hash: Vec<u8>,
+    /// Position of the call in the transaction's `calls`, in execution order.
+    pub position: usize,
+    /// Position of the call that made this one, `None` for the root call.
+    pub parent: Option<usize>,
+    /// Positions of the calls this one made, in execution order.
+    pub children: Vec<usize>,
+    /// Depth in the call tree, 0 for the root call.
+    pub depth: u32,
+    /// The call, an `sf.ethereum.type.v2.Call`.
+    pub call: DynamicMessage,
+}
+
+/// The receipt of an Ethereum transaction, with what is only known from the
+/// rest of the block resolved. See [`ethereum_receipts`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EthereumReceipt {
+    /// Position of the transaction in the block.
+    pub transaction_index: usize,
+    /// Hash of the transaction.
+    pub transaction_hash: Vec<u8>,
+    /// Sender of the transaction.
+    pub from: Vec<u8>,
+    /// Recipient of the transaction, empty for contract creations.
+    pub to: Vec<u8>,
+    /// Whether the transaction succeeded.
+    pub succeeded: bool,
+    /// Gas used by the transaction alone, where the receipt only has the
+    /// gas used by the block up to it.
+    pub gas_used: u64,
+    /// Position in the block of the transaction's first log.
+    pub first_log_index: usize,
+    /// The receipt, an `sf.ethereum.type.v2.TransactionReceipt`.
+    pub receipt: DynamicMessage,
+}
+
+/// Every call of every transaction of an Ethereum block, decoded as
+/// `sf.ethereum.type.v2.Block`, in block then execution order.
+///
+/// Firehose records the calls of a transaction as a flat list linked by
+/// `index` and `parent_index`; each [`EthereumCall`] carries the positions
+/// of its parent and children in that list instead, so call trees can be
+/// walked without rebuilding them. Calls whose parent is not in the list
+/// are treated as roots. Blocks without call traces, such as those of
+/// header-only requests, yield nothing.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{ethereum_calls, DynamicDecoder, Response};
+///
+/// # fn example(response: Response) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decoder = DynamicDecoder::new()?;
+/// decoder.add_file_descriptor_set(std::fs::read("ethereum.binpb")?.as_slice())?;
+///
+/// if let Some(block) = decoder.decode_response_block(&response)? {
+///     for call in ethereum_calls(&block).filter(|call| call.depth > 0) {
+///         println!(
+///             "transaction {} call {} called by {:?}",
+///             call.transaction_index, call.position, call.parent
+///         );
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn ethereum_calls(block: &DynamicMessage) -> impl Iterator<Item = EthereumCall> {
+    messages(block, "transaction_traces")
+        .into_iter()
+        .enumerate()
+        .flat_map(|(transaction_index, trace)| transaction_calls(transaction_index, &trace))
+}
+
+/// The receipt of every transaction of an Ethereum block, decoded as
+/// `sf.ethereum.type.v2.Block`, in block order.
+///
+/// Each [`EthereumReceipt`] carries the transaction's own gas used and the
+/// block position of its first log, both of which otherwise need the
+/// receipts before it. Blocks without transaction traces yield nothing.
+pub fn ethereum_receipts(block: &DynamicMessage) -> impl Iterator<Item = EthereumReceipt> {
+    let mut cumulative_gas_used = 0;
+    let mut log_index = 0;
+    messages(block, "transaction_traces")
+        .into_iter()
+        .enumerate()
+        .filter_map(move |(transaction_index, trace)| {
+            let receipt = message(&trace, "receipt")?;
+
+            let cumulative = unsigned(&receipt, "cumulative_gas_used");
+            let gas_used = cumulative.saturating_sub(cumulative_gas_used);
+            cumulative_gas_used = cumulative;
+            let first_log_index = log_index;
+            log_index += messages(&receipt, "logs").len();
+
+            Some(EthereumReceipt {
+                transaction_index,
+                transaction_hash: bytes(&trace, "hash"),
+                from: bytes(&trace, "from"),
+                to: bytes(&trace, "to"),
+                succeeded: enum_number(&trace, "status") == STATUS_SUCCEEDED,
+                gas_used,
+                first_log_index,
+                receipt,
+            })
+        })
+}
+
+fn transaction_calls(transaction_index: usize, trace: &DynamicMessage) -> Vec<EthereumCall> {
+    let transaction_hash = bytes(trace, "hash");
+    let calls = messages(trace, "calls");
+
+    let positions: HashMap<u64, usize> = calls
+        .iter()
+        .enumerate()
+        .map(|(position, call)| (unsigned(call, "index"), position))
+        .collect();
+    let parents: Vec<Option<usize>> = calls
+        .iter()
+        .enumerate()
+        .map(|(position, call)| {
+            // The root call's parent index is 0, as may be its own index.
+            if unsigned(call, "depth") == 0 {
+                return None;
+            }
+            positions
+                .get(&unsigned(call, "parent_index"))
+                .copied()
+                .filter(|parent| *parent != position)
+        })
+        .collect();
+
+    let mut children = vec![Vec::new(); calls.len()];
+    for (position, parent) in parents.iter().enumerate() {
+        if let Some(parent) = parent {
+            children[*parent].push(position);
+        }
+    }
+
+    calls
+        .into_iter()
+        .zip(parents)
+        .zip(children)
+        .enumerate()
+        .map(|(position, ((call, parent), children))| EthereumCall {
+            transaction_index,
+            transaction_hash: transaction_hash.clone(),
+            position,
+            parent,
+            children,
+            depth: unsigned(&call, "depth") as u32,
+            call,
+        })
+        .collect()
+}
+
+fn message(message: &DynamicMessage, name: &str) -> Option<DynamicMessage> {
+    match message.get_field_by_name(name)?.as_ref() {
+        Value::Message(inner) => Some(inner.clone()),
+        _ => None,
+    }
+}
+
+fn messages(message: &DynamicMessage, name: &str) -> Vec<DynamicMessage> {
+    match message.get_field_by_name(name).as_deref() {
+        Some(Value::List(items)) => items
+            .iter()
+            .filter_map(|item| match item {
+                Value::Message(inner) => Some(inner.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn bytes(message: &DynamicMessage, name: &str) -> Vec<u8> {
+    match message.get_field_by_name(name).as_deref() {
+        Some(Value::Bytes(bytes)) => bytes.to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+fn unsigned(message: &DynamicMessage, name: &str) -> u64 {
+    match message.get_field_by_name(name).as_deref() {
+        Some(Value::U64(number)) => *number,
+        Some(Value::U32(number)) => u64::from(*number),
+        _ => 0,
+    }
+}
+
+fn enum_number(message: &DynamicMessage, name: &str) -> i32 {
+    match message.get_field_by_name(name).as_deref() {
+        Some(Value::EnumNumber(number)) => *number,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_reflect::DescriptorPool;
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+        FileDescriptorProto, FileDescriptorSet,
+    };
+
+    use super::*;
+
+    const PACKAGE: &str = "sf.ethereum.type.v2";
+
+    /// `TransactionTraceStatus.FAILED`.
+    const STATUS_FAILED: i32 = 2;
+
+    /// The subset of `sf/ethereum/type/v2/type.proto` the helpers read, with
+    /// the upstream field names.
+    fn pool() -> DescriptorPool {
+        let field = |name: &str, field_type: Type, type_name: Option<&str>, repeated: bool| {
+            let label = if repeated {
+                Label::Repeated
+            } else {
+                Label::Optional
+            };
+            FieldDescriptorProto {
+                name: Some(name.to_string()),
+                label: Some(label as i32),
+                r#type: Some(field_type as i32),
+                type_name: type_name.map(|name| format!(".{PACKAGE}.{name}")),
+                ..Default::default()
+            }
+        };
+        let descriptor = |name: &str, mut fields: Vec<FieldDescriptorProto>| {
+            for (number, field) in fields.iter_mut().enumerate() {
+                field.number = Some(number as i32 + 1);
+            }
+            DescriptorProto {
+                name: Some(name.to_string()),
+                field: fields,
+                ..Default::default()
+            }
+        };
+
+        let file = FileDescriptorProto {
+            name: Some("sf/ethereum/type/v2/type.proto".to_string()),
+            package: Some(PACKAGE.to_string()),
+            syntax: Some("proto3".to_string()),
+            message_type: vec![
+                descriptor(
+                    "Block",
+                    vec![field(
+                        "transaction_traces",
+                        Type::Message,
+                        Some("TransactionTrace"),
+                        true,
+                    )],
+                ),
+                descriptor(
+                    "TransactionTrace",
+                    vec![
+                        field("hash", Type::Bytes, None, false),
+                        field("from", Type::Bytes, None, false),
+                        field("to", Type::Bytes, None, false),
+                        field("status", Type::Enum, Some("TransactionTraceStatus"), false),
+                        field("receipt", Type::Message, Some("TransactionReceipt"), false),
+                        field("calls", Type::Message, Some("Call"), true),
+                    ],
+                ),
+                descriptor(
+                    "TransactionReceipt",
+                    vec![
+                        field("cumulative_gas_used", Type::Uint64, None, false),
+                        field("logs", Type::Message, Some("Log"), true),
+                    ],
+                ),
+                descriptor("Log", vec![field("data", Type::Bytes, None, false)]),
+                descriptor(
+                    "Call",
+                    vec![
+                        field("index", Type::Uint32, None, false),
+                        field("parent_index", Type::Uint32, None, false),
+                        field("depth", Type::Uint32, None, false),
+                    ],
+                ),
+            ],
+            enum_type: vec![EnumDescriptorProto {
+                name: Some("TransactionTraceStatus".to_string()),
+                value: ["UNKNOWN", "SUCCEEDED", "FAILED", "REVERTED"]
+                    .iter()
+                    .zip(0..)
+                    .map(|(name, number)| EnumValueDescriptorProto {
+                        name: Some(name.to_string()),
+                        number: Some(number),
+                        ..Default::default()
+                    })
+                    .collect(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let set = FileDescriptorSet { file: vec![file] };
+        DescriptorPool::decode(set.encode_to_vec().as_slice()).unwrap()
+    }
+
+    fn build(pool: &DescriptorPool, name: &str, fields: Vec<(&str, Value)>) -> DynamicMessage {
+        let descriptor = pool
+            .get_message_by_name(&format!("{PACKAGE}.{name}"))
+            .unwrap();
+        let mut message = DynamicMessage::new(descriptor);
+        for (field, value) in fields {
+            message.set_field_by_name(field, value);
+        }
+        message
+    }
+
+    /// A call with `index`, `parent_index` and `depth`.
+    fn call(pool: &DescriptorPool, (index, parent, depth): (u32, u32, u32)) -> Value {
+        Value::Message(build(
+            pool,
+            "Call",
+            vec![
+                ("index", Value::U32(index)),
+                ("parent_index", Value::U32(parent)),
+                ("depth", Value::U32(depth)),
+            ],
+        ))
+    }
+
+    fn trace(
+        pool: &DescriptorPool,
+        hash: u8,
+        status: i32,
+        cumulative_gas_used: u64,
+        logs: usize,
+        calls: &[(u32, u32, u32)],
+    ) -> Value {
+        let log = Value::Message(build(pool, "Log", Vec::new()));
+        let receipt = build(
+            pool,
+            "TransactionReceipt",
+            vec![
+                ("cumulative_gas_used", Value::U64(cumulative_gas_used)),
+                ("logs", Value::List(vec![log; logs])),
+            ],
+        );
+        Value::Message(build(
+            pool,
+            "TransactionTrace",
+            vec![
+                ("hash", Value::Bytes(vec![hash; 32].into())),
+                ("status", Value::EnumNumber(status)),
+                ("receipt", Value::Message(receipt)),
+                (
+                    "calls",
+                    Value::List(calls.iter().map(|call| self::call(pool, *call)).collect()),
+                ),
+            ],
+        ))
+    }
+
+    fn block(pool: &DescriptorPool) -> DynamicMessage {
+        build(
+            pool,
+            "Block",
+            vec![(
+                "transaction_traces",
+                Value::List(vec![
+                    // Calls numbered from 0, as by the Firehose tracer of
+                    // go-ethereum: root, its two children, and a grandchild
+                    // under the second one.
+                    trace(
+                        pool,
+                        0xaa,
+                        STATUS_SUCCEEDED,
+                        21_000,
+                        1,
+                        &[(0, 0, 0), (1, 0, 1), (2, 0, 1), (3, 2, 2)],
+                    ),
+                    // Calls numbered from 1, as by older instrumented nodes,
+                    // in a transaction that failed.
+                    trace(
+                        pool,
+                        0xbb,
+                        STATUS_FAILED,
+                        80_000,
+                        2,
+                        &[(1, 0, 0), (2, 1, 1), (3, 2, 2)],
+                    ),
+                ]),
+            )],
+        )
+    }
+
+    #[test]
+    fn resolves_call_trees() {
+        let calls: Vec<_> = ethereum_calls(&block(&pool()))
+            .map(|call| {
+                (
+                    call.transaction_index,
+                    call.position,
+                    call.parent,
+                    call.children,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            calls,
+            [
+                (0, 0, None, vec![1, 2]),
+                (0, 1, Some(0), vec![]),
+                (0, 2, Some(0), vec![3]),
+                (0, 3, Some(2), vec![]),
+                (1, 0, None, vec![1]),
+                (1, 1, Some(0), vec![2]),
+                (1, 2, Some(1), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_gas_used_log_indexes_and_status() {
+        let receipts: Vec<_> = ethereum_receipts(&block(&pool()))
+            .map(|receipt| {
+                (
+                    receipt.transaction_hash[0],
+                    receipt.gas_used,
+                    receipt.first_log_index,
+                    receipt.succeeded,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            receipts,
+            [(0xaa, 21_000, 0, true), (0xbb, 59_000, 1, false)]
+        );
+    }
+}
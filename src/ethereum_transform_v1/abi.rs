@@ -0,0 +1,404 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Event topics and function selectors from Solidity signatures, so filters
+//! are built from what developers read in ABIs rather than hand-copied hex.
+
+use crate::{hex_bytes, TransformError};
+
+use super::filter::EthereumFilter;
+
+/// Topic 0 of the logs of the event `signature`: the Keccak-256 hash of its
+/// canonical form.
+///
+/// Accepts canonical signatures such as `Transfer(address,address,uint256)`
+/// as well as declarations copied from Solidity, such as
+/// `event Transfer(address indexed from, address indexed to, uint value)`:
+/// parameter names and `indexed` are dropped and `uint`/`int` widened to
+/// `uint256`/`int256`, since any difference in the hashed text silently
+/// matches no log at all.
+///
+/// ```rust
+/// use firehose_rs::ethereum::event_topic;
+///
+/// let topic = event_topic("event Transfer(address indexed from, address indexed to, uint value)")
+///     .unwrap();
+/// assert_eq!(topic, event_topic("Transfer(address,address,uint256)").unwrap());
+/// assert_eq!(topic[..4], [0xdd, 0xf2, 0x52, 0xad]);
+/// ```
+pub fn event_topic(signature: &str) -> Result<[u8; 32], TransformError> {
+    Ok(keccak256(canonical_signature(signature)?.as_bytes()))
+}
+
+/// The 4-byte selector of calls to the function `signature`, normalized as
+/// by [`event_topic`].
+///
+/// ```rust
+/// use firehose_rs::ethereum::function_selector;
+///
+/// let selector = function_selector("function transfer(address to, uint amount)").unwrap();
+/// assert_eq!(selector, [0xa9, 0x05, 0x9c, 0xbb]);
+/// ```
+pub fn function_selector(signature: &str) -> Result<[u8; 4], TransformError> {
+    let hash = keccak256(canonical_signature(signature)?.as_bytes());
+    Ok([hash[0], hash[1], hash[2], hash[3]])
+}
+
+/// The canonical form of a Solidity event or function `signature`, as
+/// hashed for topics and selectors: `Name(type1,type2)`.
+///
+/// ```rust
+/// use firehose_rs::ethereum::canonical_signature;
+///
+/// assert_eq!(
+///     canonical_signature("event Swap(address indexed sender, (uint a, bytes32[2] b)[] data)")
+///         .unwrap(),
+///     "Swap(address,(uint256,bytes32[2])[])"
+/// );
+/// ```
+pub fn canonical_signature(signature: &str) -> Result<String, TransformError> {
+    let invalid = |message: String| TransformError::InvalidArguments {
+        name: signature.to_string(),
+        message,
+    };
+
+    let signature = signature.trim().trim_end_matches(';').trim();
+    let signature = ["event", "function"]
+        .into_iter()
+        .find_map(|keyword| {
+            signature
+                .strip_prefix(keyword)
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+        })
+        .unwrap_or(signature)
+        .trim();
+
+    let open = signature
+        .find('(')
+        .ok_or_else(|| invalid("missing parameter list".to_string()))?;
+    let name = signature[..open].trim();
+    if !is_identifier(name) {
+        return Err(invalid(format!("`{name}` is not a valid name")));
+    }
+
+    let close = matching_paren(signature, open)
+        .ok_or_else(|| invalid("unbalanced parentheses".to_string()))?;
+    // Anything after the parameters, such as `anonymous` or `external view
+    // returns (...)`, is not part of the signature
+    let parameters = canonical_parameters(&signature[open + 1..close]).map_err(invalid)?;
+    Ok(format!("{name}({parameters})"))
+}
+
+/// Canonical, comma-separated types of a parameter list.
+fn canonical_parameters(list: &str) -> Result<String, String> {
+    if list.trim().is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut types = Vec::new();
+    for parameter in split_top_level(list) {
+        let parameter = parameter.trim();
+        if parameter.is_empty() {
+            return Err("empty parameter".to_string());
+        }
+        types.push(canonical_type(parameter)?);
+    }
+    Ok(types.join(","))
+}
+
+/// Canonical type of one parameter, dropping its name and modifiers.
+fn canonical_type(parameter: &str) -> Result<String, String> {
+    let (base, rest) = if parameter.starts_with('(') || parameter.starts_with("tuple(") {
+        let open = parameter.find('(').unwrap_or(0);
+        let close = matching_paren(parameter, open)
+            .ok_or_else(|| format!("unbalanced parentheses in `{parameter}`"))?;
+        let inner = canonical_parameters(&parameter[open + 1..close])?;
+        (format!("({inner})"), &parameter[close + 1..])
+    } else {
+        let end = parameter
+            .find(|c: char| c.is_whitespace() || c == '[')
+            .unwrap_or(parameter.len());
+        let base = elementary_type(&parameter[..end])
+            .ok_or_else(|| format!("`{}` is not a Solidity type", &parameter[..end]))?;
+        (base, &parameter[end..])
+    };
+
+    // Array suffixes, then an optional location, `indexed` and name
+    let mut rest = rest.trim_start();
+    let mut arrays = String::new();
+    while let Some(after) = rest.strip_prefix('[') {
+        let close = after
+            .find(']')
+            .ok_or_else(|| format!("unclosed array in `{parameter}`"))?;
+        let size = after[..close].trim();
+        if !size.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("invalid array size `{size}` in `{parameter}`"));
+        }
+        arrays.push_str(&format!("[{size}]"));
+        rest = after[close + 1..].trim_start();
+    }
+
+    let mut words: Vec<&str> = rest.split_whitespace().collect();
+    words.retain(|word| !matches!(*word, "indexed" | "memory" | "calldata" | "storage"));
+    match words.as_slice() {
+        [] => {}
+        [name] if is_identifier(name) => {}
+        _ => return Err(format!("unexpected `{rest}` in `{parameter}`")),
+    }
+    Ok(format!("{base}{arrays}"))
+}
+
+/// Canonical name of an elementary Solidity type, if `name` is one.
+fn elementary_type(name: &str) -> Option<String> {
+    let sized =
+        |digits: &str, valid: fn(u32) -> bool| digits.parse::<u32>().ok().is_some_and(valid);
+    match name {
+        "address" | "bool" | "string" | "bytes" | "function" => Some(name.to_string()),
+        "uint" => Some("uint256".to_string()),
+        "int" => Some("int256".to_string()),
+        "byte" => Some("bytes1".to_string()),
+        _ => {
+            let valid = if let Some(bits) = name.strip_prefix("uint") {
+                sized(bits, |bits| bits % 8 == 0 && (8..=256).contains(&bits))
+            } else if let Some(bits) = name.strip_prefix("int") {
+                sized(bits, |bits| bits % 8 == 0 && (8..=256).contains(&bits))
+            } else if let Some(size) = name.strip_prefix("bytes") {
+                sized(size, |size| (1..=32).contains(&size))
+            } else {
+                false
+            };
+            valid.then(|| name.to_string())
+        }
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Position of the parenthesis closing the one at `open`.
+fn matching_paren(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in text.char_indices().skip_while(|(i, _)| *i < open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split `list` at commas outside parentheses.
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in list.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&list[start..]);
+    parts
+}
+
+/// Parse a `0x`-prefixed or bare hex Ethereum address, checking its length.
+pub(crate) fn parse_address(address: &str) -> Result<Vec<u8>, TransformError> {
+    let bytes =
+        hex_bytes::decode(address.trim()).map_err(|message| TransformError::InvalidArguments {
+            name: address.to_string(),
+            message,
+        })?;
+    if bytes.len() != 20 {
+        return Err(TransformError::InvalidArguments {
+            name: address.to_string(),
+            message: format!("an address has 20 bytes, not {}", bytes.len()),
+        });
+    }
+    Ok(bytes)
+}
+
+impl EthereumFilter {
+    /// Also match logs of the `events`, given as Solidity signatures,
+    /// emitted by one of `addresses`, given as hex.
+    ///
+    /// Fails on malformed signatures and on addresses that are not 20 bytes
+    /// of hex, instead of building a filter that silently matches nothing.
+    ///
+    /// ```rust
+    /// use firehose_rs::ethereum::EthereumFilter;
+    ///
+    /// let filter = EthereumFilter::new()
+    ///     .events(
+    ///         ["0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"],
+    ///         ["Transfer(address,address,uint256)", "Approval(address,address,uint256)"],
+    ///     )
+    ///     .unwrap();
+    /// assert_eq!(filter.combined_filter().log_filters[0].event_signatures.len(), 2);
+    /// ```
+    pub fn events<A, E>(
+        self,
+        addresses: impl IntoIterator<Item = A>,
+        events: impl IntoIterator<Item = E>,
+    ) -> Result<Self, TransformError>
+    where
+        A: AsRef<str>,
+        E: AsRef<str>,
+    {
+        let addresses = addresses
+            .into_iter()
+            .map(|address| parse_address(address.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let topics = events
+            .into_iter()
+            .map(|event| event_topic(event.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.logs(addresses, topics))
+    }
+
+    /// Also match calls of the `functions`, given as Solidity signatures, to
+    /// one of `addresses`, given as hex, validated as by
+    /// [`events`](EthereumFilter::events).
+    pub fn functions<A, F>(
+        self,
+        addresses: impl IntoIterator<Item = A>,
+        functions: impl IntoIterator<Item = F>,
+    ) -> Result<Self, TransformError>
+    where
+        A: AsRef<str>,
+        F: AsRef<str>,
+    {
+        let addresses = addresses
+            .into_iter()
+            .map(|address| parse_address(address.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let selectors = functions
+            .into_iter()
+            .map(|function| function_selector(function.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.calls(addresses, selectors))
+    }
+}
+
+/// Keccak-256, as used by Ethereum (the original Keccak padding, not
+/// SHA3-256).
+pub(crate) fn keccak256(data: &[u8]) -> [u8; 32] {
+    const RATE: usize = 136;
+
+    let mut state = [0u64; 25];
+    let mut blocks = data.chunks_exact(RATE);
+    for block in &mut blocks {
+        absorb(&mut state, block);
+    }
+
+    let remainder = blocks.remainder();
+    let mut last = [0u8; RATE];
+    last[..remainder.len()].copy_from_slice(remainder);
+    last[remainder.len()] ^= 0x01;
+    last[RATE - 1] ^= 0x80;
+    absorb(&mut state, &last);
+
+    let mut hash = [0u8; 32];
+    for (chunk, lane) in hash.chunks_exact_mut(8).zip(state) {
+        chunk.copy_from_slice(&lane.to_le_bytes());
+    }
+    hash
+}
+
+fn absorb(state: &mut [u64; 25], block: &[u8]) {
+    for (lane, bytes) in state.iter_mut().zip(block.chunks_exact(8)) {
+        *lane ^= u64::from_le_bytes(bytes.try_into().expect("8-byte chunk"));
+    }
+    keccak_f(state);
+}
+
+/// The Keccak-f\[1600\] permutation.
+fn keccak_f(a: &mut [u64; 25]) {
+    const ROUND_CONSTANTS: [u64; 24] = [
+        0x0000_0000_0000_0001,
+        0x0000_0000_0000_8082,
+        0x8000_0000_0000_808a,
+        0x8000_0000_8000_8000,
+        0x0000_0000_0000_808b,
+        0x0000_0000_8000_0001,
+        0x8000_0000_8000_8081,
+        0x8000_0000_0000_8009,
+        0x0000_0000_0000_008a,
+        0x0000_0000_0000_0088,
+        0x0000_0000_8000_8009,
+        0x0000_0000_8000_000a,
+        0x0000_0000_8000_808b,
+        0x8000_0000_0000_008b,
+        0x8000_0000_0000_8089,
+        0x8000_0000_0000_8003,
+        0x8000_0000_0000_8002,
+        0x8000_0000_0000_0080,
+        0x0000_0000_0000_800a,
+        0x8000_0000_8000_000a,
+        0x8000_0000_8000_8081,
+        0x8000_0000_0000_8080,
+        0x0000_0000_8000_0001,
+        0x8000_0000_8000_8008,
+    ];
+    const ROTATIONS: [u32; 24] = [
+        1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+    ];
+    const PI: [usize; 24] = [
+        10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+    ];
+
+    for round_constant in ROUND_CONSTANTS {
+        // θ
+        let mut columns = [0u64; 5];
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = a[x] ^ a[x + 5] ^ a[x + 10] ^ a[x + 15] ^ a[x + 20];
+        }
+        for x in 0..5 {
+            let d = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            for y in 0..5 {
+                a[x + 5 * y] ^= d;
+            }
+        }
+
+        // ρ and π
+        let mut carried = a[1];
+        for (&to, &rotation) in PI.iter().zip(&ROTATIONS) {
+            let next = a[to];
+            a[to] = carried.rotate_left(rotation);
+            carried = next;
+        }
+
+        // χ
+        for y in 0..5 {
+            let row = [
+                a[5 * y],
+                a[5 * y + 1],
+                a[5 * y + 2],
+                a[5 * y + 3],
+                a[5 * y + 4],
+            ];
+            for x in 0..5 {
+                a[5 * y + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+
+        // ι
+        a[0] ^= round_constant;
+    }
+}
@@ -0,0 +1,276 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Builder of Ethereum log and call filters, backed by the provider's block
+//! indexes.
+
+use prost::{Message, Name};
+use prost_wkt_types::Any;
+
+use crate::{hex_bytes, Request, TransformArgs, TransformError, TransformRegistry};
+
+use super::{
+    abi::{event_topic, function_selector},
+    CallToFilter, CombinedFilter, HeaderOnly, LogFilter,
+};
+
+/// Builds an Ethereum `CombinedFilter` transform, so a request only receives
+/// the transactions emitting matching logs or calling matching contracts.
+///
+/// Firehose Ethereum keeps block indexes of log addresses, event signatures
+/// and call targets over its merged blocks. With this filter, historical
+/// ranges whose index shows no match are skipped server-side, so sparse
+/// events are indexed at a fraction of the cost of a full stream; live blocks
+/// are always sent, stripped of non-matching transactions.
+///
+/// Within one [`logs`](EthereumFilter::logs) or
+/// [`calls`](EthereumFilter::calls) filter, an address *and* a signature
+/// must match, an empty list matching any; separate filters are combined
+/// with *or*.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{ethereum::EthereumFilter, Request};
+///
+/// # fn example(usdc: [u8; 20], transfer_topic: [u8; 32]) -> Result<(), Box<dyn std::error::Error>> {
+/// let request = EthereumFilter::new()
+///     .logs([usdc], [transfer_topic])
+///     .with_final_blocks_only()
+///     .apply(Request {
+///         start_block_num: 6_082_465,
+///         stop_block_num: 20_000_000,
+///         ..Default::default()
+///     })?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EthereumFilter {
+    filter: CombinedFilter,
+    final_blocks_only: bool,
+}
+
+impl EthereumFilter {
+    /// A filter matching nothing yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also match logs emitted by one of `addresses` with topic 0 in
+    /// `event_signatures`, the Keccak-256 hashes of the event signatures.
+    ///
+    /// Prefer [`events`](EthereumFilter::events) to hash signatures from
+    /// their Solidity text.
+    pub fn logs<A, S>(
+        mut self,
+        addresses: impl IntoIterator<Item = A>,
+        event_signatures: impl IntoIterator<Item = S>,
+    ) -> Self
+    where
+        A: AsRef<[u8]>,
+        S: AsRef<[u8]>,
+    {
+        self.filter.log_filters.push(LogFilter {
+            addresses: to_vecs(addresses),
+            event_signatures: to_vecs(event_signatures),
+        });
+        self
+    }
+
+    /// Also match calls to one of `addresses` whose 4-byte method selector is
+    /// in `signatures`.
+    pub fn calls<A, S>(
+        mut self,
+        addresses: impl IntoIterator<Item = A>,
+        signatures: impl IntoIterator<Item = S>,
+    ) -> Self
+    where
+        A: AsRef<[u8]>,
+        S: AsRef<[u8]>,
+    {
+        self.filter.call_filters.push(CallToFilter {
+            addresses: to_vecs(addresses),
+            signatures: to_vecs(signatures),
+        });
+        self
+    }
+
+    /// Send the header of every block, without transactions if none match,
+    /// instead of skipping non-matching blocks.
+    ///
+    /// Useful to track chain progress, at the cost of index skipping.
+    pub fn with_all_block_headers(mut self) -> Self {
+        self.filter.send_all_block_headers = true;
+        self
+    }
+
+    /// Only request final blocks, so indexed ranges are never followed by
+    /// reorganized blocks.
+    pub fn with_final_blocks_only(mut self) -> Self {
+        self.final_blocks_only = true;
+        self
+    }
+
+    /// Check the filter as the server would.
+    ///
+    /// A filter needs at least one log or call filter, each with an address
+    /// or a signature, as an empty one would match everything.
+    pub fn validate(&self) -> Result<(), TransformError> {
+        let invalid = |message: &str| TransformError::InvalidArguments {
+            name: CombinedFilter::full_name(),
+            message: message.to_string(),
+        };
+
+        if self.filter.log_filters.is_empty() && self.filter.call_filters.is_empty() {
+            return Err(invalid("no log or call filter"));
+        }
+        if self
+            .filter
+            .log_filters
+            .iter()
+            .any(|filter| filter.addresses.is_empty() && filter.event_signatures.is_empty())
+        {
+            return Err(invalid("a log filter has no address nor event signature"));
+        }
+        if self
+            .filter
+            .call_filters
+            .iter()
+            .any(|filter| filter.addresses.is_empty() && filter.signatures.is_empty())
+        {
+            return Err(invalid("a call filter has no address nor signature"));
+        }
+        Ok(())
+    }
+
+    /// The `CombinedFilter` message.
+    pub fn combined_filter(&self) -> &CombinedFilter {
+        &self.filter
+    }
+
+    /// The filter as a request transform.
+    pub fn to_any(&self) -> Any {
+        any(&self.filter)
+    }
+
+    /// Add the filter to `request`, and ask for final blocks only if
+    /// [requested](EthereumFilter::with_final_blocks_only).
+    ///
+    /// Fails if the request already has a `CombinedFilter` or `HeaderOnly`
+    /// transform, which providers would not apply together with this one.
+    pub fn apply(&self, mut request: Request) -> Result<Request, TransformError> {
+        self.validate()?;
+        request.transforms.push(self.to_any());
+        TransformRegistry::new()
+            .register_ethereum()
+            .validate(&request.transforms)?;
+        request.final_blocks_only |= self.final_blocks_only;
+        Ok(request)
+    }
+}
+
+impl TransformRegistry {
+    /// Register the Ethereum transforms:
+    ///
+    /// - `eth-filter`, a [`EthereumFilter`] with one log filter from
+    ///   `log_addresses` and `log_signatures`, and one call filter from
+    ///   `call_addresses` and `call_signatures`, each a comma-separated list
+    ///   of hex values; `log_events` and `call_functions` add Solidity
+    ///   signatures, separated by `;`, to the topics and selectors;
+    ///   `send_all_block_headers` may be `true`
+    /// - `eth-header-only`, block headers without transactions
+    ///
+    /// Requests may have only one `eth-filter`, and not with
+    /// `eth-header-only`.
+    pub fn register_ethereum(&mut self) -> &mut Self {
+        self.register(
+            &type_url::<CombinedFilter>(),
+            "eth-filter",
+            |args: &TransformArgs| {
+                let list = |key: &str| -> Result<Vec<Vec<u8>>, String> {
+                    args.get(key).map_or(Ok(Vec::new()), |values| {
+                        values
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|value| !value.is_empty())
+                            .map(hex_bytes::decode)
+                            .collect()
+                    })
+                };
+
+                let signatures = |key: &str| {
+                    args.get(key).into_iter().flat_map(|values| {
+                        values
+                            .split(';')
+                            .map(str::trim)
+                            .filter(|value| !value.is_empty())
+                    })
+                };
+
+                let mut filter = EthereumFilter::new();
+                let (log_addresses, mut log_signatures) =
+                    (list("log_addresses")?, list("log_signatures")?);
+                for event in signatures("log_events") {
+                    log_signatures.push(event_topic(event).map_err(|e| e.to_string())?.to_vec());
+                }
+                if !log_addresses.is_empty() || !log_signatures.is_empty() {
+                    filter = filter.logs(log_addresses, log_signatures);
+                }
+                let (call_addresses, mut call_signatures) =
+                    (list("call_addresses")?, list("call_signatures")?);
+                for function in signatures("call_functions") {
+                    call_signatures.push(
+                        function_selector(function)
+                            .map_err(|e| e.to_string())?
+                            .to_vec(),
+                    );
+                }
+                if !call_addresses.is_empty() || !call_signatures.is_empty() {
+                    filter = filter.calls(call_addresses, call_signatures);
+                }
+                match args.get("send_all_block_headers").map(String::as_str) {
+                    None | Some("false") => {}
+                    Some("true") => filter = filter.with_all_block_headers(),
+                    Some(other) => {
+                        return Err(format!("`send_all_block_headers` is `{other}`"));
+                    }
+                }
+
+                filter.validate().map_err(|e| e.to_string())?;
+                Ok(filter.filter.encode_to_vec())
+            },
+        )
+        .register_message("eth-header-only", HeaderOnly {})
+        .incompatible(
+            "eth-filter",
+            "eth-filter",
+            "only one CombinedFilter is applied, merge their log and call filters",
+        )
+        .incompatible(
+            "eth-filter",
+            "eth-header-only",
+            "HeaderOnly strips the transactions the CombinedFilter selects",
+        )
+    }
+}
+
+/// The `Any` of a transform message.
+fn any<M: Message + Name>(message: &M) -> Any {
+    Any {
+        type_url: type_url::<M>(),
+        value: message.encode_to_vec(),
+    }
+}
+
+fn type_url<M: Name>() -> String {
+    format!("type.googleapis.com/{}", M::full_name())
+}
+
+fn to_vecs<T: AsRef<[u8]>>(values: impl IntoIterator<Item = T>) -> Vec<Vec<u8>> {
+    values
+        .into_iter()
+        .map(|value| value.as_ref().to_vec())
+        .collect()
+}
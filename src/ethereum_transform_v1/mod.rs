@@ -0,0 +1,8 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod abi;
+pub mod filter;
+
+tonic::include_proto!("sf.ethereum.transform.v1");
@@ -0,0 +1,63 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Conversions between the legacy `sf.firehose.v1` messages and their v2
+//! counterparts.
+
+use crate::firehose_v2::{self, ForkStep as V2ForkStep};
+
+use super::{ForkStep, Request, Response};
+
+impl From<ForkStep> for V2ForkStep {
+    fn from(step: ForkStep) -> Self {
+        match step {
+            ForkStep::StepUnknown => V2ForkStep::StepUnset,
+            ForkStep::StepNew => V2ForkStep::StepNew,
+            ForkStep::StepUndo => V2ForkStep::StepUndo,
+            ForkStep::StepIrreversible => V2ForkStep::StepFinal,
+        }
+    }
+}
+
+/// Map a v1 [`Response`] into the v2 [`Response`](firehose_v2::Response) shape.
+///
+/// `STEP_IRREVERSIBLE` becomes `STEP_FINAL`. v1 servers never send block
+/// metadata, so `metadata` is always `None` on the converted response.
+impl From<Response> for firehose_v2::Response {
+    fn from(response: Response) -> Self {
+        let step = ForkStep::try_from(response.step)
+            .map(V2ForkStep::from)
+            .unwrap_or(V2ForkStep::StepUnset);
+
+        firehose_v2::Response {
+            block: response.block,
+            step: step.into(),
+            cursor: response.cursor,
+            metadata: None,
+        }
+    }
+}
+
+/// Map a v2 [`Request`](firehose_v2::Request) onto the equivalent v1 [`Request`].
+///
+/// `final_blocks_only` selects `STEP_IRREVERSIBLE`; otherwise the stream asks
+/// for `STEP_NEW` and `STEP_UNDO`, matching the default v2 behavior.
+impl From<firehose_v2::Request> for Request {
+    fn from(request: firehose_v2::Request) -> Self {
+        let fork_steps = if request.final_blocks_only {
+            vec![ForkStep::StepIrreversible.into()]
+        } else {
+            vec![ForkStep::StepNew.into(), ForkStep::StepUndo.into()]
+        };
+
+        Request {
+            start_block_num: request.start_block_num,
+            start_cursor: request.cursor,
+            stop_block_num: request.stop_block_num,
+            fork_steps,
+            transforms: request.transforms,
+            ..Default::default()
+        }
+    }
+}
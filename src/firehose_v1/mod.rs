@@ -0,0 +1,7 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod compat;
+
+tonic::include_proto!("sf.firehose.v1");
@@ -3,5 +3,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod request;
+pub mod summary;
 
 tonic::include_proto!("sf.firehose.v2");
+
+/// Encoded `FileDescriptorSet` for the compiled Firehose protos, written by
+/// `build.rs` alongside the generated code.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptors");
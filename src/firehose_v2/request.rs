@@ -2,13 +2,21 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    hash::{Hash, Hasher},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+use prost_wkt_types::Any;
+use sha2::{Digest, Sha256};
 
 use crate::BlockNumber;
 
 use super::{
-    single_block_request::{BlockHashAndNumber, Reference},
-    Response, SingleBlockRequest,
+    single_block_request::{BlockHashAndNumber, Cursor, Reference},
+    ForkStep, Request, Response, SingleBlockRequest, SingleBlockResponse,
 };
 
 impl SingleBlockRequest {
@@ -37,6 +45,193 @@ impl SingleBlockRequest {
             ..Default::default()
         }
     }
+
+    /// Put the request in canonical form, so requests for the same blocks
+    /// compare and hash equal.
+    ///
+    /// Sorts the transforms, trims whitespace around a cursor, and writes a
+    /// block hash in lowercase without `0x` prefix. Only use it when the
+    /// order of the transforms does not matter to the server.
+    pub fn normalize(&mut self) {
+        sort_transforms(&mut self.transforms);
+        match &mut self.reference {
+            Some(Reference::BlockHashAndNumber(BlockHashAndNumber { hash, .. })) => {
+                *hash = hash.trim().trim_start_matches("0x").to_ascii_lowercase();
+            }
+            Some(Reference::Cursor(Cursor { cursor })) => trim(cursor),
+            Some(Reference::BlockNumber(_)) | None => {}
+        }
+    }
+
+    /// A SHA-256 digest identifying the block this request asks for, stable
+    /// across versions like [`Request::fingerprint`].
+    pub fn fingerprint(&self) -> [u8; 32] {
+        let mut request = self.clone();
+        request.normalize();
+
+        let mut hasher = Fingerprint::new("sf.firehose.v2.SingleBlockRequest");
+        match &request.reference {
+            Some(Reference::BlockNumber(number)) => {
+                hasher.bytes(b"number");
+                hasher.bytes(&number.num.to_be_bytes());
+            }
+            Some(Reference::BlockHashAndNumber(block)) => {
+                hasher.bytes(b"hash");
+                hasher.bytes(&block.num.to_be_bytes());
+                hasher.bytes(block.hash.as_bytes());
+            }
+            Some(Reference::Cursor(cursor)) => {
+                hasher.bytes(b"cursor");
+                hasher.bytes(cursor.cursor.as_bytes());
+            }
+            None => hasher.bytes(b"none"),
+        }
+        hasher.transforms(&request.transforms);
+        hasher.finish()
+    }
+}
+
+impl Request {
+    /// Put the request in canonical form, so requests for the same blocks
+    /// compare and hash equal.
+    ///
+    /// Sorts the transforms and trims whitespace around the cursor. Only use
+    /// it when the order of the transforms does not matter to the server.
+    pub fn normalize(&mut self) {
+        sort_transforms(&mut self.transforms);
+        trim(&mut self.cursor);
+    }
+
+    /// A SHA-256 digest identifying the blocks this request asks for, to key
+    /// scheduled work and cached results.
+    ///
+    /// Computed over the [normalized](Request::normalize) request in a fixed
+    /// layout rather than its protobuf encoding, so the fingerprint of a
+    /// request stays the same across versions of this crate and of the
+    /// Firehose protos.
+    pub fn fingerprint(&self) -> [u8; 32] {
+        let mut request = self.clone();
+        request.normalize();
+
+        let mut hasher = Fingerprint::new("sf.firehose.v2.Request");
+        hasher.bytes(&request.start_block_num.to_be_bytes());
+        hasher.bytes(&request.stop_block_num.to_be_bytes());
+        hasher.bytes(&[u8::from(request.final_blocks_only)]);
+        hasher.bytes(request.cursor.as_bytes());
+        hasher.transforms(&request.transforms);
+        hasher.finish()
+    }
+}
+
+/// Requests are equal when their fields are, transforms in the same order.
+impl Eq for Request {}
+
+/// Consistent with `Eq`: hashes the protobuf encoding, which is the same for
+/// equal requests.
+impl Hash for Request {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.encode_to_vec().hash(state);
+    }
+}
+
+/// Requests are equal when their fields are, transforms in the same order.
+impl Eq for SingleBlockRequest {}
+
+/// Consistent with `Eq`: hashes the protobuf encoding, which is the same for
+/// equal requests.
+impl Hash for SingleBlockRequest {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.encode_to_vec().hash(state);
+    }
+}
+
+/// Hashes fields as length-prefixed byte strings, so that no two sequences of
+/// fields hash the same input.
+struct Fingerprint(Sha256);
+
+impl Fingerprint {
+    fn new(message: &str) -> Self {
+        let mut fingerprint = Fingerprint(Sha256::new());
+        fingerprint.bytes(b"firehose-rs fingerprint v1");
+        fingerprint.bytes(message.as_bytes());
+        fingerprint
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.update((bytes.len() as u64).to_be_bytes());
+        self.0.update(bytes);
+    }
+
+    fn transforms(&mut self, transforms: &[Any]) {
+        self.bytes(&(transforms.len() as u64).to_be_bytes());
+        for transform in transforms {
+            self.bytes(transform.type_url.as_bytes());
+            self.bytes(&transform.value);
+        }
+    }
+
+    fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+fn sort_transforms(transforms: &mut [Any]) {
+    transforms.sort_by(|a, b| {
+        (a.type_url.as_str(), a.value.as_slice()).cmp(&(b.type_url.as_str(), b.value.as_slice()))
+    });
+}
+
+fn trim(value: &mut String) {
+    let trimmed = value.trim();
+    if trimmed.len() != value.len() {
+        *value = trimmed.to_string();
+    }
+}
+
+impl Response {
+    /// Number of the block, from its metadata.
+    ///
+    /// Like the other accessors, returns `None` when the server sent no
+    /// [`BlockMetadata`](super::BlockMetadata), as older servers do.
+    pub fn block_number(&self) -> Option<u64> {
+        self.metadata.as_ref().map(|metadata| metadata.num)
+    }
+
+    /// Hash of the block, as the chain encodes it in block IDs.
+    pub fn block_hash(&self) -> Option<&str> {
+        self.metadata.as_ref().map(|metadata| metadata.id.as_str())
+    }
+
+    /// Number of the last irreversible block when this block was sent.
+    pub fn lib_number(&self) -> Option<u64> {
+        self.metadata.as_ref().map(|metadata| metadata.lib_num)
+    }
+
+    /// Time the block was produced.
+    ///
+    /// Also `None` for timestamps before the Unix epoch.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        let time = self.metadata.as_ref()?.time.as_ref()?;
+        Some(
+            UNIX_EPOCH
+                + Duration::from_secs(u64::try_from(time.seconds).ok()?)
+                + Duration::from_nanos(u64::try_from(time.nanos).ok()?),
+        )
+    }
+}
+
+/// A fetched block as a new block of a stream, with an empty cursor, so
+/// fetches share the accessors and [`FromResponse`] conversions of
+/// responses.
+impl From<SingleBlockResponse> for Response {
+    fn from(response: SingleBlockResponse) -> Self {
+        Response {
+            block: response.block,
+            step: ForkStep::StepNew.into(),
+            cursor: String::new(),
+            metadata: response.metadata,
+        }
+    }
 }
 
 /// Work with block numbers or slots in a unified way.
@@ -161,3 +356,62 @@ where
     /// if the conversion fails.
     fn from_response(msg: Response) -> Result<Self, Self::Error>;
 }
+
+/// Raw responses, for consumers that decode blocks themselves.
+impl FromResponse for Response {
+    type Error = std::convert::Infallible;
+
+    fn from_response(msg: Response) -> Result<Self, Self::Error> {
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_ONLY: &str = "type.googleapis.com/sf.ethereum.transform.v1.HeaderOnly";
+    const COMBINED_FILTER: &str = "type.googleapis.com/sf.ethereum.transform.v1.CombinedFilter";
+
+    fn transform(type_url: &str, value: &[u8]) -> Any {
+        Any {
+            type_url: type_url.to_string(),
+            value: value.to_vec(),
+        }
+    }
+
+    /// Fingerprints key stored work, so they must never change.
+    #[test]
+    fn request_fingerprint_is_stable() {
+        let mut request = Request {
+            start_block_num: 17_000_000,
+            stop_block_num: 17_000_099,
+            final_blocks_only: true,
+            cursor: " abc ".to_string(),
+            transforms: vec![
+                transform(HEADER_ONLY, &[]),
+                transform(COMBINED_FILTER, &[0x0a, 0x00]),
+            ],
+        };
+
+        let expected = "0x22dbfaeb752d72ac5941cc7a4aed0a387c6d292b8faf4a07dffeb9a2eba1fe04";
+        assert_eq!(crate::hex_bytes::encode(&request.fingerprint()), expected);
+
+        // Transform order does not matter.
+        request.transforms.reverse();
+        assert_eq!(crate::hex_bytes::encode(&request.fingerprint()), expected);
+    }
+
+    #[test]
+    fn single_block_request_fingerprint_is_stable() {
+        let request = SingleBlockRequest::new_by_block_hash_and_number(
+            "0xD4E56740F876AEF8C010B86A40D5F56745A118D0906A34E69AEC8C0DB1CB8FA3".to_string(),
+            0,
+        );
+
+        assert_eq!(
+            crate::hex_bytes::encode(&request.fingerprint()),
+            "0x9c0e1161d1f208ae57d119fadb07b10853bb8bfb5da2e8c4f362f4e48327c20e"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{self, Display};
+
+use super::{BlockMetadata, ForkStep, Response, SingleBlockResponse};
+
+/// Characters of hashes and cursors shown in summaries.
+const PREFIX_LEN: usize = 8;
+
+/// A compact, one-line description of a [`Response`], returned by
+/// [`Response::summary`].
+///
+/// Shows the block number, hash prefix, fork step, payload size and cursor
+/// prefix, e.g. `#17000000 1a2b3c4d… new 152.3 KB cursor Xo9cTj2k…`, instead
+/// of the raw bytes the derived [`Debug`] prints.
+#[derive(Clone, Copy, Debug)]
+pub struct ResponseSummary<'a> {
+    response: &'a Response,
+}
+
+impl Response {
+    /// A compact description of this response for logs, keeping the raw
+    /// payload out of them.
+    ///
+    /// `Response` also implements [`Display`] with the same output, while
+    /// [`Debug`] still prints every field.
+    pub fn summary(&self) -> ResponseSummary<'_> {
+        ResponseSummary { response: self }
+    }
+}
+
+impl Display for ResponseSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let response = self.response;
+        write_block(f, response.metadata.as_ref())?;
+        let step = ForkStep::try_from(response.step).unwrap_or(ForkStep::StepUnset);
+        write!(f, " {} ", step_label(step))?;
+        write_size(
+            f,
+            response.block.as_ref().map_or(0, |block| block.value.len()),
+        )?;
+        if !response.cursor.is_empty() {
+            write!(f, " cursor {}", Prefix(&response.cursor))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.summary().fmt(f)
+    }
+}
+
+/// Block number, hash prefix and payload size.
+impl Display for SingleBlockResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_block(f, self.metadata.as_ref())?;
+        write!(f, " ")?;
+        write_size(f, self.block.as_ref().map_or(0, |block| block.value.len()))
+    }
+}
+
+/// `#number hash…`, or `#? -` without metadata.
+pub(crate) fn write_block(
+    f: &mut fmt::Formatter<'_>,
+    metadata: Option<&BlockMetadata>,
+) -> fmt::Result {
+    match metadata {
+        Some(metadata) => write!(
+            f,
+            "#{} {}",
+            metadata.num,
+            Prefix(metadata.id.strip_prefix("0x").unwrap_or(&metadata.id))
+        ),
+        None => write!(f, "#? -"),
+    }
+}
+
+/// `bytes` in B, KB, MB or GB.
+pub(crate) fn write_size(f: &mut fmt::Formatter<'_>, bytes: usize) -> fmt::Result {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        write!(f, "{bytes} B")
+    } else {
+        write!(f, "{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn step_label(step: ForkStep) -> &'static str {
+    match step {
+        ForkStep::StepUnset => "unset",
+        ForkStep::StepNew => "new",
+        ForkStep::StepUndo => "undo",
+        ForkStep::StepFinal => "final",
+    }
+}
+
+/// The first characters of a hash or cursor.
+struct Prefix<'a>(&'a str);
+
+impl Display for Prefix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.char_indices().nth(PREFIX_LEN) {
+            Some((end, _)) => write!(f, "{}…", &self.0[..end]),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
@@ -0,0 +1,416 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Normalized row sets of decoded blocks, for analytics sinks.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prost_reflect::{DynamicMessage, FieldDescriptor, Kind, MapKey, MessageDescriptor, Value};
+use serde_json::Value as Json;
+
+use crate::{
+    base64,
+    schema::{flat_columns, FlatColumn},
+    verify_ethereum_roots, Column, ColumnType, DynamicDecodeError, DynamicDecoder, Response,
+    TableSchema,
+};
+
+/// One value of a flattened row, of its column's [`ColumnType`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Cell {
+    /// No value: an absent field or nested message.
+    Null,
+    /// A `Boolean` value.
+    Boolean(bool),
+    /// An `Int32` or `Int64` value.
+    Int(i64),
+    /// A `UInt32` or `UInt64` value.
+    UInt(u64),
+    /// A `Float` or `Double` value.
+    Float(f64),
+    /// A `String` value, or an enum value name.
+    String(String),
+    /// A `Binary` value.
+    Binary(Vec<u8>),
+    /// A `Timestamp` value.
+    Timestamp(SystemTime),
+    /// A `Json` value.
+    Json(Json),
+}
+
+impl Cell {
+    /// The cell as JSON, with binary values `0x`-prefixed hex and timestamps
+    /// in Unix seconds.
+    pub fn to_json(&self) -> Json {
+        match self {
+            Cell::Null => Json::Null,
+            Cell::Boolean(value) => Json::from(*value),
+            Cell::Int(value) => Json::from(*value),
+            Cell::UInt(value) => Json::from(*value),
+            Cell::Float(value) => Json::from(*value),
+            Cell::String(value) => Json::from(value.as_str()),
+            Cell::Binary(value) => Json::from(crate::hex_bytes::encode(value)),
+            Cell::Timestamp(time) => Json::from(
+                time.duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+            ),
+            Cell::Json(value) => value.clone(),
+        }
+    }
+}
+
+/// The rows one block produced for one table of a [`Flattener`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowSet {
+    /// Name of the table, as in [`Flattener::schemas`].
+    pub table: String,
+    /// Rows, with one [`Cell`] per column of the table.
+    pub rows: Vec<Vec<Cell>>,
+}
+
+/// Turns decoded blocks into normalized row sets, such as one row per block,
+/// per transaction and per log, with stable column names, so analytics sinks
+/// do not need hand-written mappings.
+///
+/// The block table has a row per block with the block message flattened as
+/// by [`TableSchema::from_message`], leaving out repeated messages. Each
+/// table added with [`with_rows`](Flattener::with_rows) has a row per element
+/// of a repeated message field, reached by a dotted path such as
+/// `transaction_traces.receipt.logs`.
+///
+/// Every row starts with `block_number` and `block_id`, from the block
+/// metadata. Rows of repeated messages follow with a `<field>_index` column
+/// per repeated field on their path, such as `transaction_traces_index` and
+/// `logs_index`, so they join with their parents.
+///
+/// Undo steps produce rows too; stream final blocks only, or use the step,
+/// when tables must not hold forked blocks.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{DynamicDecoder, Flattener, Response};
+///
+/// # fn example(response: Response) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decoder = DynamicDecoder::new()?;
+/// decoder.add_file_descriptor_set(std::fs::read("ethereum.binpb")?.as_slice())?;
+///
+/// let flattener = Flattener::ethereum(decoder)?;
+/// for schema in flattener.schemas() {
+///     println!("{}", schema.to_sql());
+/// }
+/// for rows in flattener.flatten(&response)? {
+///     println!("{}: {} rows", rows.table, rows.rows.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Flattener {
+    decoder: DynamicDecoder,
+    block: MessageDescriptor,
+    tables: Vec<FlatTable>,
+    /// Chain ID to verify Ethereum roots with.
+    verify_roots: Option<u64>,
+}
+
+#[derive(Clone, Debug)]
+struct FlatTable {
+    schema: TableSchema,
+    /// Fields from the block to the repeated messages of the rows.
+    path: Vec<FieldDescriptor>,
+    columns: Vec<FlatColumn>,
+}
+
+impl Flattener {
+    /// Flatten blocks of the message type named by `type_url` into the table
+    /// `table`, using the descriptors registered with `decoder`.
+    pub fn new(
+        decoder: DynamicDecoder,
+        type_url: &str,
+        table: &str,
+    ) -> Result<Self, DynamicDecodeError> {
+        let block = decoder.message_descriptor(type_url)?;
+        let root = FlatTable::new(table, Vec::new(), &block, &[]);
+        Ok(Flattener {
+            decoder,
+            block,
+            tables: vec![root],
+            verify_roots: None,
+        })
+    }
+
+    /// Ethereum blocks (`sf.ethereum.type.v2.Block`) into `eth_blocks`,
+    /// `eth_transactions` and `eth_logs`.
+    pub fn ethereum(decoder: DynamicDecoder) -> Result<Self, DynamicDecodeError> {
+        Flattener::new(decoder, "sf.ethereum.type.v2.Block", "eth_blocks")?
+            .with_rows("eth_transactions", "transaction_traces")?
+            .with_rows("eth_logs", "transaction_traces.receipt.logs")
+    }
+
+    /// Solana blocks (`sf.solana.type.v1.Block`) into `sol_blocks`,
+    /// `sol_transactions` and `sol_instructions`.
+    pub fn solana(decoder: DynamicDecoder) -> Result<Self, DynamicDecodeError> {
+        Flattener::new(decoder, "sf.solana.type.v1.Block", "sol_blocks")?
+            .with_rows("sol_transactions", "transactions")?
+            .with_rows(
+                "sol_instructions",
+                "transactions.transaction.message.instructions",
+            )
+    }
+
+    /// Add the table `table` with a row per element of the repeated message
+    /// field at the dotted `path` from the block.
+    ///
+    /// Fails with [`DynamicDecodeError::InvalidPath`] if a field of `path`
+    /// does not exist, or if it does not end at a repeated message field.
+    pub fn with_rows(mut self, table: &str, path: &str) -> Result<Self, DynamicDecodeError> {
+        let invalid = || DynamicDecodeError::InvalidPath(path.to_string());
+
+        let mut message = self.block.clone();
+        let mut fields = Vec::new();
+        for name in path.split('.') {
+            let field = message.get_field_by_name(name).ok_or_else(invalid)?;
+            let Kind::Message(inner) = field.kind() else {
+                return Err(invalid());
+            };
+            if field.is_map() {
+                return Err(invalid());
+            }
+            fields.push(field);
+            message = inner;
+        }
+        if !fields.last().is_some_and(FieldDescriptor::is_list) {
+            return Err(invalid());
+        }
+
+        let indexes: Vec<&FieldDescriptor> =
+            fields.iter().filter(|field| field.is_list()).collect();
+        let table = FlatTable::new(table, fields.clone(), &message, &indexes);
+        self.tables.push(table);
+        Ok(self)
+    }
+
+    /// Verify every Ethereum block against the transactions and receipts
+    /// roots of its header before flattening it, with
+    /// [`verify_ethereum_roots`](crate::verify_ethereum_roots), failing with
+    /// [`DynamicDecodeError::Roots`] on a mismatch.
+    ///
+    /// For [`Flattener::ethereum`] blocks of the chain `chain_id`, such as 1
+    /// for Ethereum mainnet.
+    pub fn with_root_verification(mut self, chain_id: u64) -> Self {
+        self.verify_roots = Some(chain_id);
+        self
+    }
+
+    /// The tables, block table first.
+    pub fn schemas(&self) -> impl Iterator<Item = &TableSchema> {
+        self.tables.iter().map(|table| &table.schema)
+    }
+
+    /// The decoder of blocks.
+    pub fn decoder(&self) -> &DynamicDecoder {
+        &self.decoder
+    }
+
+    /// Decode the block of `response` and flatten it into one [`RowSet`]
+    /// per table, in [`schemas`](Flattener::schemas) order.
+    ///
+    /// Returns no row sets if `response` carries no block.
+    pub fn flatten(&self, response: &Response) -> Result<Vec<RowSet>, DynamicDecodeError> {
+        let Some(block) = self.decoder.decode_response_block(response)? else {
+            return Ok(Vec::new());
+        };
+        if let Some(chain_id) = self.verify_roots {
+            verify_ethereum_roots(&block, chain_id).map_err(DynamicDecodeError::Roots)?;
+        }
+
+        let metadata = response.metadata.as_ref();
+        let prefix = [
+            metadata.map_or(Cell::Null, |metadata| Cell::UInt(metadata.num)),
+            metadata.map_or(Cell::Null, |metadata| Cell::String(metadata.id.clone())),
+        ];
+
+        Ok(self
+            .tables
+            .iter()
+            .map(|table| RowSet {
+                table: table.schema.name.clone(),
+                rows: table.rows(&block, &prefix),
+            })
+            .collect())
+    }
+}
+
+impl FlatTable {
+    fn new(
+        name: &str,
+        path: Vec<FieldDescriptor>,
+        message: &MessageDescriptor,
+        indexes: &[&FieldDescriptor],
+    ) -> Self {
+        let columns = flat_columns(message, true);
+        let mut schema = vec![
+            Column::nullable("block_number", ColumnType::UInt64),
+            Column::nullable("block_id", ColumnType::String),
+        ];
+        schema.extend(
+            indexes.iter().map(|field| {
+                Column::required(format!("{}_index", field.name()), ColumnType::UInt64)
+            }),
+        );
+        schema.extend(columns.iter().map(|flat| flat.column.clone()));
+
+        FlatTable {
+            schema: TableSchema::new(name, schema),
+            path,
+            columns,
+        }
+    }
+
+    /// The rows of `block`, each starting with `prefix`.
+    fn rows(&self, block: &DynamicMessage, prefix: &[Cell]) -> Vec<Vec<Cell>> {
+        // The messages reached so far, with the indexes of the repeated
+        // fields crossed to reach them
+        let mut reached = vec![(block.clone(), Vec::new())];
+        for field in &self.path {
+            let mut next = Vec::new();
+            for (message, indexes) in reached {
+                if !field.is_list() && !message.has_field(field) {
+                    continue;
+                }
+                match message.get_field(field).into_owned() {
+                    Value::Message(inner) => next.push((inner, indexes)),
+                    Value::List(elements) => {
+                        for (index, element) in elements.into_iter().enumerate() {
+                            if let Value::Message(inner) = element {
+                                let mut indexes = indexes.clone();
+                                indexes.push(Cell::UInt(index as u64));
+                                next.push((inner, indexes));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            reached = next;
+        }
+
+        reached
+            .into_iter()
+            .map(|(message, indexes)| {
+                let mut row = prefix.to_vec();
+                row.extend(indexes);
+                row.extend(self.columns.iter().map(|flat| cell(&message, flat)));
+                row
+            })
+            .collect()
+    }
+}
+
+/// The value of `column` in `message`.
+fn cell(message: &DynamicMessage, column: &FlatColumn) -> Cell {
+    let Some((leaf, parents)) = column.path.split_last() else {
+        return Cell::Null;
+    };
+
+    let mut message = message.clone();
+    for field in parents {
+        if !message.has_field(field) {
+            return Cell::Null;
+        }
+        match message.get_field(field).into_owned() {
+            Value::Message(inner) => message = inner,
+            _ => return Cell::Null,
+        }
+    }
+    if leaf.supports_presence() && !message.has_field(leaf) {
+        return Cell::Null;
+    }
+
+    let value = message.get_field(leaf);
+    match column.column.column_type {
+        ColumnType::Json => Cell::Json(json(&value, &leaf.kind())),
+        ColumnType::Timestamp => match &*value {
+            Value::Message(time) => timestamp(time).map_or(Cell::Null, Cell::Timestamp),
+            _ => Cell::Null,
+        },
+        _ => scalar(&value, &leaf.kind()),
+    }
+}
+
+fn scalar(value: &Value, kind: &Kind) -> Cell {
+    match value {
+        Value::Bool(value) => Cell::Boolean(*value),
+        Value::I32(value) => Cell::Int(i64::from(*value)),
+        Value::I64(value) => Cell::Int(*value),
+        Value::U32(value) => Cell::UInt(u64::from(*value)),
+        Value::U64(value) => Cell::UInt(*value),
+        Value::F32(value) => Cell::Float(f64::from(*value)),
+        Value::F64(value) => Cell::Float(*value),
+        Value::String(value) => Cell::String(value.clone()),
+        Value::Bytes(value) => Cell::Binary(value.to_vec()),
+        Value::EnumNumber(number) => Cell::String(enum_name(kind, *number)),
+        other => Cell::Json(json(other, kind)),
+    }
+}
+
+/// `value` of a field of `kind` in the canonical proto3 JSON mapping.
+fn json(value: &Value, kind: &Kind) -> Json {
+    match value {
+        Value::Message(message) => serde_json::to_value(message).unwrap_or(Json::Null),
+        Value::List(values) => values.iter().map(|value| json(value, kind)).collect(),
+        Value::Map(entries) => {
+            let value_kind = match kind {
+                Kind::Message(entry) => entry.map_entry_value_field().kind(),
+                _ => kind.clone(),
+            };
+            entries
+                .iter()
+                .map(|(key, value)| (map_key(key), json(value, &value_kind)))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        }
+        Value::Bool(value) => Json::from(*value),
+        Value::I32(value) => Json::from(*value),
+        Value::I64(value) => Json::from(value.to_string()),
+        Value::U32(value) => Json::from(*value),
+        Value::U64(value) => Json::from(value.to_string()),
+        Value::F32(value) => Json::from(*value),
+        Value::F64(value) => Json::from(*value),
+        Value::String(value) => Json::from(value.as_str()),
+        Value::Bytes(value) => Json::from(base64::encode(value)),
+        Value::EnumNumber(number) => Json::from(enum_name(kind, *number)),
+    }
+}
+
+fn map_key(key: &MapKey) -> String {
+    match key {
+        MapKey::Bool(key) => key.to_string(),
+        MapKey::I32(key) => key.to_string(),
+        MapKey::I64(key) => key.to_string(),
+        MapKey::U32(key) => key.to_string(),
+        MapKey::U64(key) => key.to_string(),
+        MapKey::String(key) => key.clone(),
+    }
+}
+
+/// Name of the enum value `number`, or the number if it is unknown.
+fn enum_name(kind: &Kind, number: i32) -> String {
+    match kind {
+        Kind::Enum(descriptor) => descriptor
+            .get_value(number)
+            .map_or_else(|| number.to_string(), |value| value.name().to_string()),
+        _ => number.to_string(),
+    }
+}
+
+/// A `google.protobuf.Timestamp` message as a time.
+fn timestamp(message: &DynamicMessage) -> Option<SystemTime> {
+    let seconds = message.get_field_by_name("seconds")?.as_i64()?;
+    let nanos = message.get_field_by_name("nanos")?.as_i32()?;
+    let since = Duration::new(u64::try_from(seconds).ok()?, u32::try_from(nanos).ok()?);
+    UNIX_EPOCH.checked_add(since)
+}
@@ -0,0 +1,240 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use tokio::{
+    sync::{mpsc, watch},
+    task::{JoinHandle, JoinSet},
+};
+
+use crate::{
+    Anchors, BlockRange, CallMetrics, EndpointPool, FirehoseError, RangePlanner, Request,
+    ResilientStream, Response, DEFAULT_SPAWN_BUFFER,
+};
+
+/// Receiver of the blocks of a [`Handoff`], with their [`Phase`].
+type Blocks = mpsc::Receiver<(Phase, Response)>;
+
+/// Which part of a [`Handoff`] a block comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// The historical backfill, up to the final head recorded at startup.
+    Backfill,
+    /// The live stream, from the block right after the backfill.
+    Live,
+}
+
+/// Backfills history, then switches to the live stream without gaps or
+/// duplicates.
+///
+/// On startup, the last final block of the pool is recorded as the handoff
+/// point. Final blocks from the request's start block up to that point are
+/// backfilled, by one stream or by several workers sharing a
+/// [`RangePlanner`]. Once every backfilled block has been delivered, the live
+/// stream opens right after the handoff point, with the request's own
+/// `final_blocks_only` setting, so reorgs past the handoff point arrive as
+/// undo steps.
+///
+/// A non-zero `stop_block_num` bounds the whole run; a stop block at or
+/// before the handoff point skips the live phase.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{EndpointPool, FirehoseEndpoint, Handoff, Phase, Request};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = EndpointPool::new([FirehoseEndpoint::from_env()?])?;
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     ..Default::default()
+/// };
+///
+/// let (task, mut blocks) = Handoff::new(pool, request)
+///     .with_workers(4, 100_000)
+///     .spawn();
+/// while let Some((phase, response)) = blocks.recv().await {
+///     if phase == Phase::Live {
+///         // Caught up with the chain.
+///     }
+/// }
+/// task.await??;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Handoff {
+    pool: EndpointPool,
+    request: Request,
+    workers: usize,
+    unit_size: u64,
+    buffer: usize,
+    anchors: Option<Anchors>,
+    metrics: Option<(CallMetrics, String)>,
+    planner: watch::Sender<Option<RangePlanner>>,
+}
+
+impl Handoff {
+    /// Backfill and follow `request`, whose `start_block_num` is the first
+    /// block to deliver, from the endpoints of `pool`. A negative start block
+    /// counts back from the handoff point.
+    pub fn new(pool: EndpointPool, request: Request) -> Self {
+        Handoff {
+            pool,
+            request,
+            workers: 1,
+            unit_size: u64::MAX,
+            buffer: DEFAULT_SPAWN_BUFFER,
+            anchors: None,
+            metrics: None,
+            planner: watch::Sender::new(None),
+        }
+    }
+
+    /// Shard the backfill across `workers` streams, in units of `unit_size`
+    /// blocks.
+    ///
+    /// Backfilled blocks are then delivered in order within a unit, but units
+    /// interleave. Units stolen from a stalled worker may repeat a few
+    /// blocks; see [`RangePlanner`].
+    pub fn with_workers(mut self, workers: usize, unit_size: u64) -> Self {
+        self.workers = workers.max(1);
+        self.unit_size = unit_size;
+        self
+    }
+
+    /// Buffer up to `buffer` blocks before waiting for the receiver.
+    pub fn with_buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer.max(1);
+        self
+    }
+
+    /// Verify backfilled and live blocks against `anchors`, failing with
+    /// [`FirehoseError::AnchorMismatch`] on the first block at an anchored
+    /// height with another hash.
+    pub fn with_anchors(mut self, anchors: Anchors) -> Self {
+        self.anchors = Some(anchors);
+        self
+    }
+
+    /// Record the metrics of every stream in `metrics`, as
+    /// [`ResilientStream::with_metrics`] does, under `{label}/backfill-{worker}`
+    /// for each backfill worker and `{label}/live` for the live stream.
+    pub fn with_metrics(mut self, metrics: CallMetrics, label: impl Into<String>) -> Self {
+        self.metrics = Some((metrics, label.into()));
+        self
+    }
+
+    /// The [`RangePlanner`] of the backfill, to follow its progress per unit.
+    ///
+    /// Holds `None` until the handoff point is known and the backfill starts.
+    pub fn planner(&self) -> watch::Receiver<Option<RangePlanner>> {
+        self.planner.subscribe()
+    }
+
+    /// Run the backfill and the live stream on a background task.
+    ///
+    /// Blocks are sent to the returned receiver with their [`Phase`]. Every
+    /// backfilled block is sent before the first live one. The task ends once
+    /// a bounded request is complete or the receiver is dropped, and with the
+    /// first error otherwise.
+    pub fn spawn(self) -> (JoinHandle<Result<(), FirehoseError>>, Blocks) {
+        let (sender, receiver) = mpsc::channel(self.buffer);
+        (tokio::spawn(self.run(sender)), receiver)
+    }
+
+    async fn run(self, sender: mpsc::Sender<(Phase, Response)>) -> Result<(), FirehoseError> {
+        let head = self.pool.final_head().await?;
+        // Negative start blocks are relative to the head, as in Firehose.
+        let start = u64::try_from(self.request.start_block_num)
+            .unwrap_or_else(|_| head.saturating_add_signed(self.request.start_block_num + 1));
+        let stop = match self.request.stop_block_num {
+            0 => u64::MAX,
+            stop => stop,
+        };
+
+        let backfill = BlockRange::new(start, head.min(stop));
+        if !backfill.is_empty() && !self.backfill(backfill, &sender).await? {
+            return Ok(());
+        }
+        if stop <= head {
+            return Ok(());
+        }
+
+        let live = Request {
+            start_block_num: head.max(start.saturating_sub(1)) as i64 + 1,
+            cursor: String::new(),
+            ..self.request.clone()
+        };
+        let mut stream = ResilientStream::new(self.pool, live);
+        if let Some(anchors) = self.anchors {
+            stream = stream.with_anchors(anchors);
+        }
+        if let Some((metrics, label)) = self.metrics {
+            stream = stream.with_metrics(metrics, format!("{label}/live"));
+        }
+        while let Some(response) = stream.message().await? {
+            if sender.send((Phase::Live, response)).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deliver the final blocks of `range`, returning `false` if the receiver
+    /// was dropped.
+    async fn backfill(
+        &self,
+        range: BlockRange,
+        sender: &mpsc::Sender<(Phase, Response)>,
+    ) -> Result<bool, FirehoseError> {
+        let template = Request {
+            final_blocks_only: true,
+            cursor: String::new(),
+            ..self.request.clone()
+        };
+
+        let planner = RangePlanner::new(range, self.unit_size);
+        self.planner.send_replace(Some(planner.clone()));
+        let mut workers = JoinSet::new();
+        for worker in 0..self.workers {
+            let (planner, pool, template, sender, anchors, metrics) = (
+                planner.clone(),
+                self.pool.clone(),
+                template.clone(),
+                sender.clone(),
+                self.anchors.clone(),
+                self.metrics.clone(),
+            );
+            workers.spawn(async move {
+                while let Some(unit) = planner.next(worker) {
+                    let mut stream = ResilientStream::new(pool.clone(), unit.request(&template));
+                    if let Some(anchors) = &anchors {
+                        stream = stream.with_anchors(anchors.clone());
+                    }
+                    if let Some((metrics, label)) = &metrics {
+                        stream = stream
+                            .with_metrics(metrics.clone(), format!("{label}/backfill-{worker}"));
+                    }
+                    while let Some(response) = stream.message().await? {
+                        let block = response.block_number();
+                        if sender.send((Phase::Backfill, response)).await.is_err() {
+                            return Ok(false);
+                        }
+                        if let Some(block) = block {
+                            planner.progress(&unit, worker, block);
+                        }
+                    }
+                    planner.complete(&unit)?;
+                }
+                Ok::<_, FirehoseError>(true)
+            });
+        }
+
+        let mut delivered = true;
+        while let Some(joined) = workers.join_next().await {
+            delivered &= joined.map_err(|e| FirehoseError::Processing(e.to_string()))??;
+        }
+        Ok(delivered)
+    }
+}
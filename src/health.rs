@@ -0,0 +1,366 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Liveness and readiness of streams and sinks, for Kubernetes probes.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "sink")]
+use crate::{
+    sink::{Sink, SinkError},
+    Response,
+};
+
+/// Aggregates the health of named streams into `healthy()` and `ready()`
+/// answers, for liveness and readiness probes.
+///
+/// A stream is *healthy* while blocks keep arriving, at most
+/// `max_silence` apart, and its sink is not failing: a stream that is not
+/// healthy needs a restart. It is *ready* once it received a block and is
+/// healthy and, with [`with_max_lag`](HealthReporter::with_max_lag), close
+/// enough to the chain head, so traffic can be routed to it. The reporter is
+/// healthy or ready when all its streams are.
+///
+/// Feed it with
+/// [`ResilientStream::with_health`](crate::ResilientStream::with_health) and
+/// [`HealthReporter::watch_sink`], or the `record_*` methods. Clones share the
+/// same state. With the `health` feature,
+/// [`health_service`](HealthReporter::health_service) serves it as the
+/// standard gRPC health checking protocol.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use firehose_rs::{FirehoseEndpoint, HealthReporter, Request, ResilientStream};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let health = HealthReporter::new(Duration::from_secs(60)).with_max_lag(100);
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+/// let mut stream = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?
+///     .with_health(health.clone(), "blocks");
+///
+/// // In the probe handlers
+/// let live = health.healthy();
+/// let ready = health.ready();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HealthReporter {
+    streams: Arc<Mutex<BTreeMap<String, Component>>>,
+    max_silence: Duration,
+    max_lag: Option<u64>,
+}
+
+/// What a [`HealthReporter`] knows of one stream.
+#[derive(Clone, Debug)]
+struct Component {
+    registered: Instant,
+    last_block: Option<Instant>,
+    lag: Option<u64>,
+    sink_error: Option<String>,
+    sink_lag: Option<u64>,
+}
+
+/// Health of one stream, from [`HealthReporter::report`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamHealth {
+    /// Name the stream was registered with.
+    pub name: String,
+    /// Whether blocks arrive and the sink is not failing.
+    pub healthy: bool,
+    /// Whether the stream is healthy, received a block and is not lagging.
+    pub ready: bool,
+    /// Why the stream is not ready, if it is not.
+    pub reason: Option<String>,
+    /// Time since the last block, if one arrived.
+    pub since_last_block: Option<Duration>,
+    /// Blocks behind the chain head at the last block, if known.
+    pub lag: Option<u64>,
+    /// Blocks the watched sink wrote since its last flush, which it would
+    /// write again after a crash, or `None` if no sink is watched.
+    pub sink_lag: Option<u64>,
+}
+
+impl HealthReporter {
+    /// A reporter considering streams dead after `max_silence` without a
+    /// block.
+    ///
+    /// Pick it well above the chain's block time, and above the stall timeout
+    /// of [`ResilientStream`](crate::ResilientStream)s, which reconnect on
+    /// their own first.
+    pub fn new(max_silence: Duration) -> Self {
+        HealthReporter {
+            streams: Arc::default(),
+            max_silence,
+            max_lag: None,
+        }
+    }
+
+    /// Only consider streams ready while at most `blocks` behind the chain
+    /// head.
+    pub fn with_max_lag(mut self, blocks: u64) -> Self {
+        self.max_lag = Some(blocks);
+        self
+    }
+
+    /// Track the stream `name`, which is healthy but not ready until its
+    /// first block.
+    pub fn register(&self, name: &str) {
+        self.lock()
+            .entry(name.to_string())
+            .or_insert_with(|| Component {
+                registered: Instant::now(),
+                last_block: None,
+                lag: None,
+                sink_error: None,
+                sink_lag: None,
+            });
+    }
+
+    /// Record a block received by the stream `name`.
+    pub fn record_block(&self, name: &str) {
+        self.register(name);
+        if let Some(component) = self.lock().get_mut(name) {
+            component.last_block = Some(Instant::now());
+        }
+    }
+
+    /// Record that the stream `name` is `blocks` behind the chain head.
+    pub fn record_lag(&self, name: &str, blocks: u64) {
+        self.register(name);
+        if let Some(component) = self.lock().get_mut(name) {
+            component.lag = Some(blocks);
+        }
+    }
+
+    /// Record that the sink of the stream `name` failed with `error`, until
+    /// a [success](HealthReporter::record_sink_ok).
+    pub fn record_sink_error(&self, name: &str, error: &str) {
+        self.register(name);
+        if let Some(component) = self.lock().get_mut(name) {
+            component.sink_error = Some(error.to_string());
+        }
+    }
+
+    /// Record that the sink of the stream `name` wrote or flushed blocks.
+    pub fn record_sink_ok(&self, name: &str) {
+        if let Some(component) = self.lock().get_mut(name) {
+            component.sink_error = None;
+        }
+    }
+
+    /// Record that the sink of the stream `name` wrote a block, not durable
+    /// until the next [flush](HealthReporter::record_sink_flush).
+    pub fn record_sink_write(&self, name: &str) {
+        self.register(name);
+        if let Some(component) = self.lock().get_mut(name) {
+            component.sink_lag = Some(component.sink_lag.unwrap_or(0) + 1);
+        }
+    }
+
+    /// Record that the sink of the stream `name` made every block written so
+    /// far durable.
+    pub fn record_sink_flush(&self, name: &str) {
+        self.register(name);
+        if let Some(component) = self.lock().get_mut(name) {
+            component.sink_lag = Some(0);
+        }
+    }
+
+    /// Stop tracking the stream `name`, for example once it completed.
+    pub fn remove(&self, name: &str) {
+        self.lock().remove(name);
+    }
+
+    /// Whether every stream is healthy, for liveness probes.
+    pub fn healthy(&self) -> bool {
+        self.report().iter().all(|stream| stream.healthy)
+    }
+
+    /// Whether every stream is ready, for readiness probes.
+    ///
+    /// A reporter without streams is not ready.
+    pub fn ready(&self) -> bool {
+        let report = self.report();
+        !report.is_empty() && report.iter().all(|stream| stream.ready)
+    }
+
+    /// Health of every stream, by name.
+    pub fn report(&self) -> Vec<StreamHealth> {
+        let now = Instant::now();
+        self.lock()
+            .iter()
+            .map(|(name, component)| {
+                let since_last_block = component.last_block.map(|last| now.duration_since(last));
+                let silence =
+                    since_last_block.unwrap_or_else(|| now.duration_since(component.registered));
+
+                let unhealthy = if let Some(error) = &component.sink_error {
+                    Some(format!("sink failing: {error}"))
+                } else if silence > self.max_silence {
+                    Some(format!("no block for {silence:?}"))
+                } else {
+                    None
+                };
+                let reason = unhealthy.clone().or_else(|| {
+                    if component.last_block.is_none() {
+                        Some("no block received yet".to_string())
+                    } else {
+                        match (component.lag, self.max_lag) {
+                            (Some(lag), Some(max)) if lag > max => {
+                                Some(format!("{lag} blocks behind the head"))
+                            }
+                            _ => None,
+                        }
+                    }
+                });
+
+                StreamHealth {
+                    name: name.clone(),
+                    healthy: unhealthy.is_none(),
+                    ready: reason.is_none(),
+                    reason,
+                    since_last_block,
+                    lag: component.lag,
+                    sink_lag: component.sink_lag,
+                }
+            })
+            .collect()
+    }
+
+    /// Wrap `sink` so its failures and successes, and the blocks it wrote
+    /// since its last flush, are recorded for the stream `name`.
+    #[cfg(feature = "sink")]
+    pub fn watch_sink<S: Sink>(&self, name: &str, sink: S) -> HealthSink<S> {
+        self.record_sink_flush(name);
+        HealthSink {
+            inner: sink,
+            reporter: self.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    /// A gRPC health service reporting the reporter's readiness as the
+    /// overall status (the empty service name) and each stream's readiness
+    /// under its name, refreshed every `interval`.
+    ///
+    /// Add it to a tonic server for Kubernetes gRPC probes. The refresh task
+    /// stops once every other clone of the reporter is dropped.
+    #[cfg(feature = "health")]
+    pub fn health_service(
+        &self,
+        interval: Duration,
+    ) -> tonic_health::pb::health_server::HealthServer<impl tonic_health::pb::health_server::Health>
+    {
+        use tonic_health::ServingStatus;
+
+        let (service_reporter, server) = tonic_health::server::health_reporter();
+        let streams = Arc::downgrade(&self.streams);
+        let (max_silence, max_lag) = (self.max_silence, self.max_lag);
+
+        tokio::spawn(async move {
+            let status = |ready: bool| {
+                if ready {
+                    ServingStatus::Serving
+                } else {
+                    ServingStatus::NotServing
+                }
+            };
+
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(streams) = streams.upgrade() else {
+                    return;
+                };
+                let reporter = HealthReporter {
+                    streams,
+                    max_silence,
+                    max_lag,
+                };
+
+                service_reporter
+                    .set_service_status("", status(reporter.ready()))
+                    .await;
+                for stream in reporter.report() {
+                    service_reporter
+                        .set_service_status(&stream.name, status(stream.ready))
+                        .await;
+                }
+            }
+        });
+        server
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Component>> {
+        self.streams.lock().expect("health lock poisoned")
+    }
+}
+
+/// Sink recording its failures and successes in a [`HealthReporter`], from
+/// [`HealthReporter::watch_sink`].
+#[cfg(feature = "sink")]
+#[derive(Debug)]
+pub struct HealthSink<S> {
+    inner: S,
+    reporter: HealthReporter,
+    name: String,
+}
+
+#[cfg(feature = "sink")]
+impl<S> HealthSink<S> {
+    /// The wrapped sink.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn record<T>(&self, result: Result<T, SinkError>) -> Result<T, SinkError> {
+        match &result {
+            Ok(_) => self.reporter.record_sink_ok(&self.name),
+            Err(e) => self.reporter.record_sink_error(&self.name, &e.to_string()),
+        }
+        result
+    }
+}
+
+#[cfg(feature = "sink")]
+impl<S: Sink> Sink for HealthSink<S> {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        let result = self.inner.write(response).await;
+        if result.is_ok() {
+            self.reporter.record_sink_write(&self.name);
+        }
+        self.record(result)
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        let result = self.inner.flush().await;
+        if result.is_ok() {
+            self.reporter.record_sink_flush(&self.name);
+        }
+        self.record(result)
+    }
+
+    async fn finish(&mut self) -> Result<(), SinkError> {
+        let result = self.inner.finish().await;
+        if result.is_ok() {
+            self.reporter.record_sink_flush(&self.name);
+        }
+        self.record(result)
+    }
+
+    fn at_boundary(&self) -> bool {
+        self.inner.at_boundary()
+    }
+}
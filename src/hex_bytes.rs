@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serde helpers rendering `bytes` fields as `0x`-prefixed hex strings.
+//!
+//! By default serde renders `Vec<u8>` as a list of integers, which is neither
+//! readable nor what Ethereum tooling expects. Apply this module with
+//! `#[serde(with = "firehose_rs::hex_bytes")]` to serialize byte fields as
+//! `"0xdeadbeef"` instead. Deserialization accepts hex with or without the
+//! `0x` prefix.
+//!
+//! When generating chain block types with `prost-build`, attach it to the
+//! `bytes` fields you care about:
+//!
+//! ```rust,ignore
+//! config.field_attribute(
+//!     ".sf.ethereum.type.v2.BlockHeader.hash",
+//!     "#[serde(with = \"firehose_rs::hex_bytes\")]",
+//! );
+//! config.field_attribute(
+//!     ".sf.ethereum.type.v2.Log.topics",
+//!     "#[serde(with = \"firehose_rs::hex_bytes::repeated\")]",
+//! );
+//! ```
+//!
+//! # Example
+//!
+//! ```rust
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Header {
+//!     #[serde(with = "firehose_rs::hex_bytes")]
+//!     hash: Vec<u8>,
+//! }
+//!
+//! let header = Header { hash: vec![0xde, 0xad, 0xbe, 0xef] };
+//! let json = serde_json::to_string(&header).unwrap();
+//! assert_eq!(json, r#"{"hash":"0xdeadbeef"}"#);
+//!
+//! let header: Header = serde_json::from_str(r#"{"hash":"DEADBEEF"}"#).unwrap();
+//! assert_eq!(header.hash, vec![0xde, 0xad, 0xbe, 0xef]);
+//! ```
+
+use std::fmt;
+
+use serde::{
+    de::{self, Visitor},
+    Deserializer, Serializer,
+};
+
+/// Serialize bytes as a `0x`-prefixed lowercase hex string.
+pub fn serialize<T, S>(bytes: T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: AsRef<[u8]>,
+    S: Serializer,
+{
+    serializer.serialize_str(&encode(bytes.as_ref()))
+}
+
+/// Deserialize bytes from a hex string, with or without the `0x` prefix.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_str(HexVisitor)
+}
+
+/// The same encoding for `repeated bytes` fields (`Vec<Vec<u8>>`).
+pub mod repeated {
+    use serde::{
+        de::{SeqAccess, Visitor},
+        ser::SerializeSeq,
+        Deserializer, Serializer,
+    };
+    use std::fmt;
+
+    /// Serialize each element as a `0x`-prefixed lowercase hex string.
+    pub fn serialize<S>(values: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            seq.serialize_element(&super::encode(value))?;
+        }
+        seq.end()
+    }
+
+    /// Deserialize a list of hex strings, each with or without the `0x` prefix.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct HexSeqVisitor;
+
+        impl<'de> Visitor<'de> for HexSeqVisitor {
+            type Value = Vec<Vec<u8>>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a list of hex strings")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(value) = seq.next_element::<String>()? {
+                    values.push(super::decode(&value).map_err(serde::de::Error::custom)?);
+                }
+                Ok(values)
+            }
+        }
+
+        deserializer.deserialize_seq(HexSeqVisitor)
+    }
+}
+
+struct HexVisitor;
+
+impl Visitor<'_> for HexVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a hex string")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        decode(value).map_err(E::custom)
+    }
+}
+
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut out = String::with_capacity(2 + bytes.len() * 2);
+    out.push_str("0x");
+    for byte in bytes {
+        out.push(DIGITS[(byte >> 4) as usize] as char);
+        out.push(DIGITS[(byte & 0x0f) as usize] as char);
+    }
+    out
+}
+
+pub(crate) fn decode(value: &str) -> Result<Vec<u8>, String> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+
+    if digits.len() % 2 != 0 {
+        return Err(format!("hex string `{value}` has an odd number of digits"));
+    }
+
+    digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let high = nibble(pair[0]);
+            let low = nibble(pair[1]);
+            match (high, low) {
+                (Some(high), Some(low)) => Ok((high << 4) | low),
+                _ => Err(format!("invalid hex string `{value}`")),
+            }
+        })
+        .collect()
+}
+
+fn nibble(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
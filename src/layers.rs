@@ -0,0 +1,620 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use tonic::{codegen::http, Code};
+use tower::{Layer, Service};
+
+use crate::{
+    request_id, Backoff, FirehoseError, RetryBudget, SingleBlockRequest, SingleBlockResponse,
+};
+
+/// Boxed future returned by the services of this module.
+type BoxFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+/// One gRPC call seen by an [`ObserveLayer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallRecord {
+    /// The gRPC method path, e.g. `/sf.firehose.v2.Fetch/Block`.
+    pub method: String,
+    /// The status code from the response headers; `Ok` when the status only
+    /// follows in the trailers, as for streams. `None` if the call failed
+    /// before a response arrived.
+    pub code: Option<Code>,
+    /// Time until the response headers arrived.
+    pub duration: Duration,
+    /// The `x-request-id` of the call, added if the call had none.
+    pub request_id: Option<String>,
+}
+
+/// Receives a [`CallRecord`] for every call through an [`ObserveLayer`].
+///
+/// Implemented by closures, so logging is a matter of passing one that writes
+/// to the application's logger.
+pub trait CallObserver: Clone + Send + Sync + 'static {
+    /// Called once the response headers arrived or the call failed.
+    fn observe(&self, call: &CallRecord);
+}
+
+impl<F> CallObserver for F
+where
+    F: Fn(&CallRecord) + Clone + Send + Sync + 'static,
+{
+    fn observe(&self, call: &CallRecord) {
+        self(call)
+    }
+}
+
+/// Layer reporting every gRPC call of a channel to a [`CallObserver`].
+///
+/// Use it with a closure for logging, or with [`CallMetrics`] for counters.
+#[derive(Clone, Debug)]
+pub struct ObserveLayer<O> {
+    observer: O,
+}
+
+impl<O: CallObserver> ObserveLayer<O> {
+    /// Report calls to `observer`.
+    pub fn new(observer: O) -> Self {
+        ObserveLayer { observer }
+    }
+}
+
+impl<S, O: Clone> Layer<S> for ObserveLayer<O> {
+    type Service = ObserveService<S, O>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ObserveService {
+            inner,
+            observer: self.observer.clone(),
+        }
+    }
+}
+
+/// Service created by [`ObserveLayer`].
+#[derive(Clone, Debug)]
+pub struct ObserveService<S, O> {
+    inner: S,
+    observer: O,
+}
+
+impl<S, O, B, R> Service<http::Request<B>> for ObserveService<S, O>
+where
+    S: Service<http::Request<B>, Response = http::Response<R>>,
+    S::Future: Send + 'static,
+    O: CallObserver,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<S::Response, S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        let method = request.uri().path().to_string();
+        let request_id = request_id::ensure_header(request.headers_mut());
+        let observer = self.observer.clone();
+        let started = Instant::now();
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            let result = future.await;
+            let code = result.as_ref().ok().map(|response| {
+                response
+                    .headers()
+                    .get("grpc-status")
+                    .map_or(Code::Ok, |status| Code::from_bytes(status.as_bytes()))
+            });
+            observer.observe(&CallRecord {
+                method,
+                code,
+                duration: started.elapsed(),
+                request_id,
+            });
+            result
+        })
+    }
+}
+
+/// Call counters of one gRPC method, from [`CallMetrics::snapshot`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MethodMetrics {
+    /// Calls made.
+    pub calls: u64,
+    /// Calls that failed with a status in the headers or before a response.
+    pub errors: u64,
+    /// Sum of the time until the response headers arrived.
+    pub total_duration: Duration,
+}
+
+/// Counts of values per fixed bucket, each bucket holding the values above
+/// the previous bound up to its own. Unlike Prometheus histograms the counts
+/// are not cumulative; sum the buckets up to a bound to get its `le` count.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    /// Inclusive upper bounds of the buckets, ascending.
+    pub bounds: Vec<u64>,
+    /// Values recorded in each bucket, with one more for values above the
+    /// last bound.
+    pub counts: Vec<u64>,
+    /// Values recorded.
+    pub count: u64,
+    /// Sum of the values recorded.
+    pub sum: u64,
+}
+
+impl Histogram {
+    /// An empty histogram with buckets up to each of `bounds`.
+    pub fn new(mut bounds: Vec<u64>) -> Self {
+        bounds.sort_unstable();
+        bounds.dedup();
+        Histogram {
+            counts: vec![0; bounds.len() + 1],
+            bounds,
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    /// Record `value`.
+    pub fn record(&mut self, value: u64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+    }
+
+    /// Mean of the values recorded, if any.
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum as f64 / self.count as f64)
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, `q` between 0 and
+    /// 1, or `None` if no value was recorded or it is above the last bound.
+    pub fn quantile(&self, q: f64) -> Option<u64> {
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return self.bounds.get(bucket).copied();
+            }
+        }
+        None
+    }
+}
+
+/// Buckets of [`StreamMetrics::block_size`], in bytes: powers of 4 from
+/// 1 KiB to 64 MiB.
+pub const BLOCK_SIZE_BUCKETS: [u64; 9] = [
+    1 << 10,
+    1 << 12,
+    1 << 14,
+    1 << 16,
+    1 << 18,
+    1 << 20,
+    1 << 22,
+    1 << 24,
+    1 << 26,
+];
+
+/// Buckets of [`StreamMetrics::inter_arrival`], in milliseconds.
+pub const INTER_ARRIVAL_BUCKETS: [u64; 12] = [
+    10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000,
+];
+
+/// Block histograms of one stream label, from [`CallMetrics::streams`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamMetrics {
+    /// Encoded size of each block, in bytes.
+    pub block_size: Histogram,
+    /// Time between consecutive blocks, in milliseconds.
+    ///
+    /// Reconnects and stalls show up as outliers, and throttling providers
+    /// as a shift of the whole distribution.
+    pub inter_arrival: Histogram,
+    /// Blocks between the last block received and the head feed, when the
+    /// stream has one, as of the last block.
+    pub lag_blocks: Option<u64>,
+    /// Age of the last block received, from its timestamp.
+    pub lag: Option<Duration>,
+    /// Times the stream was reopened after a failure or stall.
+    pub reconnects: u64,
+    /// When the last block was recorded.
+    last_block: Option<Instant>,
+}
+
+impl Default for StreamMetrics {
+    fn default() -> Self {
+        StreamMetrics {
+            block_size: Histogram::new(BLOCK_SIZE_BUCKETS.to_vec()),
+            inter_arrival: Histogram::new(INTER_ARRIVAL_BUCKETS.to_vec()),
+            lag_blocks: None,
+            lag: None,
+            reconnects: 0,
+            last_block: None,
+        }
+    }
+}
+
+/// Per-method call counters, fed by an [`ObserveLayer`], and per-stream
+/// block histograms, fed by [`ResilientStream`](crate::ResilientStream)s.
+///
+/// Clones share the same counters.
+#[derive(Clone, Debug, Default)]
+pub struct CallMetrics {
+    methods: Arc<Mutex<BTreeMap<String, MethodMetrics>>>,
+    streams: Arc<Mutex<BTreeMap<String, StreamMetrics>>>,
+}
+
+/// [`ObserveLayer`] recording into [`CallMetrics`].
+pub type MetricsLayer = ObserveLayer<CallMetrics>;
+
+impl CallMetrics {
+    /// Create empty counters.
+    pub fn new() -> Self {
+        CallMetrics::default()
+    }
+
+    /// A layer recording the calls of a channel into these counters.
+    pub fn layer(&self) -> MetricsLayer {
+        ObserveLayer::new(self.clone())
+    }
+
+    /// Counters of every method called so far, by method path.
+    pub fn snapshot(&self) -> BTreeMap<String, MethodMetrics> {
+        self.methods.lock().expect("metrics lock poisoned").clone()
+    }
+
+    /// Record a block of `bytes` received by the stream `label`, and the
+    /// time since its previous one.
+    ///
+    /// [`ResilientStream::with_metrics`](crate::ResilientStream::with_metrics)
+    /// calls this for every block; call it directly for other streams.
+    pub fn record_block(&self, label: &str, bytes: u64) {
+        let now = Instant::now();
+        let mut streams = self.streams.lock().expect("metrics lock poisoned");
+        let stream = match streams.get_mut(label) {
+            Some(stream) => stream,
+            None => streams.entry(label.to_string()).or_default(),
+        };
+        stream.block_size.record(bytes);
+        if let Some(last) = stream.last_block.replace(now) {
+            let millis = now.duration_since(last).as_millis();
+            stream
+                .inter_arrival
+                .record(u64::try_from(millis).unwrap_or(u64::MAX));
+        }
+    }
+
+    /// Record how far the stream `label` is behind the chain, in blocks
+    /// against a head tracker and in time from block timestamps.
+    ///
+    /// [`ResilientStream::with_metrics`](crate::ResilientStream::with_metrics)
+    /// calls this for every block carrying metadata.
+    pub fn record_lag(&self, label: &str, blocks: Option<u64>, delay: Option<Duration>) {
+        let mut streams = self.streams.lock().expect("metrics lock poisoned");
+        let stream = streams.entry(label.to_string()).or_default();
+        stream.lag_blocks = blocks;
+        stream.lag = delay;
+    }
+
+    /// Record that the stream `label` was reopened after a failure or stall.
+    ///
+    /// [`ResilientStream::with_metrics`](crate::ResilientStream::with_metrics)
+    /// calls this on every reconnect.
+    pub fn record_reconnect(&self, label: &str) {
+        let mut streams = self.streams.lock().expect("metrics lock poisoned");
+        streams.entry(label.to_string()).or_default().reconnects += 1;
+    }
+
+    /// Block histograms, lag and reconnects of every stream recorded so far,
+    /// by label.
+    pub fn streams(&self) -> BTreeMap<String, StreamMetrics> {
+        self.streams.lock().expect("metrics lock poisoned").clone()
+    }
+
+    /// All metrics in the Prometheus text exposition format, for a
+    /// `/metrics` endpoint.
+    ///
+    /// Calls are counted in `firehose_calls_total`,
+    /// `firehose_call_errors_total` and `firehose_call_duration_seconds_sum`
+    /// by `method`. Streams get the `firehose_stream_block_size_bytes` and
+    /// `firehose_stream_inter_arrival_seconds` histograms, the
+    /// `firehose_stream_reconnects_total` counter and the
+    /// `firehose_stream_lag_blocks` and `firehose_stream_lag_seconds` gauges
+    /// by `stream` label.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let methods = self.snapshot();
+        let streams = self.streams();
+
+        write_metric(
+            &mut out,
+            "firehose_calls_total",
+            "gRPC calls made.",
+            "counter",
+        );
+        for (method, metrics) in &methods {
+            write_sample(
+                &mut out,
+                "firehose_calls_total",
+                "method",
+                method,
+                metrics.calls,
+            );
+        }
+        write_metric(
+            &mut out,
+            "firehose_call_errors_total",
+            "gRPC calls that failed.",
+            "counter",
+        );
+        for (method, metrics) in &methods {
+            write_sample(
+                &mut out,
+                "firehose_call_errors_total",
+                "method",
+                method,
+                metrics.errors,
+            );
+        }
+        write_metric(
+            &mut out,
+            "firehose_call_duration_seconds_sum",
+            "Time until the response headers of gRPC calls arrived.",
+            "counter",
+        );
+        for (method, metrics) in &methods {
+            let seconds = metrics.total_duration.as_secs_f64();
+            write_sample(
+                &mut out,
+                "firehose_call_duration_seconds_sum",
+                "method",
+                method,
+                seconds,
+            );
+        }
+
+        write_histograms(
+            &mut out,
+            "firehose_stream_block_size_bytes",
+            "Encoded size of streamed blocks.",
+            1.0,
+            streams
+                .iter()
+                .map(|(label, stream)| (label, &stream.block_size)),
+        );
+        write_histograms(
+            &mut out,
+            "firehose_stream_inter_arrival_seconds",
+            "Time between consecutive streamed blocks.",
+            1_000.0,
+            streams
+                .iter()
+                .map(|(label, stream)| (label, &stream.inter_arrival)),
+        );
+
+        write_metric(
+            &mut out,
+            "firehose_stream_reconnects_total",
+            "Times streams were reopened after a failure or stall.",
+            "counter",
+        );
+        for (label, stream) in &streams {
+            write_sample(
+                &mut out,
+                "firehose_stream_reconnects_total",
+                "stream",
+                label,
+                stream.reconnects,
+            );
+        }
+        write_metric(
+            &mut out,
+            "firehose_stream_lag_blocks",
+            "Blocks between the stream position and the chain head.",
+            "gauge",
+        );
+        for (label, stream) in &streams {
+            if let Some(blocks) = stream.lag_blocks {
+                write_sample(
+                    &mut out,
+                    "firehose_stream_lag_blocks",
+                    "stream",
+                    label,
+                    blocks,
+                );
+            }
+        }
+        write_metric(
+            &mut out,
+            "firehose_stream_lag_seconds",
+            "Age of the last streamed block.",
+            "gauge",
+        );
+        for (label, stream) in &streams {
+            if let Some(lag) = stream.lag {
+                let seconds = lag.as_secs_f64();
+                write_sample(
+                    &mut out,
+                    "firehose_stream_lag_seconds",
+                    "stream",
+                    label,
+                    seconds,
+                );
+            }
+        }
+        out
+    }
+}
+
+impl CallObserver for CallMetrics {
+    fn observe(&self, call: &CallRecord) {
+        let mut methods = self.methods.lock().expect("metrics lock poisoned");
+        let metrics = methods.entry(call.method.clone()).or_default();
+        metrics.calls += 1;
+        if call.code != Some(Code::Ok) {
+            metrics.errors += 1;
+        }
+        metrics.total_duration += call.duration;
+    }
+}
+
+/// Write the help and type lines of the Prometheus metric `name`.
+fn write_metric(out: &mut String, name: &str, help: &str, kind: &str) {
+    out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+}
+
+/// Write one sample of the Prometheus metric `name`.
+fn write_sample(out: &mut String, name: &str, key: &str, label: &str, value: impl Display) {
+    out.push_str(&format!(
+        "{name}{{{key}=\"{}\"}} {value}\n",
+        escape_label(label)
+    ));
+}
+
+/// Write the Prometheus histogram `name` of every stream, dividing recorded
+/// values by `scale` to get the exported unit.
+fn write_histograms<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    scale: f64,
+    histograms: impl Iterator<Item = (&'a String, &'a Histogram)>,
+) {
+    write_metric(out, name, help, "histogram");
+    for (label, histogram) in histograms {
+        let label = escape_label(label);
+        let mut cumulative = 0;
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            cumulative += count;
+            out.push_str(&format!(
+                "{name}_bucket{{stream=\"{label}\",le=\"{}\"}} {cumulative}\n",
+                *bound as f64 / scale
+            ));
+        }
+        out.push_str(&format!(
+            "{name}_bucket{{stream=\"{label}\",le=\"+Inf\"}} {}\n\
+             {name}_sum{{stream=\"{label}\"}} {}\n\
+             {name}_count{{stream=\"{label}\"}} {}\n",
+            histogram.count,
+            histogram.sum as f64 / scale,
+            histogram.count
+        ));
+    }
+}
+
+/// Escape a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Layer retrying failed fetches of a `Service<SingleBlockRequest>`, such as
+/// [`FetchService`](crate::FetchService) or
+/// [`EndpointPool`](crate::EndpointPool).
+///
+/// Only errors [classified](FirehoseError::classification) as retryable are
+/// retried, after the [`Backoff`] delay or the delay the server asked for,
+/// whichever is longer. A shared [`RetryBudget`] caps retries across every
+/// service using it.
+#[derive(Clone, Debug, Default)]
+pub struct RetryLayer {
+    backoff: Backoff,
+    budget: Option<RetryBudget>,
+}
+
+impl RetryLayer {
+    /// Retry with `backoff` between attempts.
+    pub fn new(backoff: Backoff) -> Self {
+        RetryLayer {
+            backoff,
+            budget: None,
+        }
+    }
+
+    /// Take a token from `budget` before every retry.
+    pub fn with_retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+}
+
+impl<S> Layer<S> for RetryLayer {
+    type Service = RetryService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service created by [`RetryLayer`].
+#[derive(Clone, Debug)]
+pub struct RetryService<S> {
+    inner: S,
+    layer: RetryLayer,
+}
+
+impl<S> Service<SingleBlockRequest> for RetryService<S>
+where
+    S: Service<SingleBlockRequest, Response = SingleBlockResponse, Error = FirehoseError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = SingleBlockResponse;
+    type Error = FirehoseError;
+    type Future = BoxFuture<SingleBlockResponse, FirehoseError>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: SingleBlockRequest) -> Self::Future {
+        // Use the service that was driven to readiness for the first attempt.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let RetryLayer { backoff, budget } = self.layer.clone();
+
+        Box::pin(async move {
+            let mut attempt = 0;
+            loop {
+                let error = match inner.call(request.clone()).await {
+                    Ok(response) => return Ok(response),
+                    Err(error) => error,
+                };
+
+                attempt += 1;
+                if !error.classification().is_retryable() || !backoff.allows(attempt) {
+                    return Err(error);
+                }
+
+                let delay = backoff.delay(attempt);
+                tokio::time::sleep(error.retry_delay().map_or(delay, |hint| hint.max(delay))).await;
+                if let Some(budget) = &budget {
+                    budget.acquire().await;
+                }
+                poll_fn(|cx| inner.poll_ready(cx)).await?;
+            }
+        })
+    }
+}
@@ -14,6 +14,64 @@
 //! - **Fetch support** via [`FetchClient`] for individual block retrieval
 //! - **Serde integration** for JSON serialization of all message types
 //! - **Flexible block requests** by number, hash, or cursor
+//! - **Endpoint configuration** via [`FirehoseEndpoint`], including the
+//!   standard `FIREHOSE_*` environment variables (`runtime` feature)
+//! - **Automatic reconnects** via [`ResilientStream`], with backoff and a
+//!   shared [`RetryBudget`] against reconnect storms (`runtime` feature)
+//!
+//! ## Optional Features
+//!
+//! Only `tls` is enabled by default, so the generated clients can reach
+//! `https://` endpoints. Most applications also want `runtime`.
+//!
+//! - `compression`: gzip and zstd compression of gRPC messages, for
+//!   [`FirehoseEndpoint::with_compression`] and `FIREHOSE_COMPRESSION`
+//! - `config`: load [`Request`] and [`SingleBlockRequest`] definitions, and
+//!   an [`EndpointConfig`] section, from JSON, TOML, or YAML files
+//! - `duckdb`: append streamed blocks and decoded rows to a DuckDB database
+//!   file for ad-hoc SQL (implies `sink`)
+//! - `dynamic`: decode block payloads of any chain at runtime with
+//!   [`prost-reflect`](https://docs.rs/prost-reflect), given its descriptors,
+//!   derive Parquet, Arrow, SQL and JSON schemas from them, flatten them into
+//!   rows, project them down to selected fields and verify the transactions
+//!   and receipts roots of Ethereum blocks
+//! - `health`: serve a [`HealthReporter`] as the standard gRPC health
+//!   checking service, for Kubernetes probes
+//! - `log`: log stream lifecycle events and gRPC calls as `key=value` lines
+//!   through the [`log`](https://docs.rs/log) facade
+//! - `parquet`: write streamed blocks and their flattened rows to Parquet
+//!   files (implies `dynamic` and `sink`)
+//! - `proto-json`: canonical proto3 JSON for all message types, interoperable
+//!   with `grpcurl` and the Go Firehose tooling (implies `dynamic`)
+//! - `proxy`: tunnel connections through SOCKS5 or HTTP `CONNECT` proxies,
+//!   or custom [`Connector`]s such as in-memory streams (implies `runtime`)
+//! - `redis`: append streamed blocks to a Redis Stream, with the cursor in a
+//!   Redis key (implies `sink`)
+//! - `runtime`: [`FirehoseEndpoint`] and the async building blocks on top of
+//!   the generated clients: endpoint pools, resilient streams, fetch caches,
+//!   spill queues, tower layers and cold-start handoffs. Implied by `cli`,
+//!   `health`, `log`, `proxy`, `sink`, `streamingfast-auth`, `testing` and
+//!   `tui`
+//! - `sink`: write streamed blocks to NDJSON or `dbin` files, optionally in
+//!   Hive-style partitions, with resumable exports, republish them over the
+//!   Stream API, and verify local `dbin` archives against an endpoint
+//! - `sqlite-index`: an embedded SQLite index of `dbin` archives for random
+//!   access reads (implies `sink`)
+//! - `streamingfast-auth`: exchange StreamingFast API keys for short-lived
+//!   JWTs, refreshed in the background
+//! - `test-util`: `proptest` strategies for requests, cursors and response
+//!   envelopes, on top of `testing`
+//! - `testing`: deterministic replay of recorded blocks, with their original
+//!   timing, injected reorgs and faults, synthetic blocks and chains, and
+//!   golden-file snapshots of responses and decoded blocks, for testing stream
+//!   consumers
+//! - `tls`: TLS to `https://` endpoints, verified against the system's root
+//!   certificates (default)
+//! - `v1`: legacy `sf.firehose.v1` client bindings, with conversions into the
+//!   v2 message types
+//! - `webhook`: post streamed blocks as signed JSON webhooks (implies `sink`)
+//! - `zstd`: zstd compression, optionally with a trained dictionary, of
+//!   cached and spilled blocks and of `dbin` archives
 //!
 //! ## Quick Start
 //!
@@ -82,27 +140,322 @@
 //! );
 //! ```
 
+mod adapters;
+mod anchors;
+#[cfg(feature = "sink")]
+pub mod archive;
+#[cfg(any(feature = "dynamic", feature = "proxy"))]
+mod base64;
+#[cfg(feature = "runtime")]
+mod bisect;
+mod bstream_v1;
+#[cfg(feature = "runtime")]
+mod cache;
+#[cfg(feature = "runtime")]
+mod channels;
+mod codec;
+#[cfg(feature = "zstd")]
+mod compression;
+#[cfg(feature = "config")]
+mod config;
+#[cfg(feature = "runtime")]
+mod confirmed;
+#[cfg(feature = "proxy")]
+mod connector;
+#[cfg(feature = "runtime")]
+mod coverage;
+mod cursor;
+#[cfg(feature = "tui")]
+mod dashboard;
+#[cfg(feature = "runtime")]
+mod dead_letter;
+#[cfg(feature = "runtime")]
+mod discovery;
+#[cfg(feature = "dynamic")]
+mod dynamic;
+#[cfg(feature = "runtime")]
+mod endpoint;
+mod error;
+#[cfg(feature = "dynamic")]
+mod ethereum_roots;
+#[cfg(feature = "dynamic")]
+mod ethereum_traces;
+mod ethereum_transform_v1;
+#[cfg(feature = "v1")]
+mod firehose_v1;
 mod firehose_v2;
+#[cfg(feature = "dynamic")]
+mod flatten;
+#[cfg(feature = "runtime")]
+mod handoff;
+#[cfg(feature = "runtime")]
+mod health;
+pub mod hex_bytes;
+#[cfg(feature = "runtime")]
+mod layers;
+#[cfg(feature = "log")]
+mod logging;
+#[cfg(feature = "runtime")]
+mod offline;
+#[cfg(feature = "runtime")]
+pub mod pipeline;
+mod planner;
+#[cfg(feature = "runtime")]
+mod pool;
+#[cfg(feature = "runtime")]
+mod prefetch;
+#[cfg(feature = "dynamic")]
+mod projection;
+#[cfg(feature = "proto-json")]
+mod proto_json;
+#[cfg(feature = "proxy")]
+mod proxy;
+pub mod raw;
+mod request_id;
+#[cfg(feature = "runtime")]
+mod resilient;
+#[cfg(feature = "runtime")]
+mod retry;
+#[cfg(feature = "dynamic")]
+mod schema;
+#[cfg(feature = "runtime")]
+mod service;
+#[cfg(feature = "sink")]
+pub mod sink;
+#[cfg(feature = "runtime")]
+mod spill;
+#[cfg(feature = "streamingfast-auth")]
+mod streamingfast_auth;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod transforms;
+#[cfg(feature = "runtime")]
+mod usage;
+#[cfg(feature = "runtime")]
+mod windows;
 
 pub(crate) use firehose_v2::single_block_request::BlockNumber;
 
+/// gRPC client for the Firehose v2 EndpointInfo API.
+///
+/// Use this client to discover the chain served by an endpoint, its first
+/// streamable block, and how block IDs are encoded.
+pub use firehose_v2::endpoint_info_client::EndpointInfoClient;
+
 /// gRPC client for the Firehose v2 Fetch API.
 ///
 /// Use this client to fetch individual blocks by number, hash, or cursor.
 pub use firehose_v2::fetch_client::FetchClient;
 
+/// Connection settings for a Firehose endpoint.
+///
+/// Creates authenticated [`StreamClient`]s and [`FetchClient`]s, and can be
+/// read from the `FIREHOSE_*` environment variables with
+/// [`FirehoseEndpoint::from_env`].
+#[cfg(feature = "runtime")]
+pub use endpoint::{
+    AuthInterceptor, BearerToken, ConnectionDiagnostics, FirehoseChannel, FirehoseEndpoint,
+};
+
+/// Unique `x-request-id`s sent with every call and echoed in errors, so
+/// failing requests can be referenced in provider support tickets.
+pub use request_id::{new_request_id, request_id, REQUEST_ID_HEADER};
+
+/// Clients exchanging message types generated outside this crate, such as
+/// blocks from the `protobuf` crate, through a pluggable codec.
+pub use codec::{CodecClient, WireCodec, WireDecoder, WireEncoder, WireMessage};
+
+/// Seams decoupling consumers from this crate's `tonic` and `prost` versions:
+/// channel construction behind a trait, and blocks decoded from bytes.
+pub use adapters::{ChannelSource, DecodeBlock, DecodeBlockError};
+
+/// Known `(number, hash)` checkpoints that streamed and fetched blocks must
+/// match.
+pub use anchors::Anchors;
+
+/// Custom transports for endpoint connections, such as userspace tunnels or
+/// in-memory streams.
+#[cfg(feature = "proxy")]
+pub use connector::Connector;
+
+/// Egress proxies, SOCKS5 or HTTP `CONNECT`, that connections to an endpoint
+/// can be tunnelled through.
+#[cfg(feature = "proxy")]
+pub use proxy::{Proxy, ProxyProtocol};
+
+/// The fetch path as a [`tower::Service`], for composing standard tower
+/// middleware.
+#[cfg(feature = "runtime")]
+pub use service::{FetchFuture, FetchService};
+
+/// Built-in tower layers: call observation for logging and metrics on
+/// channels, and retries around fetch services, with the block size and
+/// latency histograms of streams.
+#[cfg(feature = "runtime")]
+pub use layers::{
+    CallMetrics, CallObserver, CallRecord, Histogram, MethodMetrics, MetricsLayer, ObserveLayer,
+    ObserveService, RetryLayer, RetryService, StreamMetrics, BLOCK_SIZE_BUCKETS,
+    INTER_ARRIVAL_BUCKETS,
+};
+
+/// Call observer writing to the `log` facade.
+#[cfg(feature = "log")]
+pub use logging::LogObserver;
+
+/// Liveness and readiness of streams and sinks, for Kubernetes probes.
+#[cfg(feature = "runtime")]
+pub use health::{HealthReporter, StreamHealth};
+
+/// Sink wrapper reporting failures to a [`HealthReporter`].
+#[cfg(feature = "sink")]
+pub use health::HealthSink;
+
+/// Memory-bounded LRU cache of single-block fetches, keyed by request or by
+/// block hash.
+#[cfg(feature = "runtime")]
+pub use cache::{CacheKey, FetchCache};
+
+/// Block source serving local copies first, so reprocessing keeps working
+/// during provider outages.
+#[cfg(feature = "runtime")]
+pub use offline::OfflineSource;
+
+/// Destinations for blocks that fail to decode, so one malformed block does
+/// not abort a whole pipeline.
+#[cfg(feature = "runtime")]
+pub use dead_letter::{DeadLetter, DeadLetterSink};
+
+/// Appends dead letters to a JSON lines file.
+#[cfg(feature = "sink")]
+pub use dead_letter::DeadLetterFile;
+
+/// Which parts of a block range an endpoint can serve, from
+/// [`FirehoseEndpoint::probe_range`].
+#[cfg(feature = "runtime")]
+pub use coverage::Coverage;
+
+/// Persistence for stream cursors, so interrupted streams can resume.
+///
+/// See [`CursorStore`](crate::cursor::CursorStore) for details.
+pub use cursor::{cursor_block_num, CursorStore, FileCursorStore, MemoryCursorStore};
+
+/// Set of endpoints with latency-aware routing, failover and optional hedged
+/// fetches.
+#[cfg(feature = "runtime")]
+pub use pool::{EndpointPool, EndpointStats, Routing};
+
+/// Several channels per endpoint, so concurrent streams are not limited by
+/// the multiplexing of one HTTP/2 connection.
+#[cfg(feature = "runtime")]
+pub use channels::{ChannelAssignment, ChannelLease};
+
+/// In-order fetches over a block range, with requests kept in flight ahead.
+#[cfg(feature = "runtime")]
+pub use prefetch::{FetchRange, DEFAULT_PREFETCH};
+
+/// Binary search of a block range for the first block where a predicate
+/// flips.
+#[cfg(feature = "runtime")]
+pub use bisect::{bisect, Flip};
+
+/// Per-endpoint request, byte and block counts of an [`EndpointPool`].
+#[cfg(feature = "runtime")]
+pub use usage::{EndpointUsage, UsageReport};
+
+/// Work-stealing split of large block ranges for parallel, resumable
+/// backfills.
+pub use planner::{BlockRange, RangePlanner, UnitProgress, WorkUnit};
+
+/// Block stream that resumes from its last cursor after transient failures,
+/// the lifecycle and lag events it emits, a handle to control it, and
+/// background tasks running it.
+#[cfg(feature = "runtime")]
+pub use resilient::{
+    spawn_stream, AckHandle, CheckpointInterval, CursorRecovery, ErrorPolicy, LagAlert,
+    ResilientStream, SeekTo, SessionEnd, StreamEvent, StreamHandle, StreamSession, StreamSummary,
+    DEFAULT_SPAWN_BUFFER, SESSION_HISTORY,
+};
+
+/// Disk-backed queue of blocks between a fast stream and a slow consumer,
+/// surviving crashes.
+#[cfg(feature = "runtime")]
+pub use spill::{spill_queue, SpillReader, SpillWriter, DEFAULT_SEGMENT_SIZE};
+
+/// Disk-backed queue compressing its blocks.
+#[cfg(all(feature = "runtime", feature = "zstd"))]
+pub use spill::compressed_spill_queue;
+
+/// Zstd settings for blocks stored locally.
+#[cfg(feature = "zstd")]
+pub use compression::Zstd;
+
+/// Stream adapter holding blocks back until they are N blocks deep.
+#[cfg(feature = "runtime")]
+pub use confirmed::ConfirmedStream;
+
+/// Stream adapter grouping blocks into wall-clock or chain-time windows,
+/// for partitioned file output.
+#[cfg(feature = "runtime")]
+pub use windows::{TimeChunked, TimeWindow, WindowClock, WindowEvent};
+
+/// Cold start orchestration: a (sharded) backfill up to the final head,
+/// followed by the live stream without gaps or duplicates.
+#[cfg(feature = "runtime")]
+pub use handoff::{Handoff, Phase};
+
+/// Terminal dashboard of backfill progress, throughput, reconnects and sink
+/// lag.
+#[cfg(feature = "tui")]
+pub use dashboard::Dashboard;
+
+/// Reconnect pacing: per-stream exponential backoff and a token bucket shared
+/// across streams.
+#[cfg(feature = "runtime")]
+pub use retry::{Backoff, RetryBudget};
+
+/// Errors returned when configuring, connecting to, or calling an endpoint,
+/// and their classification for retry decisions.
+pub use error::{ErrorClass, FirehoseError};
+
+/// Typed `google.rpc.Status` details, as returned by
+/// [`FirehoseError::details`].
+#[cfg(feature = "runtime")]
+pub use tonic_types::{
+    BadRequest, ErrorDetails, FieldViolation, QuotaFailure, QuotaViolation, RetryInfo,
+};
+
+/// Transforms registered by type URL and added to requests by name.
+pub use transforms::{TransformArgs, TransformBuilder, TransformError, TransformRegistry};
+
+/// Request and response of the EndpointInfo API.
+pub use firehose_v2::{InfoRequest, InfoResponse};
+
 /// Streaming request configuration for the Firehose v2 API.
 ///
 /// Configure start/stop block numbers, cursor position, and whether to
 /// receive only finalized blocks.
 pub use firehose_v2::Request;
 
+/// Metadata about a block: number, ID, parent, last irreversible block and time.
+///
+/// Sent alongside the payload by current Firehose servers; older servers omit it.
+pub use firehose_v2::BlockMetadata;
+
+/// Position of a block in the fork-aware stream.
+///
+/// [`Response::step`] holds this enum's `i32` value; convert it with
+/// `ForkStep::try_from(response.step)`.
+pub use firehose_v2::ForkStep;
+
 /// Streaming response from the Firehose v2 API.
 ///
 /// Contains the block data, fork step information, cursor for resumption,
 /// and optional block metadata.
 pub use firehose_v2::Response;
 
+/// Compact, one-line description of a [`Response`] for logs.
+pub use firehose_v2::summary::ResponseSummary;
+
 /// Request for fetching a single block from the Firehose API.
 ///
 /// Supports fetching by block number, block hash + number, or cursor.
@@ -118,6 +471,12 @@ pub use firehose_v2::SingleBlockResponse;
 /// Use this client to stream continuous sequences of blocks.
 pub use firehose_v2::stream_client::StreamClient;
 
+/// gRPC server for the Firehose v2 Stream API.
+///
+/// Serves any implementation of the generated `Stream` service trait, such
+/// as a [`sink::Republisher`](crate::sink::Republisher).
+pub use firehose_v2::stream_server::StreamServer;
+
 /// Trait for unified access to block numbers or slots.
 ///
 /// See [`HasNumberOrSlot`](crate::firehose_v2::request::HasNumberOrSlot) for details.
@@ -127,3 +486,189 @@ pub use crate::firehose_v2::request::HasNumberOrSlot;
 ///
 /// See [`FromResponse`](crate::firehose_v2::request::FromResponse) for details.
 pub use crate::firehose_v2::request::FromResponse;
+
+/// Encoded `google.protobuf.FileDescriptorSet` for the Firehose protos.
+///
+/// Includes the `sf.firehose.v2` package and the well-known types it imports.
+/// Register it with a gRPC reflection service, a `prost_reflect::DescriptorPool`,
+/// or `buf`-based tooling instead of regenerating descriptors from the protos.
+///
+/// ```rust
+/// use prost::Message;
+/// use prost_types::FileDescriptorSet;
+///
+/// let set = FileDescriptorSet::decode(firehose_rs::FILE_DESCRIPTOR_SET).unwrap();
+/// assert!(set
+///     .file
+///     .iter()
+///     .any(|file| file.package() == "sf.firehose.v2"));
+/// ```
+pub use firehose_v2::FILE_DESCRIPTOR_SET;
+
+/// `sf.bstream.v1` block envelopes.
+///
+/// Operator flat files (one-block and merged-block bundles) and older archives
+/// produced by `firehose-core` store blocks in this envelope. Convert them into
+/// a v2 [`Response`](crate::Response) to reuse the same processing code as live
+/// streams, or back again when writing such files.
+///
+/// ```rust
+/// use firehose_rs::{bstream, Response};
+///
+/// let block = bstream::Block {
+///     number: 42,
+///     id: "0xabc".to_string(),
+///     parent_num: 41,
+///     parent_id: "0xdef".to_string(),
+///     ..Default::default()
+/// };
+///
+/// let response = Response::from(block);
+/// assert_eq!(response.metadata.as_ref().map(|m| m.num), Some(42));
+///
+/// let block = bstream::Block::try_from(response).unwrap();
+/// assert_eq!(block.parent_id, "0xdef");
+/// ```
+pub mod bstream {
+    pub use crate::bstream_v1::{envelope::MissingMetadataError, Block, Protocol};
+}
+
+/// `sf.ethereum.transform.v1` transforms for Firehose Ethereum endpoints.
+///
+/// [`EthereumFilter`](ethereum::EthereumFilter) builds a `CombinedFilter` of
+/// log and call filters. Providers skip historical block ranges without a
+/// match using their block indexes, which makes indexing sparse events much
+/// cheaper than streaming every block.
+///
+/// [`event_topic`](ethereum::event_topic) and
+/// [`function_selector`](ethereum::function_selector) hash Solidity
+/// signatures, and [`EthereumFilter::events`](ethereum::EthereumFilter::events)
+/// builds log filters from them, validating signatures and addresses.
+///
+/// ```rust
+/// use firehose_rs::{ethereum::EthereumFilter, Request};
+///
+/// let request = EthereumFilter::new()
+///     .events(
+///         Vec::<&str>::new(),
+///         ["event Transfer(address indexed from, address indexed to, uint256 value)"],
+///     )
+///     .unwrap()
+///     .with_final_blocks_only()
+///     .apply(Request::default())
+///     .unwrap();
+///
+/// assert!(request.final_blocks_only);
+/// assert_eq!(
+///     request.transforms[0].type_url,
+///     "type.googleapis.com/sf.ethereum.transform.v1.CombinedFilter"
+/// );
+/// ```
+pub mod ethereum {
+    pub use crate::ethereum_transform_v1::{
+        abi::{canonical_signature, event_topic, function_selector},
+        filter::EthereumFilter,
+        CallToFilter, CombinedFilter, HeaderOnly, LogFilter, MultiCallToFilter, MultiLogFilter,
+    };
+}
+
+/// Legacy Firehose v1 API bindings.
+///
+/// Some older deployments still serve `sf.firehose.v1`. Build requests with the
+/// v2 [`Request`](crate::Request) and convert them with [`Into`], then map each
+/// v1 [`Response`](v1::Response) back into the v2 [`Response`](crate::Response)
+/// so the rest of your code only deals with one shape.
+///
+/// ```rust,no_run
+/// use firehose_rs::v1;
+/// use tonic::transport::Channel;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let channel = Channel::from_static("https://your-legacy-endpoint:443")
+///     .connect()
+///     .await?;
+///
+/// let mut client = v1::StreamClient::new(channel);
+///
+/// let request = firehose_rs::Request {
+///     start_block_num: 1000,
+///     stop_block_num: 2000,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+///
+/// let mut stream = client.blocks(v1::Request::from(request)).await?.into_inner();
+///
+/// while let Some(response) = stream.message().await? {
+///     let response: firehose_rs::Response = response.into();
+///     println!("Received block at cursor: {}", response.cursor);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "v1")]
+pub mod v1 {
+    pub use crate::firehose_v1::{stream_client::StreamClient, ForkStep, Request, Response};
+}
+
+/// Config file formats and errors for [`Request::from_config_file`], and the
+/// endpoint section of config files.
+#[cfg(feature = "config")]
+pub use crate::config::{ConfigError, ConfigFormat, EndpointConfig};
+
+/// Runtime decoder for block payloads described by registered descriptors.
+///
+/// See [`DynamicDecoder`](crate::dynamic::DynamicDecoder) for details.
+#[cfg(feature = "dynamic")]
+pub use crate::dynamic::{DynamicDecodeError, DynamicDecoder};
+
+/// Parquet, Arrow, DuckDB and JSON schemas of block types and of the sinks'
+/// row formats, for creating downstream tables ahead of time.
+#[cfg(feature = "dynamic")]
+pub use crate::schema::{message_json_schema, Column, ColumnType, TableSchema};
+
+/// Normalized row sets of decoded blocks (blocks, transactions, logs or
+/// instructions) for analytics sinks.
+#[cfg(feature = "dynamic")]
+pub use crate::flatten::{Cell, Flattener, RowSet};
+
+/// Verification of Ethereum blocks' transactions and receipts against the
+/// roots of their header.
+#[cfg(feature = "dynamic")]
+pub use crate::ethereum_roots::{verify_ethereum_roots, RootError, TrieRoot};
+
+/// Call traces and receipts of decoded Ethereum blocks, with their call trees
+/// and per-transaction gas resolved.
+#[cfg(feature = "dynamic")]
+pub use crate::ethereum_traces::{
+    ethereum_calls, ethereum_receipts, EthereumCall, EthereumReceipt,
+};
+
+/// Field projection of block payloads, keeping only selected fields.
+#[cfg(feature = "dynamic")]
+pub use crate::projection::Projection;
+
+/// Re-export of [`tonic`], the version this crate's clients, servers and
+/// channels are built with, so users name those types without pinning a
+/// matching version themselves.
+pub use tonic;
+
+/// Re-export of [`prost`], the version the Firehose messages implement
+/// [`Message`](prost::Message) for; [`prost::bytes`] is the `bytes` crate
+/// it uses.
+pub use prost;
+
+/// Re-export of [`prost_wkt_types`], whose `Any` and `Timestamp` the
+/// Firehose messages carry.
+pub use prost_wkt_types;
+
+/// Re-export of [`prost_reflect`] so users can build descriptor pools and work
+/// with [`DynamicMessage`](prost_reflect::DynamicMessage) without pinning a
+/// matching version themselves.
+#[cfg(feature = "dynamic")]
+pub use prost_reflect;
+
+/// Exchange of StreamingFast API keys for short-lived JWTs, refreshed into a
+/// [`BearerToken`].
+#[cfg(feature = "streamingfast-auth")]
+pub use crate::streamingfast_auth::{IssuedToken, StreamingFastAuth, STREAMINGFAST_AUTH_URL};
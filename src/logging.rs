@@ -0,0 +1,162 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Lifecycle events and calls written to the [`log`] facade, as `key=value`
+//! pairs, for services that do not use `tracing`.
+
+use std::fmt::{self, Display};
+
+use log::Level;
+use tonic::Code;
+
+use crate::{CallObserver, CallRecord, SeekTo, StreamEvent};
+
+/// Logs every call through an [`ObserveLayer`](crate::ObserveLayer) with the
+/// [`log`] facade.
+///
+/// Successful calls are logged at `debug`, failed ones at `warn`, as
+/// `grpc call method=/sf.firehose.v2.Fetch/Block code=Ok duration_ms=12
+/// request_id=…`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{FirehoseEndpoint, LogObserver, ObserveLayer};
+/// use tower::ServiceBuilder;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let endpoint = FirehoseEndpoint::from_env()?;
+/// let layer = ServiceBuilder::new()
+///     .layer(ObserveLayer::new(LogObserver))
+///     .layer(endpoint.auth_layer()?)
+///     .into_inner();
+/// let client = endpoint.fetch_client_with_layer(endpoint.connect().await?, layer);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogObserver;
+
+impl CallObserver for LogObserver {
+    fn observe(&self, call: &CallRecord) {
+        let level = match call.code {
+            Some(Code::Ok) => Level::Debug,
+            _ => Level::Warn,
+        };
+        log::log!(
+            level,
+            "grpc call method={} code={} duration_ms={} request_id={}",
+            call.method,
+            call.code
+                .map_or_else(|| "none".to_string(), |code| format!("{code:?}")),
+            call.duration.as_millis(),
+            call.request_id.as_deref().unwrap_or("none"),
+        );
+    }
+}
+
+/// Log a [`StreamEvent`] of the stream connected to the URI returned by
+/// `endpoint` by the call `request_id`.
+///
+/// Failures, stalls, lag, skipped blocks and rejected cursors are logged at
+/// `warn`, progress at `trace`, everything else at `info`. `endpoint` is only
+/// called if the event is logged.
+pub(crate) fn stream_event(
+    event: &StreamEvent,
+    endpoint: impl FnOnce() -> String,
+    request_id: &str,
+) {
+    let level = match event {
+        StreamEvent::Disconnected { .. }
+        | StreamEvent::Stalled
+        | StreamEvent::LagExceeded { .. }
+        | StreamEvent::Skipped { .. }
+        | StreamEvent::CursorRecovered { .. } => Level::Warn,
+        StreamEvent::Progress { .. } => Level::Trace,
+        _ => Level::Info,
+    };
+    if !log::log_enabled!(level) {
+        return;
+    }
+
+    let endpoint = endpoint();
+    let connection = Fields(&[("endpoint", endpoint.as_str()), ("request_id", request_id)]);
+    match event {
+        StreamEvent::Connected => log::info!("stream connected {connection}"),
+        StreamEvent::Resumed { cursor } => {
+            log::info!("stream resumed {connection} cursor={}", quoted(cursor))
+        }
+        StreamEvent::Disconnected { error } => {
+            log::warn!("stream disconnected {connection} error={}", quoted(error))
+        }
+        StreamEvent::Seeked { to } => match to {
+            SeekTo::Block(block) => log::info!("stream seeked {connection} block={block}"),
+            SeekTo::Cursor(cursor) => {
+                log::info!("stream seeked {connection} cursor={}", quoted(cursor))
+            }
+        },
+        StreamEvent::Stalled => log::warn!("stream stalled {connection}"),
+        StreamEvent::Progress { block } => log::trace!("stream progress block={block}"),
+        StreamEvent::Reorg { depth } => log::info!("stream reorg {connection} depth={depth}"),
+        StreamEvent::LagExceeded { blocks, delay } => log::warn!(
+            "stream lag exceeded {connection} blocks={} delay_ms={}",
+            blocks.map_or_else(|| "none".to_string(), |blocks| blocks.to_string()),
+            delay.map_or_else(|| "none".to_string(), |delay| delay.as_millis().to_string()),
+        ),
+        StreamEvent::LagRecovered => log::info!("stream lag recovered {connection}"),
+        StreamEvent::Skipped { block, error } => log::warn!(
+            "stream skipped block={} error={}",
+            block.map_or_else(|| "none".to_string(), |block| block.to_string()),
+            quoted(error)
+        ),
+        StreamEvent::CursorRecovered {
+            cursor,
+            start_block,
+        } => log::warn!(
+            "stream cursor recovered {connection} cursor={} start_block={start_block}",
+            quoted(cursor)
+        ),
+        StreamEvent::Completed { summary } => log::info!(
+            "stream completed {connection} blocks={} bytes={} duration_ms={} reconnects={} \
+             skipped={} dead_lettered={} final_cursor={}",
+            summary.blocks_received,
+            summary.bytes,
+            summary.duration.as_millis(),
+            summary.reconnects,
+            summary.skipped,
+            summary.dead_lettered,
+            quoted(&summary.final_cursor),
+        ),
+    }
+}
+
+/// `key=value` pairs, skipping empty values.
+struct Fields<'a>(&'a [(&'a str, &'a str)]);
+
+impl Display for Fields<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (key, value) in self.0.iter().filter(|(_, value)| !value.is_empty()) {
+            if !first {
+                f.write_str(" ")?;
+            }
+            write!(f, "{key}={}", quoted(value))?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// `value` as is if it is a single word, quoted and escaped otherwise.
+fn quoted(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '"' && c != '=')
+    {
+        value.to_string()
+    } else {
+        format!("{value:?}")
+    }
+}
@@ -0,0 +1,160 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::task::{Context, Poll};
+#[cfg(feature = "sqlite-index")]
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use tower::Service;
+
+#[cfg(feature = "sqlite-index")]
+use crate::{archive::ArchiveIndex, firehose_v2::single_block_request::Reference};
+use crate::{
+    EndpointPool, FetchCache, FetchFuture, FirehoseError, SingleBlockRequest, SingleBlockResponse,
+};
+
+/// A block source that prefers local copies and falls back to an
+/// [`EndpointPool`].
+///
+/// Blocks are looked up in the [`FetchCache`] and, with the `sqlite-index`
+/// feature, an indexed `dbin` archive before any endpoint is called. Batch
+/// reprocessing over blocks already on disk keeps working while the provider
+/// is down; only blocks missing locally fail, with the endpoint's error.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{EndpointPool, FetchCache, FirehoseEndpoint, OfflineSource, SingleBlockRequest};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = EndpointPool::new([FirehoseEndpoint::from_env()?])?;
+/// let source = OfflineSource::new(pool).with_cache(FetchCache::new(512 * 1024 * 1024));
+///
+/// let response = source
+///     .fetch(SingleBlockRequest::new_by_block_number(12345))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct OfflineSource {
+    pool: EndpointPool,
+    cache: Option<FetchCache>,
+    #[cfg(feature = "sqlite-index")]
+    archive: Option<(PathBuf, Arc<Mutex<ArchiveIndex>>)>,
+}
+
+impl OfflineSource {
+    /// Fetch blocks missing locally from `pool`.
+    pub fn new(pool: EndpointPool) -> Self {
+        OfflineSource {
+            pool,
+            cache: None,
+            #[cfg(feature = "sqlite-index")]
+            archive: None,
+        }
+    }
+
+    /// Serve blocks from `cache`, and keep the blocks fetched from the pool in
+    /// it.
+    pub fn with_cache(mut self, cache: FetchCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Serve blocks from the `dbin` archive in `dir`, located through
+    /// `index`.
+    #[cfg(feature = "sqlite-index")]
+    pub fn with_archive(mut self, dir: impl Into<PathBuf>, index: ArchiveIndex) -> Self {
+        self.archive = Some((dir.into(), Arc::new(Mutex::new(index))));
+        self
+    }
+
+    /// The pool blocks missing locally are fetched from.
+    pub fn pool(&self) -> &EndpointPool {
+        &self.pool
+    }
+
+    /// The block for `request` if it is available locally, without calling
+    /// any endpoint.
+    pub fn local(&self, request: &SingleBlockRequest) -> Option<SingleBlockResponse> {
+        if let Some(response) = self.cache.as_ref().and_then(|cache| cache.get(request)) {
+            return Some(response);
+        }
+
+        #[cfg(feature = "sqlite-index")]
+        if let Some(response) = self.archived(request) {
+            return Some(response);
+        }
+
+        None
+    }
+
+    /// Fetch a single block, from a local copy if there is one.
+    ///
+    /// Returns the pool's error only when the block is missing locally and no
+    /// endpoint could serve it.
+    pub async fn fetch(
+        &self,
+        request: SingleBlockRequest,
+    ) -> Result<SingleBlockResponse, FirehoseError> {
+        if let Some(response) = self.local(&request) {
+            return Ok(response);
+        }
+
+        let response = self.pool.fetch(request.clone()).await?;
+        if let Some(cache) = &self.cache {
+            cache.insert(&request, &response);
+        }
+        Ok(response)
+    }
+
+    #[cfg(feature = "sqlite-index")]
+    fn archived(&self, request: &SingleBlockRequest) -> Option<SingleBlockResponse> {
+        let (dir, index) = self.archive.as_ref()?;
+        let index = index.lock().unwrap_or_else(|e| e.into_inner());
+
+        let location = match request.reference.as_ref()? {
+            Reference::BlockNumber(number) => index.by_number(number.num).ok()??,
+            Reference::BlockHashAndNumber(block) => {
+                let location = index.by_number(block.num).ok()??;
+                let hash = block.hash.trim_start_matches("0x");
+                if !location
+                    .id
+                    .trim_start_matches("0x")
+                    .eq_ignore_ascii_case(hash)
+                {
+                    return None;
+                }
+                location
+            }
+            // Archives do not record cursors.
+            Reference::Cursor(_) => return None,
+        };
+
+        let block = index.read_block(dir, &location).ok()?;
+        Some(SingleBlockResponse {
+            block: block.payload_any(),
+            metadata: Some(block.metadata()),
+        })
+    }
+}
+
+impl Service<SingleBlockRequest> for OfflineSource {
+    type Response = SingleBlockResponse;
+    type Error = FirehoseError;
+    type Future = FetchFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: SingleBlockRequest) -> Self::Future {
+        let source = self.clone();
+        Box::pin(async move { source.fetch(request).await })
+    }
+}
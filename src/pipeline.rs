@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Declarative block pipelines.
+//!
+//! A [`Pipeline`] reads a [`ResilientStream`], decodes its blocks through
+//! [`FromResponse`], passes them through a chain of [`Stage`]s and writes the
+//! results to a [`Sink`]. Every stage runs on its own task with its own
+//! [concurrency and buffer](StageOptions), and emits its results in block
+//! order, so indexers wire components together instead of hand-rolling task
+//! graphs.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use firehose_rs::{
+//!     pipeline::{Pipeline, StageOptions},
+//!     FirehoseEndpoint, FirehoseError, Request, ResilientStream, Response,
+//! };
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let request = Request {
+//!     start_block_num: 17_000_000,
+//!     stop_block_num: 17_099_999,
+//!     final_blocks_only: true,
+//!     ..Default::default()
+//! };
+//! let stream = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?;
+//!
+//! let summary = Pipeline::<Response>::new(stream, StageOptions::default())
+//!     .stage(
+//!         |response: Response| async move {
+//!             Ok::<_, FirehoseError>(response.metadata.map(|metadata| metadata.num))
+//!         },
+//!         StageOptions {
+//!             concurrency: 8,
+//!             ..Default::default()
+//!         },
+//!     )
+//!     .run(|number: Option<u64>| async move {
+//!         println!("{number:?}");
+//!         Ok(())
+//!     })
+//!     .await?;
+//! println!("processed {} blocks", summary.blocks_received);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::VecDeque, fmt::Display, future::Future, pin::Pin, sync::Arc};
+
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{FirehoseError, FromResponse, ResilientStream, StreamHandle, StreamSummary};
+
+/// Future returned by [`Stage`] and [`Sink`] implementations.
+pub type StageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, FirehoseError>> + Send + 'a>>;
+
+/// One step of a [`Pipeline`], turning each input into an output.
+///
+/// Implemented for async closures returning `Result<_, FirehoseError>`.
+pub trait Stage<In>: Send + Sync + 'static {
+    /// What the stage produces for each input.
+    type Output: Send + 'static;
+
+    /// Process one input.
+    fn process(&self, input: In) -> StageFuture<'_, Self::Output>;
+}
+
+impl<In, Out, F, Fut> Stage<In> for F
+where
+    F: Fn(In) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Out, FirehoseError>> + Send + 'static,
+    Out: Send + 'static,
+{
+    type Output = Out;
+
+    fn process(&self, input: In) -> StageFuture<'_, Out> {
+        Box::pin(self(input))
+    }
+}
+
+/// The last step of a [`Pipeline`], consuming its results in block order.
+///
+/// Implemented for async closures returning `Result<(), FirehoseError>`.
+pub trait Sink<T>: Send {
+    /// Consume one result.
+    fn write(&mut self, item: T) -> StageFuture<'_, ()>;
+
+    /// Called once after the last result, when the pipeline completed.
+    fn finish(&mut self) -> StageFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl<T, F, Fut> Sink<T> for F
+where
+    F: FnMut(T) -> Fut + Send,
+    Fut: Future<Output = Result<(), FirehoseError>> + Send + 'static,
+{
+    fn write(&mut self, item: T) -> StageFuture<'_, ()> {
+        Box::pin(self(item))
+    }
+}
+
+/// How a pipeline step runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StageOptions {
+    /// Inputs processed at the same time.
+    pub concurrency: usize,
+    /// Results buffered before the step waits for the next one downstream.
+    pub buffer: usize,
+}
+
+impl Default for StageOptions {
+    /// One input at a time, with a buffer of 64 results.
+    fn default() -> Self {
+        StageOptions {
+            concurrency: 1,
+            buffer: 64,
+        }
+    }
+}
+
+/// A running chain of pipeline steps, producing `T`s.
+///
+/// Steps start as soon as they are added; [`Pipeline::run`] drains the last
+/// one into a [`Sink`] and reports how the stream went.
+#[derive(Debug)]
+pub struct Pipeline<T> {
+    source: JoinHandle<Result<StreamSummary, FirehoseError>>,
+    stages: Vec<JoinHandle<Result<(), FirehoseError>>>,
+    output: mpsc::Receiver<T>,
+    handle: StreamHandle,
+}
+
+impl<T: Send + 'static> Pipeline<T> {
+    /// Read `stream`, decoding its blocks into `T` according to its
+    /// [`ErrorPolicy`](crate::ErrorPolicy).
+    ///
+    /// Decoding happens on the stream's own task, or on its
+    /// [decode workers](ResilientStream::with_decode_workers), so only
+    /// [`StageOptions::buffer`] applies.
+    pub fn new(stream: ResilientStream, options: StageOptions) -> Self
+    where
+        T: FromResponse,
+        T::Error: Display + Send,
+    {
+        let (source, output, handle) = stream.spawn(options.buffer);
+        Pipeline {
+            source,
+            stages: Vec::new(),
+            output,
+            handle,
+        }
+    }
+
+    /// Pass every result through `stage`.
+    pub fn stage<S>(self, stage: S, options: StageOptions) -> Pipeline<S::Output>
+    where
+        S: Stage<T>,
+    {
+        let Pipeline {
+            source,
+            mut stages,
+            output,
+            handle,
+        } = self;
+
+        let stage: Arc<dyn Stage<T, Output = S::Output>> = Arc::new(stage);
+        let (sender, next) = mpsc::channel(options.buffer.max(1));
+        stages.push(tokio::spawn(run_stage(
+            output,
+            stage,
+            options.concurrency.max(1),
+            sender,
+        )));
+
+        Pipeline {
+            source,
+            stages,
+            output: next,
+            handle,
+        }
+    }
+
+    /// A handle to pause, resume or seek the source stream.
+    pub fn handle(&self) -> StreamHandle {
+        self.handle.clone()
+    }
+
+    /// Write every result to `sink` until the stream completes.
+    ///
+    /// Returns the stream's [`StreamSummary`] once the sink has been
+    /// [finished](Sink::finish). The first error of the stream, a stage or
+    /// the sink stops the whole pipeline and is returned instead.
+    pub async fn run(self, mut sink: impl Sink<T>) -> Result<StreamSummary, FirehoseError> {
+        let Pipeline {
+            source,
+            stages,
+            mut output,
+            ..
+        } = self;
+
+        let mut failure = None;
+        while let Some(item) = output.recv().await {
+            if let Err(e) = sink.write(item).await {
+                failure = Some(e);
+                break;
+            }
+        }
+        drop(output);
+
+        if let Some(failure) = failure {
+            // The source may be waiting for the next block; stages then stop
+            // as their input closes.
+            source.abort();
+            for stage in stages {
+                let _ = stage.await;
+            }
+            return Err(failure);
+        }
+
+        for stage in stages {
+            joined(stage).await?;
+        }
+        let summary = joined(source).await?;
+        sink.finish().await?;
+        Ok(summary)
+    }
+}
+
+async fn joined<T>(task: JoinHandle<Result<T, FirehoseError>>) -> Result<T, FirehoseError> {
+    task.await
+        .map_err(|e| FirehoseError::Processing(e.to_string()))?
+}
+
+/// Process up to `concurrency` inputs at a time, sending the results in input
+/// order.
+async fn run_stage<In, Out>(
+    mut input: mpsc::Receiver<In>,
+    stage: Arc<dyn Stage<In, Output = Out>>,
+    concurrency: usize,
+    output: mpsc::Sender<Out>,
+) -> Result<(), FirehoseError>
+where
+    In: Send + 'static,
+    Out: Send + 'static,
+{
+    let mut running: VecDeque<JoinHandle<Result<Out, FirehoseError>>> = VecDeque::new();
+    let mut exhausted = false;
+
+    loop {
+        tokio::select! {
+            biased;
+            done = async { running.front_mut().expect("a stage task is running").await },
+                if !running.is_empty() =>
+            {
+                running.pop_front();
+                let result = done.map_err(|e| FirehoseError::Processing(e.to_string()))?;
+                if output.send(result?).await.is_err() {
+                    // Downstream stopped.
+                    return Ok(());
+                }
+            }
+            next = input.recv(), if !exhausted && running.len() < concurrency => match next {
+                Some(next) => {
+                    let stage = Arc::clone(&stage);
+                    running.push_back(tokio::spawn(async move { stage.process(next).await }));
+                }
+                None => exhausted = true,
+            },
+            else => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Request;
+
+/// How long an assigned unit may go without progress before another worker
+/// may steal it.
+const DEFAULT_STEAL_AFTER: Duration = Duration::from_secs(60);
+
+/// An inclusive range of block numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BlockRange {
+    /// First block of the range.
+    pub start: u64,
+    /// Last block of the range, included.
+    pub stop: u64,
+}
+
+impl BlockRange {
+    /// The blocks from `start` to `stop`, both included.
+    pub fn new(start: u64, stop: u64) -> Self {
+        BlockRange { start, stop }
+    }
+
+    /// Number of blocks in the range.
+    pub fn len(&self) -> u64 {
+        (self.stop + 1).saturating_sub(self.start)
+    }
+
+    /// Whether the range holds no blocks, i.e. `stop < start`.
+    pub fn is_empty(&self) -> bool {
+        self.stop < self.start
+    }
+
+    /// Whether `other` lies entirely within this range.
+    pub fn contains(&self, other: &BlockRange) -> bool {
+        self.start <= other.start && other.stop <= self.stop
+    }
+}
+
+impl fmt::Display for BlockRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.stop)
+    }
+}
+
+/// A unit of work handed out by a [`RangePlanner`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WorkUnit {
+    /// Identifier of the unit within its planner.
+    pub id: usize,
+    /// Blocks to process.
+    pub range: BlockRange,
+}
+
+impl WorkUnit {
+    /// `template` restricted to the unit's blocks.
+    pub fn request(&self, template: &Request) -> Request {
+        Request {
+            start_block_num: self.range.start as i64,
+            stop_block_num: self.range.stop,
+            cursor: String::new(),
+            ..template.clone()
+        }
+    }
+}
+
+/// Progress of one unit of a [`RangePlanner`], from [`RangePlanner::units`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnitProgress {
+    /// Blocks of the unit.
+    pub range: BlockRange,
+    /// Worker the unit is assigned to, `None` while pending or once complete.
+    pub worker: Option<usize>,
+    /// Last block reported processed, if any.
+    pub processed: Option<u64>,
+    /// Whether the unit is complete.
+    pub done: bool,
+}
+
+impl UnitProgress {
+    /// Blocks of the unit processed so far.
+    pub fn blocks_done(&self) -> u64 {
+        if self.done {
+            return self.range.len();
+        }
+        self.processed.map_or(0, |block| {
+            BlockRange::new(self.range.start, block.min(self.range.stop)).len()
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Status {
+    Pending,
+    Assigned {
+        worker: usize,
+        last_progress: Instant,
+        processed: Option<u64>,
+    },
+    Done,
+}
+
+#[derive(Debug)]
+struct Unit {
+    range: BlockRange,
+    status: Status,
+}
+
+#[derive(Debug)]
+struct State {
+    units: Vec<Unit>,
+    steal_after: Duration,
+    manifest: Option<PathBuf>,
+}
+
+/// Splits a large block range into work units for parallel backfills.
+///
+/// Workers, typically one per endpoint of an
+/// [`EndpointPool`](crate::EndpointPool), repeatedly take the next unit with
+/// [`RangePlanner::next`], stream it, report progress, and mark it complete.
+/// Once no unit is left pending, idle workers steal units whose worker has
+/// not reported progress for a while, so one slow endpoint does not hold up
+/// the whole backfill. The thief resumes after the last block the previous
+/// worker reported, but that worker may still be processing the same blocks,
+/// so output should be idempotent.
+///
+/// Completed units can be appended to a manifest file, one `start-stop` range
+/// per line, from which an interrupted backfill resumes without redoing them.
+///
+/// Clones share the same plan, so each worker task can own one.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "runtime")]
+/// # mod example {
+/// use firehose_rs::{BlockRange, EndpointPool, RangePlanner, Request};
+///
+/// # async fn example(pool: EndpointPool) -> Result<(), Box<dyn std::error::Error>> {
+/// let planner = RangePlanner::new(BlockRange::new(0, 19_999_999), 100_000)
+///     .with_manifest("backfill.manifest")?;
+///
+/// let mut workers = tokio::task::JoinSet::new();
+/// for worker in 0..pool.len() {
+///     let (planner, pool) = (planner.clone(), pool.clone());
+///     workers.spawn(async move {
+///         while let Some(unit) = planner.next(worker) {
+///             let mut client = pool.stream_client_at(worker)?;
+///             let request = unit.request(&Request::default());
+///             let mut stream = client.blocks(request).await?.into_inner();
+///             while let Some(response) = stream.message().await? {
+///                 // ... process the block ...
+///                 if let Some(metadata) = &response.metadata {
+///                     planner.progress(&unit, worker, metadata.num);
+///                 }
+///             }
+///             planner.complete(&unit)?;
+///         }
+///         Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+///     });
+/// }
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RangePlanner {
+    state: Arc<Mutex<State>>,
+}
+
+impl RangePlanner {
+    /// Split `range` into units of `unit_size` blocks, the last one possibly
+    /// shorter.
+    pub fn new(range: BlockRange, unit_size: u64) -> Self {
+        let unit_size = unit_size.max(1);
+        let mut units = Vec::new();
+        let mut start = range.start;
+        while start <= range.stop {
+            let stop = start.saturating_add(unit_size - 1).min(range.stop);
+            units.push(Unit {
+                range: BlockRange::new(start, stop),
+                status: Status::Pending,
+            });
+            if stop == u64::MAX {
+                break;
+            }
+            start = stop + 1;
+        }
+
+        RangePlanner {
+            state: Arc::new(Mutex::new(State {
+                units,
+                steal_after: DEFAULT_STEAL_AFTER,
+                manifest: None,
+            })),
+        }
+    }
+
+    /// Let idle workers steal units that made no progress for `timeout`.
+    pub fn with_steal_after(self, timeout: Duration) -> Self {
+        self.lock().steal_after = timeout;
+        self
+    }
+
+    /// Skip units lying entirely within `ranges`, completed by an earlier run.
+    pub fn with_completed(self, ranges: impl IntoIterator<Item = BlockRange>) -> Self {
+        {
+            let ranges: Vec<BlockRange> = ranges.into_iter().collect();
+            let mut state = self.lock();
+            for unit in &mut state.units {
+                if ranges.iter().any(|range| range.contains(&unit.range)) {
+                    unit.status = Status::Done;
+                }
+            }
+        }
+        self
+    }
+
+    /// Resume from the completed units recorded at `path`, if it exists, and
+    /// append each unit completed from now on.
+    pub fn with_manifest(self, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let completed = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(parse_range)
+                .collect::<io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        let planner = self.with_completed(completed);
+        planner.lock().manifest = Some(path);
+        Ok(planner)
+    }
+
+    /// The next unit for `worker`: a pending one in block order, or else a
+    /// stalled one stolen from another worker. `None` once every unit is
+    /// complete or assigned to a worker that is still making progress.
+    pub fn next(&self, worker: usize) -> Option<WorkUnit> {
+        let mut state = self.lock();
+        let steal_after = state.steal_after;
+        let now = Instant::now();
+
+        let index = state
+            .units
+            .iter()
+            .position(|unit| matches!(unit.status, Status::Pending))
+            .or_else(|| {
+                state
+                    .units
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, unit)| match unit.status {
+                        Status::Assigned {
+                            worker: owner,
+                            last_progress,
+                            ..
+                        } if owner != worker && now - last_progress >= steal_after => {
+                            Some((index, last_progress))
+                        }
+                        _ => None,
+                    })
+                    .min_by_key(|(_, last_progress)| *last_progress)
+                    .map(|(index, _)| index)
+            })?;
+
+        let unit = &mut state.units[index];
+        let processed = match unit.status {
+            Status::Assigned { processed, .. } => processed,
+            _ => None,
+        };
+        unit.status = Status::Assigned {
+            worker,
+            last_progress: now,
+            processed,
+        };
+
+        let start = processed.map_or(unit.range.start, |block| block + 1);
+        Some(WorkUnit {
+            id: index,
+            range: BlockRange::new(start, unit.range.stop),
+        })
+    }
+
+    /// Report that `worker` processed `unit` up to `block`, which keeps the
+    /// unit from being stolen, and lets a thief resume after `block` if it is.
+    pub fn progress(&self, unit: &WorkUnit, worker: usize, block: u64) {
+        let mut state = self.lock();
+        if let Some(Unit {
+            status:
+                Status::Assigned {
+                    worker: owner,
+                    last_progress,
+                    processed,
+                },
+            ..
+        }) = state.units.get_mut(unit.id)
+        {
+            if *owner == worker {
+                *last_progress = Instant::now();
+                *processed = Some(processed.map_or(block, |done| done.max(block)));
+            }
+        }
+    }
+
+    /// Mark `unit` complete, recording its whole range in the manifest if
+    /// there is one.
+    ///
+    /// Completing a unit twice, as happens after it was stolen, is harmless.
+    pub fn complete(&self, unit: &WorkUnit) -> io::Result<()> {
+        let mut state = self.lock();
+        let Some(entry) = state.units.get_mut(unit.id) else {
+            return Ok(());
+        };
+        if matches!(entry.status, Status::Done) {
+            return Ok(());
+        }
+        entry.status = Status::Done;
+
+        let range = entry.range;
+        if let Some(path) = &state.manifest {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{range}")?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Whether every unit is complete.
+    pub fn is_done(&self) -> bool {
+        self.lock()
+            .units
+            .iter()
+            .all(|unit| matches!(unit.status, Status::Done))
+    }
+
+    /// Ranges of the completed units, in block order.
+    pub fn completed(&self) -> Vec<BlockRange> {
+        self.ranges_where(|status| matches!(status, Status::Done))
+    }
+
+    /// Ranges of the units not completed yet, in block order.
+    pub fn remaining(&self) -> Vec<BlockRange> {
+        self.ranges_where(|status| !matches!(status, Status::Done))
+    }
+
+    /// Progress of every unit, in block order.
+    pub fn units(&self) -> Vec<UnitProgress> {
+        self.lock()
+            .units
+            .iter()
+            .map(|unit| {
+                let (worker, processed) = match unit.status {
+                    Status::Assigned {
+                        worker, processed, ..
+                    } => (Some(worker), processed),
+                    Status::Pending | Status::Done => (None, None),
+                };
+                UnitProgress {
+                    range: unit.range,
+                    worker,
+                    processed,
+                    done: matches!(unit.status, Status::Done),
+                }
+            })
+            .collect()
+    }
+
+    fn ranges_where(&self, predicate: impl Fn(&Status) -> bool) -> Vec<BlockRange> {
+        self.lock()
+            .units
+            .iter()
+            .filter(|unit| predicate(&unit.status))
+            .map(|unit| unit.range)
+            .collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("range planner lock poisoned")
+    }
+}
+
+fn parse_range(line: &str) -> io::Result<BlockRange> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid manifest line `{line}`"),
+        )
+    };
+
+    let (start, stop) = line.trim().split_once('-').ok_or_else(invalid)?;
+    Ok(BlockRange::new(
+        start.parse().map_err(|_| invalid())?,
+        stop.parse().map_err(|_| invalid())?,
+    ))
+}
@@ -0,0 +1,875 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use prost::Message;
+use tokio::{
+    task::{JoinHandle, JoinSet},
+    time::MissedTickBehavior,
+};
+use tonic::Code;
+
+use crate::{
+    channels::ChannelSet,
+    request_id,
+    usage::{Usage, UsageReport},
+    BlockRange, ChannelAssignment, ChannelLease, FetchCache, FetchClient, FetchRange,
+    FirehoseChannel, FirehoseEndpoint, FirehoseError, InfoRequest, Request, SingleBlockRequest,
+    SingleBlockResponse, StreamClient,
+};
+
+/// Weight of the newest sample in the latency and error-rate moving averages.
+const EWMA_ALPHA: f64 = 0.2;
+
+/// How much a 100% error rate inflates an endpoint's latency score.
+const ERROR_PENALTY: f64 = 10.0;
+
+/// How an [`EndpointPool`] orders its endpoints for each call.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Routing {
+    /// Always prefer endpoints in the order they were given.
+    #[default]
+    Priority,
+    /// Prefer the endpoint with the best exponentially weighted moving average
+    /// of latency, penalized by its recent error rate. Endpoints without
+    /// samples yet are tried first so every endpoint gets measured.
+    LatencyAware,
+}
+
+/// A set of Firehose endpoints serving the same chain.
+///
+/// Fetches go to the best endpoint according to the pool's [`Routing`] and
+/// fail over to the next ones in order. With [hedging](EndpointPool::with_hedging)
+/// enabled, a fetch that has not completed after the hedge delay is also sent
+/// to the next endpoint, and the first successful response wins.
+///
+/// Every fetch updates per-endpoint latency and error statistics, available
+/// from [`EndpointPool::stats`]. A background [health
+/// probe](EndpointPool::spawn_health_probe) can additionally mark endpoints
+/// unhealthy, which moves them behind all healthy ones, as do failed
+/// [`ResilientStream`](crate::ResilientStream) sessions. Clones of a pool share
+/// statistics and health state.
+///
+/// Channels are connected lazily, so the pool must be created from within a
+/// Tokio runtime but does not need the endpoints to be reachable yet.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use firehose_rs::{EndpointPool, FirehoseEndpoint, Routing, SingleBlockRequest};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = EndpointPool::new([
+///     FirehoseEndpoint::new("primary.example.com:443"),
+///     FirehoseEndpoint::new("fallback.example.com:443"),
+/// ])?
+/// .with_routing(Routing::LatencyAware)
+/// .with_hedging(Duration::from_millis(250));
+///
+/// let response = pool.fetch(SingleBlockRequest::new_by_block_number(12345)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct EndpointPool {
+    members: Vec<Member>,
+    routing: Routing,
+    hedge_delay: Option<Duration>,
+    cache: Option<FetchCache>,
+    usage_since: Arc<Mutex<SystemTime>>,
+}
+
+#[derive(Clone, Debug)]
+struct Member {
+    endpoint: FirehoseEndpoint,
+    channels: Arc<ChannelSet>,
+    stats: Arc<Mutex<Stats>>,
+    healthy: Arc<AtomicBool>,
+    usage: Arc<Usage>,
+}
+
+impl Member {
+    /// Call the `Info` RPC and record whether the endpoint answered in time.
+    ///
+    /// Servers that predate the `EndpointInfo` service answer `UNIMPLEMENTED`,
+    /// which still proves they are up.
+    async fn probe(&self, timeout: Duration) -> bool {
+        let healthy = match self
+            .endpoint
+            .info_client_with_channel(self.channels.channel())
+        {
+            Ok(mut client) => {
+                match tokio::time::timeout(timeout, client.info(InfoRequest {})).await {
+                    Ok(Ok(_)) => true,
+                    Ok(Err(status)) => status.code() == Code::Unimplemented,
+                    Err(_) => false,
+                }
+            }
+            Err(_) => false,
+        };
+
+        self.healthy.store(healthy, Ordering::Relaxed);
+        healthy
+    }
+}
+
+/// Snapshot of the statistics recorded for one endpoint of an [`EndpointPool`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointStats {
+    /// The endpoint URI.
+    pub uri: String,
+    /// Moving average of successful call latency, if any call succeeded.
+    pub latency: Option<Duration>,
+    /// Moving average of the error rate, from `0.0` to `1.0`.
+    pub error_rate: f64,
+    /// Calls made to the endpoint.
+    pub requests: u64,
+    /// Calls that failed.
+    pub errors: u64,
+    /// Whether the last health probe or stream call succeeded. `true` until
+    /// either happened.
+    pub healthy: bool,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    latency_secs: Option<f64>,
+    error_rate: f64,
+    requests: u64,
+    errors: u64,
+}
+
+impl Stats {
+    fn record(&mut self, latency: Duration, ok: bool) {
+        let error = if ok { 0.0 } else { 1.0 };
+        self.error_rate = if self.requests == 0 {
+            error
+        } else {
+            EWMA_ALPHA * error + (1.0 - EWMA_ALPHA) * self.error_rate
+        };
+        self.requests += 1;
+
+        if ok {
+            let sample = latency.as_secs_f64();
+            self.latency_secs = Some(match self.latency_secs {
+                Some(average) => EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * average,
+                None => sample,
+            });
+        } else {
+            self.errors += 1;
+        }
+    }
+
+    /// Lower is better.
+    fn score(&self) -> f64 {
+        match self.latency_secs {
+            Some(latency) => latency * (1.0 + ERROR_PENALTY * self.error_rate),
+            None if self.requests == 0 => 0.0,
+            None => f64::MAX,
+        }
+    }
+}
+
+impl EndpointPool {
+    /// Create a pool from `endpoints`, in priority order.
+    pub fn new(
+        endpoints: impl IntoIterator<Item = FirehoseEndpoint>,
+    ) -> Result<Self, FirehoseError> {
+        let members = endpoints
+            .into_iter()
+            .map(|endpoint| {
+                let channel = endpoint.connect_lazy()?;
+                Ok(Member {
+                    endpoint,
+                    channels: Arc::new(ChannelSet::new(
+                        vec![channel],
+                        ChannelAssignment::default(),
+                    )),
+                    stats: Arc::default(),
+                    healthy: Arc::new(AtomicBool::new(true)),
+                    usage: Arc::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, FirehoseError>>()?;
+
+        if members.is_empty() {
+            return Err(FirehoseError::Config(
+                "endpoint pool needs at least one endpoint".to_string(),
+            ));
+        }
+
+        Ok(EndpointPool {
+            members,
+            routing: Routing::default(),
+            hedge_delay: None,
+            cache: None,
+            usage_since: Arc::new(Mutex::new(SystemTime::now())),
+        })
+    }
+
+    /// Choose how endpoints are ordered for each call.
+    pub fn with_routing(mut self, routing: Routing) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Hedge fetches that take longer than `delay` by sending them to the
+    /// next endpoint as well.
+    ///
+    /// Pick a delay around the 95th percentile latency of the primary, so
+    /// only the slowest requests are duplicated.
+    pub fn with_hedging(mut self, delay: Duration) -> Self {
+        self.hedge_delay = Some(delay);
+        self
+    }
+
+    /// Open `count` channels to each endpoint instead of one, and spread
+    /// streams over them according to `assignment`.
+    ///
+    /// Each channel is a separate HTTP/2 connection, so many concurrent
+    /// streams to one endpoint are not limited by the multiplexing of a single
+    /// connection. [`ResilientStream`](crate::ResilientStream)s keep their
+    /// channel across reconnects; fetches take channels in turn, or the least
+    /// loaded one.
+    pub fn with_channels(
+        mut self,
+        count: usize,
+        assignment: ChannelAssignment,
+    ) -> Result<Self, FirehoseError> {
+        for member in &mut self.members {
+            let channels = (0..count.max(1))
+                .map(|_| member.endpoint.connect_lazy())
+                .collect::<Result<Vec<_>, _>>()?;
+            member.channels = Arc::new(ChannelSet::new(channels, assignment));
+        }
+        Ok(self)
+    }
+
+    /// Answer fetches from `cache` when possible, and cache their responses.
+    pub fn with_fetch_cache(mut self, cache: FetchCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Number of endpoints in the pool.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Whether the pool has no endpoints. Always `false`, since
+    /// [`EndpointPool::new`] rejects empty pools.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// The endpoints, in priority order.
+    pub fn endpoints(&self) -> impl Iterator<Item = &FirehoseEndpoint> {
+        self.members.iter().map(|member| &member.endpoint)
+    }
+
+    /// Statistics recorded for each endpoint, in priority order.
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        self.members
+            .iter()
+            .map(|member| {
+                let stats = member.stats.lock().expect("stats lock poisoned");
+                EndpointStats {
+                    uri: member.endpoint.uri(),
+                    latency: stats.latency_secs.map(Duration::from_secs_f64),
+                    error_rate: stats.error_rate,
+                    requests: stats.requests,
+                    errors: stats.errors,
+                    healthy: member.healthy.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+
+    /// Requests, bytes and blocks received from each endpoint since the pool
+    /// was created or its usage last reset.
+    ///
+    /// Covers fetches through the pool and [`ResilientStream`]s built on it.
+    ///
+    /// [`ResilientStream`]: crate::ResilientStream
+    pub fn usage(&self) -> UsageReport {
+        self.usage_report(false)
+    }
+
+    /// Return the usage recorded so far and start recording from zero, for
+    /// example at the start of each billing period.
+    pub fn reset_usage(&self) -> UsageReport {
+        self.usage_report(true)
+    }
+
+    fn usage_report(&self, reset: bool) -> UsageReport {
+        let mut since = self.usage_since.lock().expect("usage lock poisoned");
+        let report = UsageReport {
+            since: *since,
+            endpoints: self
+                .members
+                .iter()
+                .map(|member| member.usage.report(member.endpoint.uri(), reset))
+                .collect(),
+        };
+        if reset {
+            *since = SystemTime::now();
+        }
+        report
+    }
+
+    /// Usage counters of the endpoint at `index`, for streams built on the
+    /// pool.
+    pub(crate) fn usage_at(&self, index: usize) -> Option<&Usage> {
+        self.members.get(index).map(|member| member.usage.as_ref())
+    }
+
+    /// Whether the endpoint at `index` passed its last health probe or stream
+    /// call.
+    pub fn is_healthy(&self, index: usize) -> bool {
+        self.members
+            .get(index)
+            .is_some_and(|member| member.healthy.load(Ordering::Relaxed))
+    }
+
+    /// Record the outcome of a stream call to the endpoint at `index`, which
+    /// only counts `latency` if it succeeded.
+    ///
+    /// The call also sets the endpoint's health, so a failing stream moves
+    /// it behind the healthy endpoints until a later call or probe succeeds.
+    pub(crate) fn record_stream(&self, index: usize, latency: Duration, ok: bool) {
+        let Some(member) = self.members.get(index) else {
+            return;
+        };
+        member
+            .stats
+            .lock()
+            .expect("stats lock poisoned")
+            .record(latency, ok);
+        member.healthy.store(ok, Ordering::Relaxed);
+    }
+
+    /// Endpoint indices in the order calls should try them.
+    ///
+    /// Unhealthy endpoints always come last, so they are only used when every
+    /// healthy endpoint failed.
+    pub fn ranked(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.members.len()).collect();
+        let unhealthy: Vec<bool> = self
+            .members
+            .iter()
+            .map(|member| !member.healthy.load(Ordering::Relaxed))
+            .collect();
+
+        match self.routing {
+            // Stable sorts, so ties keep their priority order
+            Routing::Priority => order.sort_by_key(|&i| unhealthy[i]),
+            Routing::LatencyAware => {
+                let scores: Vec<f64> = self
+                    .members
+                    .iter()
+                    .map(|member| member.stats.lock().expect("stats lock poisoned").score())
+                    .collect();
+                order.sort_by(|&a, &b| {
+                    unhealthy[a]
+                        .cmp(&unhealthy[b])
+                        .then(scores[a].total_cmp(&scores[b]))
+                });
+            }
+        }
+
+        order
+    }
+
+    /// Probe every endpoint once with the `Info` RPC, updating their health.
+    ///
+    /// Returns the health of each endpoint, in priority order.
+    pub async fn probe(&self, timeout: Duration) -> Vec<bool> {
+        let mut probes = JoinSet::new();
+        for (index, member) in self.members.iter().cloned().enumerate() {
+            probes.spawn(async move { (index, member.probe(timeout).await) });
+        }
+
+        let mut health = vec![false; self.members.len()];
+        while let Some(result) = probes.join_next().await {
+            if let Ok((index, healthy)) = result {
+                health[index] = healthy;
+            }
+        }
+        health
+    }
+
+    /// Spawn a background task probing every endpoint each `interval`.
+    ///
+    /// An endpoint is marked unhealthy when its `Info` call fails or takes
+    /// longer than `timeout`, and healthy again on the next successful probe.
+    /// Abort the returned handle to stop probing.
+    pub fn spawn_health_probe(&self, interval: Duration, timeout: Duration) -> JoinHandle<()> {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                pool.probe(timeout).await;
+            }
+        })
+    }
+
+    /// Create a [`StreamClient`] for the best endpoint.
+    pub fn stream_client(&self) -> Result<StreamClient<FirehoseChannel>, FirehoseError> {
+        self.stream_client_at(self.ranked()[0])
+    }
+
+    /// Number of the last final block of the best endpoint.
+    pub async fn final_head(&self) -> Result<u64, FirehoseError> {
+        let request = Request {
+            start_block_num: -1,
+            final_blocks_only: true,
+            ..Default::default()
+        };
+        let mut stream = self.stream_client()?.blocks(request).await?.into_inner();
+        stream
+            .message()
+            .await?
+            .and_then(|response| response.block_number())
+            .ok_or_else(|| FirehoseError::Config("endpoint did not report its head".to_string()))
+    }
+
+    /// Create a [`StreamClient`] for the endpoint at `index`.
+    pub fn stream_client_at(
+        &self,
+        index: usize,
+    ) -> Result<StreamClient<FirehoseChannel>, FirehoseError> {
+        let member = self.member(index)?;
+        member
+            .endpoint
+            .stream_client_with_channel(member.channels.channel())
+    }
+
+    /// Lease a channel to the endpoint at `index` for a stream, counting
+    /// towards its load until the lease is dropped.
+    pub fn lease_at(&self, index: usize) -> Result<ChannelLease, FirehoseError> {
+        Ok(self.member(index)?.channels.lease(index))
+    }
+
+    /// Create a [`StreamClient`] on the channel of `lease`.
+    pub fn stream_client_for(
+        &self,
+        lease: &ChannelLease,
+    ) -> Result<StreamClient<FirehoseChannel>, FirehoseError> {
+        self.member(lease.endpoint())?
+            .endpoint
+            .stream_client_with_channel(lease.channel())
+    }
+
+    /// Streams carried by each channel of the endpoint at `index`.
+    pub fn channel_streams(&self, index: usize) -> Result<Vec<usize>, FirehoseError> {
+        Ok(self.member(index)?.channels.streams())
+    }
+
+    /// Create a [`FetchClient`] for the endpoint at `index`.
+    pub fn fetch_client_at(
+        &self,
+        index: usize,
+    ) -> Result<FetchClient<FirehoseChannel>, FirehoseError> {
+        let member = self.member(index)?;
+        member
+            .endpoint
+            .fetch_client_with_channel(member.channels.channel())
+    }
+
+    /// Fetch a single block, failing over (and hedging, if enabled) across
+    /// endpoints.
+    ///
+    /// Returns the error of the last endpoint tried when all of them fail.
+    pub async fn fetch(
+        &self,
+        request: SingleBlockRequest,
+    ) -> Result<SingleBlockResponse, FirehoseError> {
+        if let Some(response) = self.cache.as_ref().and_then(|cache| cache.get(&request)) {
+            return Ok(response);
+        }
+
+        let order = self.ranked();
+        let response = self.fetch_in_order(&order, request.clone()).await?;
+        if let Some(cache) = &self.cache {
+            cache.insert(&request, &response);
+        }
+        Ok(response)
+    }
+
+    /// Fetch the blocks of `range` in order, with read-ahead.
+    pub fn fetch_range(&self, range: BlockRange) -> FetchRange {
+        FetchRange::new(self.clone(), range)
+    }
+
+    async fn fetch_in_order(
+        &self,
+        order: &[usize],
+        request: SingleBlockRequest,
+    ) -> Result<SingleBlockResponse, FirehoseError> {
+        let mut last_error = None;
+        let mut remaining = order;
+
+        while let Some((&first, rest)) = remaining.split_first() {
+            let result = match (self.hedge_delay, rest.first()) {
+                (Some(delay), Some(&second)) => {
+                    remaining = &rest[1..];
+                    self.hedged_fetch(first, second, request.clone(), delay)
+                        .await
+                }
+                _ => {
+                    remaining = rest;
+                    self.fetch_at(first, request.clone()).await
+                }
+            };
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error.expect("pool has at least one endpoint"))
+    }
+
+    /// Send `request` to `first`, and also to `second` if `first` has not
+    /// answered within `delay`. The slower call is dropped, cancelling it.
+    async fn hedged_fetch(
+        &self,
+        first: usize,
+        second: usize,
+        request: SingleBlockRequest,
+        delay: Duration,
+    ) -> Result<SingleBlockResponse, FirehoseError> {
+        let primary = self.fetch_at(first, request.clone());
+        tokio::pin!(primary);
+
+        tokio::select! {
+            result = &mut primary => {
+                return match result {
+                    Ok(response) => Ok(response),
+                    Err(_) => self.fetch_at(second, request).await,
+                };
+            }
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        let hedge = self.fetch_at(second, request);
+        tokio::pin!(hedge);
+
+        tokio::select! {
+            result = &mut primary => match result {
+                Ok(response) => Ok(response),
+                Err(_) => hedge.await,
+            },
+            result = &mut hedge => match result {
+                Ok(response) => Ok(response),
+                Err(_) => primary.await,
+            },
+        }
+    }
+
+    async fn fetch_at(
+        &self,
+        index: usize,
+        request: SingleBlockRequest,
+    ) -> Result<SingleBlockResponse, FirehoseError> {
+        let mut client = self.fetch_client_at(index)?;
+
+        let member = &self.members[index];
+        member.usage.record_request();
+
+        let mut request = tonic::Request::new(request);
+        let id = request_id::ensure(request.metadata_mut());
+        let started = Instant::now();
+        let result = client
+            .block(request)
+            .await
+            .map_err(|status| request_id::tag(status, &id));
+        member
+            .stats
+            .lock()
+            .expect("stats lock poisoned")
+            .record(started.elapsed(), result.is_ok());
+
+        if let Ok(response) = &result {
+            member.usage.record_block(response.get_ref().encoded_len());
+        }
+
+        Ok(result?.into_inner())
+    }
+
+    fn member(&self, index: usize) -> Result<&Member, FirehoseError> {
+        self.members.get(index).ok_or_else(|| {
+            FirehoseError::Config(format!(
+                "endpoint index {index} out of range for pool of {}",
+                self.members.len()
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use tonic::{
+        transport::{server::TcpIncoming, Server},
+        Status,
+    };
+
+    use super::*;
+    use crate::{
+        firehose_v2::{
+            endpoint_info_server::{EndpointInfo, EndpointInfoServer},
+            fetch_server::{Fetch, FetchServer},
+        },
+        BlockMetadata, InfoResponse,
+    };
+
+    /// A Fetch and EndpointInfo server whose blocks carry its name as ID.
+    #[derive(Clone)]
+    struct Node {
+        name: &'static str,
+        delay: Duration,
+        down: Arc<AtomicBool>,
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl Node {
+        fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                delay: Duration::ZERO,
+                down: Arc::default(),
+                fetches: Arc::default(),
+            }
+        }
+
+        /// Answer every call after `delay`.
+        fn with_delay(mut self, delay: Duration) -> Self {
+            self.delay = delay;
+            self
+        }
+
+        /// Fail every call with `UNAVAILABLE` while `down` is set.
+        fn set_down(&self, down: bool) {
+            self.down.store(down, Ordering::Relaxed);
+        }
+
+        fn fetches(&self) -> usize {
+            self.fetches.load(Ordering::Relaxed)
+        }
+
+        async fn serve(&self) -> FirehoseEndpoint {
+            let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            let addr = incoming.local_addr().unwrap();
+            tokio::spawn(
+                Server::builder()
+                    .add_service(FetchServer::new(self.clone()))
+                    .add_service(EndpointInfoServer::new(self.clone()))
+                    .serve_with_incoming(incoming),
+            );
+            FirehoseEndpoint::new(format!("http://{addr}"))
+        }
+
+        async fn answer<T>(&self, response: T) -> Result<tonic::Response<T>, Status> {
+            tokio::time::sleep(self.delay).await;
+            if self.down.load(Ordering::Relaxed) {
+                return Err(Status::unavailable(format!("{} is down", self.name)));
+            }
+            Ok(tonic::Response::new(response))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Fetch for Node {
+        async fn block(
+            &self,
+            _request: tonic::Request<SingleBlockRequest>,
+        ) -> Result<tonic::Response<SingleBlockResponse>, Status> {
+            self.fetches.fetch_add(1, Ordering::Relaxed);
+            self.answer(SingleBlockResponse {
+                metadata: Some(BlockMetadata {
+                    id: self.name.to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .await
+        }
+    }
+
+    #[tonic::async_trait]
+    impl EndpointInfo for Node {
+        async fn info(
+            &self,
+            _request: tonic::Request<InfoRequest>,
+        ) -> Result<tonic::Response<InfoResponse>, Status> {
+            self.answer(InfoResponse::default()).await
+        }
+    }
+
+    fn answered_by(response: SingleBlockResponse) -> String {
+        response.metadata.expect("block metadata").id
+    }
+
+    #[tokio::test]
+    async fn hedges_a_slow_fetch_after_the_delay() {
+        let slow = Node::new("slow").with_delay(Duration::from_secs(2));
+        let fast = Node::new("fast");
+        let pool = EndpointPool::new([slow.serve().await, fast.serve().await])
+            .unwrap()
+            .with_hedging(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let response = pool
+            .fetch(SingleBlockRequest::new_by_block_number(1))
+            .await
+            .unwrap();
+
+        assert_eq!(answered_by(response), "fast");
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!((slow.fetches(), fast.fetches()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn does_not_hedge_a_fetch_answered_within_the_delay() {
+        let primary = Node::new("primary");
+        let secondary = Node::new("secondary");
+        let pool = EndpointPool::new([primary.serve().await, secondary.serve().await])
+            .unwrap()
+            .with_hedging(Duration::from_secs(2));
+
+        let response = pool
+            .fetch(SingleBlockRequest::new_by_block_number(1))
+            .await
+            .unwrap();
+
+        assert_eq!(answered_by(response), "primary");
+        assert_eq!((primary.fetches(), secondary.fetches()), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn hedge_answers_when_the_first_endpoint_fails() {
+        let broken = Node::new("broken").with_delay(Duration::from_millis(100));
+        broken.set_down(true);
+        let slow = Node::new("slow").with_delay(Duration::from_millis(300));
+        let pool = EndpointPool::new([broken.serve().await, slow.serve().await])
+            .unwrap()
+            .with_hedging(Duration::from_millis(50));
+
+        let response = pool
+            .fetch(SingleBlockRequest::new_by_block_number(1))
+            .await
+            .unwrap();
+
+        assert_eq!(answered_by(response), "slow");
+        assert_eq!((broken.fetches(), slow.fetches()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn latency_aware_ranking_puts_unhealthy_endpoints_last() {
+        let slow = Node::new("slow").with_delay(Duration::from_millis(100));
+        let fast = Node::new("fast");
+        let unhealthy = Node::new("unhealthy");
+        let pool = EndpointPool::new([
+            slow.serve().await,
+            fast.serve().await,
+            unhealthy.serve().await,
+        ])
+        .unwrap()
+        .with_routing(Routing::LatencyAware);
+
+        // Unmeasured endpoints score best and keep their priority order
+        assert_eq!(pool.ranked(), [0, 1, 2]);
+
+        for index in 0..3 {
+            pool.fetch_at(index, SingleBlockRequest::new_by_block_number(1))
+                .await
+                .unwrap();
+        }
+        pool.members[2].healthy.store(false, Ordering::Relaxed);
+        assert_eq!(pool.ranked(), [1, 0, 2]);
+
+        // Under priority routing only health reorders endpoints
+        let pool = pool.with_routing(Routing::Priority);
+        assert_eq!(pool.ranked(), [0, 1, 2]);
+        pool.members[0].healthy.store(false, Ordering::Relaxed);
+        assert_eq!(pool.ranked(), [1, 0, 2]);
+    }
+
+    #[test]
+    fn errors_inflate_the_latency_score() {
+        let mut flaky = Stats::default();
+        let mut steady = Stats::default();
+        assert_eq!(flaky.score(), 0.0);
+
+        flaky.record(Duration::from_millis(10), true);
+        steady.record(Duration::from_millis(20), true);
+        assert!(flaky.score() < steady.score());
+
+        for _ in 0..5 {
+            flaky.record(Duration::ZERO, false);
+        }
+        assert!(flaky.score() > steady.score());
+        assert_eq!((flaky.requests, flaky.errors), (6, 5));
+
+        // Endpoints that never answered rank behind every measured one
+        let mut down = Stats::default();
+        down.record(Duration::ZERO, false);
+        assert_eq!(down.score(), f64::MAX);
+    }
+
+    #[tokio::test]
+    async fn probes_flip_endpoint_health() {
+        let node = Node::new("node");
+        let slow = Node::new("slow").with_delay(Duration::from_secs(2));
+        let pool = EndpointPool::new([node.serve().await, slow.serve().await]).unwrap();
+        assert!(pool.is_healthy(0) && pool.is_healthy(1));
+
+        // A probe slower than the timeout counts as a failure
+        assert_eq!(pool.probe(Duration::from_millis(200)).await, [true, false]);
+        assert!(!pool.is_healthy(1));
+
+        node.set_down(true);
+        assert_eq!(pool.probe(Duration::from_millis(200)).await, [false, false]);
+        assert!(!pool.is_healthy(0));
+        assert!(!pool.stats()[0].healthy);
+
+        node.set_down(false);
+        assert_eq!(pool.probe(Duration::from_millis(200)).await, [true, false]);
+        assert!(pool.is_healthy(0));
+        assert_eq!(pool.ranked(), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn background_probe_restores_health() {
+        let node = Node::new("node");
+        let pool = EndpointPool::new([node.serve().await]).unwrap();
+        pool.record_stream(0, Duration::ZERO, false);
+        assert!(!pool.is_healthy(0));
+
+        let probe = pool.spawn_health_probe(Duration::from_millis(20), Duration::from_secs(1));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !pool.is_healthy(0) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("probe marks the endpoint healthy");
+        probe.abort();
+    }
+}
@@ -0,0 +1,124 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::VecDeque;
+
+use tokio::task::JoinHandle;
+
+use crate::{
+    Anchors, BlockRange, EndpointPool, FirehoseError, SingleBlockRequest, SingleBlockResponse,
+};
+
+/// Block requests kept in flight ahead of the one being returned, by default.
+pub const DEFAULT_PREFETCH: usize = 4;
+
+/// Fetches a range of blocks one by one, in order, with read-ahead.
+///
+/// While the caller processes one block, the next `prefetch` blocks are
+/// already being fetched, hiding the round trip of each request. Fetches go
+/// through [`EndpointPool::fetch`], so failover, hedging and the fetch cache
+/// apply. Obtained from [`EndpointPool::fetch_range`].
+///
+/// Dropping the iterator cancels the outstanding fetches.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{BlockRange, EndpointPool, FirehoseEndpoint};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = EndpointPool::new([FirehoseEndpoint::from_env()?])?;
+///
+/// let mut blocks = pool
+///     .fetch_range(BlockRange::new(17_000_000, 17_000_999))
+///     .with_prefetch(8);
+/// while let Some(response) = blocks.next().await {
+///     let response = response?;
+///     // ... process the block ...
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FetchRange {
+    pool: EndpointPool,
+    range: BlockRange,
+    /// Next block to request.
+    next: u64,
+    prefetch: usize,
+    in_flight: VecDeque<JoinHandle<Result<SingleBlockResponse, FirehoseError>>>,
+    anchors: Option<Anchors>,
+}
+
+impl FetchRange {
+    pub(crate) fn new(pool: EndpointPool, range: BlockRange) -> Self {
+        FetchRange {
+            pool,
+            range,
+            next: range.start,
+            prefetch: DEFAULT_PREFETCH,
+            in_flight: VecDeque::new(),
+            anchors: None,
+        }
+    }
+
+    /// Keep up to `prefetch` requests in flight ahead of the block being
+    /// returned. Zero fetches one block at a time.
+    pub fn with_prefetch(mut self, prefetch: usize) -> Self {
+        self.prefetch = prefetch;
+        self
+    }
+
+    /// Fail with [`FirehoseError::AnchorMismatch`] on a block at the height of
+    /// one of `anchors` with another hash.
+    pub fn with_anchors(mut self, anchors: Anchors) -> Self {
+        self.anchors = Some(anchors);
+        self
+    }
+
+    /// The blocks fetched, in order.
+    pub fn range(&self) -> BlockRange {
+        self.range
+    }
+
+    /// Fetch the next block, or `None` after the last block of the range.
+    pub async fn next(&mut self) -> Option<Result<SingleBlockResponse, FirehoseError>> {
+        while self.in_flight.len() <= self.prefetch && self.next <= self.range.stop {
+            let (pool, request) = (
+                self.pool.clone(),
+                SingleBlockRequest::new_by_block_number(self.next),
+            );
+            self.in_flight
+                .push_back(tokio::spawn(async move { pool.fetch(request).await }));
+            if self.next == u64::MAX {
+                break;
+            }
+            self.next += 1;
+        }
+
+        let fetch = self.in_flight.pop_front()?;
+        Some(
+            fetch
+                .await
+                .map_err(|e| FirehoseError::Processing(e.to_string()))
+                .and_then(|result| result)
+                .and_then(|response| {
+                    if let Some(anchors) = &self.anchors {
+                        if let Some(metadata) = anchors.metadata(response.metadata.as_ref())? {
+                            anchors.verify_metadata(metadata)?;
+                        }
+                    }
+                    Ok(response)
+                }),
+        )
+    }
+}
+
+impl Drop for FetchRange {
+    fn drop(&mut self) {
+        for fetch in &self.in_flight {
+            fetch.abort();
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Field projection of block payloads, dropping what consumers do not need
+//! before blocks are buffered or written.
+
+use std::collections::BTreeMap;
+
+use prost::Message;
+use prost_reflect::{DynamicMessage, Kind, MessageDescriptor, ReflectMessage, Value};
+use prost_wkt_types::Any;
+
+use crate::{dynamic::message_name, DynamicDecodeError, DynamicDecoder, Response};
+
+/// Keeps only selected fields of decoded blocks, such as the header and a
+/// few transaction fields, so consumers that do not need full traces hold
+/// and store a fraction of each block.
+///
+/// Fields are selected by dotted paths from the block message: `header`
+/// keeps the whole header, `transaction_traces.hash` keeps only the hash of
+/// every transaction. Every other field is cleared, and the payload is
+/// re-encoded with the same type URL, so projected blocks decode with the
+/// usual types, with unselected fields at their defaults.
+///
+/// Apply it to a [`ResilientStream`](crate::ResilientStream) with
+/// [`with_projection`](crate::ResilientStream::with_projection), so blocks
+/// are projected as soon as they are received, before memory limits,
+/// buffering and sinks see them.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "runtime")]
+/// # mod example {
+/// use firehose_rs::{DynamicDecoder, FirehoseEndpoint, Projection, Request, ResilientStream};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decoder = DynamicDecoder::new()?;
+/// decoder.add_file_descriptor_set(std::fs::read("ethereum.binpb")?.as_slice())?;
+///
+/// let projection = Projection::new(
+///     decoder,
+///     "sf.ethereum.type.v2.Block",
+///     ["number", "hash", "header", "transaction_traces.hash", "transaction_traces.from"],
+/// )?;
+///
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+/// let mut stream = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?
+///     .with_projection(projection);
+/// while let Some(response) = stream.message().await? {
+///     // `response.block` only holds the selected fields
+/// }
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Projection {
+    decoder: DynamicDecoder,
+    message: MessageDescriptor,
+    selection: Selection,
+}
+
+/// Selected fields of a message by number, with the selection within each,
+/// or `None` to keep the whole field.
+#[derive(Clone, Debug, Default)]
+struct Selection(BTreeMap<u32, Option<Selection>>);
+
+impl Projection {
+    /// Keep the fields at `paths` of blocks of the message type named by
+    /// `type_url`, using the descriptors registered with `decoder`.
+    ///
+    /// Fails with [`DynamicDecodeError::InvalidPath`] if a path names a field
+    /// that does not exist, or goes through a field that is not a message.
+    pub fn new<I, P>(
+        decoder: DynamicDecoder,
+        type_url: &str,
+        paths: I,
+    ) -> Result<Self, DynamicDecodeError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let message = decoder.message_descriptor(type_url)?;
+        let mut selection = Selection::default();
+        for path in paths {
+            selection.insert(&message, path.as_ref())?;
+        }
+
+        Ok(Projection {
+            decoder,
+            message,
+            selection,
+        })
+    }
+
+    /// Project the block of `response`, if any.
+    pub fn apply(&self, response: &mut Response) -> Result<(), DynamicDecodeError> {
+        match &mut response.block {
+            Some(block) => self.project(block),
+            None => Ok(()),
+        }
+    }
+
+    /// Project `block` in place.
+    ///
+    /// Blocks of other types than the projection's are left unchanged.
+    pub fn project(&self, block: &mut Any) -> Result<(), DynamicDecodeError> {
+        if message_name(&block.type_url) != self.message.full_name() {
+            return Ok(());
+        }
+
+        let mut message = self.decoder.decode(block)?;
+        self.selection.apply(&mut message);
+        block.value = message.encode_to_vec();
+        Ok(())
+    }
+}
+
+impl Selection {
+    /// Select the field at the dotted `path` of `message`.
+    fn insert(
+        &mut self,
+        message: &MessageDescriptor,
+        path: &str,
+    ) -> Result<(), DynamicDecodeError> {
+        let invalid = || DynamicDecodeError::InvalidPath(path.to_string());
+
+        let mut selection = self;
+        let mut message = message.clone();
+        let mut names = path.split('.').peekable();
+        while let Some(name) = names.next() {
+            let field = message.get_field_by_name(name).ok_or_else(invalid)?;
+            if names.peek().is_none() {
+                // Keep the whole field, even if parts were selected before
+                selection.0.insert(field.number(), None);
+                return Ok(());
+            }
+
+            let Kind::Message(inner) = field.kind() else {
+                return Err(invalid());
+            };
+            if field.is_map() {
+                return Err(invalid());
+            }
+            let entry = selection
+                .0
+                .entry(field.number())
+                .or_insert_with(|| Some(Selection::default()));
+            match entry {
+                Some(nested) => selection = nested,
+                // The whole field is already kept
+                None => return Ok(()),
+            }
+            message = inner;
+        }
+        Ok(())
+    }
+
+    /// Clear the fields of `message` that are not selected.
+    fn apply(&self, message: &mut DynamicMessage) {
+        for field in message.descriptor().fields() {
+            match self.0.get(&field.number()) {
+                None => message.clear_field(&field),
+                Some(None) => {}
+                Some(Some(_)) if !field.is_list() && !message.has_field(&field) => {}
+                Some(Some(nested)) => match message.get_field_mut(&field) {
+                    Value::Message(inner) => nested.apply(inner),
+                    Value::List(elements) => {
+                        for element in elements {
+                            if let Value::Message(inner) = element {
+                                nested.apply(inner);
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+}
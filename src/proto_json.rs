@@ -0,0 +1,79 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Canonical proto3 JSON for Firehose messages.
+//!
+//! The serde derives on the generated types follow Rust conventions
+//! (`snake_case` fields, numeric enums, integer lists for bytes). The methods
+//! here instead follow the [proto3 JSON mapping], matching `grpcurl` and the
+//! Go Firehose tooling: `lowerCamelCase` field names, enum names, base64
+//! encoded bytes, 64-bit integers as strings, and `Any` payloads expanded
+//! with an `@type` field.
+//!
+//! [proto3 JSON mapping]: https://protobuf.dev/programming-guides/proto3/#json
+
+use prost::{Message, Name};
+use prost_reflect::{DynamicMessage, MessageDescriptor, SerializeOptions};
+
+use crate::{DynamicDecodeError, DynamicDecoder};
+
+impl DynamicDecoder {
+    /// Render a message as canonical proto3 JSON.
+    ///
+    /// Block payloads inside `Any` fields are expanded, so the descriptors of
+    /// the chain's block type must be registered first.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use firehose_rs::{DynamicDecoder, Request};
+    ///
+    /// let decoder = DynamicDecoder::new().unwrap();
+    /// let request = Request {
+    ///     start_block_num: -10,
+    ///     stop_block_num: 20_000_000,
+    ///     final_blocks_only: true,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let json = decoder.to_proto_json(&request).unwrap();
+    /// assert_eq!(
+    ///     json,
+    ///     r#"{"startBlockNum":"-10","stopBlockNum":"20000000","finalBlocksOnly":true}"#
+    /// );
+    /// ```
+    pub fn to_proto_json<M>(&self, message: &M) -> Result<String, DynamicDecodeError>
+    where
+        M: Message + Name,
+    {
+        let descriptor = self.descriptor_for::<M>()?;
+        let dynamic = DynamicMessage::decode(descriptor, message.encode_to_vec().as_slice())?;
+
+        let mut serializer = serde_json::Serializer::new(Vec::new());
+        dynamic.serialize_with_options(&mut serializer, &SerializeOptions::new())?;
+
+        Ok(String::from_utf8(serializer.into_inner()).expect("serde_json emits valid UTF-8"))
+    }
+
+    /// Parse a message from canonical proto3 JSON.
+    pub fn from_proto_json<M>(&self, json: &str) -> Result<M, DynamicDecodeError>
+    where
+        M: Message + Name + Default,
+    {
+        let descriptor = self.descriptor_for::<M>()?;
+
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        let dynamic = DynamicMessage::deserialize(descriptor, &mut deserializer)?;
+        deserializer.end()?;
+
+        Ok(dynamic.transcode_to::<M>()?)
+    }
+
+    fn descriptor_for<M: Name>(&self) -> Result<MessageDescriptor, DynamicDecodeError> {
+        let name = M::full_name();
+        self.pool()
+            .get_message_by_name(&name)
+            .ok_or(DynamicDecodeError::UnknownType(name))
+    }
+}
@@ -0,0 +1,174 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Undecoded response frames, for archival at wire speed.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+use prost::bytes::Buf;
+use tonic::{
+    codec::{Codec, DecodeBuf, Decoder},
+    codegen::Bytes,
+    Status,
+};
+
+use crate::{WireEncoder, WireMessage};
+
+/// Largest frame [`read_delimited`] accepts, well above any block.
+const MAX_FRAME: u64 = 1 << 30;
+
+/// A tonic [`Codec`] sending `E` and receiving each response frame as the
+/// bytes of the encoded message, without decoding it.
+///
+/// Used by [`CodecClient::raw_blocks`](crate::CodecClient::raw_blocks) and
+/// [`raw_block`](crate::CodecClient::raw_block). A frame decodes with
+/// `Response::decode` when its contents are needed, for example the cursor
+/// of the last frame written to resume from.
+pub struct RawCodec<E> {
+    _request: PhantomData<fn(E)>,
+}
+
+impl<E> RawCodec<E> {
+    /// A codec for requests of type `E`.
+    pub fn new() -> Self {
+        RawCodec {
+            _request: PhantomData,
+        }
+    }
+}
+
+impl<E> Default for RawCodec<E> {
+    fn default() -> Self {
+        RawCodec::new()
+    }
+}
+
+impl<E> Clone for RawCodec<E> {
+    fn clone(&self) -> Self {
+        RawCodec::new()
+    }
+}
+
+impl<E> fmt::Debug for RawCodec<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawCodec").finish()
+    }
+}
+
+impl<E: WireMessage> Codec for RawCodec<E> {
+    type Encode = E;
+    type Decode = Bytes;
+    type Encoder = WireEncoder<E>;
+    type Decoder = RawDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        WireEncoder::new()
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        RawDecoder
+    }
+}
+
+/// Decoder of a [`RawCodec`], yielding frames as they arrived.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawDecoder;
+
+impl Decoder for RawDecoder {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Bytes>, Status> {
+        Ok(Some(src.copy_to_bytes(src.remaining())))
+    }
+}
+
+/// Write `frame` to `writer` prefixed with its length as a varint, the
+/// protobuf length-delimited format that `Response::decode_length_delimited`
+/// and [`read_delimited`] read back.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[cfg(feature = "runtime")]
+/// # mod example {
+/// use std::{fs::File, io::BufWriter};
+///
+/// use firehose_rs::{raw, FirehoseEndpoint, Request};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut client = FirehoseEndpoint::from_env()?.codec_client().await?;
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     stop_block_num: 17_099_999,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+///
+/// let mut file = BufWriter::new(File::create("blocks.bin")?);
+/// let mut frames = client.raw_blocks(request).await?.into_inner();
+/// while let Some(frame) = frames.message().await? {
+///     raw::write_delimited(&mut file, &frame)?;
+/// }
+/// # Ok(())
+/// # }
+/// # }
+/// ```
+pub fn write_delimited(writer: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    let mut len = frame.len() as u64;
+    let mut prefix = [0u8; 10];
+    let mut n = 0;
+    while len >= 0x80 {
+        prefix[n] = len as u8 | 0x80;
+        len >>= 7;
+        n += 1;
+    }
+    prefix[n] = len as u8;
+    writer.write_all(&prefix[..=n])?;
+    writer.write_all(frame)
+}
+
+/// Read the next frame written by [`write_delimited`], or `None` at the end
+/// of `reader`.
+///
+/// A truncated last frame, as left by an interrupted export, is an
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error.
+pub fn read_delimited(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        len |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift >= 64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame length overflows",
+            ));
+        }
+    }
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes exceeds the {MAX_FRAME} bytes limit"),
+        ));
+    }
+
+    let mut frame = vec![0; len as usize];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
@@ -0,0 +1,93 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Unique IDs sent with every call, so a failing request can be named in a
+//! provider support ticket.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tonic::metadata::MetadataMap;
+#[cfg(feature = "runtime")]
+use tonic::{
+    codegen::http::{HeaderMap, HeaderValue},
+    metadata::MetadataValue,
+    Status,
+};
+
+/// Metadata key of the ID attached to every call.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// A new random request ID, formatted as a version 4 UUID.
+pub fn new_request_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let random = |salt: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(salt);
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos()),
+        );
+        hasher.finish()
+    };
+    let bits = (u128::from(random(0)) << 64) | u128::from(random(1));
+    // Version 4, variant 1
+    let bits = (bits & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// The request ID in `metadata`, if any.
+pub fn request_id(metadata: &MetadataMap) -> Option<&str> {
+    metadata.get(REQUEST_ID_HEADER)?.to_str().ok()
+}
+
+/// The request ID in `metadata`, after inserting a new one if it had none.
+#[cfg(feature = "runtime")]
+pub(crate) fn ensure(metadata: &mut MetadataMap) -> String {
+    if let Some(id) = request_id(metadata) {
+        return id.to_string();
+    }
+    let id = new_request_id();
+    if let Ok(value) = MetadataValue::try_from(id.as_str()) {
+        metadata.insert(REQUEST_ID_HEADER, value);
+    }
+    id
+}
+
+/// As [`ensure`], on the HTTP headers of a call.
+#[cfg(feature = "runtime")]
+pub(crate) fn ensure_header(headers: &mut HeaderMap) -> Option<String> {
+    let value = headers
+        .entry(REQUEST_ID_HEADER)
+        .or_insert_with(|| HeaderValue::from_str(&new_request_id()).expect("UUIDs are ASCII"));
+    value.to_str().ok().map(str::to_string)
+}
+
+/// Attach `id` to `status`, so the error names the failed call, unless the
+/// server already echoed one.
+#[cfg(feature = "runtime")]
+pub(crate) fn tag(mut status: Status, id: &str) -> Status {
+    if request_id(status.metadata()).is_none() {
+        if let Ok(value) = MetadataValue::try_from(id) {
+            status.metadata_mut().insert(REQUEST_ID_HEADER, value);
+        }
+    }
+    status
+}
@@ -0,0 +1,2138 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use prost::Message;
+use tokio::{
+    sync::{broadcast, mpsc, watch, Notify, Semaphore},
+    task::JoinHandle,
+    time::Instant,
+};
+use tonic::{
+    metadata::{Ascii, MetadataKey, MetadataValue},
+    Status, Streaming,
+};
+
+#[cfg(feature = "dynamic")]
+use crate::Projection;
+use crate::{
+    cursor_block_num, request_id, Anchors, Backoff, BlockMetadata, CallMetrics, ChannelLease,
+    CursorStore, DeadLetter, DeadLetterSink, EndpointPool, FirehoseEndpoint, FirehoseError,
+    ForkStep, FromResponse, HealthReporter, Request, Response, RetryBudget, SpillWriter,
+};
+
+/// Events buffered per subscriber before the slowest one starts lagging.
+const EVENT_CAPACITY: usize = 1024;
+
+/// Blocks buffered by [`spawn_stream`] before the background task waits for
+/// the receiver.
+pub const DEFAULT_SPAWN_BUFFER: usize = 64;
+
+/// Sessions kept by [`StreamHandle::sessions`]; older ones are dropped.
+pub const SESSION_HISTORY: usize = 100;
+
+/// Lifecycle events of a [`ResilientStream`], delivered to every receiver
+/// returned by [`ResilientStream::subscribe`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StreamEvent {
+    /// The stream was opened for the first time.
+    Connected,
+    /// The stream was reopened after a failure or stall.
+    Resumed {
+        /// Cursor the stream resumed from, empty if no block had been
+        /// received yet.
+        cursor: String,
+    },
+    /// The stream failed. It is reopened if the error is retryable.
+    Disconnected {
+        /// Description of the failure.
+        error: String,
+    },
+    /// The stream was moved by [`StreamHandle::seek`] and is reopened at
+    /// `to`.
+    Seeked {
+        /// Where the stream was moved to.
+        to: SeekTo,
+    },
+    /// No block arrived within the stall timeout, so the stream is reopened.
+    Stalled,
+    /// A block was received.
+    Progress {
+        /// Number of the block.
+        block: u64,
+    },
+    /// The chain reorganized: `depth` blocks were undone before the next new
+    /// block.
+    Reorg {
+        /// Number of consecutive undo steps received.
+        depth: u64,
+    },
+    /// The stream fell further behind the chain than its [`LagAlert`]
+    /// allows.
+    LagExceeded {
+        /// Blocks behind the head feed, if the stream has one.
+        blocks: Option<u64>,
+        /// Age of the last block received, if it carries a timestamp.
+        delay: Option<Duration>,
+    },
+    /// The stream caught up again after a [`StreamEvent::LagExceeded`].
+    LagRecovered,
+    /// A block failed to decode and was skipped, according to the stream's
+    /// [`ErrorPolicy`].
+    Skipped {
+        /// Number of the block, if the response carried its metadata.
+        block: Option<u64>,
+        /// Why decoding failed.
+        error: String,
+    },
+    /// The server rejected the stream's cursor, so the stream is reopened
+    /// from a block number according to its [`CursorRecovery`].
+    CursorRecovered {
+        /// The rejected cursor.
+        cursor: String,
+        /// Block the stream is reopened at.
+        start_block: u64,
+    },
+    /// The stream reached its stop block.
+    Completed {
+        /// What the stream received overall.
+        summary: StreamSummary,
+    },
+}
+
+/// What a [`ResilientStream`] received, from [`ResilientStream::summary`] or
+/// [`StreamEvent::Completed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamSummary {
+    /// Blocks returned by [`ResilientStream::message`], undo steps included.
+    pub blocks_received: u64,
+    /// Encoded size of those blocks.
+    pub bytes: u64,
+    /// Time since the first call to [`ResilientStream::message`], up to
+    /// completion if the stream completed.
+    pub duration: Duration,
+    /// Times the stream was reopened after a failure or stall.
+    pub reconnects: u64,
+    /// Blocks skipped by [`ResilientStream::message_as`] after failing to
+    /// decode, dead-lettered ones included.
+    pub skipped: u64,
+    /// Skipped blocks sent to the dead-letter sink.
+    pub dead_lettered: u64,
+    /// Cursor of the last block received, or emitted for
+    /// [`ResilientStream::process_ordered`], to resume from.
+    pub final_cursor: String,
+}
+
+/// One connection of a [`ResilientStream`] to an endpoint, from
+/// [`StreamHandle::sessions`].
+///
+/// A stream opens a new session on every reconnect, so the history shows
+/// which endpoints flapped, for how long sessions lasted and why they ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamSession {
+    /// URI of the endpoint.
+    pub endpoint: String,
+    /// The `x-request-id` of the call that opened the session, to reference
+    /// it with the provider.
+    pub request_id: String,
+    /// When the session was opened.
+    pub started: SystemTime,
+    /// How long the session lasted, or has lasted so far if still open.
+    pub duration: Duration,
+    /// Cursor the session was opened with, empty when streaming from a block
+    /// number.
+    pub start_cursor: String,
+    /// Cursor of the last block received in the session, or `start_cursor`
+    /// if none was.
+    pub end_cursor: String,
+    /// Blocks received in the session.
+    pub blocks: u64,
+    /// Encoded size of those blocks.
+    pub bytes: u64,
+    /// Why the session ended, `None` while it is open.
+    pub end: Option<SessionEnd>,
+}
+
+/// Why a [`StreamSession`] ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SessionEnd {
+    /// The stream could not be opened, failed or ended early.
+    Failed {
+        /// Description of the failure.
+        error: String,
+    },
+    /// No block arrived within the stall timeout.
+    Stalled,
+    /// The stream was moved by [`StreamHandle::seek`].
+    Seeked,
+    /// The stream reached its stop block.
+    Completed,
+}
+
+/// Session history of a stream, shared with its handles.
+#[derive(Debug, Default)]
+struct Sessions {
+    /// Sessions with the instant they were opened, oldest first. Only the
+    /// last one may still be open.
+    log: VecDeque<(StreamSession, Instant)>,
+}
+
+impl Sessions {
+    fn open(&mut self, endpoint: String, request_id: &str, cursor: &str) {
+        if self.log.len() == SESSION_HISTORY {
+            self.log.pop_front();
+        }
+        let session = StreamSession {
+            endpoint,
+            request_id: request_id.to_string(),
+            started: SystemTime::now(),
+            duration: Duration::ZERO,
+            start_cursor: cursor.to_string(),
+            end_cursor: cursor.to_string(),
+            blocks: 0,
+            bytes: 0,
+            end: None,
+        };
+        self.log.push_back((session, Instant::now()));
+    }
+
+    fn current(&mut self) -> Option<&mut (StreamSession, Instant)> {
+        self.log
+            .back_mut()
+            .filter(|(session, _)| session.end.is_none())
+    }
+
+    fn received(&mut self, cursor: &str, bytes: u64) {
+        if let Some((session, _)) = self.current() {
+            session.end_cursor.clear();
+            session.end_cursor.push_str(cursor);
+            session.blocks += 1;
+            session.bytes += bytes;
+        }
+    }
+
+    fn close(&mut self, end: SessionEnd) {
+        if let Some((session, opened)) = self.current() {
+            session.duration = opened.elapsed();
+            session.end = Some(end);
+        }
+    }
+
+    fn snapshot(&self) -> Vec<StreamSession> {
+        self.log
+            .iter()
+            .map(|(session, opened)| {
+                let mut session = session.clone();
+                if session.end.is_none() {
+                    session.duration = opened.elapsed();
+                }
+                session
+            })
+            .collect()
+    }
+}
+
+/// Thresholds for the [`StreamEvent::LagExceeded`] alert of a
+/// [`ResilientStream`].
+///
+/// The alert is raised when either threshold is exceeded, and only cleared
+/// once the lag is back below `recovery` times every threshold, so a stream
+/// hovering around a threshold does not flap between alerts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LagAlert {
+    /// Maximum number of blocks behind the
+    /// [head feed](ResilientStream::with_head).
+    pub max_blocks: Option<u64>,
+    /// Maximum age of the last block received.
+    pub max_delay: Option<Duration>,
+    /// Fraction of the thresholds the lag must fall below to clear the alert.
+    pub recovery: f64,
+}
+
+impl Default for LagAlert {
+    fn default() -> Self {
+        LagAlert {
+            max_blocks: None,
+            max_delay: None,
+            recovery: 0.5,
+        }
+    }
+}
+
+impl LagAlert {
+    fn exceeded(&self, factor: f64, blocks: Option<u64>, delay: Option<Duration>) -> bool {
+        let blocks_exceeded = self
+            .max_blocks
+            .zip(blocks)
+            .is_some_and(|(max, lag)| lag as f64 > max as f64 * factor);
+        let delay_exceeded = self
+            .max_delay
+            .zip(delay)
+            .is_some_and(|(max, lag)| lag > max.mul_f64(factor));
+        blocks_exceeded || delay_exceeded
+    }
+}
+
+/// What [`ResilientStream::message_as`] does with blocks that fail to decode
+/// or validate in [`FromResponse`].
+///
+/// Skipped blocks are counted in the [`StreamSummary`] and reported as
+/// [`StreamEvent::Skipped`], so skipping never goes unnoticed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Return the error and stop.
+    #[default]
+    FailFast,
+    /// Skip failed blocks, but return the error once more than
+    /// `max_consecutive` blocks in a row have failed.
+    Skip {
+        /// Failures in a row tolerated before giving up.
+        max_consecutive: u32,
+    },
+    /// Send failed blocks to the
+    /// [dead-letter sink](ResilientStream::with_dead_letters) and continue.
+    /// Without a sink, behaves like [`ErrorPolicy::FailFast`].
+    DeadLetter,
+}
+
+/// What a [`ResilientStream`] does when the server rejects its cursor, see
+/// [`ResilientStream::with_cursor_recovery`].
+///
+/// Restarting from a block number instead of a cursor loses the fork
+/// position the cursor carried: a block the cursor pointed at on a fork that
+/// was since abandoned is not undone. Recovered streams emit
+/// [`StreamEvent::CursorRecovered`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CursorRecovery {
+    /// Return the error and stop.
+    #[default]
+    Fail,
+    /// Restart right after the block the cursor points at, read with
+    /// [`cursor_block_num`](crate::cursor_block_num), or after the last block
+    /// received when the cursor does not reveal it.
+    CursorBlock,
+    /// Restart `margin` blocks before the last block received, or before the
+    /// block the cursor points at if none was received yet, replaying those
+    /// blocks.
+    LastBlock {
+        /// Blocks replayed before the last known one.
+        margin: u64,
+    },
+}
+
+/// How often a [`ResilientStream`] commits its cursor to a [`CursorStore`].
+///
+/// The cursor is committed as soon as either limit is reached. Committing
+/// less often than every block saves checkpoint I/O, at the cost of replaying
+/// the blocks processed since the last commit after a crash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CheckpointInterval {
+    /// Commit after this many processed blocks.
+    pub blocks: Option<u64>,
+    /// Commit once this much time has passed since the last commit.
+    pub period: Option<Duration>,
+}
+
+impl Default for CheckpointInterval {
+    /// Commit after every block.
+    fn default() -> Self {
+        CheckpointInterval {
+            blocks: Some(1),
+            period: None,
+        }
+    }
+}
+
+struct Checkpointer {
+    store: Box<dyn CursorStore + Send>,
+    interval: CheckpointInterval,
+    /// Blocks processed since the last commit.
+    pending: u64,
+    committed_at: Instant,
+}
+
+impl Checkpointer {
+    fn is_due(&self) -> bool {
+        self.pending > 0
+            && (self
+                .interval
+                .blocks
+                .is_some_and(|blocks| self.pending >= blocks)
+                || self
+                    .interval
+                    .period
+                    .is_some_and(|period| self.committed_at.elapsed() >= period))
+    }
+
+    /// Count one more processed block, committing `cursor` if due.
+    fn processed(&mut self, cursor: &str) -> Result<(), FirehoseError> {
+        self.pending += 1;
+        if self.is_due() {
+            self.commit(cursor)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, cursor: &str) -> Result<(), FirehoseError> {
+        if self.pending > 0 && !cursor.is_empty() {
+            self.store.store(cursor)?;
+        }
+        self.pending = 0;
+        self.committed_at = Instant::now();
+        Ok(())
+    }
+}
+
+struct DeadLetters(Box<dyn DeadLetterSink>);
+
+impl fmt::Debug for DeadLetters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DeadLetters").finish_non_exhaustive()
+    }
+}
+
+/// How a stream handles blocks that fail to convert, and how many it
+/// skipped. Shared with the emitting task when decoding is offloaded.
+#[derive(Debug, Default)]
+struct Decoding {
+    policy: ErrorPolicy,
+    dead_letters: Option<DeadLetters>,
+    consecutive_failures: u32,
+    skipped: u64,
+    dead_lettered: u64,
+}
+
+impl Decoding {
+    /// Whether failed blocks are dead-lettered, so responses must be kept
+    /// until they are converted.
+    fn keeps_responses(&self) -> bool {
+        self.policy == ErrorPolicy::DeadLetter && self.dead_letters.is_some()
+    }
+
+    /// Apply the [`ErrorPolicy`] to the conversion `result` of `block`,
+    /// returning `None` if the block is skipped.
+    fn outcome<T>(
+        &mut self,
+        events: &broadcast::Sender<StreamEvent>,
+        block: Option<u64>,
+        response: Option<Response>,
+        result: Result<T, String>,
+    ) -> Result<Option<T>, FirehoseError> {
+        let error = match result {
+            Ok(decoded) => {
+                self.consecutive_failures = 0;
+                return Ok(Some(decoded));
+            }
+            Err(error) => error,
+        };
+        self.consecutive_failures += 1;
+
+        match (self.policy, &mut self.dead_letters, response) {
+            (ErrorPolicy::Skip { max_consecutive }, _, _)
+                if self.consecutive_failures <= max_consecutive => {}
+            (ErrorPolicy::DeadLetter, Some(DeadLetters(sink)), Some(response)) => {
+                sink.send(DeadLetter::new(response, &error))?;
+                self.dead_lettered += 1;
+            }
+            _ => return Err(FirehoseError::Decode(error)),
+        }
+
+        self.skipped += 1;
+        let event = StreamEvent::Skipped { block, error };
+        #[cfg(feature = "log")]
+        crate::logging::stream_event(&event, String::new, "");
+        // Sending only fails when nobody is subscribed.
+        let _ = events.send(event);
+        Ok(None)
+    }
+}
+
+impl fmt::Debug for Checkpointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Checkpointer")
+            .field("interval", &self.interval)
+            .field("pending", &self.pending)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where a [`StreamHandle::seek`] moves a stream to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SeekTo {
+    /// Restart at this block number. Negative numbers are relative to the
+    /// chain head, as in [`Request::start_block_num`].
+    Block(i64),
+    /// Resume right after the block of this cursor.
+    Cursor(String),
+}
+
+impl From<i64> for SeekTo {
+    fn from(block: i64) -> Self {
+        SeekTo::Block(block)
+    }
+}
+
+impl From<String> for SeekTo {
+    fn from(cursor: String) -> Self {
+        SeekTo::Cursor(cursor)
+    }
+}
+
+impl From<&str> for SeekTo {
+    fn from(cursor: &str) -> Self {
+        SeekTo::Cursor(cursor.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Control {
+    paused: bool,
+    seek: Option<SeekTo>,
+}
+
+/// Controls a [`ResilientStream`] from another task.
+///
+/// Obtained from [`ResilientStream::handle`]. Clones control the same stream.
+#[derive(Clone, Debug)]
+pub struct StreamHandle {
+    control: Arc<watch::Sender<Control>>,
+    sessions: Arc<Mutex<Sessions>>,
+}
+
+impl StreamHandle {
+    fn new() -> Self {
+        StreamHandle {
+            control: Arc::new(watch::channel(Control::default()).0),
+            sessions: Arc::default(),
+        }
+    }
+
+    /// The last [`SESSION_HISTORY`] sessions of the stream, oldest first,
+    /// including failed connection attempts and the open session, if any.
+    ///
+    /// Lets operators diagnose a flapping endpoint after the fact, for
+    /// example from an admin endpoint, instead of from scattered logs.
+    pub fn sessions(&self) -> Vec<StreamSession> {
+        self.session_log().snapshot()
+    }
+
+    fn session_log(&self) -> MutexGuard<'_, Sessions> {
+        // The log stays consistent even if a holder panicked.
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stop pulling blocks from the endpoint.
+    ///
+    /// [`ResilientStream::message`] waits until the stream is resumed. The
+    /// connection stays open, and HTTP/2 flow control pushes back on the
+    /// server once the buffered blocks fill up, so nothing is lost or
+    /// re-fetched. Useful while a downstream store is under maintenance.
+    pub fn pause(&self) {
+        self.control.send_modify(|control| control.paused = true);
+    }
+
+    /// Continue pulling blocks after [`StreamHandle::pause`].
+    pub fn resume(&self) {
+        self.control.send_modify(|control| control.paused = false);
+    }
+
+    /// Whether the stream is paused.
+    pub fn is_paused(&self) -> bool {
+        self.control.borrow().paused
+    }
+
+    /// Move the stream to another block or cursor.
+    ///
+    /// The current upstream stream is torn down, including a pending
+    /// [`ResilientStream::message`] call, and reopened at `to`, while the
+    /// [`ResilientStream`] itself stays usable. The stop block of the request
+    /// is kept. A seek requested while paused applies on resume; of several
+    /// seeks requested before the stream notices, the last one wins.
+    pub fn seek(&self, to: impl Into<SeekTo>) {
+        let to = to.into();
+        self.control.send_modify(|control| control.seek = Some(to));
+    }
+
+    fn take_seek(&self) -> Option<SeekTo> {
+        let mut seek = None;
+        // Taking the request is not a change other tasks need to see.
+        self.control.send_if_modified(|control| {
+            seek = control.seek.take();
+            false
+        });
+        seek
+    }
+}
+
+/// Acknowledges processed blocks of a [`ResilientStream`] in
+/// [acknowledged mode](ResilientStream::with_acks).
+///
+/// Obtained from [`ResilientStream::ack_handle`]. Clones acknowledge blocks
+/// of the same stream, from any task.
+#[derive(Clone, Debug)]
+pub struct AckHandle {
+    sender: mpsc::UnboundedSender<String>,
+}
+
+impl AckHandle {
+    /// Acknowledge the block with `cursor` and every block received before
+    /// it, so a whole batch is acknowledged through its last cursor.
+    ///
+    /// Acknowledging a block that is already covered by a later
+    /// acknowledgement does nothing.
+    pub fn ack(&self, cursor: impl Into<String>) {
+        // A dropped stream has nothing left to checkpoint.
+        let _ = self.sender.send(cursor.into());
+    }
+}
+
+/// Acknowledged-delivery state of a stream.
+#[derive(Debug)]
+struct Acks {
+    handle: AckHandle,
+    receiver: mpsc::UnboundedReceiver<String>,
+    /// Cursors and encoded sizes of the blocks received but not acknowledged
+    /// yet, oldest first.
+    outstanding: VecDeque<(String, u64)>,
+    /// Cursor of the last acknowledged block.
+    acked: String,
+}
+
+impl Acks {
+    /// Apply the acknowledgements received so far, returning the number of
+    /// blocks they cover and their encoded size.
+    fn apply(&mut self) -> (u64, u64) {
+        let (mut blocks, mut bytes) = (0, 0);
+        while let Ok(cursor) = self.receiver.try_recv() {
+            let (acked, size) = self.acknowledge(cursor);
+            blocks += acked;
+            bytes += size;
+        }
+        (blocks, bytes)
+    }
+
+    /// Apply the acknowledgement of `cursor`, returning the number of blocks
+    /// it covers and their encoded size.
+    fn acknowledge(&mut self, cursor: String) -> (u64, u64) {
+        let Some(position) = self.outstanding.iter().position(|(c, _)| *c == cursor) else {
+            return (0, 0);
+        };
+        let bytes = self
+            .outstanding
+            .drain(..=position)
+            .map(|(_, size)| size)
+            .sum();
+        self.acked = cursor;
+        (position as u64 + 1, bytes)
+    }
+}
+
+/// Encoded size of the blocks handed out but not processed yet, shared with
+/// the tasks of [`ResilientStream::process_ordered`].
+#[derive(Debug)]
+struct MemoryLimit {
+    max: u64,
+    used: AtomicU64,
+    released: Notify,
+}
+
+impl MemoryLimit {
+    fn take(&self, bytes: u64) {
+        self.used.fetch_add(bytes, Ordering::AcqRel);
+    }
+
+    fn release(&self, bytes: u64) {
+        if bytes > 0 {
+            self.used.fetch_sub(bytes, Ordering::AcqRel);
+            self.released.notify_waiters();
+        }
+    }
+
+    fn exceeded(&self) -> bool {
+        self.used.load(Ordering::Acquire) >= self.max
+    }
+
+    /// Wait until blocks were released below the limit.
+    async fn drained(&self) {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            if !self.exceeded() {
+                return;
+            }
+            released.await;
+        }
+    }
+}
+
+/// A block stream that reconnects after transient failures.
+///
+/// When the connection drops or the endpoint answers with a retryable status,
+/// the stream waits according to its [`Backoff`], or longer if the server
+/// asked for it with a [`RetryInfo`](crate::RetryInfo) status detail. It then
+/// reopens the request from the cursor of the last block it returned, on the
+/// best endpoint of its [`EndpointPool`] other than the one that just failed.
+/// Blocks are therefore neither skipped nor repeated across reconnects.
+///
+/// Failures count against the endpoint's [statistics](EndpointPool::stats)
+/// and mark it unhealthy, like failed fetches and probes do, so later
+/// sessions and fetches prefer the other endpoints until it recovers.
+///
+/// A [stall timeout](ResilientStream::with_stall_timeout) also reopens the
+/// stream when no block has arrived for a while, which catches connections
+/// that hang without ever reporting an error.
+///
+/// Applications can follow connections, progress and reorgs without parsing
+/// logs by [subscribing](ResilientStream::subscribe) to its [`StreamEvent`]s.
+///
+/// A [bandwidth limit](ResilientStream::with_bandwidth_limit) paces
+/// backfills on shared links or against provider bandwidth quotas.
+///
+/// A [`StreamHandle`] can pause, resume and seek the stream from another
+/// task.
+///
+/// Streams that share a [`RetryBudget`] additionally wait for a budget token
+/// before every reconnect, which bounds the reconnect rate of a whole fleet of
+/// streams during a provider outage.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use firehose_rs::{FirehoseEndpoint, Request, ResilientStream, RetryBudget};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let budget = RetryBudget::new(20, Duration::from_secs(60));
+///
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+///
+/// let mut stream = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?
+///     .with_retry_budget(budget.clone());
+///
+/// while let Some(response) = stream.message().await? {
+///     println!("Received block at cursor: {}", response.cursor);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ResilientStream {
+    pool: EndpointPool,
+    endpoint: usize,
+    request: Request,
+    backoff: Backoff,
+    budget: Option<RetryBudget>,
+    stall_timeout: Option<Duration>,
+    head: Option<watch::Receiver<u64>>,
+    lag_alert: Option<LagAlert>,
+    lagging: bool,
+    bandwidth_limit: Option<u64>,
+    acks: Option<Acks>,
+    memory: Option<Arc<MemoryLimit>>,
+    /// Size of the last block returned, released at the next call to
+    /// [`ResilientStream::message`].
+    held: u64,
+    /// Whether blocks are released as [`ResilientStream::spawn_ordered`]
+    /// emits their results instead.
+    release_on_emit: bool,
+    decoding: Arc<Mutex<Decoding>>,
+    decode_workers: Option<usize>,
+    #[cfg(feature = "dynamic")]
+    projection: Option<Projection>,
+    ready_at: Instant,
+    events: broadcast::Sender<StreamEvent>,
+    handle: StreamHandle,
+    control: watch::Receiver<Control>,
+    stream: Option<Streaming<Response>>,
+    last_block: Option<u64>,
+    undo_depth: u64,
+    connected: bool,
+    attempt: u32,
+    reconnects: u64,
+    stalls: u64,
+    blocks_received: u64,
+    bytes_received: u64,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    checkpointer: Option<Checkpointer>,
+    metadata: Vec<(MetadataKey<Ascii>, MetadataValue<Ascii>)>,
+    metrics: Option<(CallMetrics, String)>,
+    health: Option<(HealthReporter, String)>,
+    /// ID of the call of the current session.
+    request_id: String,
+    /// Channel kept across reconnects to the same endpoint.
+    lease: Option<ChannelLease>,
+    /// Endpoint of the last failed session, avoided by the next one.
+    failed: Option<usize>,
+    cursor_recovery: CursorRecovery,
+    anchors: Option<Anchors>,
+}
+
+impl ResilientStream {
+    /// Stream `request` from the endpoints of `pool`.
+    ///
+    /// Nothing is sent until the first call to [`ResilientStream::message`].
+    pub fn new(pool: EndpointPool, request: Request) -> Self {
+        let handle = StreamHandle::new();
+        let control = handle.control.subscribe();
+
+        ResilientStream {
+            pool,
+            endpoint: 0,
+            request,
+            backoff: Backoff::default(),
+            budget: None,
+            stall_timeout: None,
+            head: None,
+            lag_alert: None,
+            lagging: false,
+            bandwidth_limit: None,
+            acks: None,
+            memory: None,
+            held: 0,
+            release_on_emit: false,
+            decoding: Arc::default(),
+            decode_workers: None,
+            #[cfg(feature = "dynamic")]
+            projection: None,
+            ready_at: Instant::now(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            handle,
+            control,
+            stream: None,
+            last_block: None,
+            undo_depth: 0,
+            connected: false,
+            attempt: 0,
+            reconnects: 0,
+            stalls: 0,
+            blocks_received: 0,
+            bytes_received: 0,
+            started: None,
+            finished: None,
+            checkpointer: None,
+            metadata: Vec::new(),
+            metrics: None,
+            health: None,
+            request_id: String::new(),
+            lease: None,
+            failed: None,
+            cursor_recovery: CursorRecovery::default(),
+            anchors: None,
+        }
+    }
+
+    /// Stream `request` from a single endpoint.
+    ///
+    /// Must be called from within a Tokio runtime, see [`EndpointPool::new`].
+    pub fn from_endpoint(
+        endpoint: FirehoseEndpoint,
+        request: Request,
+    ) -> Result<Self, FirehoseError> {
+        Ok(Self::new(EndpointPool::new([endpoint])?, request))
+    }
+
+    /// Set the delays between reconnect attempts.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Take a token from `budget` before every reconnect.
+    pub fn with_retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Resume from the cursor in `store`, if any, and commit the cursor of
+    /// processed blocks back to it according to `interval`.
+    ///
+    /// A block counts as processed once [`ResilientStream::message`] is called
+    /// again, or once acknowledged [with acknowledgements](ResilientStream::with_acks),
+    /// so a crash while handling it replays it instead of skipping it.
+    /// Call [`ResilientStream::checkpoint`] on shutdown to commit the blocks
+    /// processed since the last commit.
+    pub fn with_cursor_store(
+        mut self,
+        store: impl CursorStore + Send + 'static,
+        interval: CheckpointInterval,
+    ) -> Result<Self, FirehoseError> {
+        if let Some(cursor) = store.load()? {
+            self.request.cursor = cursor;
+        }
+
+        self.checkpointer = Some(Checkpointer {
+            store: Box::new(store),
+            interval,
+            pending: 0,
+            committed_at: Instant::now(),
+        });
+        Ok(self)
+    }
+
+    /// Reopen the stream from a block number according to `recovery` when the
+    /// server rejects its cursor, instead of failing.
+    ///
+    /// Stored cursors become invalid when the provider prunes the history
+    /// they point into, or when the stream moves to another provider. The
+    /// recovered stream commits new cursors to the
+    /// [cursor store](ResilientStream::with_cursor_store) as usual.
+    pub fn with_cursor_recovery(mut self, recovery: CursorRecovery) -> Self {
+        self.cursor_recovery = recovery;
+        self
+    }
+
+    /// Fail with [`FirehoseError::AnchorMismatch`] as soon as a final block
+    /// at the height of one of `anchors` has another hash, see
+    /// [`Anchors::verify`].
+    ///
+    /// The mismatching block is not returned, and the stream does not
+    /// reconnect, since another session would likely serve the same fork.
+    pub fn with_anchors(mut self, anchors: Anchors) -> Self {
+        self.anchors = Some(anchors);
+        self
+    }
+
+    /// Only commit cursors the consumer has acknowledged through the
+    /// [`AckHandle`] returned by [`ResilientStream::ack_handle`].
+    ///
+    /// Instead of counting a block as processed once
+    /// [`ResilientStream::message`] is called again, the
+    /// [cursor store](ResilientStream::with_cursor_store) only advances to
+    /// blocks acknowledged together with all blocks before them, for example
+    /// once downstream durable storage confirmed a batch. Acknowledgements are
+    /// applied at the next checkpoint, according to the
+    /// [`CheckpointInterval`]. Unacknowledged cursors are kept in memory, so
+    /// consumers must keep acknowledging.
+    pub fn with_acks(mut self) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.acks = Some(Acks {
+            handle: AckHandle { sender },
+            receiver,
+            outstanding: VecDeque::new(),
+            acked: String::new(),
+        });
+        self
+    }
+
+    /// A handle to acknowledge processed blocks, if the stream was created
+    /// [with acknowledgements](ResilientStream::with_acks).
+    pub fn ack_handle(&self) -> Option<AckHandle> {
+        self.acks.as_ref().map(|acks| acks.handle.clone())
+    }
+
+    /// Commit the cursor of the last processed block to the cursor store now.
+    ///
+    /// Does nothing without a [cursor store](ResilientStream::with_cursor_store)
+    /// or when no block was processed since the last commit.
+    pub fn checkpoint(&mut self) -> Result<(), FirehoseError> {
+        self.apply_acks();
+        let Some(checkpointer) = &mut self.checkpointer else {
+            return Ok(());
+        };
+
+        match &self.acks {
+            Some(acks) => checkpointer.commit(&acks.acked),
+            None => checkpointer.commit(&self.request.cursor),
+        }
+    }
+
+    /// Apply the acknowledgements received so far, if the stream was created
+    /// [with acknowledgements](ResilientStream::with_acks).
+    fn apply_acks(&mut self) {
+        if let Some(acks) = &mut self.acks {
+            let acked = acks.apply();
+            self.processed(acked);
+        }
+    }
+
+    /// Count `blocks` of `bytes` in encoded size as processed.
+    fn processed(&mut self, (blocks, bytes): (u64, u64)) {
+        if let Some(checkpointer) = &mut self.checkpointer {
+            checkpointer.pending += blocks;
+        }
+        if let Some(memory) = &self.memory {
+            memory.release(bytes);
+        }
+    }
+
+    /// Wait until the consumer holds less than the
+    /// [memory limit](ResilientStream::with_memory_limit).
+    async fn within_memory_limit(&mut self) {
+        let Some(memory) = self.memory.clone() else {
+            return;
+        };
+        memory.release(std::mem::take(&mut self.held));
+
+        while memory.exceeded() {
+            match &mut self.acks {
+                Some(acks) => {
+                    // The stream keeps a sender alive through its handle, so
+                    // this cannot fail.
+                    if let Some(cursor) = acks.receiver.recv().await {
+                        let acked = acks.acknowledge(cursor);
+                        self.processed(acked);
+                    }
+                }
+                None => memory.drained().await,
+            }
+        }
+    }
+
+    /// Send `key: value` metadata with every call opening the stream, on top
+    /// of the [endpoint's](FirehoseEndpoint::with_metadata).
+    ///
+    /// Every call carries a new `x-request-id` unless one is set here; it is
+    /// recorded in the [session](StreamSession::request_id) and echoed in the
+    /// errors of the session, see [`FirehoseError::request_id`].
+    ///
+    /// # Panics
+    ///
+    /// Here rather than on each call, if `key` is not a valid ASCII metadata
+    /// key.
+    pub fn with_metadata(mut self, key: &'static str, value: MetadataValue<Ascii>) -> Self {
+        self.metadata.push((MetadataKey::from_static(key), value));
+        self
+    }
+
+    /// Record the size of every block received, the time between blocks,
+    /// the stream's lag and its reconnects, in the
+    /// [stream metrics](CallMetrics::streams) of `metrics` under `label`.
+    ///
+    /// The lag in blocks is measured against the [head feed](Self::with_head),
+    /// if any, and the lag in time against block timestamps.
+    ///
+    /// Sizes are those of blocks as received, before any
+    /// [projection](ResilientStream::with_projection).
+    pub fn with_metrics(mut self, metrics: CallMetrics, label: impl Into<String>) -> Self {
+        self.metrics = Some((metrics, label.into()));
+        self
+    }
+
+    /// Report the stream's liveness and lag to `health` under `name`.
+    ///
+    /// The lag is measured against the [head feed](Self::with_head), if any.
+    pub fn with_health(mut self, health: HealthReporter, name: impl Into<String>) -> Self {
+        let name = name.into();
+        health.register(&name);
+        self.health = Some((health, name));
+        self
+    }
+
+    /// Reopen the stream from its last cursor when no block arrives for
+    /// `timeout`.
+    ///
+    /// Pick a timeout comfortably above the chain's block time, since an idle
+    /// chain is indistinguishable from a stalled stream unless a
+    /// [head feed](ResilientStream::with_head) is also given.
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
+    /// Only treat a silent stream as stalled if `head`, the latest known block
+    /// number of the chain, has moved past the last block received.
+    pub fn with_head(mut self, head: watch::Receiver<u64>) -> Self {
+        self.head = Some(head);
+        self
+    }
+
+    /// Emit [`StreamEvent::LagExceeded`] and [`StreamEvent::LagRecovered`]
+    /// according to `alert`.
+    ///
+    /// Lag in blocks is only known with a
+    /// [head feed](ResilientStream::with_head).
+    pub fn with_lag_alert(mut self, alert: LagAlert) -> Self {
+        self.lag_alert = Some(alert);
+        self
+    }
+
+    /// Receive at most `bytes_per_sec` bytes per second on average.
+    ///
+    /// After each block, [`ResilientStream::message`] sleeps as long as the
+    /// block's encoded size takes at this rate, leaving less to read from the
+    /// connection, whose flow control then slows down the server.
+    pub fn with_bandwidth_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.bandwidth_limit = Some(bytes_per_sec.max(1));
+        self
+    }
+
+    /// Stop receiving while blocks of `max_bytes` or more in encoded size are
+    /// held by the consumer, until it processes some of them.
+    ///
+    /// A block is held from the moment [`ResilientStream::message`] returns
+    /// it until it is processed, which is when `message` is called again,
+    /// when it is [acknowledged](ResilientStream::with_acks), or when
+    /// [`ResilientStream::process_ordered`] emits its result. While the limit
+    /// is exceeded, the gRPC stream is not polled, so the connection's flow
+    /// control stops the server and memory stays bounded however long
+    /// downstream stalls. A single block larger than the limit is still
+    /// received once nothing else is held.
+    ///
+    /// Consumers holding blocks until later ones arrive, such as
+    /// [`ConfirmedStream`](crate::ConfirmedStream), need a limit above the
+    /// size of all the blocks they wait for, or they wait forever.
+    pub fn with_memory_limit(mut self, max_bytes: u64) -> Self {
+        self.memory = Some(Arc::new(MemoryLimit {
+            max: max_bytes.max(1),
+            used: AtomicU64::new(0),
+            released: Notify::new(),
+        }));
+        self
+    }
+
+    /// Encoded size in bytes of the blocks held by the consumer, with a
+    /// [memory limit](ResilientStream::with_memory_limit).
+    pub fn memory_used(&self) -> Option<u64> {
+        self.memory
+            .as_ref()
+            .map(|memory| memory.used.load(Ordering::Acquire))
+    }
+
+    /// Send blocks that fail to decode in [`ResilientStream::message_as`] to
+    /// `sink` and continue with the next block, instead of failing.
+    ///
+    /// Selects [`ErrorPolicy::DeadLetter`].
+    pub fn with_dead_letters(self, sink: impl DeadLetterSink + 'static) -> Self {
+        {
+            let mut decoding = self.decoding();
+            decoding.dead_letters = Some(DeadLetters(Box::new(sink)));
+            decoding.policy = ErrorPolicy::DeadLetter;
+        }
+        self
+    }
+
+    /// Handle blocks that fail to decode in [`ResilientStream::message_as`]
+    /// according to `policy`, instead of failing on the first one.
+    pub fn with_error_policy(self, policy: ErrorPolicy) -> Self {
+        self.decoding().policy = policy;
+        self
+    }
+
+    /// Convert blocks for [`ResilientStream::spawn`] on up to `workers`
+    /// blocking threads, instead of on the stream's task.
+    ///
+    /// Converting multi-megabyte blocks takes long enough to delay other
+    /// tasks sharing the runtime thread. Offloaded conversions run in
+    /// parallel through [`tokio::task::spawn_blocking`], and blocks are still
+    /// delivered in order.
+    pub fn with_decode_workers(mut self, workers: usize) -> Self {
+        self.decode_workers = Some(workers.max(1));
+        self
+    }
+
+    /// Keep only the fields `projection` selects of every block, as soon as
+    /// it is received.
+    ///
+    /// Projected blocks count against the
+    /// [memory limit](ResilientStream::with_memory_limit) with their projected
+    /// size, while received bytes and bandwidth limits still count the full
+    /// blocks. A block that fails to decode fails the stream.
+    #[cfg(feature = "dynamic")]
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Receive the [`StreamEvent`]s emitted from now on.
+    ///
+    /// Events are only produced while the stream is being polled. A receiver
+    /// that falls more than 1024 events behind skips the oldest ones.
+    pub fn subscribe(&self) -> broadcast::Receiver<StreamEvent> {
+        self.events.subscribe()
+    }
+
+    /// A handle to pause, resume and seek this stream from another task.
+    pub fn handle(&self) -> StreamHandle {
+        self.handle.clone()
+    }
+
+    /// The session history of the stream, see [`StreamHandle::sessions`].
+    pub fn sessions(&self) -> Vec<StreamSession> {
+        self.handle.sessions()
+    }
+
+    /// The cursor of the last block returned, or the request cursor if no
+    /// block has been returned yet.
+    pub fn cursor(&self) -> &str {
+        &self.request.cursor
+    }
+
+    /// Number of times the stream has been reopened after a failure.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    /// Number of times the stream has been reopened because it stalled.
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    /// Number of blocks skipped after failing to decode.
+    pub fn skipped(&self) -> u64 {
+        self.decoding().skipped
+    }
+
+    /// Number of skipped blocks sent to the dead-letter sink.
+    pub fn dead_lettered(&self) -> u64 {
+        self.decoding().dead_lettered
+    }
+
+    /// What the stream has received so far.
+    ///
+    /// Once a bounded stream completed, this is the final account, also sent
+    /// with [`StreamEvent::Completed`], for batch jobs to log or assert on.
+    pub fn summary(&self) -> StreamSummary {
+        let duration = match (self.started, self.finished) {
+            (Some(started), Some(finished)) => finished - started,
+            (Some(started), None) => started.elapsed(),
+            (None, _) => Duration::ZERO,
+        };
+
+        let decoding = self.decoding();
+        StreamSummary {
+            blocks_received: self.blocks_received,
+            bytes: self.bytes_received,
+            duration,
+            reconnects: self.reconnects,
+            skipped: decoding.skipped,
+            dead_lettered: decoding.dead_lettered,
+            final_cursor: self.request.cursor.clone(),
+        }
+    }
+
+    /// Receive the next block, converted into `T`.
+    ///
+    /// Blocks that fail to convert are handled according to the stream's
+    /// [`ErrorPolicy`]: by default, the conversion error is returned as
+    /// [`FirehoseError::Decode`].
+    pub async fn message_as<T>(&mut self) -> Result<Option<T>, FirehoseError>
+    where
+        T: FromResponse,
+        T::Error: Display + Send,
+    {
+        while let Some(response) = self.message().await? {
+            let block = response.block_number();
+            let copy = self.decoding().keeps_responses().then(|| response.clone());
+
+            let result = T::from_response(response).map_err(|e| e.to_string());
+            let decoded = self.decoding().outcome(&self.events, block, copy, result)?;
+            if decoded.is_some() {
+                return Ok(decoded);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run the stream on a background task, converting every block into `T`.
+    ///
+    /// Blocks that fail to convert end the task, unless the stream's
+    /// [`ErrorPolicy`] skips them.
+    ///
+    /// Blocks are sent to the returned receiver, which buffers up to `buffer`
+    /// of them before the task waits. With
+    /// [decode workers](ResilientStream::with_decode_workers), conversions
+    /// are offloaded to blocking threads. The [`StreamHandle`] pauses, resumes and
+    /// seeks the stream as usual. With a
+    /// [memory limit](ResilientStream::with_memory_limit), blocks count as
+    /// processed once sent, so the receiver's buffer comes on top of it.
+    /// The task ends with the run's
+    /// [`StreamSummary`] once a bounded request reaches its stop block or the
+    /// receiver is dropped, and with the error otherwise, after which the
+    /// receiver is closed.
+    pub fn spawn<T>(
+        mut self,
+        buffer: usize,
+    ) -> (
+        JoinHandle<Result<StreamSummary, FirehoseError>>,
+        mpsc::Receiver<T>,
+        StreamHandle,
+    )
+    where
+        T: FromResponse + Send + 'static,
+        T::Error: Display + Send,
+    {
+        if let Some(workers) = self.decode_workers {
+            return self.spawn_offloaded(workers, buffer);
+        }
+
+        let (sender, receiver) = mpsc::channel(buffer.max(1));
+        let handle = self.handle();
+
+        let task = tokio::spawn(async move {
+            while let Some(block) = self.message_as::<T>().await? {
+                if sender.send(block).await.is_err() {
+                    break;
+                }
+            }
+            Ok(self.summary())
+        });
+
+        (task, receiver, handle)
+    }
+
+    /// Run the stream on a background task, appending every block to a
+    /// [spill queue](crate::spill_queue) for a slower consumer to read.
+    ///
+    /// The stream never waits for the consumer, and resumes after the last
+    /// block in the queue, whatever the request's cursor. The task ends with
+    /// the run's [`StreamSummary`] once a bounded request reaches its stop
+    /// block, after syncing the queue and dropping `writer` so that the
+    /// [`SpillReader`](crate::SpillReader) ends too, and with the error
+    /// otherwise.
+    pub fn spill(
+        mut self,
+        mut writer: SpillWriter,
+    ) -> (
+        JoinHandle<Result<StreamSummary, FirehoseError>>,
+        StreamHandle,
+    ) {
+        if let Some(cursor) = writer.last_cursor() {
+            self.request.cursor = cursor.to_owned();
+        }
+        let handle = self.handle();
+
+        let task = tokio::spawn(async move {
+            while let Some(response) = self.message().await? {
+                writer.append(&response)?;
+            }
+            writer.sync()?;
+            Ok(self.summary())
+        });
+
+        (task, handle)
+    }
+
+    /// Run `f` on up to `concurrency` blocks at a time on background tasks,
+    /// emitting the results strictly in block order.
+    ///
+    /// Results are sent to the returned receiver in the order the blocks were
+    /// received, however long each call takes. With a
+    /// [cursor store](ResilientStream::with_cursor_store), a block's cursor
+    /// is committed only once its result and those of all earlier blocks have
+    /// been sent, so a crash replays every block whose result may not have
+    /// been handled.
+    ///
+    /// The task ends with the run's [`StreamSummary`], whose final cursor is
+    /// the last one emitted, once a bounded request is fully processed or the
+    /// receiver is dropped. It ends with [`FirehoseError::Processing`] as soon
+    /// as `f` fails for a block, after emitting the results before it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use firehose_rs::{FirehoseEndpoint, Request, ResilientStream};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let request = Request {
+    ///     start_block_num: 17_000_000,
+    ///     stop_block_num: 17_099_999,
+    ///     final_blocks_only: true,
+    ///     ..Default::default()
+    /// };
+    ///
+    /// let stream = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?;
+    /// let (task, mut results, _handle) = stream.process_ordered(16, |response| async move {
+    ///     // Expensive per-block work, such as calls to other services.
+    ///     Ok::<_, std::io::Error>(response.metadata.map(|metadata| metadata.num))
+    /// });
+    ///
+    /// while let Some(number) = results.recv().await {
+    ///     println!("{number:?}");
+    /// }
+    /// task.await??;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn process_ordered<T, E, F, Fut>(
+        self,
+        concurrency: usize,
+        f: F,
+    ) -> (
+        JoinHandle<Result<StreamSummary, FirehoseError>>,
+        mpsc::Receiver<T>,
+        StreamHandle,
+    )
+    where
+        T: Send + 'static,
+        E: Display + Send + 'static,
+        F: FnMut(Response) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        self.spawn_ordered(concurrency, concurrency, f, |result: Result<T, E>| {
+            result
+                .map(Some)
+                .map_err(|e| FirehoseError::Processing(e.to_string()))
+        })
+    }
+
+    /// [`ResilientStream::spawn`] with conversions on blocking threads.
+    fn spawn_offloaded<T>(
+        self,
+        workers: usize,
+        buffer: usize,
+    ) -> (
+        JoinHandle<Result<StreamSummary, FirehoseError>>,
+        mpsc::Receiver<T>,
+        StreamHandle,
+    )
+    where
+        T: FromResponse + Send + 'static,
+        T::Error: Display + Send,
+    {
+        let decoding = Arc::clone(&self.decoding);
+        let events = self.events.clone();
+        let convert = {
+            let decoding = Arc::clone(&decoding);
+            move |response: Response| {
+                let block = response.block_number();
+                let copy = lock(&decoding).keeps_responses().then(|| response.clone());
+                let converted = tokio::task::spawn_blocking(move || {
+                    T::from_response(response).map_err(|e| e.to_string())
+                });
+                async move { (block, copy, converted.await) }
+            }
+        };
+
+        self.spawn_ordered(workers, buffer, convert, move |(block, copy, converted)| {
+            let result = converted.map_err(|e| FirehoseError::Processing(e.to_string()))?;
+            lock(&decoding).outcome(&events, block, copy, result)
+        })
+    }
+
+    /// Run `f` on up to `concurrency` blocks at a time, and hand the results
+    /// to `emit` in block order, sending what it returns.
+    fn spawn_ordered<R, T, F, Fut, H>(
+        mut self,
+        concurrency: usize,
+        buffer: usize,
+        mut f: F,
+        mut emit: H,
+    ) -> (
+        JoinHandle<Result<StreamSummary, FirehoseError>>,
+        mpsc::Receiver<T>,
+        StreamHandle,
+    )
+    where
+        R: Send + 'static,
+        T: Send + 'static,
+        F: FnMut(Response) -> Fut + Send + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+        H: FnMut(R) -> Result<Option<T>, FirehoseError> + Send + 'static,
+    {
+        let concurrency = concurrency.max(1);
+        let (sender, receiver) = mpsc::channel(buffer.max(1));
+        let handle = self.handle();
+
+        // Cursors are committed as results are emitted, not as blocks are
+        // received or acknowledged.
+        let mut checkpointer = self.checkpointer.take();
+        self.acks = None;
+        // So is held memory.
+        let memory = self.memory.clone();
+        self.release_on_emit = true;
+
+        let task = tokio::spawn(async move {
+            let slots = Arc::new(Semaphore::new(concurrency));
+            let (queue, mut started) = mpsc::unbounded_channel();
+
+            let puller = tokio::spawn(async move {
+                loop {
+                    let response = tokio::select! {
+                        response = self.message() => response?,
+                        // Nothing is emitted anymore, stop pulling.
+                        _ = queue.closed() => break,
+                    };
+                    let Some(response) = response else {
+                        break;
+                    };
+
+                    let slot = Arc::clone(&slots)
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed");
+                    let cursor = response.cursor.clone();
+                    let bytes = response.encoded_len() as u64;
+                    if queue
+                        .send((cursor, bytes, slot, tokio::spawn(f(response))))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Ok::<_, FirehoseError>(self.summary())
+            });
+
+            let mut emitted = None;
+            let mut failure = None;
+            while let Some((cursor, bytes, slot, processing)) = started.recv().await {
+                let output = processing
+                    .await
+                    .map_err(|e| FirehoseError::Processing(e.to_string()))
+                    .and_then(&mut emit);
+                drop(slot);
+
+                match output {
+                    Ok(Some(output)) => {
+                        if sender.send(output).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Skipped blocks are processed too.
+                    Ok(None) => {}
+                    Err(e) => {
+                        failure = Some(e);
+                        break;
+                    }
+                }
+                if let Some(memory) = &memory {
+                    memory.release(bytes);
+                }
+                if let Some(checkpointer) = &mut checkpointer {
+                    checkpointer.processed(&cursor)?;
+                }
+                emitted = Some(cursor);
+            }
+            drop(started);
+
+            let summary = puller
+                .await
+                .map_err(|e| FirehoseError::Processing(e.to_string()))?;
+            if let (Some(checkpointer), Some(cursor)) = (&mut checkpointer, &emitted) {
+                checkpointer.commit(cursor)?;
+            }
+            if let Some(failure) = failure {
+                return Err(failure);
+            }
+
+            let mut summary = summary?;
+            if let Some(cursor) = emitted {
+                summary.final_cursor = cursor;
+            }
+            Ok(summary)
+        });
+
+        (task, receiver, handle)
+    }
+
+    /// Receive the next block, reconnecting as needed.
+    ///
+    /// Waits while the stream is [paused](StreamHandle::pause), and applies
+    /// [seeks](StreamHandle::seek) before receiving.
+    ///
+    /// Returns `Ok(None)` once a bounded request has reached its stop block,
+    /// after which [`ResilientStream::summary`] reports the whole run.
+    /// Errors not [classified](FirehoseError::classification) as retryable,
+    /// and retryable ones once [`Backoff::max_attempts`] is exceeded, are
+    /// returned to the caller.
+    pub async fn message(&mut self) -> Result<Option<Response>, FirehoseError> {
+        self.started.get_or_insert_with(Instant::now);
+        self.apply_acks();
+        self.within_memory_limit().await;
+        if self.checkpointer.as_ref().is_some_and(Checkpointer::is_due) {
+            self.checkpoint()?;
+        }
+
+        loop {
+            // The stream keeps a sender alive through its handle, so this
+            // cannot fail.
+            let _ = self.control.wait_for(|control| !control.paused).await;
+            if let Some(to) = self.handle.take_seek() {
+                self.seek(to)?;
+            }
+
+            if self.stream.is_none() {
+                let opened = self.open().await;
+                self.handle.session_log().open(
+                    self.endpoint_uri(),
+                    &self.request_id,
+                    &self.request.cursor,
+                );
+                match opened {
+                    Ok(stream) => {
+                        self.stream = Some(stream);
+                        self.emit(if self.connected {
+                            StreamEvent::Resumed {
+                                cursor: self.request.cursor.clone(),
+                            }
+                        } else {
+                            StreamEvent::Connected
+                        });
+                        self.connected = true;
+                    }
+                    Err(e) => {
+                        self.end_session(SessionEnd::Failed {
+                            error: e.to_string(),
+                        });
+                        self.retry_after(e).await?;
+                        continue;
+                    }
+                }
+            }
+            let stream = self.stream.as_mut().expect("stream was just opened");
+
+            let stall_timeout = self.stall_timeout;
+            let pulled = tokio::select! {
+                pulled = async {
+                    match stall_timeout {
+                        None => Some(stream.message().await),
+                        Some(timeout) => tokio::time::timeout(timeout, stream.message()).await.ok(),
+                    }
+                } => pulled,
+                // Pause or seek requested, start over.
+                _ = self.control.changed() => continue,
+            };
+
+            let next = match pulled {
+                Some(next) => next,
+                None if self.head_advanced() => {
+                    self.stream = None;
+                    self.end_session(SessionEnd::Stalled);
+                    self.stalls += 1;
+                    self.emit(StreamEvent::Stalled);
+                    self.reconnect().await;
+                    continue;
+                }
+                None => continue,
+            };
+
+            let error: FirehoseError = match next {
+                Ok(Some(response)) => {
+                    if let Some(anchors) = &self.anchors {
+                        if let Err(e) = anchors.verify(&response) {
+                            self.stream = None;
+                            self.end_session(SessionEnd::Failed {
+                                error: e.to_string(),
+                            });
+                            return Err(e);
+                        }
+                    }
+                    let bytes = response.encoded_len();
+                    if let Some((metrics, label)) = &self.metrics {
+                        metrics.record_block(label, bytes as u64);
+                    }
+                    if let Some((health, name)) = &self.health {
+                        health.record_block(name);
+                    }
+                    #[cfg(feature = "dynamic")]
+                    let response = self.project(response)?;
+                    self.observe(&response, response.encoded_len() as u64);
+                    self.attempt = 0;
+                    self.failed = None;
+                    self.blocks_received += 1;
+                    self.bytes_received += bytes as u64;
+                    self.handle
+                        .session_log()
+                        .received(&response.cursor, bytes as u64);
+                    if let Some(usage) = self.pool.usage_at(self.endpoint) {
+                        usage.record_block(bytes);
+                    }
+                    self.pace(bytes).await;
+                    return Ok(Some(response));
+                }
+                Ok(None) if self.is_complete() => {
+                    self.stream = None;
+                    self.end_session(SessionEnd::Completed);
+                    self.checkpoint()?;
+                    self.finished = Some(Instant::now());
+                    self.emit(StreamEvent::Completed {
+                        summary: self.summary(),
+                    });
+                    return Ok(None);
+                }
+                Ok(None) => Status::unavailable("stream ended before its stop block").into(),
+                Err(status) => request_id::tag(status, &self.request_id).into(),
+            };
+
+            self.stream = None;
+            self.pool
+                .record_stream(self.endpoint, Duration::ZERO, false);
+            self.failed = Some(self.endpoint);
+            self.end_session(SessionEnd::Failed {
+                error: error.to_string(),
+            });
+            self.retry_after(error).await?;
+        }
+    }
+
+    fn seek(&mut self, to: SeekTo) -> Result<(), FirehoseError> {
+        // Blocks returned before the seek have been processed.
+        self.checkpoint()?;
+
+        match &to {
+            SeekTo::Block(block) => {
+                self.request.start_block_num = *block;
+                self.request.cursor.clear();
+            }
+            SeekTo::Cursor(cursor) => self.request.cursor.clone_from(cursor),
+        }
+
+        if self.stream.take().is_some() {
+            self.end_session(SessionEnd::Seeked);
+        }
+        self.last_block = None;
+        self.undo_depth = 0;
+        self.attempt = 0;
+        self.emit(StreamEvent::Seeked { to });
+        Ok(())
+    }
+
+    /// Apply the [projection](ResilientStream::with_projection), if any.
+    #[cfg(feature = "dynamic")]
+    fn project(&self, mut response: Response) -> Result<Response, FirehoseError> {
+        if let Some(projection) = &self.projection {
+            projection
+                .apply(&mut response)
+                .map_err(|e| FirehoseError::Decode(e.to_string()))?;
+        }
+        Ok(response)
+    }
+
+    fn observe(&mut self, response: &Response, bytes: u64) {
+        self.request.cursor.clone_from(&response.cursor);
+        if let Some(memory) = &self.memory {
+            memory.take(bytes);
+            if self.acks.is_none() && !self.release_on_emit {
+                self.held = bytes;
+            }
+        }
+        match &mut self.acks {
+            Some(acks) => acks.outstanding.push_back((response.cursor.clone(), bytes)),
+            None => {
+                if let Some(checkpointer) = &mut self.checkpointer {
+                    checkpointer.pending += 1;
+                }
+            }
+        }
+
+        if response.step() == ForkStep::StepUndo {
+            self.undo_depth += 1;
+        } else if self.undo_depth > 0 {
+            let depth = std::mem::take(&mut self.undo_depth);
+            self.emit(StreamEvent::Reorg { depth });
+        }
+
+        if let Some(metadata) = &response.metadata {
+            self.last_block = Some(metadata.num);
+            self.emit(StreamEvent::Progress {
+                block: metadata.num,
+            });
+            self.check_lag(metadata);
+        }
+    }
+
+    fn check_lag(&mut self, metadata: &BlockMetadata) {
+        if self.lag_alert.is_none() && self.metrics.is_none() && self.health.is_none() {
+            return;
+        }
+
+        let blocks = self
+            .head
+            .as_ref()
+            .map(|head| head.borrow().saturating_sub(metadata.num));
+        let delay = metadata.time.as_ref().and_then(|time| {
+            let seconds = u64::try_from(time.seconds).ok()?;
+            let block_time = UNIX_EPOCH + Duration::new(seconds, time.nanos.max(0) as u32);
+            SystemTime::now().duration_since(block_time).ok()
+        });
+
+        if let Some((metrics, label)) = &self.metrics {
+            metrics.record_lag(label, blocks, delay);
+        }
+        if let (Some((health, name)), Some(blocks)) = (&self.health, blocks) {
+            health.record_lag(name, blocks);
+        }
+        let Some(alert) = self.lag_alert else {
+            return;
+        };
+        if !self.lagging && alert.exceeded(1.0, blocks, delay) {
+            self.lagging = true;
+            self.emit(StreamEvent::LagExceeded { blocks, delay });
+        } else if self.lagging && !alert.exceeded(alert.recovery, blocks, delay) {
+            self.lagging = false;
+            self.emit(StreamEvent::LagRecovered);
+        }
+    }
+
+    /// Sleep until `bytes` more are allowed by the bandwidth limit.
+    async fn pace(&mut self, bytes: usize) {
+        let Some(bytes_per_sec) = self.bandwidth_limit else {
+            return;
+        };
+
+        let transfer = Duration::from_secs_f64(bytes as f64 / bytes_per_sec as f64);
+        self.ready_at = self.ready_at.max(Instant::now()) + transfer;
+        tokio::time::sleep_until(self.ready_at).await;
+    }
+
+    fn decoding(&self) -> MutexGuard<'_, Decoding> {
+        lock(&self.decoding)
+    }
+
+    fn end_session(&self, end: SessionEnd) {
+        self.handle.session_log().close(end);
+    }
+
+    fn endpoint_uri(&self) -> String {
+        self.pool
+            .endpoints()
+            .nth(self.endpoint)
+            .map(FirehoseEndpoint::uri)
+            .unwrap_or_default()
+    }
+
+    fn emit(&self, event: StreamEvent) {
+        #[cfg(feature = "log")]
+        crate::logging::stream_event(&event, || self.endpoint_uri(), &self.request_id);
+        // Sending only fails when nobody is subscribed.
+        let _ = self.events.send(event);
+    }
+
+    async fn open(&mut self) -> Result<Streaming<Response>, FirehoseError> {
+        let mut request = tonic::Request::new(self.request.clone());
+        for (key, value) in &self.metadata {
+            request.metadata_mut().insert(key.clone(), value.clone());
+        }
+        self.request_id = request_id::ensure(request.metadata_mut());
+
+        let ranked = self.pool.ranked();
+        let index = ranked
+            .iter()
+            .copied()
+            .find(|&index| Some(index) != self.failed)
+            .unwrap_or(ranked[0]);
+        let lease = match self.lease.take() {
+            Some(lease) if lease.endpoint() == index => lease,
+            _ => self.pool.lease_at(index)?,
+        };
+        let mut client = self.pool.stream_client_for(&lease)?;
+        self.lease = Some(lease);
+        self.endpoint = index;
+        if let Some(usage) = self.pool.usage_at(index) {
+            usage.record_request();
+        }
+
+        let started = Instant::now();
+        let result = client.blocks(request).await;
+        self.pool
+            .record_stream(index, started.elapsed(), result.is_ok());
+        if result.is_err() {
+            self.failed = Some(index);
+        }
+
+        let response = result.map_err(|status| request_id::tag(status, &self.request_id))?;
+        Ok(response.into_inner())
+    }
+
+    /// Wait before the next attempt, or give up with `error`.
+    async fn retry_after(&mut self, error: FirehoseError) -> Result<(), FirehoseError> {
+        self.attempt += 1;
+        self.emit(StreamEvent::Disconnected {
+            error: error.to_string(),
+        });
+        if error.is_cursor_invalid() {
+            let Some(start_block) = self.recovery_block() else {
+                return Err(error);
+            };
+            let cursor = std::mem::take(&mut self.request.cursor);
+            self.request.start_block_num = start_block as i64;
+            self.last_block = None;
+            self.undo_depth = 0;
+            self.emit(StreamEvent::CursorRecovered {
+                cursor,
+                start_block,
+            });
+            self.reconnect().await;
+            return Ok(());
+        }
+        if !error.classification().is_retryable() || !self.backoff.allows(self.attempt) {
+            return Err(error);
+        }
+
+        let delay = self.backoff.delay(self.attempt);
+        tokio::time::sleep(error.retry_delay().map_or(delay, |hint| hint.max(delay))).await;
+        self.reconnect().await;
+        Ok(())
+    }
+
+    /// Block to reopen the stream at after its cursor was rejected, if the
+    /// [`CursorRecovery`] allows it and the block is known.
+    fn recovery_block(&self) -> Option<u64> {
+        let cursor_block = || cursor_block_num(&self.request.cursor);
+        match self.cursor_recovery {
+            CursorRecovery::Fail => None,
+            CursorRecovery::CursorBlock => cursor_block().or(self.last_block).map(|num| num + 1),
+            CursorRecovery::LastBlock { margin } => self
+                .last_block
+                .or_else(cursor_block)
+                .map(|num| num.saturating_sub(margin)),
+        }
+    }
+
+    /// Account for a reconnect, waiting for the retry budget if there is one.
+    async fn reconnect(&mut self) {
+        if let Some(budget) = &self.budget {
+            budget.acquire().await;
+        }
+        self.reconnects += 1;
+        if let Some((metrics, label)) = &self.metrics {
+            metrics.record_reconnect(label);
+        }
+    }
+
+    fn head_advanced(&self) -> bool {
+        match &self.head {
+            Some(head) => self.last_block.is_none_or(|num| *head.borrow() > num),
+            None => true,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        let stop = self.request.stop_block_num;
+        stop != 0 && self.last_block.is_none_or(|num| num >= stop)
+    }
+}
+
+/// Stream `request` from `pool` on a background task, as typed blocks.
+///
+/// A shorthand for [`ResilientStream::spawn`] with default settings and a
+/// buffer of [`DEFAULT_SPAWN_BUFFER`] blocks; configure a [`ResilientStream`]
+/// and spawn it directly for cursor stores, backoff or events.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{spawn_stream, EndpointPool, FirehoseEndpoint, Request, Response};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let pool = EndpointPool::new([FirehoseEndpoint::from_env()?])?;
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+///
+/// let (task, mut blocks, handle) = spawn_stream::<Response>(pool, request);
+/// while let Some(block) = blocks.recv().await {
+///     println!("{}", block.cursor);
+/// }
+/// task.await??;
+/// # Ok(())
+/// # }
+/// ```
+pub fn spawn_stream<T>(
+    pool: EndpointPool,
+    request: Request,
+) -> (
+    JoinHandle<Result<StreamSummary, FirehoseError>>,
+    mpsc::Receiver<T>,
+    StreamHandle,
+)
+where
+    T: FromResponse + Send + 'static,
+    T::Error: Display + Send,
+{
+    ResilientStream::new(pool, request).spawn(DEFAULT_SPAWN_BUFFER)
+}
+
+fn lock(decoding: &Mutex<Decoding>) -> MutexGuard<'_, Decoding> {
+    // Decoding state stays consistent even if a sink panicked mid-send.
+    decoding.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::testing::{ethereum_block, Fault, Replay};
+
+    /// Blocks 1 to 10 of a synthetic chain.
+    fn chain() -> Vec<Response> {
+        (1..=10)
+            .map(|number| ethereum_block().number(number).build())
+            .collect()
+    }
+
+    async fn stream(replay: Replay, start: i64, stop: u64) -> ResilientStream {
+        let request = Request {
+            start_block_num: start,
+            stop_block_num: stop,
+            ..Default::default()
+        };
+        let pool = EndpointPool::new([replay.serve().await]).unwrap();
+        ResilientStream::new(pool, request).with_backoff(Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(10),
+            multiplier: 1.0,
+            max_attempts: Some(3),
+        })
+    }
+
+    /// A cursor store recording every commit, readable while the stream owns
+    /// it.
+    #[derive(Clone, Default)]
+    struct Commits(Arc<Mutex<Vec<String>>>);
+
+    impl Commits {
+        fn last(&self) -> Option<String> {
+            self.0.lock().unwrap().last().cloned()
+        }
+    }
+
+    impl CursorStore for Commits {
+        fn load(&self) -> io::Result<Option<String>> {
+            Ok(self.last())
+        }
+
+        fn store(&mut self, cursor: &str) -> io::Result<()> {
+            self.0.lock().unwrap().push(cursor.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_cursor_after_a_disconnect() {
+        let blocks = chain();
+        let replay = Replay::new(blocks.clone()).with_fault(Fault::Disconnect { after: 4 });
+        let mut stream = stream(replay, 1, 10).await;
+
+        let mut cursors = Vec::new();
+        while let Some(response) = stream.message().await.unwrap() {
+            cursors.push(response.cursor);
+        }
+
+        let expected: Vec<String> = blocks.iter().map(|block| block.cursor.clone()).collect();
+        assert_eq!(cursors, expected);
+
+        let sessions = stream.sessions();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].blocks, 4);
+        assert_eq!(sessions[1].start_cursor, blocks[3].cursor);
+        assert_eq!(stream.summary().reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn resumes_on_the_next_endpoint_after_a_failure() {
+        let blocks = chain();
+        let failing = Replay::new(blocks.clone())
+            .with_fault(Fault::Disconnect { after: 4 })
+            .serve()
+            .await;
+        let healthy = Replay::new(blocks.clone()).serve().await;
+        let uris = [failing.uri(), healthy.uri()];
+        let pool = EndpointPool::new([failing, healthy]).unwrap();
+        let request = Request {
+            start_block_num: 1,
+            stop_block_num: 10,
+            ..Default::default()
+        };
+        let mut stream = ResilientStream::new(pool.clone(), request);
+
+        let mut cursors = Vec::new();
+        while let Some(response) = stream.message().await.unwrap() {
+            cursors.push(response.cursor);
+        }
+
+        let expected: Vec<String> = blocks.iter().map(|block| block.cursor.clone()).collect();
+        assert_eq!(cursors, expected);
+
+        let sessions = stream.sessions();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].endpoint, uris[0]);
+        assert_eq!(sessions[1].endpoint, uris[1]);
+        assert_eq!(sessions[1].start_cursor, blocks[3].cursor);
+
+        let stats = pool.stats();
+        assert_eq!(stats[0].errors, 1);
+        assert!(!stats[0].healthy);
+        assert_eq!(stats[1].errors, 0);
+        assert_eq!(pool.ranked(), [1, 0]);
+    }
+
+    #[tokio::test]
+    async fn ends_at_the_stop_block() {
+        let mut stream = stream(Replay::new(chain()), 3, 6).await;
+
+        let mut numbers = Vec::new();
+        while let Some(response) = stream.message().await.unwrap() {
+            numbers.extend(response.block_number());
+        }
+
+        assert_eq!(numbers, [3, 4, 5, 6]);
+        assert!(stream.is_complete());
+        assert_eq!(stream.summary().reconnects, 0);
+    }
+
+    #[tokio::test]
+    async fn retries_a_stream_ending_before_its_stop_block() {
+        // The replay ends at block 10, short of the stop block.
+        let mut stream = stream(Replay::new(chain()), 8, 20).await;
+
+        for number in 8..=10 {
+            let response = stream.message().await.unwrap().unwrap();
+            assert_eq!(response.block_number(), Some(number));
+        }
+        let error = stream.message().await.unwrap_err();
+
+        assert!(!stream.is_complete());
+        assert!(error
+            .to_string()
+            .contains("stream ended before its stop block"));
+        // One reconnect per attempt the backoff allows.
+        assert_eq!(stream.summary().reconnects, 3);
+    }
+
+    #[tokio::test]
+    async fn never_checkpoints_ahead_of_processed_blocks() {
+        let blocks = chain();
+        let replay = Replay::new(blocks.clone()).with_fault(Fault::Disconnect { after: 4 });
+        let commits = Commits::default();
+        let mut stream = stream(replay, 1, 10)
+            .await
+            .with_cursor_store(commits.clone(), CheckpointInterval::default())
+            .unwrap();
+
+        let mut previous: Option<String> = None;
+        while let Some(response) = stream.message().await.unwrap() {
+            // The block just returned is not processed yet, only the one
+            // before it.
+            assert_eq!(commits.last(), previous);
+            previous = Some(response.cursor);
+        }
+
+        // Completion commits the last block.
+        assert_eq!(
+            commits.last(),
+            blocks.last().map(|block| block.cursor.clone())
+        );
+        let committed = commits.0.lock().unwrap().clone();
+        let positions: Vec<usize> = committed
+            .iter()
+            .map(|cursor| {
+                blocks
+                    .iter()
+                    .position(|block| block.cursor == *cursor)
+                    .unwrap()
+            })
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,173 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Exponential backoff with full jitter between reconnect attempts.
+///
+/// The delay before attempt `n` (starting at 1) is drawn uniformly between
+/// zero and `min(max, initial * multiplier^(n - 1))`, which spreads out
+/// clients that lost their connection at the same moment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    /// Upper bound of the first delay.
+    pub initial: Duration,
+    /// Upper bound of any delay.
+    pub max: Duration,
+    /// Growth factor of the upper bound between attempts.
+    pub multiplier: f64,
+    /// Give up after this many consecutive failed attempts. `None` retries
+    /// forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Upper bound of the delay before attempt `attempt`, without jitter.
+    pub fn ceiling(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(64) as i32;
+        let secs = self.initial.as_secs_f64() * self.multiplier.powi(exponent);
+        Duration::from_secs_f64(secs.min(self.max.as_secs_f64()))
+    }
+
+    /// Jittered delay before attempt `attempt`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.ceiling(attempt).mul_f64(unit_random())
+    }
+
+    /// Whether attempt `attempt` is still allowed.
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt <= max)
+    }
+}
+
+/// A uniformly distributed value in `[0, 1)`: the wall clock and a call
+/// counter, hashed with the standard library's randomly keyed hasher.
+///
+/// The counter keeps calls within the clock's resolution apart.
+fn unit_random() -> f64 {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(now.as_nanos());
+    hasher.write_u64(CALLS.fetch_add(1, Ordering::Relaxed));
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A token bucket limiting reconnect attempts across many streams.
+///
+/// Per-stream [`Backoff`] keeps a single stream from hammering an endpoint,
+/// but a provider outage disconnects every stream at once, and they all come
+/// back together. Share one budget between them (it is cheap to clone) to cap
+/// the total number of reconnects per time window: once it is spent, further
+/// reconnects wait for tokens to refill.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use firehose_rs::RetryBudget;
+///
+/// // At most 10 reconnects per minute, across all streams sharing the budget
+/// let budget = RetryBudget::new(10, Duration::from_secs(60));
+///
+/// assert!(budget.try_acquire());
+/// assert_eq!(budget.available(), 9);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryBudget {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.refilled_at = now;
+    }
+}
+
+impl RetryBudget {
+    /// Allow `max_retries` reconnects per `window`, refilling continuously.
+    ///
+    /// The bucket starts full, so up to `max_retries` reconnects may happen
+    /// back to back.
+    pub fn new(max_retries: u32, window: Duration) -> Self {
+        let capacity = f64::from(max_retries.max(1));
+        RetryBudget {
+            bucket: Arc::new(Mutex::new(Bucket {
+                capacity,
+                tokens: capacity,
+                refill_per_sec: capacity / window.as_secs_f64().max(f64::EPSILON),
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Take a token if one is available.
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.bucket.lock().expect("retry budget lock poisoned");
+        bucket.refill();
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Take a token, waiting for one to refill if the budget is spent.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().expect("retry budget lock poisoned");
+                bucket.refill();
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.refill_per_sec)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Whole tokens currently available.
+    pub fn available(&self) -> u32 {
+        let mut bucket = self.bucket.lock().expect("retry budget lock poisoned");
+        bucket.refill();
+        bucket.tokens as u32
+    }
+}
@@ -0,0 +1,499 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Table and JSON schemas of block types, for creating downstream tables
+//! ahead of time.
+
+use std::fmt::Write as _;
+
+use prost_reflect::{FieldDescriptor, Kind, MessageDescriptor};
+use serde_json::{json, Map, Value};
+
+const TIMESTAMP: &str = "google.protobuf.Timestamp";
+
+/// Type of a [`Column`], mapped to the closest Parquet, Arrow and SQL types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnType {
+    /// `boolean`.
+    Boolean,
+    /// Signed 32-bit integer.
+    Int32,
+    /// Signed 64-bit integer.
+    Int64,
+    /// Unsigned 32-bit integer.
+    UInt32,
+    /// Unsigned 64-bit integer.
+    UInt64,
+    /// 32-bit float.
+    Float,
+    /// 64-bit float.
+    Double,
+    /// UTF-8 text, including enum value names.
+    String,
+    /// Raw bytes.
+    Binary,
+    /// UTC time with microsecond precision.
+    Timestamp,
+    /// A JSON document, for repeated fields and recursive messages that do
+    /// not flatten into columns.
+    Json,
+}
+
+impl ColumnType {
+    /// Parquet physical type and logical type annotation, if any.
+    fn parquet(self) -> (&'static str, Option<&'static str>) {
+        match self {
+            ColumnType::Boolean => ("boolean", None),
+            ColumnType::Int32 => ("int32", None),
+            ColumnType::Int64 => ("int64", None),
+            ColumnType::UInt32 => ("int32", Some("INTEGER(32,false)")),
+            ColumnType::UInt64 => ("int64", Some("INTEGER(64,false)")),
+            ColumnType::Float => ("float", None),
+            ColumnType::Double => ("double", None),
+            ColumnType::String => ("binary", Some("STRING")),
+            ColumnType::Binary => ("binary", None),
+            ColumnType::Timestamp => ("int64", Some("TIMESTAMP(MICROS,true)")),
+            ColumnType::Json => ("binary", Some("JSON")),
+        }
+    }
+
+    /// Arrow type in the JSON form of Arrow's integration format.
+    fn arrow(self) -> Value {
+        let int =
+            |bits: u32, signed: bool| json!({"name": "int", "bitWidth": bits, "isSigned": signed});
+        match self {
+            ColumnType::Boolean => json!({"name": "bool"}),
+            ColumnType::Int32 => int(32, true),
+            ColumnType::Int64 => int(64, true),
+            ColumnType::UInt32 => int(32, false),
+            ColumnType::UInt64 => int(64, false),
+            ColumnType::Float => json!({"name": "floatingpoint", "precision": "SINGLE"}),
+            ColumnType::Double => json!({"name": "floatingpoint", "precision": "DOUBLE"}),
+            ColumnType::String | ColumnType::Json => json!({"name": "utf8"}),
+            ColumnType::Binary => json!({"name": "binary"}),
+            ColumnType::Timestamp => {
+                json!({"name": "timestamp", "unit": "MICROSECOND", "timezone": "UTC"})
+            }
+        }
+    }
+
+    /// DuckDB SQL type.
+    fn sql(self) -> &'static str {
+        match self {
+            ColumnType::Boolean => "BOOLEAN",
+            ColumnType::Int32 => "INTEGER",
+            ColumnType::Int64 => "BIGINT",
+            ColumnType::UInt32 => "UINTEGER",
+            ColumnType::UInt64 => "UBIGINT",
+            ColumnType::Float => "FLOAT",
+            ColumnType::Double => "DOUBLE",
+            ColumnType::String | ColumnType::Json => "VARCHAR",
+            ColumnType::Binary => "BLOB",
+            ColumnType::Timestamp => "TIMESTAMP",
+        }
+    }
+
+    /// JSON Schema of the column's values in JSON rows.
+    fn json_schema(self) -> Value {
+        match self {
+            ColumnType::Boolean => json!({"type": "boolean"}),
+            ColumnType::Int32 | ColumnType::Int64 => json!({"type": "integer"}),
+            ColumnType::UInt32 | ColumnType::UInt64 => json!({"type": "integer", "minimum": 0}),
+            ColumnType::Float | ColumnType::Double => json!({"type": "number"}),
+            ColumnType::String => json!({"type": "string"}),
+            ColumnType::Binary => json!({"type": "string", "contentEncoding": "base64"}),
+            ColumnType::Timestamp => json!({"type": "string", "format": "date-time"}),
+            ColumnType::Json => json!({}),
+        }
+    }
+}
+
+/// One column of a [`TableSchema`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Column {
+    /// Column name.
+    pub name: String,
+    /// Column type.
+    pub column_type: ColumnType,
+    /// Whether the column may be null.
+    pub nullable: bool,
+}
+
+impl Column {
+    /// A column that may be null.
+    pub fn nullable(name: impl Into<String>, column_type: ColumnType) -> Self {
+        Column {
+            name: name.into(),
+            column_type,
+            nullable: true,
+        }
+    }
+
+    /// A column that is never null.
+    pub fn required(name: impl Into<String>, column_type: ColumnType) -> Self {
+        Column {
+            name: name.into(),
+            column_type,
+            nullable: false,
+        }
+    }
+}
+
+/// Flat schema of a table of block rows, renderable as a Parquet schema,
+/// an Arrow schema, DuckDB DDL or a JSON Schema of its rows.
+///
+/// Use [`TableSchema::blocks`] for the one-row-per-block format of the sinks,
+/// or derive a schema from any block type with
+/// [`TableSchema::from_message`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{DynamicDecoder, TableSchema};
+///
+/// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decoder = DynamicDecoder::new()?;
+/// decoder.add_file_descriptor_set(std::fs::read("blocks.binpb")?.as_slice())?;
+///
+/// let headers = decoder.table_schema("block_headers", "sf.ethereum.type.v2.BlockHeader")?;
+/// println!("{}", headers.to_sql());
+/// std::fs::write("blocks.parquet-schema", TableSchema::blocks().to_parquet())?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TableSchema {
+    /// Table name.
+    pub name: String,
+    /// Columns, in row order.
+    pub columns: Vec<Column>,
+}
+
+impl TableSchema {
+    /// A table named `name` with `columns`.
+    pub fn new(name: impl Into<String>, columns: Vec<Column>) -> Self {
+        TableSchema {
+            name: name.into(),
+            columns,
+        }
+    }
+
+    /// The `blocks` table: one row per streamed block, with its metadata,
+    /// step, cursor and encoded payload, as written by the DuckDB sink.
+    pub fn blocks() -> Self {
+        TableSchema::new(
+            "blocks",
+            vec![
+                Column::required("number", ColumnType::UInt64),
+                Column::required("id", ColumnType::String),
+                Column::nullable("parent_number", ColumnType::UInt64),
+                Column::nullable("parent_id", ColumnType::String),
+                Column::nullable("timestamp", ColumnType::Timestamp),
+                Column::nullable("lib_number", ColumnType::UInt64),
+                Column::required("step", ColumnType::String),
+                Column::required("cursor", ColumnType::String),
+                Column::nullable("type_url", ColumnType::String),
+                Column::nullable("payload", ColumnType::Binary),
+            ],
+        )
+    }
+
+    /// A table with one row per `message`, flattened into columns.
+    ///
+    /// Fields of nested messages become columns named by their path, such as
+    /// `header_number`, and are nullable since the message may be absent.
+    /// `google.protobuf.Timestamp` fields become timestamps and enums their
+    /// value names. Repeated fields, maps and recursive messages become JSON
+    /// columns; a [`Flattener`](crate::Flattener) gives repeated messages
+    /// tables of their own.
+    pub fn from_message(name: impl Into<String>, message: &MessageDescriptor) -> Self {
+        let columns = flat_columns(message, false)
+            .into_iter()
+            .map(|flat| flat.column)
+            .collect();
+        TableSchema::new(name, columns)
+    }
+
+    /// The column named `name`, if any.
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    /// The Parquet schema, in the message type syntax of the Parquet tools.
+    pub fn to_parquet(&self) -> String {
+        let mut schema = format!("message {} {{\n", self.name);
+        for column in &self.columns {
+            let repetition = if column.nullable {
+                "optional"
+            } else {
+                "required"
+            };
+            let (physical, logical) = column.column_type.parquet();
+            let _ = write!(schema, "  {repetition} {physical} {}", column.name);
+            if let Some(logical) = logical {
+                let _ = write!(schema, " ({logical})");
+            }
+            schema.push_str(";\n");
+        }
+        schema.push('}');
+        schema
+    }
+
+    /// The Arrow schema, in the JSON form of Arrow's integration format.
+    pub fn to_arrow(&self) -> Value {
+        let fields: Vec<Value> = self
+            .columns
+            .iter()
+            .map(|column| {
+                json!({
+                    "name": column.name,
+                    "nullable": column.nullable,
+                    "type": column.column_type.arrow(),
+                    "children": [],
+                })
+            })
+            .collect();
+        json!({ "fields": fields })
+    }
+
+    /// A DuckDB `CREATE TABLE IF NOT EXISTS` statement.
+    pub fn to_sql(&self) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let not_null = if column.nullable { "" } else { " NOT NULL" };
+                format!("    {} {}{not_null}", column.name, column.column_type.sql())
+            })
+            .collect();
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (\n{}\n);",
+            self.name,
+            columns.join(",\n")
+        )
+    }
+
+    /// JSON Schema (draft 2020-12) of the table's rows as JSON objects, with
+    /// binary columns base64-encoded and timestamps in RFC 3339.
+    pub fn to_json_schema(&self) -> Value {
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        for column in &self.columns {
+            let mut schema = column.column_type.json_schema();
+            if column.nullable && column.column_type != ColumnType::Json {
+                schema = json!({ "anyOf": [schema, {"type": "null"}] });
+            } else if !column.nullable {
+                required.push(Value::from(column.name.clone()));
+            }
+            properties.insert(column.name.clone(), schema);
+        }
+
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": self.name,
+            "type": "object",
+            "properties": properties,
+            "required": required,
+        })
+    }
+}
+
+/// A column of a flattened message, and the fields leading from the message
+/// to its value.
+#[derive(Clone, Debug)]
+pub(crate) struct FlatColumn {
+    pub(crate) column: Column,
+    pub(crate) path: Vec<FieldDescriptor>,
+}
+
+/// The columns `message` flattens into, optionally leaving out repeated
+/// message fields that get tables of their own.
+pub(crate) fn flat_columns(
+    message: &MessageDescriptor,
+    skip_repeated_messages: bool,
+) -> Vec<FlatColumn> {
+    let mut columns = Vec::new();
+    flatten(
+        message,
+        "",
+        &mut Vec::new(),
+        skip_repeated_messages,
+        &mut vec![message.full_name().to_string()],
+        &mut columns,
+    );
+    columns
+}
+
+/// Append the columns of `message`'s fields, prefixed by `prefix` and
+/// reached through `path`, to `columns`. `ancestors` holds the messages being
+/// flattened, to detect recursion.
+fn flatten(
+    message: &MessageDescriptor,
+    prefix: &str,
+    path: &mut Vec<FieldDescriptor>,
+    skip_repeated_messages: bool,
+    ancestors: &mut Vec<String>,
+    columns: &mut Vec<FlatColumn>,
+) {
+    for field in message.fields() {
+        let name = format!("{prefix}{}", field.name());
+        let nullable = !path.is_empty() || field.supports_presence();
+        let column_type = if field.is_list() || field.is_map() {
+            if skip_repeated_messages && field.is_list() && matches!(field.kind(), Kind::Message(_))
+            {
+                continue;
+            }
+            ColumnType::Json
+        } else {
+            match field.kind() {
+                Kind::Message(inner) if inner.full_name() == TIMESTAMP => ColumnType::Timestamp,
+                Kind::Message(inner) if ancestors.iter().any(|name| name == inner.full_name()) => {
+                    ColumnType::Json
+                }
+                Kind::Message(inner) => {
+                    ancestors.push(inner.full_name().to_string());
+                    path.push(field);
+                    flatten(
+                        &inner,
+                        &format!("{name}_"),
+                        path,
+                        skip_repeated_messages,
+                        ancestors,
+                        columns,
+                    );
+                    path.pop();
+                    ancestors.pop();
+                    continue;
+                }
+                kind => scalar_type(&kind),
+            }
+        };
+
+        let mut field_path = path.clone();
+        field_path.push(field);
+        columns.push(FlatColumn {
+            column: Column {
+                name,
+                column_type,
+                nullable: nullable || column_type == ColumnType::Json,
+            },
+            path: field_path,
+        });
+    }
+}
+
+fn scalar_type(kind: &Kind) -> ColumnType {
+    match kind {
+        Kind::Bool => ColumnType::Boolean,
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => ColumnType::Int32,
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => ColumnType::Int64,
+        Kind::Uint32 | Kind::Fixed32 => ColumnType::UInt32,
+        Kind::Uint64 | Kind::Fixed64 => ColumnType::UInt64,
+        Kind::Float => ColumnType::Float,
+        Kind::Double => ColumnType::Double,
+        Kind::String | Kind::Enum(_) => ColumnType::String,
+        Kind::Bytes => ColumnType::Binary,
+        Kind::Message(_) => ColumnType::Json,
+    }
+}
+
+/// JSON Schema (draft 2020-12) of `message` in the canonical proto3 JSON
+/// mapping, as rendered by the
+/// [`DynamicDecoder`](crate::DynamicDecoder).
+///
+/// Nested message types are described once under `$defs`, keyed by their
+/// full name, so recursive messages are supported.
+pub fn message_json_schema(message: &MessageDescriptor) -> Value {
+    let mut defs = Map::new();
+    define(message, &mut defs);
+
+    let mut schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$ref": reference(message),
+    });
+    schema["$defs"] = Value::Object(defs);
+    schema
+}
+
+/// Add `message` and the messages it refers to to `defs`.
+fn define(message: &MessageDescriptor, defs: &mut Map<String, Value>) {
+    if defs.contains_key(message.full_name()) {
+        return;
+    }
+    // Reserve the entry first, so recursive references stop here
+    defs.insert(message.full_name().to_string(), Value::Null);
+
+    let mut properties = Map::new();
+    for field in message.fields() {
+        properties.insert(field.json_name().to_string(), field_schema(&field, defs));
+    }
+    defs.insert(
+        message.full_name().to_string(),
+        json!({
+            "title": message.name(),
+            "type": "object",
+            "properties": properties,
+        }),
+    );
+}
+
+fn field_schema(field: &FieldDescriptor, defs: &mut Map<String, Value>) -> Value {
+    if field.is_map() {
+        let Kind::Message(entry) = field.kind() else {
+            return json!({"type": "object"});
+        };
+        let value = entry.map_entry_value_field();
+        return json!({
+            "type": "object",
+            "additionalProperties": kind_schema(&value.kind(), defs),
+        });
+    }
+
+    let schema = kind_schema(&field.kind(), defs);
+    if field.is_list() {
+        json!({"type": "array", "items": schema})
+    } else {
+        schema
+    }
+}
+
+fn kind_schema(kind: &Kind, defs: &mut Map<String, Value>) -> Value {
+    match kind {
+        Kind::Bool => json!({"type": "boolean"}),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 | Kind::Uint32 | Kind::Fixed32 => {
+            json!({"type": "integer"})
+        }
+        // 64-bit integers are strings in proto3 JSON
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => {
+            json!({"type": "string", "pattern": "^-?[0-9]+$"})
+        }
+        Kind::Uint64 | Kind::Fixed64 => json!({"type": "string", "pattern": "^[0-9]+$"}),
+        Kind::Float | Kind::Double => json!({
+            "anyOf": [
+                {"type": "number"},
+                {"enum": ["NaN", "Infinity", "-Infinity"]},
+            ]
+        }),
+        Kind::String => json!({"type": "string"}),
+        Kind::Bytes => json!({"type": "string", "contentEncoding": "base64"}),
+        Kind::Enum(descriptor) => {
+            let names: Vec<String> = descriptor
+                .values()
+                .map(|value| value.name().to_string())
+                .collect();
+            json!({"enum": names})
+        }
+        Kind::Message(message) if message.full_name() == TIMESTAMP => {
+            json!({"type": "string", "format": "date-time"})
+        }
+        Kind::Message(message) => {
+            define(message, defs);
+            json!({"$ref": reference(message)})
+        }
+    }
+}
+
+fn reference(message: &MessageDescriptor) -> String {
+    format!("#/$defs/{}", message.full_name())
+}
@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tower::Service;
+
+use crate::{
+    request_id, EndpointPool, FetchClient, FirehoseChannel, FirehoseError, SingleBlockRequest,
+    SingleBlockResponse,
+};
+
+/// Future returned by the fetch [`Service`] implementations.
+pub type FetchFuture =
+    Pin<Box<dyn Future<Output = Result<SingleBlockResponse, FirehoseError>> + Send>>;
+
+/// A [`FetchClient`] as a [`tower::Service`].
+///
+/// Standard tower middleware (timeouts, retries, rate and concurrency limits,
+/// load shedding) can wrap it instead of only the crate's built-in policies.
+/// [`EndpointPool`] implements the same service, with failover, hedging and
+/// caching underneath.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::future::poll_fn;
+///
+/// use firehose_rs::{FetchService, FirehoseEndpoint, SingleBlockRequest};
+/// use tower::Service;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = FirehoseEndpoint::from_env()?.fetch_client().await?;
+/// let mut service = FetchService::new(client);
+///
+/// poll_fn(|cx| service.poll_ready(cx)).await?;
+/// let response = service
+///     .call(SingleBlockRequest::new_by_block_number(12345))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FetchService {
+    client: FetchClient<FirehoseChannel>,
+}
+
+impl FetchService {
+    /// Wrap `client`.
+    pub fn new(client: FetchClient<FirehoseChannel>) -> Self {
+        FetchService { client }
+    }
+
+    /// The wrapped client.
+    pub fn into_inner(self) -> FetchClient<FirehoseChannel> {
+        self.client
+    }
+}
+
+impl Service<SingleBlockRequest> for FetchService {
+    type Response = SingleBlockResponse;
+    type Error = FirehoseError;
+    type Future = FetchFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The channel buffers calls itself; readiness is checked per call.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: SingleBlockRequest) -> Self::Future {
+        let mut client = self.client.clone();
+        let mut request = tonic::Request::new(request);
+        let id = request_id::ensure(request.metadata_mut());
+        Box::pin(async move {
+            let response = client
+                .block(request)
+                .await
+                .map_err(|status| request_id::tag(status, &id))?;
+            Ok(response.into_inner())
+        })
+    }
+}
+
+impl Service<SingleBlockRequest> for EndpointPool {
+    type Response = SingleBlockResponse;
+    type Error = FirehoseError;
+    type Future = FetchFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: SingleBlockRequest) -> Self::Future {
+        let pool = self.clone();
+        Box::pin(async move { pool.fetch(request).await })
+    }
+}
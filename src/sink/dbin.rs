@@ -0,0 +1,337 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+use prost::Message;
+
+#[cfg(feature = "sqlite-index")]
+use crate::archive::{ArchiveIndex, BlockLocation};
+use crate::{bstream::Block, BlockRange, Response};
+
+use super::{
+    manifest::{sha256_file, ExportManifest, ManifestPart, OpenPart},
+    Sink, SinkError,
+};
+
+/// Number of blocks per merged-blocks file used by Firehose operators.
+pub const DEFAULT_BUNDLE_SIZE: u64 = 100;
+
+/// A [`Sink`] writing merged-blocks bundles in the `dbin` format.
+///
+/// Blocks are wrapped in [`bstream::Block`](crate::bstream::Block) envelopes
+/// and grouped into files of `bundle_size` blocks named after the first block
+/// number of the bundle, zero-padded to ten digits (`0017000000.dbin`), the
+/// same layout `firehose-core` uses for its merged-blocks store.
+///
+/// Every block needs [`BlockMetadata`](crate::Response::metadata), which all
+/// current Firehose servers send.
+///
+/// With a [manifest](DbinSink::with_manifest), every finished bundle is
+/// recorded with its range, last cursor and checksum. With an
+/// [index](DbinSink::with_index), every block's file and offset is recorded
+/// for random access. With [compression](DbinSink::with_compression),
+/// bundles are written as `.dbin.zst` files instead.
+#[derive(Debug)]
+pub struct DbinSink {
+    dir: PathBuf,
+    bundle_size: u64,
+    current: Option<Bundle>,
+    /// Whether the last write closed a bundle.
+    closed: bool,
+    manifest: Option<(PathBuf, ExportManifest)>,
+    #[cfg(feature = "sqlite-index")]
+    index: Option<ArchiveIndex>,
+    #[cfg(feature = "zstd")]
+    compression: Option<i32>,
+}
+
+#[derive(Debug)]
+struct Bundle {
+    base: u64,
+    file: String,
+    writer: BufWriter<File>,
+    needs_header: bool,
+    /// Uncompressed length of the file, where the next message starts.
+    position: u64,
+    /// Number and cursor of the last block written.
+    last: Option<(u64, String)>,
+}
+
+impl DbinSink {
+    /// Write bundles of [`DEFAULT_BUNDLE_SIZE`] blocks into `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        DbinSink::with_bundle_size(dir, DEFAULT_BUNDLE_SIZE)
+    }
+
+    /// Write bundles of `bundle_size` blocks into `dir`.
+    pub fn with_bundle_size(dir: impl Into<PathBuf>, bundle_size: u64) -> std::io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(DbinSink {
+            dir,
+            bundle_size: bundle_size.max(1),
+            current: None,
+            closed: false,
+            manifest: None,
+            #[cfg(feature = "sqlite-index")]
+            index: None,
+            #[cfg(feature = "zstd")]
+            compression: None,
+        })
+    }
+
+    /// Record finished bundles in the [`ExportManifest`] at `path`, resuming
+    /// the one already there, if any.
+    pub fn with_manifest(mut self, path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let manifest = ExportManifest::load(&path)?.unwrap_or_default();
+        self.manifest = Some((path, manifest));
+        Ok(self)
+    }
+
+    /// The manifest of finished bundles, if enabled.
+    pub fn manifest(&self) -> Option<&ExportManifest> {
+        self.manifest.as_ref().map(|(_, manifest)| manifest)
+    }
+
+    /// Delete the bundle an interrupted run left open, and drop any part it
+    /// was appended to, so that its blocks show up again in the manifest's
+    /// [gaps](ExportManifest::gaps) and are rewritten from scratch.
+    ///
+    /// Needed when resuming without the cursor of the last block written,
+    /// which would otherwise write the same blocks into the bundle again.
+    pub(super) fn discard_open_part(&mut self) -> std::io::Result<()> {
+        let Some((manifest_path, manifest)) = &mut self.manifest else {
+            return Ok(());
+        };
+        let Some(open) = manifest.open_part.take() else {
+            return Ok(());
+        };
+
+        match fs::remove_file(self.dir.join(&open.file)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        manifest.parts.retain(|part| part.file != open.file);
+        manifest.save(manifest_path)
+    }
+
+    /// Record the location of every written block in `index`.
+    #[cfg(feature = "sqlite-index")]
+    pub fn with_index(mut self, index: ArchiveIndex) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// The index of written blocks, if enabled.
+    #[cfg(feature = "sqlite-index")]
+    pub fn index(&self) -> Option<&ArchiveIndex> {
+        self.index.as_ref()
+    }
+
+    /// Compress bundles with zstd at `level`, writing `.dbin.zst` files as
+    /// `firehose-core` does for compressed merged-blocks stores.
+    ///
+    /// Every write is compressed into its own frame, so a bundle can be
+    /// appended to after a restart, and the file decompresses with any zstd
+    /// tool into a regular `dbin` file. Offsets recorded in the
+    /// [index](DbinSink::with_index) are offsets in that decompressed file.
+    #[cfg(feature = "zstd")]
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression = Some(level);
+        self
+    }
+
+    /// Path of the bundle starting at block `base`.
+    pub fn bundle_path(&self, base: u64) -> PathBuf {
+        self.dir.join(self.bundle_name(base))
+    }
+
+    fn bundle_name(&self, base: u64) -> String {
+        #[cfg(feature = "zstd")]
+        if self.compression.is_some() {
+            return format!("{base:010}.dbin.zst");
+        }
+        format!("{base:010}.dbin")
+    }
+
+    fn open(&mut self, base: u64, first_block: u64) -> std::io::Result<Bundle> {
+        let path = self.bundle_path(base);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let position = file.metadata()?.len();
+        let needs_header = position == 0;
+        // Offsets count uncompressed bytes.
+        #[cfg(feature = "zstd")]
+        let position = match self.compression {
+            Some(_) if !needs_header => {
+                let mut decoder = zstd::Decoder::new(File::open(&path)?)?;
+                std::io::copy(&mut decoder, &mut std::io::sink())?
+            }
+            _ => position,
+        };
+        let name = self.bundle_name(base);
+
+        if let Some((manifest_path, manifest)) = &mut self.manifest {
+            let resumed = manifest
+                .open_part
+                .as_ref()
+                .is_some_and(|part| part.file == name);
+            if needs_header || !resumed {
+                manifest.open_part = Some(OpenPart {
+                    file: name.clone(),
+                    start: if needs_header { first_block } else { base },
+                });
+                manifest.save(manifest_path)?;
+            }
+        }
+
+        Ok(Bundle {
+            base,
+            file: name,
+            writer: BufWriter::new(file),
+            needs_header,
+            position,
+            last: None,
+        })
+    }
+
+    /// Flush `bundle` and record it in the manifest.
+    fn close(&mut self, mut bundle: Bundle) -> std::io::Result<()> {
+        bundle.writer.flush()?;
+        bundle.writer.get_ref().sync_data()?;
+
+        let (Some((manifest_path, manifest)), Some((stop, cursor))) =
+            (&mut self.manifest, bundle.last)
+        else {
+            return Ok(());
+        };
+
+        let mut start = match manifest.open_part.take() {
+            Some(part) if part.file == bundle.file => part.start,
+            _ => bundle.base,
+        };
+        // A bundle appended to after it was closed replaces its earlier part.
+        manifest.parts.retain(|part| {
+            let appended = part.file == bundle.file;
+            if appended {
+                start = start.min(part.range.start);
+            }
+            !appended
+        });
+        manifest.parts.push(ManifestPart {
+            range: BlockRange::new(start, stop),
+            sha256: sha256_file(&self.dir.join(&bundle.file))?,
+            file: bundle.file,
+            cursor,
+        });
+        manifest.save(manifest_path)
+    }
+}
+
+impl Sink for DbinSink {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        let block = Block::try_from(response.clone())?;
+        let base = block.number - block.number % self.bundle_size;
+
+        self.closed = false;
+        if self.current.as_ref().map(|bundle| bundle.base) != Some(base) {
+            if let Some(finished) = self.current.take() {
+                self.close(finished)?;
+                self.closed = true;
+            }
+            self.current = Some(self.open(base, block.number)?);
+        }
+
+        #[cfg(feature = "zstd")]
+        let compression = self.compression;
+        let bundle = self.current.as_mut().expect("bundle was just opened");
+        let mut data = Vec::new();
+        if bundle.needs_header {
+            let content_type = block
+                .payload
+                .as_ref()
+                .map(|payload| payload.type_url.as_str())
+                .unwrap_or_default();
+            write_header(&mut data, content_type)?;
+            bundle.needs_header = false;
+            bundle.position += data.len() as u64;
+        }
+
+        let message = block.encode_to_vec();
+        write_message(&mut data, &message)?;
+        #[cfg(feature = "zstd")]
+        let data = match compression {
+            Some(level) => zstd::bulk::compress(&data, level)?,
+            None => data,
+        };
+        bundle.writer.write_all(&data)?;
+
+        #[cfg(feature = "sqlite-index")]
+        if let Some(index) = &self.index {
+            index.insert(&BlockLocation {
+                number: block.number,
+                id: block.id.clone(),
+                file: bundle.file.clone(),
+                offset: bundle.position + 4,
+                length: message.len() as u64,
+            })?;
+        }
+
+        bundle.position += 4 + message.len() as u64;
+        bundle.last = Some((block.number, response.cursor.clone()));
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        if let Some(bundle) = self.current.as_mut() {
+            bundle.writer.flush()?;
+            bundle.writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), SinkError> {
+        if let Some(bundle) = self.current.take() {
+            self.close(bundle)?;
+        }
+        Ok(())
+    }
+
+    fn at_boundary(&self) -> bool {
+        self.closed
+    }
+}
+
+/// Write a version 1 `dbin` header: magic, version, then the length-prefixed
+/// content type.
+fn write_header(writer: &mut impl Write, content_type: &str) -> std::io::Result<()> {
+    let length = u16::try_from(content_type.len()).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "content type too long")
+    })?;
+
+    writer.write_all(b"dbin")?;
+    writer.write_all(&[1])?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(content_type.as_bytes())
+}
+
+/// Write one message, prefixed with its big-endian `u32` length.
+fn write_message(writer: &mut impl Write, message: &[u8]) -> std::io::Result<()> {
+    let length = u32::try_from(message.len()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "message too large for dbin",
+        )
+    })?;
+
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(message)
+}
@@ -0,0 +1,264 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{fmt, path::Path, time::UNIX_EPOCH};
+
+use duckdb::{
+    appender_params_from_iter,
+    types::{TimeUnit, Value},
+    AccessMode, Config, Connection,
+};
+
+use crate::Response;
+#[cfg(feature = "dynamic")]
+use crate::{Cell, Flattener};
+
+use super::{Sink, SinkError};
+
+/// Turns a block into rows of a [`DuckDbSink`] table, with one [`Value`] per
+/// column in table order.
+pub type RowMapper = Box<dyn FnMut(&Response) -> Result<Vec<Vec<Value>>, SinkError> + Send>;
+
+/// A [`Sink`] appending blocks to a DuckDB database file, a zero-infrastructure
+/// path from a Firehose stream to ad-hoc SQL.
+///
+/// Every block becomes a row of the `blocks` table:
+///
+/// | Column | Type |
+/// |--------|------|
+/// | `number` | `UBIGINT` |
+/// | `id` | `VARCHAR` |
+/// | `parent_number` | `UBIGINT` |
+/// | `parent_id` | `VARCHAR` |
+/// | `timestamp` | `TIMESTAMP` |
+/// | `lib_number` | `UBIGINT` |
+/// | `step` | `VARCHAR`, e.g. `STEP_NEW` |
+/// | `cursor` | `VARCHAR` |
+/// | `type_url` | `VARCHAR` |
+/// | `payload` | `BLOB`, the encoded chain-specific block |
+///
+/// Decoded rows, such as one per transaction, go to tables added with
+/// [`with_table`](DuckDbSink::with_table), or, with the `dynamic` feature, to
+/// the tables of a [`Flattener`](crate::Flattener) added with
+/// `with_flattener`. Rows are written in a transaction
+/// committed on every [flush](Sink::flush), so the database only ever holds
+/// whole blocks.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use duckdb::types::Value;
+/// use firehose_rs::{
+///     sink::{export, open_duckdb, DuckDbSink},
+///     FileCursorStore, FirehoseEndpoint, Request,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     stop_block_num: 17_000_999,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+///
+/// let mut sink = DuckDbSink::open("eth.duckdb")?.with_table(
+///     "block_sizes",
+///     "number UBIGINT, size UBIGINT",
+///     Box::new(|response| {
+///         let number = response.block_number().unwrap_or_default();
+///         let size = response.block.as_ref().map_or(0, |block| block.value.len());
+///         Ok(vec![vec![Value::UBigInt(number), Value::UBigInt(size as u64)]])
+///     }),
+/// )?;
+/// let mut cursors = FileCursorStore::new("eth.cursor");
+/// export(&FirehoseEndpoint::from_env()?, request, &mut sink, &mut cursors).await?;
+/// drop(sink);
+///
+/// let connection = open_duckdb("eth.duckdb")?;
+/// let blocks: u64 = connection.query_row("SELECT count(*) FROM blocks", [], |row| row.get(0))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DuckDbSink {
+    connection: Connection,
+    tables: Vec<(String, RowMapper)>,
+    #[cfg(feature = "dynamic")]
+    flattener: Option<Flattener>,
+    in_transaction: bool,
+}
+
+impl DuckDbSink {
+    /// Open the database at `path`, creating it and the `blocks` table if
+    /// needed.
+    pub fn open(path: impl AsRef<Path>) -> duckdb::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS blocks (
+                 number UBIGINT NOT NULL,
+                 id VARCHAR NOT NULL,
+                 parent_number UBIGINT,
+                 parent_id VARCHAR,
+                 timestamp TIMESTAMP,
+                 lib_number UBIGINT,
+                 step VARCHAR NOT NULL,
+                 cursor VARCHAR NOT NULL,
+                 type_url VARCHAR,
+                 payload BLOB
+             );",
+        )?;
+        Ok(DuckDbSink {
+            connection,
+            tables: Vec::new(),
+            #[cfg(feature = "dynamic")]
+            flattener: None,
+            in_transaction: false,
+        })
+    }
+
+    /// Also append the rows `rows` returns for each block to table `name`,
+    /// created with the column definitions `columns` if it does not exist.
+    pub fn with_table(
+        mut self,
+        name: impl Into<String>,
+        columns: &str,
+        rows: RowMapper,
+    ) -> duckdb::Result<Self> {
+        let name = name.into();
+        self.connection
+            .execute_batch(&format!("CREATE TABLE IF NOT EXISTS {name} ({columns});"))?;
+        self.tables.push((name, rows));
+        Ok(self)
+    }
+
+    /// Also append the row sets `flattener` produces for each block, creating
+    /// its tables if they do not exist.
+    #[cfg(feature = "dynamic")]
+    pub fn with_flattener(mut self, flattener: Flattener) -> duckdb::Result<Self> {
+        for schema in flattener.schemas() {
+            self.connection.execute_batch(&schema.to_sql())?;
+        }
+        self.flattener = Some(flattener);
+        Ok(self)
+    }
+
+    /// The database connection, for queries while exporting.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    fn commit(&mut self) -> duckdb::Result<()> {
+        if std::mem::take(&mut self.in_transaction) {
+            self.connection.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+}
+
+/// Open the database written by a [`DuckDbSink`] at `path` read-only, for
+/// ad-hoc SQL.
+pub fn open_duckdb(path: impl AsRef<Path>) -> duckdb::Result<Connection> {
+    Connection::open_with_flags(path, Config::default().access_mode(AccessMode::ReadOnly)?)
+}
+
+impl Sink for DuckDbSink {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        if !self.in_transaction {
+            self.connection.execute_batch("BEGIN TRANSACTION")?;
+            self.in_transaction = true;
+        }
+
+        self.connection
+            .appender("blocks")?
+            .append_row(appender_params_from_iter(block_row(response)))?;
+        for (table, rows) in &mut self.tables {
+            let mut appender = self.connection.appender(table)?;
+            for row in rows(response)? {
+                appender.append_row(appender_params_from_iter(row))?;
+            }
+        }
+
+        #[cfg(feature = "dynamic")]
+        if let Some(flattener) = &self.flattener {
+            for rows in flattener.flatten(response)? {
+                let mut appender = self.connection.appender(&rows.table)?;
+                for row in rows.rows {
+                    appender
+                        .append_row(appender_params_from_iter(row.into_iter().map(cell_value)))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.commit()?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DuckDbSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DuckDbSink")
+            .field(
+                "tables",
+                &self.tables.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .field("in_transaction", &self.in_transaction)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The `blocks` row of `response`.
+fn block_row(response: &Response) -> Vec<Value> {
+    let text = |value: &str| Value::Text(value.to_string());
+    let metadata = response.metadata.as_ref();
+    let timestamp = response
+        .timestamp()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .and_then(|since| i64::try_from(since.as_micros()).ok())
+        .map_or(Value::Null, |micros| {
+            Value::Timestamp(TimeUnit::Microsecond, micros)
+        });
+
+    vec![
+        Value::UBigInt(metadata.map_or(0, |metadata| metadata.num)),
+        text(metadata.map_or("", |metadata| &metadata.id)),
+        metadata.map_or(Value::Null, |metadata| Value::UBigInt(metadata.parent_num)),
+        metadata.map_or(Value::Null, |metadata| text(&metadata.parent_id)),
+        timestamp,
+        metadata.map_or(Value::Null, |metadata| Value::UBigInt(metadata.lib_num)),
+        text(response.step().as_str_name()),
+        text(&response.cursor),
+        response
+            .block
+            .as_ref()
+            .map_or(Value::Null, |block| text(&block.type_url)),
+        response
+            .block
+            .as_ref()
+            .map_or(Value::Null, |block| Value::Blob(block.value.clone())),
+    ]
+}
+
+/// `cell` as the value of its column.
+#[cfg(feature = "dynamic")]
+fn cell_value(cell: Cell) -> Value {
+    match cell {
+        Cell::Null => Value::Null,
+        Cell::Boolean(value) => Value::Boolean(value),
+        Cell::Int(value) => Value::BigInt(value),
+        Cell::UInt(value) => Value::UBigInt(value),
+        Cell::Float(value) => Value::Double(value),
+        Cell::String(value) => Value::Text(value),
+        Cell::Binary(value) => Value::Blob(value),
+        Cell::Timestamp(time) => time
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|since| i64::try_from(since.as_micros()).ok())
+            .map_or(Value::Null, |micros| {
+                Value::Timestamp(TimeUnit::Microsecond, micros)
+            }),
+        Cell::Json(value) => Value::Text(value.to_string()),
+    }
+}
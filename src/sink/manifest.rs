@@ -0,0 +1,179 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::BlockRange;
+
+/// Version of the manifest format written by this crate.
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// A completed output file of an export.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestPart {
+    /// Blocks contained in the file.
+    pub range: BlockRange,
+    /// File name, relative to the manifest's directory.
+    pub file: String,
+    /// Cursor of the last block of the file.
+    pub cursor: String,
+    /// SHA-256 of the file contents.
+    #[serde(with = "crate::hex_bytes")]
+    pub sha256: Vec<u8>,
+}
+
+/// An output file still being written.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenPart {
+    /// File name, relative to the manifest's directory.
+    pub file: String,
+    /// First block written to the file.
+    pub start: u64,
+}
+
+/// JSON manifest of a multi-part export.
+///
+/// Lists every completed output file with its block range, the cursor of its
+/// last block and its SHA-256, plus the file currently being written. A
+/// killed export reopens the manifest and carries on with the open file,
+/// while downstream tools can check with [`ExportManifest::gaps`] and
+/// [`ExportManifest::verify`] that a range is complete and intact before
+/// reading it.
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "parts": [
+///     {
+///       "range": { "start": 17000000, "stop": 17000099 },
+///       "file": "0017000000.dbin",
+///       "cursor": "...",
+///       "sha256": "0x9f86d081..."
+///     }
+///   ],
+///   "open_part": { "file": "0017000100.dbin", "start": 17000100 }
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportManifest {
+    /// Format version, [`MANIFEST_VERSION`].
+    pub version: u32,
+    /// Completed files, in block order.
+    pub parts: Vec<ManifestPart>,
+    /// The file being written, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_part: Option<OpenPart>,
+}
+
+impl Default for ExportManifest {
+    fn default() -> Self {
+        ExportManifest {
+            version: MANIFEST_VERSION,
+            parts: Vec::new(),
+            open_part: None,
+        }
+    }
+}
+
+impl ExportManifest {
+    /// Read the manifest at `path`, or `None` if there is none yet.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        let contents = match fs::read(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let manifest: ExportManifest = serde_json::from_slice(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported manifest version {}", manifest.version),
+            ));
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Write the manifest to `path`, atomically replacing any previous one.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.to_path_buf().into_os_string();
+        tmp.push(".tmp");
+
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Cursor of the last block of the last completed part.
+    pub fn last_cursor(&self) -> Option<&str> {
+        self.parts.last().map(|part| part.cursor.as_str())
+    }
+
+    /// Block ranges covered by completed parts, with adjacent parts merged.
+    pub fn covered(&self) -> Vec<BlockRange> {
+        let mut ranges: Vec<BlockRange> = self.parts.iter().map(|part| part.range).collect();
+        ranges.sort();
+
+        let mut merged: Vec<BlockRange> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.stop.saturating_add(1) => {
+                    last.stop = last.stop.max(range.stop);
+                }
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    /// Sub-ranges of `range` not covered by any completed part.
+    pub fn gaps(&self, range: BlockRange) -> Vec<BlockRange> {
+        let mut gaps = Vec::new();
+        let mut next = range.start;
+        for covered in self.covered() {
+            if covered.stop < next || covered.start > range.stop {
+                continue;
+            }
+            if covered.start > next {
+                gaps.push(BlockRange::new(next, covered.start - 1));
+            }
+            next = covered.stop.saturating_add(1);
+            if next > range.stop {
+                return gaps;
+            }
+        }
+        gaps.push(BlockRange::new(next, range.stop));
+        gaps
+    }
+
+    /// Parts whose file in `dir` is missing or does not match its checksum.
+    pub fn verify(&self, dir: impl AsRef<Path>) -> io::Result<Vec<&ManifestPart>> {
+        let mut invalid = Vec::new();
+        for part in &self.parts {
+            match sha256_file(&dir.as_ref().join(&part.file)) {
+                Ok(digest) if digest == part.sha256 => {}
+                Ok(_) => invalid.push(part),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => invalid.push(part),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(invalid)
+    }
+}
+
+/// SHA-256 of the file at `path`.
+pub(crate) fn sha256_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
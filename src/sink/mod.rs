@@ -0,0 +1,188 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Destinations for streamed blocks, the export loop driving them,
+//! exports of time windows, and differential sync of local archives.
+
+mod dbin;
+#[cfg(feature = "duckdb")]
+mod duckdb_sink;
+mod manifest;
+mod ndjson;
+#[cfg(feature = "parquet")]
+mod parquet_sink;
+mod partitioned;
+#[cfg(feature = "redis")]
+mod redis_stream;
+mod republish;
+mod sync;
+#[cfg(feature = "webhook")]
+mod webhook;
+mod window;
+
+pub use dbin::{DbinSink, DEFAULT_BUNDLE_SIZE};
+#[cfg(feature = "duckdb")]
+pub use duckdb_sink::{open_duckdb, DuckDbSink, RowMapper};
+pub use manifest::{ExportManifest, ManifestPart, OpenPart, MANIFEST_VERSION};
+pub use ndjson::NdjsonSink;
+#[cfg(feature = "parquet")]
+pub use parquet_sink::{ParquetSink, DEFAULT_PARQUET_BLOCKS};
+pub use partitioned::{OpenPartition, Partition, PartitionedSink, DEFAULT_PARTITION_BLOCKS};
+#[cfg(feature = "redis")]
+pub use redis_stream::{RedisCursorStore, RedisStreamSink, DEFAULT_STREAM_MAX_LEN};
+pub use republish::{Republisher, DEFAULT_REPUBLISH_BUFFER};
+pub use sync::{available_range, sync, SyncReport};
+#[cfg(feature = "webhook")]
+pub use webhook::{JsonEncoder, WebhookSink, WEBHOOK_SIGNATURE_HEADER, WEBHOOK_TIMESTAMP_HEADER};
+pub use window::{block_at_time, export_window, WindowExport};
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use crate::{CheckpointInterval, CursorStore, FirehoseEndpoint, FirehoseError, Request, Response};
+
+/// Error type returned by [`Sink`] implementations.
+pub type SinkError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// A destination for streamed blocks.
+///
+/// [`export`] calls [`write`](Sink::write) for every received block,
+/// [`flush`](Sink::flush) before each cursor checkpoint, and
+/// [`finish`](Sink::finish) when the stream ends. Once `flush` returns,
+/// everything written so far must survive a crash.
+pub trait Sink: Send {
+    /// Write one block.
+    fn write(&mut self, response: &Response) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    /// Make every block written so far durable.
+    fn flush(&mut self) -> impl Future<Output = Result<(), SinkError>> + Send;
+
+    /// Finalize the output once the stream reached its stop block.
+    ///
+    /// Defaults to [`flush`](Sink::flush).
+    fn finish(&mut self) -> impl Future<Output = Result<(), SinkError>> + Send {
+        self.flush()
+    }
+
+    /// Whether the last [`write`](Sink::write) completed a unit of output,
+    /// such as a file or a posted batch, where [`export`] checkpoints without
+    /// waiting for its [interval](CheckpointInterval).
+    ///
+    /// Defaults to `false`.
+    fn at_boundary(&self) -> bool {
+        false
+    }
+}
+
+/// How often [`export`] flushes its sink and stores the cursor: every 1000
+/// blocks or 10 seconds, whichever comes first.
+pub const DEFAULT_EXPORT_INTERVAL: CheckpointInterval = CheckpointInterval {
+    blocks: Some(1_000),
+    period: Some(Duration::from_secs(10)),
+};
+
+/// Stream `request` from `endpoint` into `sink`, checkpointing cursors in
+/// `cursors`.
+///
+/// When `cursors` already holds a cursor, the stream resumes right after that
+/// block and `request.cursor` is ignored. The sink is flushed and the cursor
+/// stored every [`DEFAULT_EXPORT_INTERVAL`], whenever the sink reaches a
+/// [boundary](Sink::at_boundary), and when the stream ends. The sink is
+/// flushed before each cursor is stored, so after a crash the export restarts
+/// at or before the last durable block: delivery is at-least-once.
+///
+/// Returns the number of blocks written.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{sink::{export, NdjsonSink}, FileCursorStore, FirehoseEndpoint, Request};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let endpoint = FirehoseEndpoint::from_env()?;
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     stop_block_num: 17_000_999,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+///
+/// let mut sink = NdjsonSink::create("blocks.ndjson")?;
+/// let mut cursors = FileCursorStore::new("blocks.cursor");
+///
+/// let blocks = export(&endpoint, request, &mut sink, &mut cursors).await?;
+/// println!("exported {blocks} blocks");
+/// # Ok(())
+/// # }
+/// ```
+pub async fn export<S, C>(
+    endpoint: &FirehoseEndpoint,
+    request: Request,
+    sink: &mut S,
+    cursors: &mut C,
+) -> Result<u64, FirehoseError>
+where
+    S: Sink,
+    C: CursorStore,
+{
+    export_with_interval(endpoint, request, sink, cursors, DEFAULT_EXPORT_INTERVAL).await
+}
+
+/// [`export`], checkpointing on `interval` instead of
+/// [`DEFAULT_EXPORT_INTERVAL`].
+///
+/// Checkpointing more often replays fewer blocks after a crash, at the cost
+/// of a flush of the sink per checkpoint.
+pub async fn export_with_interval<S, C>(
+    endpoint: &FirehoseEndpoint,
+    mut request: Request,
+    sink: &mut S,
+    cursors: &mut C,
+    interval: CheckpointInterval,
+) -> Result<u64, FirehoseError>
+where
+    S: Sink,
+    C: CursorStore,
+{
+    if let Some(cursor) = cursors.load()? {
+        request.cursor = cursor;
+    }
+
+    let mut client = endpoint.stream_client().await?;
+    let mut stream = client.blocks(request).await?.into_inner();
+
+    let mut blocks = 0;
+    // Blocks written since the last checkpoint, and the cursor of the last.
+    let mut pending = 0;
+    let mut uncommitted = None;
+    let mut committed_at = Instant::now();
+    while let Some(response) = stream.message().await? {
+        sink.write(&response).await.map_err(FirehoseError::Sink)?;
+        blocks += 1;
+        pending += 1;
+
+        let due = sink.at_boundary()
+            || interval.blocks.is_some_and(|limit| pending >= limit)
+            || interval
+                .period
+                .is_some_and(|period| committed_at.elapsed() >= period);
+        if due {
+            sink.flush().await.map_err(FirehoseError::Sink)?;
+            cursors.store(&response.cursor)?;
+            pending = 0;
+            uncommitted = None;
+            committed_at = Instant::now();
+        } else {
+            uncommitted = Some(response.cursor);
+        }
+    }
+    sink.finish().await.map_err(FirehoseError::Sink)?;
+    if let Some(cursor) = uncommitted {
+        cursors.store(&cursor)?;
+    }
+
+    Ok(blocks)
+}
@@ -0,0 +1,63 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::Response;
+
+use super::{Sink, SinkError};
+
+/// A [`Sink`] writing one JSON-encoded [`Response`] per line.
+///
+/// Files opened with [`create`](NdjsonSink::create) are synced to disk on
+/// every [flush](Sink::flush); other writers are only flushed.
+#[derive(Debug)]
+pub struct NdjsonSink<W: Write> {
+    writer: W,
+    /// The file behind `writer`, to sync on flush.
+    file: Option<File>,
+}
+
+impl NdjsonSink<BufWriter<File>> {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(NdjsonSink {
+            file: Some(file.try_clone()?),
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl<W: Write> NdjsonSink<W> {
+    /// Write lines to `writer`.
+    pub fn new(writer: W) -> Self {
+        NdjsonSink { writer, file: None }
+    }
+
+    /// Consume the sink, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> Sink for NdjsonSink<W> {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        serde_json::to_writer(&mut self.writer, response)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.writer.flush()?;
+        if let Some(file) = &self.file {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
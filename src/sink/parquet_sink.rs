@@ -0,0 +1,365 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{self, File},
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use arrow::{
+    array::{
+        ArrayRef, BinaryArray, BooleanArray, Float32Array, Float64Array, Int32Array, Int64Array,
+        StringArray, TimestampMicrosecondArray, UInt32Array, UInt64Array,
+    },
+    datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    record_batch::RecordBatch,
+};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+
+use crate::{Cell, ColumnType, Flattener, Response, TableSchema};
+
+use super::{Sink, SinkError};
+
+/// Blocks per file of a [`ParquetSink`], unless changed with
+/// [`ParquetSink::with_blocks_per_file`].
+pub const DEFAULT_PARQUET_BLOCKS: u64 = 1_000;
+
+/// A [`Sink`] writing blocks and their flattened rows to Parquet files, for
+/// analytics engines to query in place.
+///
+/// Every block becomes a row of the `blocks` table, with the columns of
+/// [`TableSchema::blocks`], and the tables of a [`Flattener`] added with
+/// [`with_flattener`](ParquetSink::with_flattener) get its row sets. Each
+/// table is a directory of Snappy-compressed files named after the first and
+/// last block they hold:
+///
+/// ```text
+/// <dir>/blocks/0017000000-0017000999.parquet
+/// <dir>/eth_transactions/0017000000-0017000999.parquet
+/// ```
+///
+/// Parquet files cannot be appended to, so rows are held in memory and
+/// written out whenever [blocks per file](ParquetSink::with_blocks_per_file)
+/// are buffered and on every [flush](Sink::flush). Files are written under a
+/// temporary name, synced and renamed, so a crash never leaves a truncated
+/// file, and a replayed range overwrites the files of its first run.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{
+///     sink::{export, ParquetSink},
+///     DynamicDecoder, FileCursorStore, FirehoseEndpoint, Flattener, Request,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut decoder = DynamicDecoder::new()?;
+/// decoder.add_file_descriptor_set(std::fs::read("ethereum.binpb")?.as_slice())?;
+///
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     stop_block_num: 17_099_999,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+/// let mut sink = ParquetSink::new("eth")?.with_flattener(Flattener::ethereum(decoder)?);
+/// let mut cursors = FileCursorStore::new("eth/cursor");
+/// export(&FirehoseEndpoint::from_env()?, request, &mut sink, &mut cursors).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ParquetSink {
+    dir: PathBuf,
+    blocks_per_file: u64,
+    flattener: Option<Flattener>,
+    /// The `blocks` table, then those of the flattener.
+    tables: Vec<Table>,
+    /// Numbers of the first and last buffered blocks.
+    buffered: Option<(u64, u64)>,
+    blocks: u64,
+    /// Whether the last write wrote out files.
+    written: bool,
+}
+
+#[derive(Debug)]
+struct Table {
+    schema: TableSchema,
+    arrow: SchemaRef,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    fn new(schema: TableSchema) -> Self {
+        let fields: Vec<Field> = schema
+            .columns
+            .iter()
+            .map(|column| Field::new(&column.name, data_type(column.column_type), column.nullable))
+            .collect();
+        Table {
+            arrow: Arc::new(Schema::new(fields)),
+            schema,
+            rows: Vec::new(),
+        }
+    }
+
+    /// The buffered rows as one batch.
+    fn batch(&self) -> Result<RecordBatch, SinkError> {
+        let columns = self
+            .schema
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| array(column.column_type, &self.rows, index))
+            .collect();
+        Ok(RecordBatch::try_new(self.arrow.clone(), columns)?)
+    }
+}
+
+impl ParquetSink {
+    /// Write the tables into directories under `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(ParquetSink {
+            dir,
+            blocks_per_file: DEFAULT_PARQUET_BLOCKS,
+            flattener: None,
+            tables: vec![Table::new(TableSchema::blocks())],
+            buffered: None,
+            blocks: 0,
+            written: false,
+        })
+    }
+
+    /// Put `blocks` blocks in each file, at most.
+    pub fn with_blocks_per_file(mut self, blocks: u64) -> Self {
+        self.blocks_per_file = blocks.max(1);
+        self
+    }
+
+    /// Also write the row sets `flattener` produces for each block, to a
+    /// directory per table.
+    pub fn with_flattener(mut self, flattener: Flattener) -> Self {
+        self.tables
+            .extend(flattener.schemas().cloned().map(Table::new));
+        self.flattener = Some(flattener);
+        self
+    }
+
+    /// Write the buffered rows of every table to a file, if any.
+    fn write_files(&mut self) -> Result<(), SinkError> {
+        let Some((first, last)) = self.buffered.take() else {
+            return Ok(());
+        };
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        for table in &mut self.tables {
+            let dir = self.dir.join(&table.schema.name);
+            fs::create_dir_all(&dir)?;
+            let path = dir.join(format!("{first:010}-{last:010}.parquet"));
+            let tmp = path.with_extension("parquet.tmp");
+
+            let file = File::create(&tmp)?;
+            let mut writer = ArrowWriter::try_new(
+                file.try_clone()?,
+                table.arrow.clone(),
+                Some(properties.clone()),
+            )?;
+            writer.write(&table.batch()?)?;
+            writer.close()?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)?;
+
+            table.rows.clear();
+        }
+        self.blocks = 0;
+        Ok(())
+    }
+}
+
+impl Sink for ParquetSink {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        let number = response
+            .block_number()
+            .ok_or("parquet sinks need block metadata")?;
+
+        self.tables[0].rows.push(block_row(response));
+        if let Some(flattener) = &self.flattener {
+            for rows in flattener.flatten(response)? {
+                if let Some(table) = self
+                    .tables
+                    .iter_mut()
+                    .find(|table| table.schema.name == rows.table)
+                {
+                    table.rows.extend(rows.rows);
+                }
+            }
+        }
+
+        self.buffered = Some(match self.buffered {
+            Some((first, _)) => (first, number),
+            None => (number, number),
+        });
+        self.blocks += 1;
+        self.written = self.blocks >= self.blocks_per_file;
+        if self.written {
+            self.write_files()?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.write_files()
+    }
+
+    fn at_boundary(&self) -> bool {
+        self.written
+    }
+}
+
+/// Arrow type of a column of `column_type`.
+fn data_type(column_type: ColumnType) -> DataType {
+    match column_type {
+        ColumnType::Boolean => DataType::Boolean,
+        ColumnType::Int32 => DataType::Int32,
+        ColumnType::Int64 => DataType::Int64,
+        ColumnType::UInt32 => DataType::UInt32,
+        ColumnType::UInt64 => DataType::UInt64,
+        ColumnType::Float => DataType::Float32,
+        ColumnType::Double => DataType::Float64,
+        ColumnType::String | ColumnType::Json => DataType::Utf8,
+        ColumnType::Binary => DataType::Binary,
+        ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+    }
+}
+
+/// Column `index` of `rows`, as an array of `column_type`. Cells of another
+/// type are null.
+fn array(column_type: ColumnType, rows: &[Vec<Cell>], index: usize) -> ArrayRef {
+    let cells = rows.iter().map(|row| row.get(index).unwrap_or(&Cell::Null));
+    match column_type {
+        ColumnType::Boolean => Arc::new(BooleanArray::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::Boolean(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::Int32 => Arc::new(Int32Array::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::Int(value) => i32::try_from(*value).ok(),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::Int64 => Arc::new(Int64Array::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::Int(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::UInt32 => Arc::new(UInt32Array::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::UInt(value) => u32::try_from(*value).ok(),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::UInt64 => Arc::new(UInt64Array::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::UInt(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::Float => Arc::new(Float32Array::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::Float(value) => Some(*value as f32),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::Double => Arc::new(Float64Array::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::Float(value) => Some(*value),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::String | ColumnType::Json => Arc::new(StringArray::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::String(value) => Some(value.clone()),
+                    Cell::Json(value) => Some(value.to_string()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::Binary => Arc::new(BinaryArray::from(
+            cells
+                .map(|cell| match cell {
+                    Cell::Binary(value) => Some(value.as_slice()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        ColumnType::Timestamp => Arc::new(
+            TimestampMicrosecondArray::from(
+                cells
+                    .map(|cell| match cell {
+                        Cell::Timestamp(time) => micros(*time),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .with_timezone("UTC"),
+        ),
+    }
+}
+
+/// The `blocks` row of `response`.
+fn block_row(response: &Response) -> Vec<Cell> {
+    let text = |value: &str| Cell::String(value.to_string());
+    let metadata = response.metadata.as_ref();
+
+    vec![
+        Cell::UInt(metadata.map_or(0, |metadata| metadata.num)),
+        text(metadata.map_or("", |metadata| &metadata.id)),
+        metadata.map_or(Cell::Null, |metadata| Cell::UInt(metadata.parent_num)),
+        metadata.map_or(Cell::Null, |metadata| text(&metadata.parent_id)),
+        response.timestamp().map_or(Cell::Null, Cell::Timestamp),
+        metadata.map_or(Cell::Null, |metadata| Cell::UInt(metadata.lib_num)),
+        text(response.step().as_str_name()),
+        text(&response.cursor),
+        response
+            .block
+            .as_ref()
+            .map_or(Cell::Null, |block| text(&block.type_url)),
+        response
+            .block
+            .as_ref()
+            .map_or(Cell::Null, |block| Cell::Binary(block.value.clone())),
+    ]
+}
+
+/// Microseconds since the Unix epoch.
+fn micros(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| i64::try_from(since.as_micros()).ok())
+}
@@ -0,0 +1,234 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fmt, fs,
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::Response;
+
+use super::{NdjsonSink, Sink, SinkError};
+
+/// Blocks per `block_range` file of a [`PartitionedSink`], unless changed
+/// with [`PartitionedSink::with_blocks_per_file`].
+pub const DEFAULT_PARTITION_BLOCKS: u64 = 10_000;
+
+/// Opens the file of one partition of a [`PartitionedSink`].
+pub type OpenPartition<S> = fn(&Path) -> io::Result<S>;
+
+/// One file of a [`PartitionedSink`]:
+/// `chain=<chain>/date=<yyyy-mm-dd>/block_range=<first>-<last>.<extension>`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Partition {
+    /// Chain name, as given to the sink.
+    pub chain: String,
+    /// UTC date of the blocks' timestamps, as `yyyy-mm-dd`.
+    pub date: String,
+    /// First block number of the file's range.
+    pub first_block: u64,
+    /// Last block number of the file's range.
+    pub last_block: u64,
+}
+
+impl Partition {
+    /// Path of the partition's file relative to the sink's root.
+    pub fn path(&self, extension: &str) -> PathBuf {
+        PathBuf::from(format!("chain={}", self.chain))
+            .join(format!("date={}", self.date))
+            .join(format!(
+                "block_range={}-{}.{extension}",
+                self.first_block, self.last_block
+            ))
+    }
+}
+
+/// A [`Sink`] spreading blocks over Hive-style partitioned directories, so
+/// exports can be queried by Athena, Spark or DuckDB as is:
+///
+/// ```text
+/// <root>/chain=eth/date=2023-04-13/block_range=17040000-17049999.ndjson
+/// ```
+///
+/// The date comes from each block's timestamp, in UTC, and block ranges are
+/// aligned to multiples of the [blocks per file](Self::with_blocks_per_file),
+/// so a range that spans midnight is split over two date directories under
+/// the same file name. Blocks without a timestamp stay in the current file.
+///
+/// Each file is written by an inner sink opened by a function of its path,
+/// which should append to an existing file: a resumed export, or an undo
+/// step of a block in an earlier file, reopens it. The previous file is
+/// [finished](Sink::finish) whenever blocks move to another one.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{
+///     sink::{export, PartitionedSink},
+///     FileCursorStore, FirehoseEndpoint, Request,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     stop_block_num: 17_099_999,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+///
+/// let mut sink = PartitionedSink::ndjson("exports", "eth");
+/// let mut cursors = FileCursorStore::new("exports/eth.cursor");
+/// export(&FirehoseEndpoint::from_env()?, request, &mut sink, &mut cursors).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct PartitionedSink<S> {
+    root: PathBuf,
+    chain: String,
+    extension: String,
+    blocks_per_file: u64,
+    open: OpenPartition<S>,
+    current: Option<(Partition, S)>,
+}
+
+impl PartitionedSink<NdjsonSink<BufWriter<File>>> {
+    /// Write NDJSON files for `chain` under `root`.
+    pub fn ndjson(root: impl Into<PathBuf>, chain: impl Into<String>) -> Self {
+        PartitionedSink::new(root, chain, "ndjson", |path| NdjsonSink::create(path))
+    }
+}
+
+impl<S: Sink> PartitionedSink<S> {
+    /// Write files with `extension` for `chain` under `root`, each through
+    /// the sink returned by `open` for its path.
+    pub fn new(
+        root: impl Into<PathBuf>,
+        chain: impl Into<String>,
+        extension: impl Into<String>,
+        open: OpenPartition<S>,
+    ) -> Self {
+        PartitionedSink {
+            root: root.into(),
+            chain: chain.into(),
+            extension: extension.into(),
+            blocks_per_file: DEFAULT_PARTITION_BLOCKS,
+            open,
+            current: None,
+        }
+    }
+
+    /// Put `blocks` consecutive block numbers in each file, at most.
+    pub fn with_blocks_per_file(mut self, blocks: u64) -> Self {
+        self.blocks_per_file = blocks.max(1);
+        self
+    }
+
+    /// The partition blocks are currently written to, if any.
+    pub fn current_partition(&self) -> Option<&Partition> {
+        self.current.as_ref().map(|(partition, _)| partition)
+    }
+
+    /// The partition `response` belongs in.
+    fn partition_of(&self, response: &Response) -> Result<Partition, SinkError> {
+        let number = response
+            .block_number()
+            .ok_or("partitioned sinks need block metadata")?;
+        let first_block = number - number % self.blocks_per_file;
+
+        let date = match response.timestamp() {
+            Some(time) => {
+                let secs = time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let (year, month, day) = civil_date(secs / 86_400);
+                format!("{year:04}-{month:02}-{day:02}")
+            }
+            None => match self.current_partition() {
+                Some(current) if current.first_block == first_block => current.date.clone(),
+                _ => return Err(format!("block {number} has no timestamp").into()),
+            },
+        };
+
+        Ok(Partition {
+            chain: self.chain.clone(),
+            date,
+            first_block,
+            last_block: first_block + self.blocks_per_file - 1,
+        })
+    }
+}
+
+impl<S: Sink> Sink for PartitionedSink<S> {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        let partition = self.partition_of(response)?;
+        if self.current_partition() != Some(&partition) {
+            if let Some((_, mut previous)) = self.current.take() {
+                previous.finish().await?;
+            }
+            let path = self.root.join(partition.path(&self.extension));
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            self.current = Some((partition, (self.open)(&path)?));
+        }
+
+        let (_, sink) = self.current.as_mut().expect("partition was just opened");
+        sink.write(response).await
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        match &mut self.current {
+            Some((_, sink)) => sink.flush().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn finish(&mut self) -> Result<(), SinkError> {
+        match self.current.take() {
+            Some((_, mut sink)) => sink.finish().await,
+            None => Ok(()),
+        }
+    }
+
+    fn at_boundary(&self) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|(_, sink)| sink.at_boundary())
+    }
+}
+
+impl<S> fmt::Debug for PartitionedSink<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartitionedSink")
+            .field("root", &self.root)
+            .field("chain", &self.chain)
+            .field("extension", &self.extension)
+            .field("blocks_per_file", &self.blocks_per_file)
+            .field(
+                "current",
+                &self.current.as_ref().map(|(partition, _)| partition),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Year, month and day of the date `days` days after 1970-01-01.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Howard Hinnant's `civil_from_days`, for dates on or after the epoch
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,182 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{fmt, io};
+
+use prost::Message;
+use redis::{aio::MultiplexedConnection, Client};
+
+use crate::{CursorStore, Response};
+
+use super::{Sink, SinkError};
+
+/// Entries a [`RedisStreamSink`] keeps in its stream, unless changed with
+/// [`RedisStreamSink::with_max_len`].
+pub const DEFAULT_STREAM_MAX_LEN: u64 = 100_000;
+
+/// A [`Sink`] appending blocks to a Redis Stream with `XADD`, a lightweight
+/// buffer between one Firehose connection and fleets of small consumers
+/// reading with `XREAD` or consumer groups.
+///
+/// Each entry has the fields `number`, `id`, `step` (e.g. `STEP_NEW`),
+/// `cursor` and `block`, the protobuf-encoded [`Response`]. The stream is
+/// trimmed to about [`DEFAULT_STREAM_MAX_LEN`] entries (`MAXLEN ~`).
+///
+/// Blocks are buffered until [flushed](Sink::flush), then added in one
+/// `MULTI` transaction that also stores the last cursor in the cursor key, so
+/// the stream and the cursor never disagree. [`RedisCursorStore`] reads it
+/// back to resume an export.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{
+///     sink::{export, RedisCursorStore, RedisStreamSink},
+///     FirehoseEndpoint, Request,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+///
+/// let client = redis::Client::open("redis://127.0.0.1/")?;
+/// let mut sink = RedisStreamSink::connect(&client, "eth:blocks", "eth:cursor")
+///     .await?
+///     .with_max_len(10_000);
+/// let mut cursors = RedisCursorStore::new(client, "eth:cursor");
+/// export(&FirehoseEndpoint::from_env()?, request, &mut sink, &mut cursors).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct RedisStreamSink {
+    connection: MultiplexedConnection,
+    stream_key: String,
+    cursor_key: String,
+    max_len: u64,
+    pending: redis::Pipeline,
+    last_cursor: Option<String>,
+}
+
+impl RedisStreamSink {
+    /// Append to the stream at `stream_key` of the server `client` connects
+    /// to, storing the cursor at `cursor_key`.
+    pub async fn connect(
+        client: &Client,
+        stream_key: impl Into<String>,
+        cursor_key: impl Into<String>,
+    ) -> redis::RedisResult<Self> {
+        let mut pending = redis::pipe();
+        pending.atomic();
+        Ok(RedisStreamSink {
+            connection: client.get_multiplexed_async_connection().await?,
+            stream_key: stream_key.into(),
+            cursor_key: cursor_key.into(),
+            max_len: DEFAULT_STREAM_MAX_LEN,
+            pending,
+            last_cursor: None,
+        })
+    }
+
+    /// Trim the stream to about `entries` entries.
+    pub fn with_max_len(mut self, entries: u64) -> Self {
+        self.max_len = entries;
+        self
+    }
+}
+
+impl Sink for RedisStreamSink {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        let metadata = response.metadata.as_ref();
+        self.pending
+            .cmd("XADD")
+            .arg(&self.stream_key)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(self.max_len)
+            .arg("*")
+            .arg("number")
+            .arg(metadata.map_or(0, |metadata| metadata.num))
+            .arg("id")
+            .arg(metadata.map_or("", |metadata| metadata.id.as_str()))
+            .arg("step")
+            .arg(response.step().as_str_name())
+            .arg("cursor")
+            .arg(&response.cursor)
+            .arg("block")
+            .arg(response.encode_to_vec())
+            .ignore();
+        self.last_cursor = Some(response.cursor.clone());
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        let Some(cursor) = &self.last_cursor else {
+            return Ok(());
+        };
+
+        // A failed transaction is retried whole on the next flush.
+        let mut transaction = self.pending.clone();
+        transaction
+            .cmd("SET")
+            .arg(&self.cursor_key)
+            .arg(cursor)
+            .ignore();
+        let () = transaction.query_async(&mut self.connection).await?;
+
+        self.pending.clear();
+        self.last_cursor = None;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RedisStreamSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisStreamSink")
+            .field("stream_key", &self.stream_key)
+            .field("cursor_key", &self.cursor_key)
+            .field("max_len", &self.max_len)
+            .field("last_cursor", &self.last_cursor)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`CursorStore`] keeping the cursor in a Redis key, such as the cursor
+/// key of a [`RedisStreamSink`].
+#[derive(Clone, Debug)]
+pub struct RedisCursorStore {
+    client: Client,
+    key: String,
+}
+
+impl RedisCursorStore {
+    /// Store the cursor at `key` of the server `client` connects to.
+    pub fn new(client: Client, key: impl Into<String>) -> Self {
+        RedisCursorStore {
+            client,
+            key: key.into(),
+        }
+    }
+}
+
+impl CursorStore for RedisCursorStore {
+    fn load(&self) -> io::Result<Option<String>> {
+        let mut connection = self.client.get_connection().map_err(io::Error::other)?;
+        redis::cmd("GET")
+            .arg(&self.key)
+            .query(&mut connection)
+            .map_err(io::Error::other)
+    }
+
+    fn store(&mut self, cursor: &str) -> io::Result<()> {
+        let mut connection = self.client.get_connection().map_err(io::Error::other)?;
+        redis::cmd("SET")
+            .arg(&self.key)
+            .arg(cursor)
+            .query(&mut connection)
+            .map_err(io::Error::other)
+    }
+}
@@ -0,0 +1,312 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{broadcast, mpsc};
+use tonic::{codegen::tokio_stream::wrappers::ReceiverStream, Status};
+
+use crate::{
+    firehose_v2::stream_server::{Stream, StreamServer},
+    ForkStep, Request, Response,
+};
+
+use super::{Sink, SinkError};
+
+/// Recent blocks a [`Republisher`] keeps for clients that connect or resume
+/// behind the head, unless created with another size.
+pub const DEFAULT_REPUBLISH_BUFFER: usize = 1_000;
+
+/// Blocks queued for each client of a [`Republisher`] before it is
+/// considered behind.
+const CLIENT_QUEUE: usize = 64;
+
+/// A [`Sink`] that serves the blocks written to it over the Firehose Stream
+/// API, to bridge networks or to aggregate several upstreams into one
+/// internal endpoint.
+///
+/// [`into_server`](Republisher::into_server) turns a republisher into a
+/// [`StreamServer`] for `tonic::transport::Server`. Clones share the same
+/// blocks, so several exports, for example from redundant providers of the
+/// same chain, can write to clones of one republisher. Blocks are identified
+/// by their ID and step, and one already written by another upstream is
+/// dropped.
+///
+/// The republisher keeps the last blocks in memory, so clients requesting a
+/// recent `start_block_num`, a negative one relative to the head, or
+/// resuming from a `cursor` it served first receive those. Older start
+/// blocks and unknown cursors are refused with `OUT_OF_RANGE`. Clients that
+/// fall further behind than the buffer are disconnected with `DATA_LOSS`, so
+/// they resume from their last cursor. `stop_block_num` and
+/// `final_blocks_only` are honored; transforms are not supported.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{
+///     sink::{export, Republisher, DEFAULT_REPUBLISH_BUFFER},
+///     FileCursorStore, FirehoseEndpoint, Request,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let republisher = Republisher::new(DEFAULT_REPUBLISH_BUFFER);
+/// let server = tonic::transport::Server::builder()
+///     .add_service(republisher.clone().into_server())
+///     .serve("0.0.0.0:10015".parse()?);
+/// tokio::spawn(server);
+///
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+/// let mut sink = republisher.clone();
+/// let mut cursors = FileCursorStore::new("upstream.cursor");
+/// export(&FirehoseEndpoint::from_env()?, request, &mut sink, &mut cursors).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Republisher {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    sender: broadcast::Sender<Response>,
+    recent: Mutex<Recent>,
+}
+
+/// The buffered blocks, and the ID and step of each.
+struct Recent {
+    blocks: VecDeque<Response>,
+    seen: HashSet<(String, i32)>,
+    capacity: usize,
+}
+
+impl Republisher {
+    /// Serve written blocks, keeping the last `buffer` of them for clients
+    /// behind the head.
+    pub fn new(buffer: usize) -> Self {
+        let capacity = buffer.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+        Republisher {
+            shared: Arc::new(Shared {
+                sender,
+                recent: Mutex::new(Recent {
+                    blocks: VecDeque::with_capacity(capacity),
+                    seen: HashSet::with_capacity(capacity),
+                    capacity,
+                }),
+            }),
+        }
+    }
+
+    /// A gRPC service serving the blocks, for `tonic::transport::Server`.
+    pub fn into_server(self) -> StreamServer<Republisher> {
+        StreamServer::new(self)
+    }
+
+    /// Send `response` to every connected client.
+    ///
+    /// Returns `false` if the same block and step was already published.
+    pub fn publish(&self, response: &Response) -> bool {
+        let key = block_key(response);
+        let mut recent = self
+            .shared
+            .recent
+            .lock()
+            .expect("republisher lock poisoned");
+        if !recent.seen.insert(key) {
+            return false;
+        }
+
+        if recent.blocks.len() == recent.capacity {
+            if let Some(oldest) = recent.blocks.pop_front() {
+                recent.seen.remove(&block_key(&oldest));
+            }
+        }
+        recent.blocks.push_back(response.clone());
+
+        // Sent under the lock, so new clients see each block exactly once:
+        // either buffered or received.
+        let _ = self.shared.sender.send(response.clone());
+        true
+    }
+
+    /// Number of connected clients.
+    pub fn clients(&self) -> usize {
+        self.shared.sender.receiver_count()
+    }
+
+    /// The buffered blocks `request` starts with, and a receiver of the
+    /// following ones.
+    fn subscribe(
+        &self,
+        request: &Request,
+    ) -> Result<(Vec<Response>, broadcast::Receiver<Response>), Status> {
+        let recent = self
+            .shared
+            .recent
+            .lock()
+            .expect("republisher lock poisoned");
+        let blocks = &recent.blocks;
+
+        let skip = if !request.cursor.is_empty() {
+            match blocks
+                .iter()
+                .position(|block| block.cursor == request.cursor)
+            {
+                Some(position) => position + 1,
+                None => {
+                    return Err(Status::out_of_range(
+                        "cursor is not among the republished blocks",
+                    ))
+                }
+            }
+        } else if request.start_block_num < 0 {
+            let from_head =
+                usize::try_from(request.start_block_num.unsigned_abs()).unwrap_or(usize::MAX);
+            blocks.len().saturating_sub(from_head)
+        } else {
+            let start = request.start_block_num as u64;
+            match blocks.front().and_then(Response::block_number) {
+                Some(oldest) if start < oldest => {
+                    return Err(Status::out_of_range(format!(
+                        "block {start} is older than the oldest republished block {oldest}"
+                    )))
+                }
+                _ => blocks
+                    .iter()
+                    .position(|block| block.block_number().is_some_and(|n| n >= start))
+                    .unwrap_or(blocks.len()),
+            }
+        };
+
+        let backlog = blocks.iter().skip(skip).cloned().collect();
+        Ok((backlog, self.shared.sender.subscribe()))
+    }
+}
+
+impl Sink for Republisher {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        self.publish(response);
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Stream for Republisher {
+    type BlocksStream = ReceiverStream<Result<Response, Status>>;
+
+    async fn blocks(
+        &self,
+        request: tonic::Request<Request>,
+    ) -> Result<tonic::Response<Self::BlocksStream>, Status> {
+        let request = request.into_inner();
+        if !request.transforms.is_empty() {
+            return Err(Status::unimplemented(
+                "transforms are not supported by this endpoint",
+            ));
+        }
+
+        let (backlog, mut receiver) = self.subscribe(&request)?;
+        let (sender, stream) = mpsc::channel(CLIENT_QUEUE);
+        tokio::spawn(async move {
+            for response in backlog {
+                match forward(&request, &sender, response).await {
+                    Forwarded::More => {}
+                    Forwarded::Done => return,
+                }
+            }
+            loop {
+                let response = match receiver.recv().await {
+                    Ok(response) => response,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        let _ = sender
+                            .send(Err(Status::data_loss(format!(
+                                "client fell {missed} blocks behind, resume from its last cursor"
+                            ))))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                match forward(&request, &sender, response).await {
+                    Forwarded::More => {}
+                    Forwarded::Done => return,
+                }
+            }
+        });
+
+        Ok(tonic::Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+impl fmt::Debug for Republisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let recent = self
+            .shared
+            .recent
+            .lock()
+            .expect("republisher lock poisoned");
+        f.debug_struct("Republisher")
+            .field("buffered", &recent.blocks.len())
+            .field("capacity", &recent.capacity)
+            .field("clients", &self.clients())
+            .finish()
+    }
+}
+
+enum Forwarded {
+    More,
+    Done,
+}
+
+/// Send `response` to a client if its request wants it, and tell whether the
+/// client wants more.
+async fn forward(
+    request: &Request,
+    sender: &mpsc::Sender<Result<Response, Status>>,
+    response: Response,
+) -> Forwarded {
+    let step = response.step();
+    if request.final_blocks_only && step != ForkStep::StepFinal {
+        return Forwarded::More;
+    }
+    let number = response.block_number();
+    if request.start_block_num >= 0
+        && request.cursor.is_empty()
+        && number.is_some_and(|n| n < request.start_block_num as u64)
+    {
+        return Forwarded::More;
+    }
+
+    if sender.send(Ok(response)).await.is_err() {
+        return Forwarded::Done;
+    }
+    let stopped = request.stop_block_num > 0
+        && step != ForkStep::StepUndo
+        && number.is_some_and(|n| n >= request.stop_block_num);
+    if stopped {
+        Forwarded::Done
+    } else {
+        Forwarded::More
+    }
+}
+
+/// What identifies a block across upstreams: its ID and step, or its cursor
+/// without metadata.
+fn block_key(response: &Response) -> (String, i32) {
+    match &response.metadata {
+        Some(metadata) => (metadata.id.clone(), response.step),
+        None => (response.cursor.clone(), response.step),
+    }
+}
@@ -0,0 +1,127 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{BlockRange, FirehoseEndpoint, FirehoseError, InfoRequest, MemoryCursorStore, Request};
+
+use super::{export, DbinSink};
+
+/// What a [`sync`] run found and fetched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Blocks the endpoint can serve, from its first streamable block to its
+    /// last final block, clipped to the requested range.
+    pub available: BlockRange,
+    /// Spans that were missing locally and have been fetched.
+    pub fetched: Vec<BlockRange>,
+    /// Number of blocks written.
+    pub blocks: u64,
+}
+
+/// Blocks `endpoint` can serve: from its first streamable block, as reported
+/// by `Info`, to its last final block.
+pub async fn available_range(endpoint: &FirehoseEndpoint) -> Result<BlockRange, FirehoseError> {
+    let info = endpoint
+        .info_client()
+        .await?
+        .info(InfoRequest {})
+        .await?
+        .into_inner();
+
+    let mut client = endpoint.stream_client().await?;
+    let request = Request {
+        start_block_num: -1,
+        final_blocks_only: true,
+        ..Default::default()
+    };
+    let mut stream = client.blocks(request).await?.into_inner();
+    let head = stream
+        .message()
+        .await?
+        .and_then(|response| response.block_number())
+        .ok_or_else(|| FirehoseError::Config("endpoint did not report its head".to_string()))?;
+
+    Ok(BlockRange::new(info.first_streamable_block_num, head))
+}
+
+/// Bring the `dbin` archive written by `sink` up to date with `endpoint`,
+/// fetching only the spans its [manifest](super::ExportManifest) does not
+/// cover.
+///
+/// `sink` needs a [manifest](DbinSink::with_manifest); build it with the
+/// bundle size the archive was written with, so that every bundle keeps the
+/// same layout. The local store is compared with the
+/// [range](available_range) the endpoint can serve, limited to `range` if
+/// given, and every gap is exported with final blocks only. Running `sync`
+/// periodically turns the archive into a self-maintaining mirror of the
+/// chain.
+///
+/// Interrupted runs pick up after the last completed bundle: the bundle that
+/// was still open is deleted and written again, since the cursor of its last
+/// block is not known.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{
+///     sink::{sync, DbinSink},
+///     BlockRange, FirehoseEndpoint,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let endpoint = FirehoseEndpoint::from_env()?;
+/// let mut sink = DbinSink::new("archive")?.with_manifest("archive/manifest.json")?;
+/// let range = BlockRange::new(17_000_000, u64::MAX);
+///
+/// let report = sync(&endpoint, &mut sink, Some(range)).await?;
+/// println!("fetched {} blocks in {} spans", report.blocks, report.fetched.len());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn sync(
+    endpoint: &FirehoseEndpoint,
+    sink: &mut DbinSink,
+    range: Option<BlockRange>,
+) -> Result<SyncReport, FirehoseError> {
+    if sink.manifest().is_none() {
+        return Err(FirehoseError::Config(
+            "sync needs a DbinSink with a manifest".to_string(),
+        ));
+    }
+
+    let mut available = available_range(endpoint).await?;
+    if let Some(range) = range {
+        available = BlockRange::new(
+            available.start.max(range.start),
+            available.stop.min(range.stop),
+        );
+    }
+
+    let mut report = SyncReport {
+        available,
+        fetched: Vec::new(),
+        blocks: 0,
+    };
+    if available.is_empty() {
+        return Ok(report);
+    }
+
+    sink.discard_open_part()?;
+    let gaps = sink
+        .manifest()
+        .map(|manifest| manifest.gaps(available))
+        .unwrap_or_default();
+
+    for gap in gaps {
+        let request = Request {
+            start_block_num: gap.start as i64,
+            stop_block_num: gap.stop,
+            final_blocks_only: true,
+            ..Default::default()
+        };
+        report.blocks += export(endpoint, request, sink, &mut MemoryCursorStore::default()).await?;
+        report.fetched.push(gap);
+    }
+
+    Ok(report)
+}
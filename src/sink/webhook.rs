@@ -0,0 +1,383 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{hex_bytes::encode, Backoff, Response};
+
+use super::{Sink, SinkError};
+
+/// Header carrying the Unix time, in seconds, a [`WebhookSink`] request was
+/// signed at.
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "x-firehose-timestamp";
+
+/// Header carrying the `sha256=<hex>` HMAC signature of a [`WebhookSink`]
+/// request.
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "x-firehose-signature";
+
+/// Turns a block into the JSON a [`WebhookSink`] posts.
+pub type JsonEncoder = fn(&Response) -> Result<Value, SinkError>;
+
+/// A [`Sink`] posting blocks as JSON to an HTTP endpoint, so consumers that
+/// do not speak gRPC, such as low-code automation tools, receive chain data
+/// as webhooks.
+///
+/// Each block is posted on its own as a JSON object, or with
+/// [batching](WebhookSink::with_batch_size) as a JSON array of up to that many
+/// blocks. Blocks are serialized with serde by default, or by an
+/// [encoder](WebhookSink::with_encoder) producing decoded, chain-specific
+/// JSON. Buffered blocks are posted on every [flush](Sink::flush).
+///
+/// Connection failures, timeouts, `408`, `429` and `5xx` answers are retried
+/// with the sink's [`Backoff`]; other answers fail the sink. Retried requests
+/// are identical, so receivers should deduplicate by block.
+///
+/// With a [secret](WebhookSink::with_secret), every request is signed: the
+/// `x-firehose-signature` header holds `sha256=` followed by the hex HMAC-SHA256
+/// of `<timestamp>.<body>`, where `<timestamp>` is the `x-firehose-timestamp`
+/// header. Receivers recompute it to authenticate the sender, and reject old
+/// timestamps to prevent replays.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{
+///     sink::{export, WebhookSink},
+///     FileCursorStore, FirehoseEndpoint, Request,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let request = Request {
+///     start_block_num: -1,
+///     ..Default::default()
+/// };
+///
+/// let mut sink = WebhookSink::new("https://hooks.example.com/blocks")
+///     .with_secret("shared-secret")
+///     .with_batch_size(10);
+/// let mut cursors = FileCursorStore::new("webhook.cursor");
+/// export(&FirehoseEndpoint::from_env()?, request, &mut sink, &mut cursors).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct WebhookSink {
+    url: String,
+    http: reqwest::Client,
+    secret: Option<Vec<u8>>,
+    headers: Vec<(String, String)>,
+    batch_size: usize,
+    backoff: Backoff,
+    encoder: JsonEncoder,
+    buffer: Vec<Value>,
+}
+
+impl WebhookSink {
+    /// Post blocks to `url`.
+    ///
+    /// Failed posts are retried up to 5 times by default.
+    pub fn new(url: impl Into<String>) -> Self {
+        WebhookSink {
+            url: url.into(),
+            http: reqwest::Client::new(),
+            secret: None,
+            headers: Vec::new(),
+            batch_size: 1,
+            backoff: Backoff {
+                max_attempts: Some(5),
+                ..Backoff::default()
+            },
+            encoder: |response| Ok(serde_json::to_value(response)?),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Sign every request with `secret`.
+    pub fn with_secret(mut self, secret: impl AsRef<[u8]>) -> Self {
+        self.secret = Some(secret.as_ref().to_vec());
+        self
+    }
+
+    /// Send `name: value` with every request, for example an API key of the
+    /// receiving service.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Post arrays of up to `blocks` blocks instead of single blocks.
+    pub fn with_batch_size(mut self, blocks: usize) -> Self {
+        self.batch_size = blocks.max(1);
+        self
+    }
+
+    /// Set the delays between attempts to post a request, and how many
+    /// attempts are made.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fail requests that take longer than `timeout`, which are then retried.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+        self
+    }
+
+    /// Post the JSON `encoder` returns for each block.
+    pub fn with_encoder(mut self, encoder: JsonEncoder) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Post the buffered blocks, if any.
+    async fn post_buffer(&mut self) -> Result<(), SinkError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let body = if self.batch_size == 1 {
+            serde_json::to_vec(&self.buffer[0])?
+        } else {
+            serde_json::to_vec(&self.buffer)?
+        };
+        self.post(body).await?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    async fn post(&self, body: Vec<u8>) -> Result<(), SinkError> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.request(&body).send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    let error = format!("webhook answered {status}");
+                    if !(status.is_server_error()
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS)
+                    {
+                        return Err(error.into());
+                    }
+                    error
+                }
+                Err(e) => format!("webhook request failed: {e}"),
+            };
+
+            attempt += 1;
+            if !self.backoff.allows(attempt) {
+                return Err(error.into());
+            }
+            tokio::time::sleep(self.backoff.delay(attempt)).await;
+        }
+    }
+
+    fn request(&self, body: &[u8]) -> reqwest::RequestBuilder {
+        let mut request = self
+            .http
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_vec());
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        if let Some(secret) = &self.secret {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                .to_string();
+            let signature = signature(secret, &timestamp, body);
+            request = request
+                .header(WEBHOOK_TIMESTAMP_HEADER, timestamp)
+                .header(WEBHOOK_SIGNATURE_HEADER, signature);
+        }
+        request
+    }
+}
+
+impl Sink for WebhookSink {
+    async fn write(&mut self, response: &Response) -> Result<(), SinkError> {
+        self.buffer.push((self.encoder)(response)?);
+        if self.buffer.len() >= self.batch_size {
+            self.post_buffer().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), SinkError> {
+        self.post_buffer().await
+    }
+
+    /// After a write, the buffer is empty only if a batch was just posted.
+    fn at_boundary(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+impl fmt::Debug for WebhookSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookSink")
+            .field("url", &self.url)
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|(name, _)| (name, "<redacted>"))
+                    .collect::<Vec<_>>(),
+            )
+            .field("batch_size", &self.batch_size)
+            .field("backoff", &self.backoff)
+            .field("buffered", &self.buffer.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The `sha256=<hex>` signature header value of `body` sent at `timestamp`.
+fn signature(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+    let mut signed = Vec::with_capacity(timestamp.len() + 1 + body.len());
+    signed.extend_from_slice(timestamp.as_bytes());
+    signed.push(b'.');
+    signed.extend_from_slice(body);
+
+    format!("sha256={}", &encode(&hmac_sha256(secret, &signed))[2..])
+}
+
+/// HMAC-SHA256 (RFC 2104) of `message` with `key`.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: [u8; 32]) -> String {
+        encode(&bytes)[2..].to_string()
+    }
+
+    /// Test cases 1 to 4, 6 and 7 of RFC 4231. Case 5 checks truncated
+    /// output, which the sink does not use.
+    #[test]
+    fn hmac_sha256_matches_rfc_4231() {
+        let cases: [(&[u8], &[u8], &str); 6] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe",
+            ),
+            (
+                &[
+                    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+                    0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19,
+                ],
+                &[0xcd; 50],
+                "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b",
+            ),
+            // Keys longer than the 64-byte block are hashed first
+            (
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+
+        for (key, message, expected) in cases {
+            assert_eq!(
+                hex(hmac_sha256(key, message)),
+                expected,
+                "key of {} bytes",
+                key.len()
+            );
+        }
+    }
+
+    #[test]
+    fn hmac_sha256_uses_a_block_sized_key_as_is() {
+        assert_eq!(
+            hex(hmac_sha256(&[b'k'; 64], b"exactly one block")),
+            "521b48c3c879586f93e372c93d693664ea7a718f14e1a7eae1730b9ea2928b3b"
+        );
+    }
+
+    #[test]
+    fn signs_the_timestamp_and_body() {
+        assert_eq!(
+            signature(b"whsec_test", "1700000000", br#"[{"number":1}]"#),
+            "sha256=3eea5c21a0f1b5cac957efecf5ff21e785ddcd1590e1083f9cb2e3df1a7031e8"
+        );
+    }
+
+    #[test]
+    fn signed_requests_carry_the_timestamp_and_signature_headers() {
+        let body = br#"{"number":1}"#;
+        let request = WebhookSink::new("http://localhost/hook")
+            .with_secret("whsec_test")
+            .request(body)
+            .build()
+            .unwrap();
+
+        let headers = request.headers();
+        let timestamp = headers[WEBHOOK_TIMESTAMP_HEADER].to_str().unwrap();
+        assert!(timestamp.parse::<u64>().is_ok());
+        assert_eq!(
+            headers[WEBHOOK_SIGNATURE_HEADER],
+            signature(b"whsec_test", timestamp, body)
+        );
+
+        let unsigned = WebhookSink::new("http://localhost/hook")
+            .request(body)
+            .build()
+            .unwrap();
+        assert!(!unsigned.headers().contains_key(WEBHOOK_SIGNATURE_HEADER));
+        assert!(!unsigned.headers().contains_key(WEBHOOK_TIMESTAMP_HEADER));
+    }
+}
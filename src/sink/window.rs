@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::SystemTime;
+
+use crate::{
+    request_id, BlockRange, FetchClient, FirehoseChannel, FirehoseEndpoint, FirehoseError,
+    MemoryCursorStore, Request, Response, SingleBlockRequest,
+};
+
+use super::{available_range, export, Sink};
+
+/// What an [`export_window`] run resolved and wrote.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowExport {
+    /// Blocks produced within the window, empty if none was.
+    pub range: BlockRange,
+    /// Number of blocks written.
+    pub blocks: u64,
+}
+
+/// Number of the first block `endpoint` serves that was produced at or after
+/// `time`, or `None` if its last final block is older.
+///
+/// Binary-searches the [range](available_range) the endpoint serves by block
+/// timestamp, fetching about `log2` of its length blocks. Times before the
+/// first streamable block resolve to that block.
+pub async fn block_at_time(
+    endpoint: &FirehoseEndpoint,
+    time: SystemTime,
+) -> Result<Option<u64>, FirehoseError> {
+    let available = available_range(endpoint).await?;
+    let mut client = endpoint.fetch_client().await?;
+    first_at_or_after(&mut client, available, time).await
+}
+
+/// Export the final blocks produced from `from` (included) to `to`
+/// (excluded) into `sink`.
+///
+/// Both bounds are resolved to block numbers by binary search over block
+/// timestamps, see [`block_at_time`], and the blocks in between are streamed
+/// into `sink` with [`export`]. A window ending after the endpoint's last
+/// final block stops at that block.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// use firehose_rs::{
+///     sink::{export_window, NdjsonSink},
+///     FirehoseEndpoint,
+/// };
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let endpoint = FirehoseEndpoint::from_env()?;
+/// // 2024-01-01, all day (UTC).
+/// let from = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+/// let to = from + Duration::from_secs(24 * 3600);
+///
+/// let mut sink = NdjsonSink::create("2024-01-01.ndjson")?;
+/// let export = export_window(&endpoint, from, to, &mut sink).await?;
+/// println!("exported blocks {} ({} blocks)", export.range, export.blocks);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn export_window<S: Sink>(
+    endpoint: &FirehoseEndpoint,
+    from: SystemTime,
+    to: SystemTime,
+    sink: &mut S,
+) -> Result<WindowExport, FirehoseError> {
+    if to <= from {
+        return Err(FirehoseError::Config(
+            "time window ends before it starts".to_string(),
+        ));
+    }
+
+    let available = available_range(endpoint).await?;
+    let mut client = endpoint.fetch_client().await?;
+    let start = first_at_or_after(&mut client, available, from).await?;
+    let end = first_at_or_after(&mut client, available, to).await?;
+
+    let (start, stop) = match (start, end) {
+        (Some(start), Some(end)) if end > start => (start, end - 1),
+        (Some(start), None) => (start, available.stop),
+        (start, _) => {
+            // Empty window: `start` to one block before it.
+            let start = start.unwrap_or(available.stop.saturating_add(1));
+            return Ok(WindowExport {
+                range: BlockRange::new(start, start.saturating_sub(1)),
+                blocks: 0,
+            });
+        }
+    };
+
+    let request = Request {
+        start_block_num: start as i64,
+        stop_block_num: stop,
+        final_blocks_only: true,
+        ..Default::default()
+    };
+    let blocks = export(endpoint, request, sink, &mut MemoryCursorStore::default()).await?;
+
+    Ok(WindowExport {
+        range: BlockRange::new(start, stop),
+        blocks,
+    })
+}
+
+/// The first block of `range` produced at or after `time`.
+async fn first_at_or_after(
+    client: &mut FetchClient<FirehoseChannel>,
+    range: BlockRange,
+    time: SystemTime,
+) -> Result<Option<u64>, FirehoseError> {
+    if range.is_empty() || block_time(client, range.stop).await? < time {
+        return Ok(None);
+    }
+
+    // Blocks before `low` are older than `time`, `high` is not.
+    let (mut low, mut high) = (range.start, range.stop);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if block_time(client, mid).await? < time {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(Some(high))
+}
+
+async fn block_time(
+    client: &mut FetchClient<FirehoseChannel>,
+    block: u64,
+) -> Result<SystemTime, FirehoseError> {
+    let mut request = tonic::Request::new(SingleBlockRequest::new_by_block_number(block));
+    let id = request_id::ensure(request.metadata_mut());
+    let response = client
+        .block(request)
+        .await
+        .map_err(|status| request_id::tag(status, &id))?
+        .into_inner();
+
+    Response::from(response)
+        .timestamp()
+        .ok_or_else(|| FirehoseError::Decode(format!("block {block} carries no timestamp")))
+}
@@ -0,0 +1,598 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use prost::Message;
+use tokio::{sync::watch, time::Instant};
+
+use crate::Response;
+#[cfg(feature = "zstd")]
+use crate::Zstd;
+
+/// Size after which [`SpillWriter`] starts a new segment file, 64 MiB.
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How often appended blocks are synced to disk.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Name of the file holding the reader's committed position.
+const POSITION_FILE: &str = "position";
+
+/// Extension of segment files.
+const SEGMENT_EXTENSION: &str = "spill";
+
+/// Length and checksum prefixed to every record.
+const RECORD_HEADER: u64 = 8;
+
+/// Flag in the length of compressed records.
+const COMPRESSED: u32 = 1 << 31;
+
+/// Position of a record in the queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct Position {
+    segment: u64,
+    offset: u64,
+}
+
+/// Open the disk-backed queue in `dir`, creating it if needed.
+///
+/// The queue is a directory of append-only segment files of about
+/// `segment_size` bytes, holding length-prefixed, CRC-32 checksummed blocks.
+/// Blocks appended to the [`SpillWriter`] are read back in order from the
+/// [`SpillReader`], so a fast stream keeps going while a slow consumer falls
+/// behind on disk instead of in memory.
+///
+/// Reopening the queue after a crash drops a torn record at its end and
+/// resumes reading at the last [committed](SpillReader::commit) position, so
+/// blocks not committed yet are read again. [`SpillWriter::last_cursor`]
+/// tells where to resume the stream.
+///
+/// With the `zstd` feature, [`compressed_spill_queue`] also compresses the
+/// blocks.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{spill_queue, FirehoseEndpoint, Request, ResilientStream, DEFAULT_SEGMENT_SIZE};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     stop_block_num: 17_999_999,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+///
+/// let (writer, mut reader) = spill_queue("spill", DEFAULT_SEGMENT_SIZE)?;
+/// let stream = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?;
+/// let (task, _handle) = stream.spill(writer);
+///
+/// while let Some(response) = reader.next().await? {
+///     // Slow processing, such as writes to a database.
+///     println!("{}", response.cursor);
+///     reader.commit()?;
+/// }
+/// task.await??;
+/// # Ok(())
+/// # }
+/// ```
+pub fn spill_queue(
+    dir: impl Into<PathBuf>,
+    segment_size: u64,
+) -> io::Result<(SpillWriter, SpillReader)> {
+    open(dir.into(), segment_size, Codec::default())
+}
+
+/// Open the disk-backed queue in `dir` like [`spill_queue`], compressing
+/// appended blocks with `zstd`.
+///
+/// Blocks already in the queue are read whether they are compressed or not,
+/// but compressed ones need the same dictionary, if any.
+#[cfg(feature = "zstd")]
+pub fn compressed_spill_queue(
+    dir: impl Into<PathBuf>,
+    segment_size: u64,
+    zstd: Zstd,
+) -> io::Result<(SpillWriter, SpillReader)> {
+    open(dir.into(), segment_size, Codec { zstd: Some(zstd) })
+}
+
+fn open(dir: PathBuf, segment_size: u64, codec: Codec) -> io::Result<(SpillWriter, SpillReader)> {
+    fs::create_dir_all(&dir)?;
+
+    let segments = segments(&dir)?;
+    let mut read = read_position(&dir)?.unwrap_or(Position {
+        segment: segments.first().copied().unwrap_or(0),
+        offset: 0,
+    });
+
+    let last = segments.last().copied().unwrap_or(0).max(read.segment);
+    let (end, mut last_cursor) = recover(&segment_path(&dir, last), &codec)?;
+    // Earlier segments were synced before the next one was started.
+    for &segment in segments.iter().rev().filter(|&&segment| segment < last) {
+        if last_cursor.is_some() {
+            break;
+        }
+        last_cursor = recover(&segment_path(&dir, segment), &codec)?.1;
+    }
+
+    let written = Position {
+        segment: last,
+        offset: end,
+    };
+    // Records lost with the torn end of the queue cannot have been read.
+    read = read.min(written);
+
+    let file = OpenOptions::new()
+        .append(true)
+        .open(segment_path(&dir, last))?;
+    let (published, appended) = watch::channel(written);
+
+    let writer = SpillWriter {
+        dir: dir.clone(),
+        segment_size: segment_size.max(1),
+        position: written,
+        file: BufWriter::new(file),
+        last_cursor,
+        synced_at: Instant::now(),
+        published,
+        codec: codec.clone(),
+    };
+    let reader = SpillReader {
+        dir,
+        position: read,
+        file: None,
+        appended,
+        codec,
+    };
+    Ok((writer, reader))
+}
+
+/// Appending end of a [spill queue](spill_queue).
+///
+/// Dropping the writer ends the queue: the reader returns the remaining
+/// blocks and then `None`.
+#[derive(Debug)]
+pub struct SpillWriter {
+    dir: PathBuf,
+    segment_size: u64,
+    position: Position,
+    file: BufWriter<File>,
+    last_cursor: Option<String>,
+    synced_at: Instant,
+    published: watch::Sender<Position>,
+    codec: Codec,
+}
+
+impl SpillWriter {
+    /// Append `response` to the queue.
+    ///
+    /// The block is readable as soon as this returns, and synced to disk at
+    /// most a second later, or by [`SpillWriter::sync`].
+    pub fn append(&mut self, response: &Response) -> io::Result<()> {
+        if self.position.offset >= self.segment_size {
+            self.roll()?;
+        }
+
+        let (payload, flags) = self.codec.encode(response.encode_to_vec())?;
+        let length = u32::try_from(payload.len())
+            .ok()
+            .filter(|length| length & COMPRESSED == 0)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "block too large to spill")
+            })?;
+        self.file.write_all(&(length | flags).to_le_bytes())?;
+        self.file.write_all(&crc32(&payload).to_le_bytes())?;
+        self.file.write_all(&payload)?;
+        self.file.flush()?;
+
+        self.position.offset += RECORD_HEADER + payload.len() as u64;
+        self.last_cursor = Some(response.cursor.clone());
+        if self.synced_at.elapsed() >= SYNC_INTERVAL {
+            self.sync()?;
+        }
+        self.published.send_replace(self.position);
+        Ok(())
+    }
+
+    /// Sync every appended block to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.synced_at = Instant::now();
+        Ok(())
+    }
+
+    /// Cursor of the last block in the queue, to resume the stream after it.
+    pub fn last_cursor(&self) -> Option<&str> {
+        self.last_cursor.as_deref()
+    }
+
+    /// Finish the current segment and start the next one.
+    fn roll(&mut self) -> io::Result<()> {
+        self.sync()?;
+        let segment = self.position.segment + 1;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&self.dir, segment))?;
+        sync_dir(&self.dir)?;
+        self.file = BufWriter::new(file);
+        self.position = Position { segment, offset: 0 };
+        Ok(())
+    }
+}
+
+/// Reading end of a [spill queue](spill_queue).
+#[derive(Debug)]
+pub struct SpillReader {
+    dir: PathBuf,
+    position: Position,
+    file: Option<BufReader<File>>,
+    appended: watch::Receiver<Position>,
+    codec: Codec,
+}
+
+impl SpillReader {
+    /// Read the next block, waiting for the writer to append it.
+    ///
+    /// Returns `Ok(None)` once the [`SpillWriter`] is dropped and every block
+    /// has been read, and an [`io::ErrorKind::InvalidData`] error for a
+    /// record that fails its checksum.
+    pub async fn next(&mut self) -> io::Result<Option<Response>> {
+        loop {
+            let written = *self.appended.borrow_and_update();
+            if self.position < written {
+                if let Some(response) = self.read()? {
+                    return Ok(Some(response));
+                }
+                if self.position.segment >= written.segment {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "spill segment ends before its appended blocks",
+                    ));
+                }
+                // The end of a finished segment.
+                self.position = Position {
+                    segment: self.position.segment + 1,
+                    offset: 0,
+                };
+                self.file = None;
+                continue;
+            }
+
+            if self.appended.changed().await.is_err() && *self.appended.borrow() == written {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Mark every block read so far as consumed, so a reopened queue resumes
+    /// after them, and delete the segments they filled.
+    pub fn commit(&mut self) -> io::Result<()> {
+        let path = self.dir.join(POSITION_FILE);
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        writeln!(file, "{} {}", self.position.segment, self.position.offset)?;
+        // Neither a torn position nor, after the rename, a stale one may
+        // survive a crash.
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp, &path)?;
+        sync_dir(&self.dir)?;
+
+        for segment in segments(&self.dir)? {
+            if segment >= self.position.segment {
+                break;
+            }
+            match fs::remove_file(segment_path(&self.dir, segment)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the record at the current position, or `None` at the end of its
+    /// segment.
+    fn read(&mut self) -> io::Result<Option<Response>> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            file => {
+                let mut opened = File::open(segment_path(&self.dir, self.position.segment))?;
+                opened.seek(SeekFrom::Start(self.position.offset))?;
+                file.insert(BufReader::new(opened))
+            }
+        };
+
+        let Some((payload, length)) = read_record(file)? else {
+            return Ok(None);
+        };
+        let response = self.codec.decode(payload)?;
+        self.position.offset += length;
+        Ok(Some(response))
+    }
+}
+
+/// Compression of spilled blocks.
+#[derive(Clone, Debug, Default)]
+struct Codec {
+    #[cfg(feature = "zstd")]
+    zstd: Option<Zstd>,
+}
+
+/// A record's payload, as stored.
+struct Payload {
+    bytes: Vec<u8>,
+    compressed: bool,
+}
+
+impl Codec {
+    /// The payload to store for an encoded block, and the flags of its
+    /// length.
+    fn encode(&self, block: Vec<u8>) -> io::Result<(Vec<u8>, u32)> {
+        #[cfg(feature = "zstd")]
+        if let Some(zstd) = &self.zstd {
+            return Ok((zstd.compress(&block)?, COMPRESSED));
+        }
+        Ok((block, 0))
+    }
+
+    fn decode(&self, payload: Payload) -> io::Result<Response> {
+        let bytes = if payload.compressed {
+            self.decompress(&payload.bytes)?
+        } else {
+            payload.bytes
+        };
+        Response::decode(bytes.as_slice())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    #[cfg(feature = "zstd")]
+    fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        // Without settings, blocks compressed without a dictionary still
+        // decompress.
+        self.zstd.clone().unwrap_or_default().decompress(bytes)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn decompress(&self, _: &[u8]) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "spilled block is compressed, which needs the `zstd` feature",
+        ))
+    }
+}
+
+/// Read one record, returning its payload and its length including the
+/// header, or `None` at the end of the file.
+fn read_record(reader: &mut impl Read) -> io::Result<Option<(Payload, u64)>> {
+    let mut header = [0; RECORD_HEADER as usize];
+    match reader.read_exact(&mut header) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let compressed = length & COMPRESSED != 0;
+    let length = length & !COMPRESSED;
+
+    let mut bytes = vec![0; length as usize];
+    reader.read_exact(&mut bytes)?;
+    if crc32(&bytes) != checksum {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "spilled block fails its checksum",
+        ));
+    }
+    let payload = Payload { bytes, compressed };
+    Ok(Some((payload, RECORD_HEADER + u64::from(length))))
+}
+
+/// Find the end of the valid records of the segment at `path`, creating it if
+/// needed, and cut off anything after it. Returns that end and the cursor of
+/// the last valid block.
+fn recover(path: &Path, codec: &Codec) -> io::Result<(u64, Option<String>)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)?;
+
+    let mut reader = BufReader::new(&file);
+    let mut end = 0;
+    let mut last = None;
+    // A torn or corrupt record ends the valid part.
+    while let Ok(Some((payload, length))) = read_record(&mut reader) {
+        last = Some(payload);
+        end += length;
+    }
+
+    if file.metadata()?.len() > end {
+        file.set_len(end)?;
+        file.sync_data()?;
+    }
+    let last_cursor = last
+        .map(|payload| codec.decode(payload))
+        .transpose()?
+        .map(|response| response.cursor);
+    Ok((end, last_cursor))
+}
+
+/// Indices of the segments in `dir`, in order.
+fn segments(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+            if let Some(segment) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                segments.push(segment);
+            }
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{segment:020}.{SEGMENT_EXTENSION}"))
+}
+
+/// The committed reader position in `dir`, if any.
+fn read_position(dir: &Path) -> io::Result<Option<Position>> {
+    let contents = match fs::read_to_string(dir.join(POSITION_FILE)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut fields = contents.split_whitespace().map(str::parse);
+    match (fields.next(), fields.next()) {
+        (Some(Ok(segment)), Some(Ok(offset))) => Ok(Some(Position { segment, offset })),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed spill queue position",
+        )),
+    }
+}
+
+/// Sync the entries of `dir`, so files created or renamed in it survive a
+/// crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories cannot be opened for syncing on other platforms.
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// CRC-32 (IEEE) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockMetadata;
+
+    /// Block `num` with a cursor naming it.
+    fn response(num: u64) -> Response {
+        Response {
+            cursor: format!("cursor-{num}"),
+            metadata: Some(BlockMetadata {
+                num,
+                id: format!("{num:064x}"),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// An empty directory for the queue of one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("firehose-spill-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn read_all(reader: &mut SpillReader) -> Vec<Response> {
+        let mut responses = Vec::new();
+        while let Some(response) = reader.next().await.unwrap() {
+            responses.push(response);
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn reads_back_appended_blocks_in_order() {
+        let dir = scratch_dir("round-trip");
+        // Every block after the first starts a new segment.
+        let (mut writer, mut reader) = spill_queue(&dir, 1).unwrap();
+        let blocks: Vec<Response> = (1..=5).map(response).collect();
+        for block in &blocks {
+            writer.append(block).unwrap();
+        }
+        assert_eq!(writer.last_cursor(), Some("cursor-5"));
+        drop(writer);
+
+        assert_eq!(read_all(&mut reader).await, blocks);
+        assert_eq!(segments(&dir).unwrap().len(), 5);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_after_the_committed_position_when_reopened() {
+        let dir = scratch_dir("reopen");
+        let (mut writer, mut reader) = spill_queue(&dir, 1).unwrap();
+        for num in 1..=5 {
+            writer.append(&response(num)).unwrap();
+        }
+        writer.sync().unwrap();
+        for num in 1..=2 {
+            assert_eq!(reader.next().await.unwrap(), Some(response(num)));
+        }
+        reader.commit().unwrap();
+        // Read but not committed, so read again after reopening.
+        assert_eq!(reader.next().await.unwrap(), Some(response(3)));
+        drop((writer, reader));
+
+        // The segments of committed blocks are deleted.
+        assert_eq!(segments(&dir).unwrap().len(), 4);
+
+        let (writer, mut reader) = spill_queue(&dir, 1).unwrap();
+        assert_eq!(writer.last_cursor(), Some("cursor-5"));
+        drop(writer);
+        assert_eq!(
+            read_all(&mut reader).await,
+            (3..=5).map(response).collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_a_corrupt_record() {
+        let dir = scratch_dir("corrupt");
+        let (mut writer, mut reader) = spill_queue(&dir, DEFAULT_SEGMENT_SIZE).unwrap();
+        writer.append(&response(1)).unwrap();
+        writer.append(&response(2)).unwrap();
+        writer.sync().unwrap();
+        drop(writer);
+
+        // Flip a bit in the payload of the first record.
+        let path = segment_path(&dir, 0);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[RECORD_HEADER as usize + 2] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let error = reader.next().await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+
+use crate::{BearerToken, FirehoseError};
+
+/// StreamingFast's token issuing endpoint.
+pub const STREAMINGFAST_AUTH_URL: &str = "https://auth.streamingfast.io/v1/auth/issue";
+
+/// Refresh when this fraction of a token's lifetime has elapsed.
+const REFRESH_AT: f64 = 0.9;
+
+/// Lifetime assumed for tokens issued without an expiry.
+const DEFAULT_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Delay before retrying a failed refresh.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// A short-lived JWT issued for an API key.
+#[derive(Clone)]
+pub struct IssuedToken {
+    /// The JWT, to send as `authorization: Bearer`.
+    pub token: String,
+    /// When the token expires, if the auth service said so.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl fmt::Debug for IssuedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IssuedToken")
+            .field("token", &"<redacted>")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+/// Exchanges a long-lived StreamingFast API key for short-lived JWTs.
+///
+/// StreamingFast endpoints expect a JWT rather than the API key itself.
+/// [`StreamingFastAuth::spawn_refresh`] issues one, stores it in a
+/// [`BearerToken`] for [`FirehoseEndpoint::with_bearer_token`], and keeps
+/// replacing it before it expires.
+///
+/// [`FirehoseEndpoint::with_bearer_token`]: crate::FirehoseEndpoint::with_bearer_token
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{FirehoseEndpoint, StreamingFastAuth};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let (token, _refresh) = StreamingFastAuth::new("my-api-key").spawn_refresh().await?;
+///
+/// let endpoint = FirehoseEndpoint::new("mainnet.eth.streamingfast.io:443")
+///     .with_bearer_token(token);
+/// let mut client = endpoint.stream_client().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct StreamingFastAuth {
+    api_key: String,
+    url: String,
+    http: reqwest::Client,
+}
+
+impl StreamingFastAuth {
+    /// Issue tokens for `api_key` from [`STREAMINGFAST_AUTH_URL`].
+    pub fn new(api_key: impl Into<String>) -> Self {
+        StreamingFastAuth {
+            api_key: api_key.into(),
+            url: STREAMINGFAST_AUTH_URL.to_string(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Issue tokens from another auth service speaking the same protocol.
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Exchange the API key for a token.
+    pub async fn issue(&self) -> Result<IssuedToken, FirehoseError> {
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&json!({ "api_key": self.api_key }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| FirehoseError::Auth(format!("token request failed: {e}")))?;
+
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| FirehoseError::Auth(format!("invalid token response: {e}")))?;
+
+        let token = body["token"]
+            .as_str()
+            .ok_or_else(|| FirehoseError::Auth("token response has no `token`".to_string()))?
+            .to_string();
+        let expires_at = body["expires_at"]
+            .as_u64()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+        Ok(IssuedToken { token, expires_at })
+    }
+
+    /// Issue a first token, then keep refreshing it in the background.
+    ///
+    /// Returns once the first token is stored, so clients built with the
+    /// returned [`BearerToken`] are authenticated from their first call.
+    /// Tokens are refreshed after 90% of their lifetime; failed refreshes are
+    /// retried every 30 seconds while the current token stays in place.
+    /// Abort the returned task to stop refreshing.
+    pub async fn spawn_refresh(self) -> Result<(BearerToken, JoinHandle<()>), FirehoseError> {
+        let token = BearerToken::new();
+        let issued = self.issue().await?;
+        token.set(&issued.token)?;
+
+        let refreshed = token.clone();
+        let handle = tokio::spawn(async move {
+            let mut wait = refresh_delay(&issued);
+            loop {
+                tokio::time::sleep(wait).await;
+                wait = match self.issue().await {
+                    Ok(issued) if refreshed.set(&issued.token).is_ok() => refresh_delay(&issued),
+                    _ => RETRY_DELAY,
+                };
+            }
+        });
+
+        Ok((token, handle))
+    }
+}
+
+impl fmt::Debug for StreamingFastAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingFastAuth")
+            .field("api_key", &"<redacted>")
+            .field("url", &self.url)
+            .finish()
+    }
+}
+
+fn refresh_delay(issued: &IssuedToken) -> Duration {
+    let lifetime = issued
+        .expires_at
+        .and_then(|expires_at| expires_at.duration_since(SystemTime::now()).ok())
+        .unwrap_or(DEFAULT_LIFETIME);
+    lifetime.mul_f64(REFRESH_AT)
+}
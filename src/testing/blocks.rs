@@ -0,0 +1,686 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prost::Message;
+use prost_wkt_types::{Any, Timestamp};
+use sha2::{Digest, Sha256};
+
+use crate::{hex_bytes, BlockMetadata, ForkStep, Response};
+
+/// Type URL of Ethereum blocks.
+const ETHEREUM_BLOCK: &str = "type.googleapis.com/sf.ethereum.type.v2.Block";
+
+/// Type URL of Solana blocks.
+const SOLANA_BLOCK: &str = "type.googleapis.com/sf.solana.type.v1.Block";
+
+/// Timestamp of block 0 of synthetic chains, in seconds since the epoch.
+const GENESIS_TIME: u64 = 1_700_000_000;
+
+/// Builder of a synthetic `sf.ethereum.type.v2.Block`, from
+/// [`ethereum_block`].
+///
+/// Hashes are derived from the block number, so blocks built with
+/// consecutive numbers form a chain: each one's parent hash is the hash of
+/// the block before it. Timestamps are 12 seconds apart by default.
+#[derive(Clone, Debug)]
+pub struct EthereumBlockBuilder {
+    number: u64,
+    txs: u32,
+    hash: Option<[u8; 32]>,
+    parent_hash: Option<[u8; 32]>,
+    timestamp: Option<SystemTime>,
+    gas_used_per_tx: u64,
+    envelope: Envelope,
+}
+
+/// A synthetic Ethereum block, encoded as the `sf.ethereum.type.v2.Block`
+/// message Firehose serves, for tests of [`FromResponse`](crate::FromResponse)
+/// implementations and pipelines without recorded fixtures.
+///
+/// Block header, transaction hashes, senders, receivers and gas are filled
+/// in, with the upstream field numbers, so decoders generated from the
+/// Ethereum protobuf definitions read them back. The response's metadata
+/// matches the block.
+///
+/// # Example
+///
+/// ```rust
+/// use firehose_rs::testing::ethereum_block;
+///
+/// let response = ethereum_block().number(17_000_000).txs(3).build();
+///
+/// assert_eq!(response.block_number(), Some(17_000_000));
+/// assert_eq!(
+///     response.block.unwrap().type_url,
+///     "type.googleapis.com/sf.ethereum.type.v2.Block"
+/// );
+/// ```
+pub fn ethereum_block() -> EthereumBlockBuilder {
+    EthereumBlockBuilder {
+        number: 0,
+        txs: 0,
+        hash: None,
+        parent_hash: None,
+        timestamp: None,
+        gas_used_per_tx: 21_000,
+        envelope: Envelope::default(),
+    }
+}
+
+impl EthereumBlockBuilder {
+    /// Block number, `0` by default.
+    pub fn number(mut self, number: u64) -> Self {
+        self.number = number;
+        self
+    }
+
+    /// Number of transactions, `0` by default.
+    pub fn txs(mut self, txs: u32) -> Self {
+        self.txs = txs;
+        self
+    }
+
+    /// Block hash, instead of one derived from the number.
+    pub fn hash(mut self, hash: [u8; 32]) -> Self {
+        self.hash = Some(hash);
+        self
+    }
+
+    /// Parent hash, instead of the derived hash of the previous number, for
+    /// example to build forks.
+    pub fn parent_hash(mut self, hash: [u8; 32]) -> Self {
+        self.parent_hash = Some(hash);
+        self
+    }
+
+    /// Block time, instead of 12 seconds per block from a fixed genesis.
+    pub fn timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Gas used by each transaction, `21000` by default.
+    pub fn gas_used_per_tx(mut self, gas: u64) -> Self {
+        self.gas_used_per_tx = gas;
+        self
+    }
+
+    /// Fork step of the response, `STEP_NEW` by default.
+    pub fn step(mut self, step: ForkStep) -> Self {
+        self.envelope.step = step;
+        self
+    }
+
+    /// Last irreversible block of the response, the block itself by
+    /// default.
+    pub fn lib(mut self, lib: u64) -> Self {
+        self.envelope.lib = Some(lib);
+        self
+    }
+
+    /// Cursor of the response, instead of one derived from the block.
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.envelope.cursor = Some(cursor.into());
+        self
+    }
+
+    /// The encoded block.
+    pub fn payload(&self) -> Any {
+        Any {
+            type_url: ETHEREUM_BLOCK.to_string(),
+            value: self.block().encode_to_vec(),
+        }
+    }
+
+    /// A response carrying the block.
+    pub fn build(self) -> Response {
+        let payload = self.payload();
+        let identity = Identity {
+            number: self.number,
+            id: hex_id(&self.block_hash()),
+            parent_id: hex_id(&self.parent_block_hash()),
+            time: self.time(),
+        };
+        self.envelope.response(payload, identity)
+    }
+
+    fn block_hash(&self) -> [u8; 32] {
+        self.hash
+            .unwrap_or_else(|| derived_hash("ethereum", self.number))
+    }
+
+    fn parent_block_hash(&self) -> [u8; 32] {
+        self.parent_hash.unwrap_or_else(|| {
+            self.number
+                .checked_sub(1)
+                .map_or([0; 32], |parent| derived_hash("ethereum", parent))
+        })
+    }
+
+    fn time(&self) -> SystemTime {
+        self.timestamp
+            .unwrap_or_else(|| UNIX_EPOCH + Duration::from_secs(GENESIS_TIME + self.number * 12))
+    }
+
+    fn block(&self) -> ethereum::Block {
+        let hash = self.block_hash();
+        let transaction_traces: Vec<_> = (0..self.txs)
+            .map(|index| {
+                let seed = format!("{}:{index}", self.number);
+                ethereum::TransactionTrace {
+                    to: derived_hash("ethereum to", seed.as_bytes())[..20].to_vec(),
+                    nonce: u64::from(index),
+                    gas_limit: self.gas_used_per_tx,
+                    gas_used: self.gas_used_per_tx,
+                    index,
+                    hash: derived_hash("ethereum tx", seed.as_bytes()).to_vec(),
+                    from: derived_hash("ethereum from", seed.as_bytes())[..20].to_vec(),
+                    status: ethereum::STATUS_SUCCEEDED,
+                }
+            })
+            .collect();
+
+        ethereum::Block {
+            ver: 3,
+            hash: hash.to_vec(),
+            number: self.number,
+            header: Some(ethereum::BlockHeader {
+                parent_hash: self.parent_block_hash().to_vec(),
+                number: self.number,
+                gas_limit: 30_000_000,
+                gas_used: self.gas_used_per_tx * u64::from(self.txs),
+                timestamp: Some(timestamp(self.time())),
+                hash: hash.to_vec(),
+            }),
+            transaction_traces,
+        }
+    }
+}
+
+/// Builder of a synthetic `sf.solana.type.v1.Block`, from [`solana_block`].
+///
+/// Block hashes are derived from the slot, so blocks built with consecutive
+/// slots form a chain. Block times are 400 milliseconds apart by default,
+/// rounded down to the second as on chain.
+#[derive(Clone, Debug)]
+pub struct SolanaBlockBuilder {
+    slot: u64,
+    txs: u32,
+    timestamp: Option<SystemTime>,
+    fee_per_tx: u64,
+    envelope: Envelope,
+}
+
+/// A synthetic Solana block, encoded as the `sf.solana.type.v1.Block`
+/// message Firehose serves.
+///
+/// Slot, parent slot, block hashes, block time and height, and transaction
+/// signatures and fees are filled in, with the upstream field numbers.
+///
+/// # Example
+///
+/// ```rust
+/// use firehose_rs::testing::solana_block;
+///
+/// let response = solana_block().slot(250_000_000).txs(10).build();
+///
+/// assert_eq!(response.block_number(), Some(250_000_000));
+/// ```
+pub fn solana_block() -> SolanaBlockBuilder {
+    SolanaBlockBuilder {
+        slot: 0,
+        txs: 0,
+        timestamp: None,
+        fee_per_tx: 5_000,
+        envelope: Envelope::default(),
+    }
+}
+
+impl SolanaBlockBuilder {
+    /// Slot, `0` by default.
+    pub fn slot(mut self, slot: u64) -> Self {
+        self.slot = slot;
+        self
+    }
+
+    /// Alias of [`slot`](SolanaBlockBuilder::slot), as Firehose numbers
+    /// Solana blocks by slot.
+    pub fn number(self, number: u64) -> Self {
+        self.slot(number)
+    }
+
+    /// Number of transactions, `0` by default.
+    pub fn txs(mut self, txs: u32) -> Self {
+        self.txs = txs;
+        self
+    }
+
+    /// Block time, instead of 400 milliseconds per slot from a fixed genesis.
+    pub fn timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Fee paid by each transaction, in lamports, `5000` by default.
+    pub fn fee_per_tx(mut self, fee: u64) -> Self {
+        self.fee_per_tx = fee;
+        self
+    }
+
+    /// Fork step of the response, `STEP_NEW` by default.
+    pub fn step(mut self, step: ForkStep) -> Self {
+        self.envelope.step = step;
+        self
+    }
+
+    /// Last irreversible slot of the response, the block itself by default.
+    pub fn lib(mut self, lib: u64) -> Self {
+        self.envelope.lib = Some(lib);
+        self
+    }
+
+    /// Cursor of the response, instead of one derived from the block.
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.envelope.cursor = Some(cursor.into());
+        self
+    }
+
+    /// The encoded block.
+    pub fn payload(&self) -> Any {
+        Any {
+            type_url: SOLANA_BLOCK.to_string(),
+            value: self.block().encode_to_vec(),
+        }
+    }
+
+    /// A response carrying the block.
+    pub fn build(self) -> Response {
+        let payload = self.payload();
+        let block = self.block();
+        let identity = Identity {
+            number: self.slot,
+            id: block.blockhash,
+            parent_id: block.previous_blockhash,
+            time: self.time(),
+        };
+        self.envelope.response(payload, identity)
+    }
+
+    fn time(&self) -> SystemTime {
+        self.timestamp.unwrap_or_else(|| {
+            UNIX_EPOCH + Duration::from_secs(GENESIS_TIME) + Duration::from_millis(self.slot * 400)
+        })
+    }
+
+    fn block(&self) -> solana::Block {
+        let blockhash = |slot: u64| base58(&derived_hash("solana", slot));
+        let transactions = (0..self.txs)
+            .map(|index| {
+                let seed = format!("{}:{index}", self.slot);
+                let mut signature = derived_hash("solana signature", seed.as_bytes()).to_vec();
+                signature.extend_from_slice(&derived_hash("solana signature 2", seed.as_bytes()));
+                solana::ConfirmedTransaction {
+                    transaction: Some(solana::Transaction {
+                        signatures: vec![signature],
+                    }),
+                    meta: Some(solana::TransactionStatusMeta {
+                        fee: self.fee_per_tx,
+                    }),
+                }
+            })
+            .collect();
+        let block_time = self
+            .time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as i64);
+
+        solana::Block {
+            previous_blockhash: self.slot.checked_sub(1).map_or_else(String::new, blockhash),
+            blockhash: blockhash(self.slot),
+            parent_slot: self.slot.saturating_sub(1),
+            transactions,
+            block_time: Some(solana::UnixTimestamp {
+                timestamp: block_time,
+            }),
+            block_height: Some(solana::BlockHeight {
+                block_height: self.slot,
+            }),
+            slot: self.slot,
+        }
+    }
+}
+
+/// Builder of a synthetic block of any chain, from [`block`], for chains
+/// without a dedicated builder.
+///
+/// The payload is a message or bytes of the test's choosing; the metadata
+/// is derived from the number like for the other builders.
+#[derive(Clone, Debug)]
+pub struct BlockBuilder {
+    type_url: String,
+    value: Vec<u8>,
+    number: u64,
+    timestamp: Option<SystemTime>,
+    envelope: Envelope,
+}
+
+/// A synthetic block of the message type named by `type_url`, with an
+/// empty payload until [`message`](BlockBuilder::message) or
+/// [`value`](BlockBuilder::value) sets one.
+///
+/// # Example
+///
+/// ```rust
+/// use firehose_rs::testing::block;
+///
+/// let response = block("type.googleapis.com/sf.near.type.v1.Block")
+///     .number(100_000_000)
+///     .build();
+///
+/// assert_eq!(response.block_number(), Some(100_000_000));
+/// ```
+pub fn block(type_url: impl Into<String>) -> BlockBuilder {
+    BlockBuilder {
+        type_url: type_url.into(),
+        value: Vec::new(),
+        number: 0,
+        timestamp: None,
+        envelope: Envelope::default(),
+    }
+}
+
+impl BlockBuilder {
+    /// Block number, `0` by default.
+    pub fn number(mut self, number: u64) -> Self {
+        self.number = number;
+        self
+    }
+
+    /// Encode `message` as the payload.
+    pub fn message(mut self, message: &impl Message) -> Self {
+        self.value = message.encode_to_vec();
+        self
+    }
+
+    /// Already encoded payload.
+    pub fn value(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.value = value.into();
+        self
+    }
+
+    /// Block time, instead of one second per block from a fixed genesis.
+    pub fn timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Fork step of the response, `STEP_NEW` by default.
+    pub fn step(mut self, step: ForkStep) -> Self {
+        self.envelope.step = step;
+        self
+    }
+
+    /// Last irreversible block of the response, the block itself by
+    /// default.
+    pub fn lib(mut self, lib: u64) -> Self {
+        self.envelope.lib = Some(lib);
+        self
+    }
+
+    /// Cursor of the response, instead of one derived from the block.
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.envelope.cursor = Some(cursor.into());
+        self
+    }
+
+    /// The payload.
+    pub fn payload(&self) -> Any {
+        Any {
+            type_url: self.type_url.clone(),
+            value: self.value.clone(),
+        }
+    }
+
+    /// A response carrying the block.
+    pub fn build(self) -> Response {
+        let id = |number: u64| hex_id(&derived_hash(&self.type_url, number));
+        let time = self
+            .timestamp
+            .unwrap_or_else(|| UNIX_EPOCH + Duration::from_secs(GENESIS_TIME + self.number));
+        let identity = Identity {
+            number: self.number,
+            id: id(self.number),
+            parent_id: self.number.checked_sub(1).map_or_else(String::new, id),
+            time,
+        };
+        let payload = self.payload();
+        self.envelope.response(payload, identity)
+    }
+}
+
+/// Response fields shared by the builders.
+#[derive(Clone, Debug, Default)]
+struct Envelope {
+    step: ForkStep,
+    lib: Option<u64>,
+    cursor: Option<String>,
+}
+
+/// What the metadata of a synthetic block says about it.
+struct Identity {
+    number: u64,
+    id: String,
+    parent_id: String,
+    time: SystemTime,
+}
+
+impl Envelope {
+    fn response(self, block: Any, identity: Identity) -> Response {
+        let step = match self.step {
+            ForkStep::StepUnset => ForkStep::StepNew,
+            step => step,
+        };
+        let cursor = self
+            .cursor
+            .unwrap_or_else(|| format!("synthetic:{}:{}", identity.number, identity.id));
+        Response {
+            block: Some(block),
+            step: step.into(),
+            cursor,
+            metadata: Some(BlockMetadata {
+                num: identity.number,
+                id: identity.id,
+                parent_num: identity.number.saturating_sub(1),
+                parent_id: identity.parent_id,
+                lib_num: self.lib.unwrap_or(identity.number),
+                time: Some(timestamp(identity.time)),
+            }),
+        }
+    }
+}
+
+/// Deterministic 32-byte hash of `seed` in the namespace `kind`.
+fn derived_hash(kind: &str, seed: impl Seed) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"firehose-rs synthetic ");
+    hasher.update(kind.as_bytes());
+    hasher.update(b" ");
+    seed.update(&mut hasher);
+    hasher.finalize().into()
+}
+
+/// What a [`derived_hash`] is derived from.
+trait Seed {
+    fn update(&self, hasher: &mut Sha256);
+}
+
+impl Seed for u64 {
+    fn update(&self, hasher: &mut Sha256) {
+        hasher.update(self.to_be_bytes());
+    }
+}
+
+impl Seed for &[u8] {
+    fn update(&self, hasher: &mut Sha256) {
+        hasher.update(self);
+    }
+}
+
+/// Block ID as Firehose sends it for EVM chains: hex without `0x`.
+fn hex_id(hash: &[u8]) -> String {
+    hex_bytes::encode(hash)[2..].to_string()
+}
+
+fn timestamp(time: SystemTime) -> Timestamp {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Timestamp {
+        seconds: since_epoch.as_secs() as i64,
+        nanos: since_epoch.subsec_nanos() as i32,
+    }
+}
+
+/// `bytes` in the Bitcoin base58 alphabet, as Solana encodes hashes.
+fn base58(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+    // Base 58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::with_capacity(bytes.len() * 138 / 100 + 1);
+    for &byte in bytes {
+        let mut carry = u32::from(byte);
+        for digit in &mut digits {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    let mut out = "1".repeat(zeros);
+    out.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|&digit| ALPHABET[digit as usize] as char),
+    );
+    out
+}
+
+/// The fields of `sf.ethereum.type.v2` the Ethereum builder fills in, with
+/// their upstream tags.
+mod ethereum {
+    use prost_wkt_types::Timestamp;
+
+    /// `TransactionTraceStatus.SUCCEEDED`.
+    pub(super) const STATUS_SUCCEEDED: i32 = 1;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct Block {
+        #[prost(int32, tag = "1")]
+        pub ver: i32,
+        #[prost(bytes = "vec", tag = "2")]
+        pub hash: Vec<u8>,
+        #[prost(uint64, tag = "3")]
+        pub number: u64,
+        #[prost(message, optional, tag = "5")]
+        pub header: Option<BlockHeader>,
+        #[prost(message, repeated, tag = "10")]
+        pub transaction_traces: Vec<TransactionTrace>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct BlockHeader {
+        #[prost(bytes = "vec", tag = "1")]
+        pub parent_hash: Vec<u8>,
+        #[prost(uint64, tag = "9")]
+        pub number: u64,
+        #[prost(uint64, tag = "10")]
+        pub gas_limit: u64,
+        #[prost(uint64, tag = "11")]
+        pub gas_used: u64,
+        #[prost(message, optional, tag = "12")]
+        pub timestamp: Option<Timestamp>,
+        #[prost(bytes = "vec", tag = "16")]
+        pub hash: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct TransactionTrace {
+        #[prost(bytes = "vec", tag = "1")]
+        pub to: Vec<u8>,
+        #[prost(uint64, tag = "2")]
+        pub nonce: u64,
+        #[prost(uint64, tag = "4")]
+        pub gas_limit: u64,
+        #[prost(uint64, tag = "10")]
+        pub gas_used: u64,
+        #[prost(uint32, tag = "20")]
+        pub index: u32,
+        #[prost(bytes = "vec", tag = "21")]
+        pub hash: Vec<u8>,
+        #[prost(bytes = "vec", tag = "22")]
+        pub from: Vec<u8>,
+        #[prost(int32, tag = "30")]
+        pub status: i32,
+    }
+}
+
+/// The fields of `sf.solana.type.v1` the Solana builder fills in, with their
+/// upstream tags.
+mod solana {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct Block {
+        #[prost(string, tag = "1")]
+        pub previous_blockhash: String,
+        #[prost(string, tag = "2")]
+        pub blockhash: String,
+        #[prost(uint64, tag = "3")]
+        pub parent_slot: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub transactions: Vec<ConfirmedTransaction>,
+        #[prost(message, optional, tag = "6")]
+        pub block_time: Option<UnixTimestamp>,
+        #[prost(message, optional, tag = "7")]
+        pub block_height: Option<BlockHeight>,
+        #[prost(uint64, tag = "20")]
+        pub slot: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct ConfirmedTransaction {
+        #[prost(message, optional, tag = "1")]
+        pub transaction: Option<Transaction>,
+        #[prost(message, optional, tag = "2")]
+        pub meta: Option<TransactionStatusMeta>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct Transaction {
+        #[prost(bytes = "vec", repeated, tag = "1")]
+        pub signatures: Vec<Vec<u8>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct TransactionStatusMeta {
+        #[prost(uint64, tag = "2")]
+        pub fee: u64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct UnixTimestamp {
+        #[prost(int64, tag = "1")]
+        pub timestamp: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct BlockHeight {
+        #[prost(uint64, tag = "1")]
+        pub block_height: u64,
+    }
+}
@@ -0,0 +1,289 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tonic::Status;
+
+use crate::{ForkStep, Response};
+
+/// Field number of the unknown field [`Fault::Oversized`] pads payloads
+/// with, far above the fields of chain blocks so decoders skip it.
+const PADDING_FIELD: u64 = 536_870_911;
+
+/// A fault a [`Replay`](super::Replay) server injects, with
+/// [`with_fault`](super::Replay::with_fault).
+///
+/// Block faults apply to the first response for the block in each call,
+/// orphans of [injected reorgs](super::Replay::with_reorg) included, and
+/// only to calls streaming past it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// End the stream with `UNAVAILABLE` right after block `after`, as a
+    /// dropped connection or restarted server does.
+    Disconnect {
+        /// Last block sent.
+        after: u64,
+    },
+    /// Right after block `block`, send it and the `depth - 1` responses
+    /// before it again, as a server resuming from an older cursor does.
+    Duplicate {
+        /// Block after which responses are repeated.
+        block: u64,
+        /// Responses repeated, block `block` included.
+        depth: usize,
+    },
+    /// Send block `block` after the response following it.
+    OutOfOrder {
+        /// Block sent late.
+        block: u64,
+    },
+    /// Pad the payload of block `block` to at least `bytes` bytes with an
+    /// unknown field, to exceed message size limits. Decoders skipping
+    /// unknown fields still read the block.
+    Oversized {
+        /// Block padded.
+        block: u64,
+        /// Payload size at least reached.
+        bytes: usize,
+    },
+    /// Refuse the call with `UNAUTHENTICATED`, as an expired or revoked API
+    /// key is.
+    RejectAuth,
+}
+
+/// A [`Fault`] and how many more calls it may apply to.
+#[derive(Clone, Debug)]
+pub(super) struct ScheduledFault {
+    fault: Fault,
+    /// Shared by the clones of a replay, so faults limited to some calls
+    /// stay limited across them. `None` for faults applying to every call.
+    remaining: Option<Arc<AtomicU32>>,
+}
+
+impl ScheduledFault {
+    pub(super) fn new(fault: Fault, calls: Option<u32>) -> Self {
+        ScheduledFault {
+            fault,
+            remaining: calls.map(|calls| Arc::new(AtomicU32::new(calls))),
+        }
+    }
+
+    /// Whether the fault applies to a call streaming `schedule`, using up
+    /// one of its calls if so.
+    fn take(&self, schedule: &[(Response, Duration)]) -> bool {
+        let applies = match &self.fault {
+            Fault::RejectAuth => true,
+            Fault::Disconnect { after: block }
+            | Fault::Duplicate { block, .. }
+            | Fault::OutOfOrder { block }
+            | Fault::Oversized { block, .. } => position(schedule, *block).is_some(),
+        };
+        applies
+            && self.remaining.as_ref().is_none_or(|remaining| {
+                remaining
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                        left.checked_sub(1)
+                    })
+                    .is_ok()
+            })
+    }
+}
+
+/// Apply `faults` to a call streaming `schedule`.
+///
+/// Returns the number of responses to send before ending the stream with
+/// `UNAVAILABLE`, if it is to be disconnected, or the status the call is to
+/// be refused with.
+pub(super) fn inject(
+    faults: &[ScheduledFault],
+    schedule: &mut Vec<(Response, Duration)>,
+) -> Result<Option<usize>, Status> {
+    let active: Vec<&Fault> = faults
+        .iter()
+        .filter(|fault| fault.take(schedule))
+        .map(|fault| &fault.fault)
+        .collect();
+
+    if active.contains(&&Fault::RejectAuth) {
+        return Err(Status::unauthenticated("injected fault: API key rejected"));
+    }
+
+    for fault in &active {
+        match **fault {
+            Fault::Oversized { block, bytes } => {
+                if let Some(i) = position(schedule, block) {
+                    if let Some(payload) = &mut schedule[i].0.block {
+                        pad(&mut payload.value, bytes);
+                    }
+                }
+            }
+            Fault::OutOfOrder { block } => {
+                if let Some(i) = position(schedule, block) {
+                    if i + 1 < schedule.len() {
+                        schedule.swap(i, i + 1);
+                    }
+                }
+            }
+            Fault::Duplicate { block, depth } => {
+                if let Some(i) = position(schedule, block) {
+                    let repeated: Vec<_> = schedule[(i + 1).saturating_sub(depth)..=i]
+                        .iter()
+                        .map(|(response, _)| (response.clone(), Duration::ZERO))
+                        .collect();
+                    schedule.splice(i + 1..i + 1, repeated);
+                }
+            }
+            Fault::Disconnect { .. } | Fault::RejectAuth => {}
+        }
+    }
+
+    Ok(active
+        .iter()
+        .filter_map(|fault| match fault {
+            Fault::Disconnect { after } => position(schedule, *after).map(|i| i + 1),
+            _ => None,
+        })
+        .min())
+}
+
+/// Index of the first response for block `block` in `schedule`.
+fn position(schedule: &[(Response, Duration)], block: u64) -> Option<usize> {
+    schedule.iter().position(|(response, _)| {
+        response.block_number() == Some(block) && response.step() != ForkStep::StepUndo
+    })
+}
+
+/// Append an unknown bytes field to the encoded message `value`, so it is at
+/// least `bytes` long.
+fn pad(value: &mut Vec<u8>, bytes: usize) {
+    let key = (PADDING_FIELD << 3) | 2;
+    // Bytes the length and the padding itself are to fill.
+    let remaining = bytes.saturating_sub(value.len() + varint_len(key));
+    let mut len = remaining.saturating_sub(varint_len(remaining as u64));
+    if len + varint_len(len as u64) < remaining {
+        len += 1;
+    }
+
+    write_varint(value, key);
+    write_varint(value, len as u64);
+    value.resize(value.len() + len, 0);
+}
+
+/// Number of bytes `value` takes as a varint.
+fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::{
+        testing::{ethereum_block, Replay},
+        Backoff, BlockMetadata, EndpointPool, ErrorClass, Request, ResilientStream,
+    };
+
+    /// Blocks 1 to 10 of a synthetic chain.
+    fn replay() -> Replay {
+        Replay::new((1..=10).map(|number| ethereum_block().number(number).build()))
+    }
+
+    async fn stream(replay: Replay) -> ResilientStream {
+        let request = Request {
+            start_block_num: 1,
+            stop_block_num: 10,
+            ..Default::default()
+        };
+        let pool = EndpointPool::new([replay.serve().await]).unwrap();
+        ResilientStream::new(pool, request).with_backoff(Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(10),
+            multiplier: 1.0,
+            max_attempts: Some(3),
+        })
+    }
+
+    async fn block_numbers(stream: &mut ResilientStream) -> Vec<u64> {
+        let mut numbers = Vec::new();
+        while let Some(response) = stream.message().await.unwrap() {
+            numbers.extend(response.block_number());
+        }
+        numbers
+    }
+
+    #[test]
+    fn pads_past_the_requested_size() {
+        let metadata = BlockMetadata {
+            num: 17_000_000,
+            id: "ab".repeat(32),
+            ..Default::default()
+        };
+        // Around the sizes where the length grows a byte.
+        for bytes in [
+            0,
+            100,
+            127,
+            128,
+            129,
+            16_383,
+            16_384,
+            16_390,
+            4 * 1024 * 1024 + 1,
+        ] {
+            let mut value = metadata.encode_to_vec();
+            pad(&mut value, bytes);
+
+            assert!(value.len() >= bytes, "padded to {} of {bytes}", value.len());
+            assert!(value.len() <= bytes.max(metadata.encoded_len() + 6) + 1);
+            assert_eq!(BlockMetadata::decode(value.as_slice()).unwrap(), metadata);
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_after_disconnect() {
+        let mut stream = stream(replay().with_fault(Fault::Disconnect { after: 4 })).await;
+
+        assert_eq!(
+            block_numbers(&mut stream).await,
+            (1..=10).collect::<Vec<_>>()
+        );
+        assert_eq!(stream.summary().reconnects, 1);
+    }
+
+    #[tokio::test]
+    async fn passes_duplicate_deliveries_on() {
+        let mut stream = stream(replay().with_fault(Fault::Duplicate { block: 5, depth: 2 })).await;
+
+        assert_eq!(
+            block_numbers(&mut stream).await,
+            [1, 2, 3, 4, 5, 4, 5, 6, 7, 8, 9, 10]
+        );
+        assert_eq!(stream.summary().reconnects, 0);
+    }
+
+    #[tokio::test]
+    async fn fails_on_rejected_auth() {
+        let mut stream = stream(replay().with_fault(Fault::RejectAuth)).await;
+
+        let error = stream.message().await.unwrap_err();
+        assert_eq!(error.classification(), ErrorClass::AuthExpired);
+        assert_eq!(stream.summary().reconnects, 0);
+    }
+}
@@ -0,0 +1,25 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for testing consumers of Firehose streams without a provider:
+//! deterministic playback of recorded blocks, with their original timing,
+//! injected reorgs and faults, synthetic blocks and chains, and golden-file
+//! snapshots of responses and decoded blocks. With the `test-util` feature,
+//! [`strategies`] generates Firehose messages for property-based tests.
+
+mod blocks;
+mod faults;
+mod replay;
+mod simulator;
+mod snapshot;
+#[cfg(feature = "test-util")]
+pub mod strategies;
+
+pub use blocks::{
+    block, ethereum_block, solana_block, BlockBuilder, EthereumBlockBuilder, SolanaBlockBuilder,
+};
+pub use faults::Fault;
+pub use replay::{Playback, Replay, Timing};
+pub use simulator::ChainSimulator;
+pub use snapshot::{assert_block_snapshot, assert_snapshot, Snapshots, UPDATE_SNAPSHOTS_ENV};
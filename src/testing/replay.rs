@@ -0,0 +1,408 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{sync::mpsc, time::Instant};
+use tonic::{codegen::tokio_stream::wrappers::ReceiverStream, Status};
+
+use super::faults::{self, Fault, ScheduledFault};
+use crate::{
+    firehose_v2::stream_server::{Stream, StreamServer},
+    hex_bytes, ForkStep, Request, Response,
+};
+
+/// Responses queued for each client of a [`Replay`] server.
+const CLIENT_QUEUE: usize = 64;
+
+/// Pace of a [`Replay`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Timing {
+    /// Deliver every block as soon as it is asked for.
+    #[default]
+    Immediate,
+    /// Wait between blocks as long as between their timestamps.
+    Original,
+    /// Wait between blocks as long as between their timestamps divided by
+    /// this factor, e.g. `10.0` for ten times faster than the chain.
+    Speed(f64),
+}
+
+/// Deterministic playback of recorded blocks, as a stand-in for a Firehose
+/// endpoint in load and reorg-handling tests.
+///
+/// Blocks come from fixtures: responses recorded by an
+/// [`NdjsonSink`](crate::sink::NdjsonSink), a `dbin` archive, or built in
+/// the test. With [`Timing::Original`] or [`Timing::Speed`] they are spaced
+/// like on chain, from their timestamps. [`with_reorg`](Replay::with_reorg)
+/// injects a reorg: an orphaned fork of the next blocks is sent, then undone,
+/// then the recorded blocks follow, as a real endpoint does.
+///
+/// The sequence of responses only depends on the fixtures and reorgs, so
+/// every run sees the same blocks, cursors and forks. Play it in process
+/// with [`play`](Replay::play), or serve it over the Stream API with
+/// [`into_server`](Replay::into_server) for clients under test, such as a
+/// [`ResilientStream`](crate::ResilientStream). The server can also inject
+/// [`Fault`]s, such as disconnects and duplicate deliveries, to exercise
+/// their resilience.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::testing::{Replay, Timing};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let replay = Replay::from_ndjson("tests/fixtures/blocks.ndjson")?
+///     .with_timing(Timing::Speed(10.0))
+///     .with_reorg(17_000_005, 2);
+///
+/// let mut playback = replay.play();
+/// while let Some(response) = playback.next().await {
+///     // Blocks 17_000_005 and 17_000_006 arrive twice: orphaned, undone,
+///     // then canonical
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Replay {
+    blocks: Arc<Vec<Response>>,
+    timing: Timing,
+    /// Depth of the reorg injected before each block number.
+    reorgs: BTreeMap<u64, u64>,
+    faults: Vec<ScheduledFault>,
+}
+
+impl Replay {
+    /// Replay `blocks`, in order.
+    pub fn new(blocks: impl IntoIterator<Item = Response>) -> Self {
+        Replay {
+            blocks: Arc::new(blocks.into_iter().collect()),
+            timing: Timing::default(),
+            reorgs: BTreeMap::new(),
+            faults: Vec::new(),
+        }
+    }
+
+    /// Replay the responses of a JSON lines file, as written by
+    /// [`NdjsonSink`](crate::sink::NdjsonSink).
+    pub fn from_ndjson(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut blocks = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            blocks.push(serde_json::from_str(&line)?);
+        }
+        Ok(Replay::new(blocks))
+    }
+
+    /// Replay the blocks of a `dbin` archive as new blocks.
+    ///
+    /// Flat files carry no cursors, so each response gets its block ID as
+    /// cursor.
+    #[cfg(feature = "sink")]
+    pub fn from_dbin(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = crate::archive::DbinReader::open(path)?;
+        let mut blocks = Vec::new();
+        while let Some(block) = reader.next_block()? {
+            let mut response = block.into_response(ForkStep::StepNew);
+            response.cursor = response
+                .metadata
+                .as_ref()
+                .map_or_else(String::new, |metadata| metadata.id.clone());
+            blocks.push(response);
+        }
+        Ok(Replay::new(blocks))
+    }
+
+    /// Pace blocks according to `timing`.
+    pub fn with_timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Before block `block`, send an orphaned fork of it and the `depth - 1`
+    /// blocks after it, then undo the fork.
+    ///
+    /// Orphans keep the payload of the block they stand in for, with new
+    /// IDs, parents and cursors in their metadata.
+    pub fn with_reorg(mut self, block: u64, depth: u64) -> Self {
+        if depth > 0 {
+            self.reorgs.insert(block, depth);
+        }
+        self
+    }
+
+    /// Inject `fault` in every call to the [server](Replay::into_server) it
+    /// applies to.
+    pub fn with_fault(mut self, fault: Fault) -> Self {
+        self.faults.push(ScheduledFault::new(fault, None));
+        self
+    }
+
+    /// Inject `fault` in the first `calls` calls to the
+    /// [server](Replay::into_server) it applies to, for example to disconnect
+    /// a client once and check it resumes. Clones of the replay share the
+    /// count.
+    pub fn with_fault_times(mut self, fault: Fault, calls: u32) -> Self {
+        self.faults.push(ScheduledFault::new(fault, Some(calls)));
+        self
+    }
+
+    /// Every response of the replay, orphans and undo steps included, with
+    /// how long to wait before each at [`Timing::Original`] pace.
+    fn schedule(&self) -> Vec<(Response, Duration)> {
+        let blocks = &self.blocks;
+        let mut schedule = Vec::with_capacity(blocks.len());
+        let mut previous_time = None;
+        let mut delay = |response: &Response| {
+            let time = timestamp(response);
+            let delay = match (previous_time, time) {
+                (Some(previous), Some(time)) if time > previous => time - previous,
+                _ => Duration::ZERO,
+            };
+            previous_time = time.or(previous_time);
+            delay
+        };
+
+        for (i, block) in blocks.iter().enumerate() {
+            let depth = block
+                .block_number()
+                .and_then(|number| self.reorgs.get(&number));
+            if let Some(&depth) = depth {
+                let canonical = &blocks[i..blocks.len().min(i + depth as usize)];
+                let orphans = orphan_fork(canonical);
+                for orphan in &orphans {
+                    schedule.push((orphan.clone(), delay(orphan)));
+                }
+                for orphan in orphans.into_iter().rev() {
+                    let mut undo = orphan;
+                    undo.step = ForkStep::StepUndo.into();
+                    undo.cursor = format!("undo:{}", undo.cursor);
+                    schedule.push((undo, Duration::ZERO));
+                }
+            }
+            schedule.push((block.clone(), delay(block)));
+        }
+        schedule
+    }
+
+    /// Play the replay from its first response.
+    pub fn play(&self) -> Playback {
+        Playback::new(self.schedule().into(), self.timing)
+    }
+
+    /// Serve the replay over the Stream API, for `tonic::transport::Server`.
+    ///
+    /// Each call plays the replay from the block or cursor it requests:
+    /// `start_block_num`, negative ones relative to the last block,
+    /// `cursor`, `stop_block_num` and `final_blocks_only` are honored, and
+    /// unknown cursors are refused with `INVALID_ARGUMENT`. Faults added
+    /// with [`with_fault`](Replay::with_fault) are injected.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use firehose_rs::testing::{Fault, Replay};
+    ///
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let replay = Replay::from_ndjson("tests/fixtures/blocks.ndjson")?
+    ///     .with_fault_times(Fault::Disconnect { after: 17_000_010 }, 1)
+    ///     .with_fault(Fault::Duplicate {
+    ///         block: 17_000_020,
+    ///         depth: 3,
+    ///     });
+    ///
+    /// tonic::transport::Server::builder()
+    ///     .add_service(replay.into_server())
+    ///     .serve("127.0.0.1:10015".parse()?)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_server(self) -> StreamServer<Replay> {
+        StreamServer::new(self)
+    }
+
+    /// Index in `schedule` where `request` starts.
+    fn start(&self, schedule: &[(Response, Duration)], request: &Request) -> Result<usize, Status> {
+        if !request.cursor.is_empty() {
+            return schedule
+                .iter()
+                .position(|(response, _)| response.cursor == request.cursor)
+                .map(|i| i + 1)
+                .ok_or_else(|| Status::invalid_argument("cursor not found in this replay"));
+        }
+
+        let start = if request.start_block_num >= 0 {
+            request.start_block_num as u64
+        } else {
+            let last = self
+                .blocks
+                .iter()
+                .rev()
+                .find_map(Response::block_number)
+                .unwrap_or_default();
+            last.saturating_sub(request.start_block_num.unsigned_abs() - 1)
+        };
+        Ok(schedule
+            .iter()
+            .position(|(response, _)| response.block_number().is_some_and(|n| n >= start))
+            .unwrap_or(schedule.len()))
+    }
+}
+
+#[cfg(test)]
+impl Replay {
+    /// Serve the replay on a free local port, for clients under test.
+    pub(crate) async fn serve(self) -> crate::FirehoseEndpoint {
+        let incoming =
+            tonic::transport::server::TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = incoming.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(self.into_server())
+                .serve_with_incoming(incoming),
+        );
+        crate::FirehoseEndpoint::new(format!("http://{addr}"))
+    }
+}
+
+#[tonic::async_trait]
+impl Stream for Replay {
+    type BlocksStream = ReceiverStream<Result<Response, Status>>;
+
+    async fn blocks(
+        &self,
+        request: tonic::Request<Request>,
+    ) -> Result<tonic::Response<Self::BlocksStream>, Status> {
+        let request = request.into_inner();
+        if !request.transforms.is_empty() {
+            return Err(Status::unimplemented(
+                "transforms are not supported by this endpoint",
+            ));
+        }
+
+        let mut schedule = self.schedule();
+        let start = self.start(&schedule, &request)?;
+        schedule.drain(..start);
+        let disconnect = faults::inject(&self.faults, &mut schedule)?;
+        if let Some(sent) = disconnect {
+            schedule.truncate(sent);
+        }
+        let mut playback = Playback::new(schedule.into(), self.timing);
+
+        let (sender, stream) = mpsc::channel(CLIENT_QUEUE);
+        tokio::spawn(async move {
+            while let Some(response) = playback.next().await {
+                let step = response.step();
+                if request.final_blocks_only && step != ForkStep::StepFinal {
+                    continue;
+                }
+                let number = response.block_number();
+                if sender.send(Ok(response)).await.is_err() {
+                    return;
+                }
+                if request.stop_block_num > 0
+                    && step != ForkStep::StepUndo
+                    && number.is_some_and(|n| n >= request.stop_block_num)
+                {
+                    return;
+                }
+            }
+            if disconnect.is_some() {
+                let status = Status::unavailable("injected fault: connection lost");
+                let _ = sender.send(Err(status)).await;
+            }
+        });
+
+        Ok(tonic::Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+/// A [`Replay`] being played, from [`Replay::play`].
+#[derive(Debug)]
+pub struct Playback {
+    schedule: VecDeque<(Response, Duration)>,
+    timing: Timing,
+    /// When the next response is due.
+    due: Option<Instant>,
+}
+
+impl Playback {
+    fn new(schedule: VecDeque<(Response, Duration)>, timing: Timing) -> Self {
+        Playback {
+            schedule,
+            timing,
+            due: None,
+        }
+    }
+
+    /// The next response, once due, or `None` at the end of the replay.
+    ///
+    /// Delays accumulate from the first response, so slow consumers do not
+    /// drift the replay behind its pace.
+    pub async fn next(&mut self) -> Option<Response> {
+        let (response, delay) = self.schedule.pop_front()?;
+        let delay = match self.timing {
+            Timing::Immediate => Duration::ZERO,
+            Timing::Original => delay,
+            Timing::Speed(factor) if factor > 0.0 => delay.div_f64(factor),
+            Timing::Speed(_) => Duration::ZERO,
+        };
+
+        let due = match self.due {
+            None => Instant::now(),
+            Some(previous) => previous + delay,
+        };
+        self.due = Some(due);
+        tokio::time::sleep_until(due).await;
+        Some(response)
+    }
+
+    /// Responses left to play.
+    pub fn remaining(&self) -> usize {
+        self.schedule.len()
+    }
+}
+
+/// Time of a block, from its metadata.
+fn timestamp(response: &Response) -> Option<Duration> {
+    let time = response.metadata.as_ref()?.time.as_ref()?;
+    Some(Duration::new(
+        u64::try_from(time.seconds).ok()?,
+        u32::try_from(time.nanos).ok()?,
+    ))
+}
+
+/// Orphaned stand-ins for `canonical`, forking from the same parent.
+fn orphan_fork(canonical: &[Response]) -> Vec<Response> {
+    let mut parent = None;
+    canonical
+        .iter()
+        .map(|block| {
+            let mut orphan = block.clone();
+            orphan.step = ForkStep::StepNew.into();
+            orphan.cursor = format!("orphan:{}", block.cursor);
+            if let Some(metadata) = &mut orphan.metadata {
+                let hash = Sha256::digest(format!("firehose-rs orphan {}", metadata.id));
+                metadata.id = hex_bytes::encode(&hash);
+                if let Some(parent) = parent.replace(metadata.id.clone()) {
+                    metadata.parent_id = parent;
+                }
+            }
+            orphan
+        })
+        .collect()
+}
@@ -0,0 +1,233 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{ethereum_block, solana_block, Replay};
+use crate::{firehose_v2::stream_server::StreamServer, Response};
+
+/// Seed of simulations without [`with_seed`](ChainSimulator::with_seed).
+const DEFAULT_SEED: u64 = 0x5eed_f12e_4052_0001;
+
+/// Chains a [`ChainSimulator`] produces blocks of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Chain {
+    Ethereum,
+    Solana,
+}
+
+/// A coherent synthetic chain for end-to-end tests of reorg handling and
+/// finality buffers.
+///
+/// Blocks are built with [`ethereum_block`] or [`solana_block`]: hashes link
+/// each block to its parent, timestamps advance by the block time, and the
+/// last irreversible block trails the head by the finality depth. With
+/// [`with_forks`](ChainSimulator::with_forks), forks occasionally happen:
+/// an orphaned branch is sent, undone, then the canonical chain resumes, as
+/// a [`Replay`] with [`with_reorg`](Replay::with_reorg) does. With a
+/// finality depth, forks never reach below the last irreversible block.
+///
+/// The chain only depends on its parameters and seed, so every run sees
+/// the same blocks and forks. Serve it with
+/// [`into_server`](ChainSimulator::into_server), or get the [`Replay`] to
+/// add timing and faults.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::testing::{ChainSimulator, Timing};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let chain = ChainSimulator::ethereum(17_000_000, 1_000)
+///     .with_txs(5)
+///     .with_finality(64)
+///     .with_forks(0.05, 3);
+///
+/// tonic::transport::Server::builder()
+///     .add_service(chain.replay().with_timing(Timing::Speed(100.0)).into_server())
+///     .serve("127.0.0.1:10015".parse()?)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChainSimulator {
+    chain: Chain,
+    start: u64,
+    count: u64,
+    block_time: Duration,
+    genesis_time: Option<SystemTime>,
+    txs: u32,
+    finality: u64,
+    fork_rate: f64,
+    max_fork_depth: u64,
+    seed: u64,
+}
+
+impl ChainSimulator {
+    /// `count` Ethereum blocks from `start`, 12 seconds apart.
+    pub fn ethereum(start: u64, count: u64) -> Self {
+        ChainSimulator::new(Chain::Ethereum, start, count, Duration::from_secs(12))
+    }
+
+    /// `count` Solana blocks from slot `start`, 400 milliseconds apart.
+    pub fn solana(start: u64, count: u64) -> Self {
+        ChainSimulator::new(Chain::Solana, start, count, Duration::from_millis(400))
+    }
+
+    fn new(chain: Chain, start: u64, count: u64, block_time: Duration) -> Self {
+        ChainSimulator {
+            chain,
+            start,
+            count,
+            block_time,
+            genesis_time: None,
+            txs: 0,
+            finality: 0,
+            fork_rate: 0.0,
+            max_fork_depth: 1,
+            seed: DEFAULT_SEED,
+        }
+    }
+
+    /// Time between blocks.
+    pub fn with_block_time(mut self, block_time: Duration) -> Self {
+        self.block_time = block_time;
+        self
+    }
+
+    /// Time of block `start`, instead of the default of the block builders.
+    pub fn with_start_time(mut self, time: SystemTime) -> Self {
+        self.genesis_time = Some(time);
+        self
+    }
+
+    /// Transactions per block, `0` by default.
+    pub fn with_txs(mut self, txs: u32) -> Self {
+        self.txs = txs;
+        self
+    }
+
+    /// Keep the last irreversible block `depth` blocks behind each block,
+    /// instead of at the block itself.
+    pub fn with_finality(mut self, depth: u64) -> Self {
+        self.finality = depth;
+        self
+    }
+
+    /// Fork before each block with probability `rate`, orphaning between 1
+    /// and `max_depth` blocks.
+    ///
+    /// With a [finality depth](ChainSimulator::with_finality), forks are at
+    /// most that deep.
+    pub fn with_forks(mut self, rate: f64, max_depth: u64) -> Self {
+        self.fork_rate = rate.clamp(0.0, 1.0);
+        self.max_fork_depth = max_depth.max(1);
+        self
+    }
+
+    /// Seed of the fork positions and depths, to explore other chains.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The canonical blocks, in order, as `STEP_NEW` responses.
+    pub fn blocks(&self) -> Vec<Response> {
+        let start_time = self.start_time();
+        (self.start..self.start.saturating_add(self.count))
+            .map(|number| self.block(number, start_time))
+            .collect()
+    }
+
+    /// Where the chain forks: the first orphaned block and how many blocks
+    /// are orphaned, in order.
+    pub fn forks(&self) -> Vec<(u64, u64)> {
+        let mut max_depth = self.max_fork_depth;
+        if self.finality > 0 {
+            max_depth = max_depth.min(self.finality);
+        }
+
+        let mut rng = SplitMix64(self.seed);
+        let end = self.start.saturating_add(self.count);
+        let mut forks = Vec::new();
+        // The first block has no parent in the chain to fork from
+        let mut number = self.start.saturating_add(1);
+        while number < end {
+            if rng.next_f64() < self.fork_rate {
+                let depth = (1 + rng.next_u64() % max_depth).min(end - number);
+                forks.push((number, depth));
+                number += depth;
+            } else {
+                number += 1;
+            }
+        }
+        forks
+    }
+
+    /// A [`Replay`] of the chain and its forks, to play in process, serve,
+    /// or add timing and faults to.
+    pub fn replay(&self) -> Replay {
+        self.forks()
+            .into_iter()
+            .fold(Replay::new(self.blocks()), |replay, (block, depth)| {
+                replay.with_reorg(block, depth)
+            })
+    }
+
+    /// Serve the chain over the Stream API, as
+    /// [`Replay::into_server`] does.
+    pub fn into_server(self) -> StreamServer<Replay> {
+        self.replay().into_server()
+    }
+
+    fn block(&self, number: u64, start_time: SystemTime) -> Response {
+        let lib = number.saturating_sub(self.finality);
+        let offset = u32::try_from(number - self.start).unwrap_or(u32::MAX);
+        let time = start_time + self.block_time * offset;
+        match self.chain {
+            Chain::Ethereum => ethereum_block()
+                .number(number)
+                .txs(self.txs)
+                .timestamp(time)
+                .lib(lib)
+                .build(),
+            Chain::Solana => solana_block()
+                .slot(number)
+                .txs(self.txs)
+                .timestamp(time)
+                .lib(lib)
+                .build(),
+        }
+    }
+
+    /// Time of the start block: the builders' time for it by default.
+    fn start_time(&self) -> SystemTime {
+        self.genesis_time.unwrap_or_else(|| {
+            let start = match self.chain {
+                Chain::Ethereum => ethereum_block().number(self.start).build(),
+                Chain::Solana => solana_block().slot(self.start).build(),
+            };
+            start.timestamp().unwrap_or(UNIX_EPOCH)
+        })
+    }
+}
+
+/// SplitMix64, to draw forks reproducibly from a seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
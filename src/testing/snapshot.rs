@@ -0,0 +1,332 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use prost_wkt_types::Any;
+use serde::Serialize;
+
+use crate::{hex_bytes, Response};
+#[cfg(feature = "dynamic")]
+use crate::{DynamicDecodeError, DynamicDecoder};
+
+/// Environment variable rewriting golden files with the current output when
+/// set to `1`.
+pub const UPDATE_SNAPSHOTS_ENV: &str = "FIREHOSE_UPDATE_SNAPSHOTS";
+
+/// Lines of unchanged context shown around each difference.
+const DIFF_CONTEXT: usize = 3;
+
+/// Above this many line pairs, differences are shown from the first
+/// mismatch on instead of as a minimal diff.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Golden files of canonical JSON that test output is compared against.
+///
+/// Values are serialized to pretty-printed JSON with sorted object keys, so
+/// files are stable across runs and review well. A mismatch panics with a
+/// line diff of the golden file against the new output. Missing golden
+/// files are written, except on CI (when `CI` is set), where they fail the
+/// test; set `FIREHOSE_UPDATE_SNAPSHOTS=1` to rewrite every golden file
+/// after an intended change, and review the result before committing it.
+///
+/// [Block snapshots](Snapshots::assert_block) render the payload as `0x`-
+/// prefixed hex, or, [with a decoder](Snapshots::with_decoder), as the
+/// proto3 JSON of the decoded block, so a change shows up as the fields
+/// that changed.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::testing::{assert_block_snapshot, Replay};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut playback = Replay::from_ndjson("tests/fixtures/blocks.ndjson")?.play();
+/// let response = playback.next().await.unwrap();
+///
+/// // Compared with tests/snapshots/first_block.json
+/// assert_block_snapshot("first_block", &response);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Snapshots {
+    dir: PathBuf,
+    #[cfg(feature = "dynamic")]
+    decoder: Option<DynamicDecoder>,
+}
+
+impl Snapshots {
+    /// Golden files in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Snapshots {
+            dir: dir.into(),
+            #[cfg(feature = "dynamic")]
+            decoder: None,
+        }
+    }
+
+    /// Render block payloads of the types registered with `decoder` as the
+    /// proto3 JSON of the decoded block, with its type URL as `@type`.
+    /// Payloads of other types stay hex.
+    #[cfg(feature = "dynamic")]
+    pub fn with_decoder(mut self, decoder: DynamicDecoder) -> Self {
+        self.decoder = Some(decoder);
+        self
+    }
+
+    /// Golden files in `tests/snapshots` of the crate under test.
+    pub fn in_crate() -> Self {
+        let root = env::var_os("CARGO_MANIFEST_DIR").map_or_else(PathBuf::new, PathBuf::from);
+        Snapshots::new(root.join("tests").join("snapshots"))
+    }
+
+    /// Path of the golden file `name`.
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.json"))
+    }
+
+    /// Compare `value`, serialized to canonical JSON, with the golden file
+    /// `name`.
+    ///
+    /// # Panics
+    ///
+    /// If they differ, if `value` cannot be serialized, or if the golden file
+    /// cannot be read or written.
+    #[track_caller]
+    pub fn assert<T: Serialize + ?Sized>(&self, name: &str, value: &T) {
+        let actual = match canonical_json(value) {
+            Ok(json) => json,
+            Err(e) => panic!("snapshot `{name}` cannot be serialized: {e}"),
+        };
+        let path = self.path(name);
+
+        let update = env::var(UPDATE_SNAPSHOTS_ENV).is_ok_and(|value| value == "1");
+        let expected = match fs::read_to_string(&path) {
+            Ok(expected) if !update => expected,
+            Ok(_) => return write(&path, &actual),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if env::var_os("CI").is_some() && !update {
+                    panic!(
+                        "snapshot `{name}` has no golden file at {}; run the tests locally to \
+                         create it",
+                        path.display()
+                    );
+                }
+                return write(&path, &actual);
+            }
+            Err(e) => panic!("cannot read snapshot {}: {e}", path.display()),
+        };
+
+        if expected != actual {
+            panic!(
+                "snapshot `{name}` does not match {}\n{}\nrerun with {UPDATE_SNAPSHOTS_ENV}=1 \
+                 to accept the new output",
+                path.display(),
+                diff(&expected, &actual)
+            );
+        }
+    }
+
+    /// Compare `response` with the golden file `name`, as
+    /// [`assert`](Snapshots::assert), with the block payload rendered as hex
+    /// or, [with a decoder](Snapshots::with_decoder), as decoded JSON.
+    #[track_caller]
+    pub fn assert_block(&self, name: &str, response: &Response) {
+        let mut json = match serde_json::to_value(response) {
+            Ok(json) => json,
+            Err(e) => panic!("snapshot `{name}` cannot be serialized: {e}"),
+        };
+        if let (Some(block), Some(object)) = (&response.block, json.as_object_mut()) {
+            object.insert("block".to_string(), self.block_json(name, block));
+        }
+        self.assert(name, &json);
+    }
+
+    #[track_caller]
+    fn block_json(&self, name: &str, block: &Any) -> serde_json::Value {
+        #[cfg(feature = "dynamic")]
+        if let Some(decoder) = &self.decoder {
+            match decoder.to_json(block) {
+                Ok(serde_json::Value::Object(mut fields)) => {
+                    fields.insert("@type".to_string(), block.type_url.clone().into());
+                    return serde_json::Value::Object(fields);
+                }
+                // Well-known types render as a single value.
+                Ok(value) => {
+                    return serde_json::json!({ "@type": block.type_url, "value": value });
+                }
+                Err(DynamicDecodeError::UnknownType(_)) => {}
+                Err(e) => panic!("snapshot `{name}` has a block that cannot be decoded: {e}"),
+            }
+        }
+        #[cfg(not(feature = "dynamic"))]
+        let _ = name;
+
+        serde_json::json!({
+            "type_url": block.type_url,
+            "value": hex_bytes::encode(&block.value),
+        })
+    }
+}
+
+/// Compare `response` with the golden file `tests/snapshots/{name}.json`,
+/// see [`Snapshots`].
+#[track_caller]
+pub fn assert_block_snapshot(name: &str, response: &Response) {
+    Snapshots::in_crate().assert_block(name, response);
+}
+
+/// Compare `value`, such as a decoded block, with the golden file
+/// `tests/snapshots/{name}.json`, see [`Snapshots`].
+#[track_caller]
+pub fn assert_snapshot<T: Serialize + ?Sized>(name: &str, value: &T) {
+    Snapshots::in_crate().assert(name, value);
+}
+
+/// `value` as pretty-printed JSON with sorted keys and a final newline.
+fn canonical_json<T: Serialize + ?Sized>(value: &T) -> serde_json::Result<String> {
+    let value = sort_keys(serde_json::to_value(value)?);
+    let mut json = serde_json::to_string_pretty(&value)?;
+    json.push('\n');
+    Ok(json)
+}
+
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_keys).collect())
+        }
+        value => value,
+    }
+}
+
+#[track_caller]
+fn write(path: &Path, json: &str) {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            panic!("cannot create snapshot directory {}: {e}", dir.display());
+        }
+    }
+    if let Err(e) = fs::write(path, json) {
+        panic!("cannot write snapshot {}: {e}", path.display());
+    }
+}
+
+/// Unified-style line diff of `expected` against `actual`: removed lines
+/// start with `-`, added ones with `+`, with a few lines of context.
+fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+
+    // Strip the common prefix and suffix, then diff what is left
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_middle, new_middle) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut lines: Vec<(char, &str)> = old[..prefix].iter().map(|line| (' ', *line)).collect();
+    if old_middle.len().saturating_mul(new_middle.len()) <= MAX_DIFF_CELLS {
+        lines.extend(lcs_diff(old_middle, new_middle));
+    } else {
+        lines.extend(old_middle.iter().map(|line| ('-', *line)));
+        lines.extend(new_middle.iter().map(|line| ('+', *line)));
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|line| (' ', *line)));
+
+    // Keep changed lines and their context
+    let changed: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].0 != ' ').collect();
+    let mut out = String::new();
+    let mut last_shown = None;
+    for (i, (sign, line)) in lines.iter().enumerate() {
+        let near = changed
+            .iter()
+            .any(|&c| i + DIFF_CONTEXT >= c && i <= c + DIFF_CONTEXT);
+        if !near {
+            continue;
+        }
+        if last_shown.is_some_and(|last| i > last + 1) || (last_shown.is_none() && i > 0) {
+            out.push_str("@@ ... @@\n");
+        }
+        out.push_str(&format!("{sign} {line}\n"));
+        last_shown = Some(i);
+    }
+    out
+}
+
+/// Minimal line diff through the longest common subsequence.
+fn lcs_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    let (n, m) = (old.len(), new.len());
+    // lengths[i][j]: LCS of old[i..] and new[j..]
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old[i] == new[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|line| ('-', *line)));
+    lines.extend(new[j..].iter().map(|line| ('+', *line)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_block_payloads_as_hex() {
+        let block = Any {
+            type_url: "type.googleapis.com/sf.ethereum.type.v2.Block".to_string(),
+            value: vec![0x08, 0x96, 0x01],
+        };
+
+        assert_eq!(
+            Snapshots::new("snapshots").block_json("block", &block),
+            serde_json::json!({
+                "type_url": "type.googleapis.com/sf.ethereum.type.v2.Block",
+                "value": "0x089601",
+            })
+        );
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! [`proptest`] strategies for the Firehose messages, to fuzz serialization
+//! and handling code against realistic inputs.
+//!
+//! Values favor what providers and clients actually exchange, such as block
+//! numbers of live chains, base64 cursors and 32-byte hashes, and keep some
+//! arbitrary ones to reach edge cases.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use firehose_rs::{testing::strategies, Request};
+//! use prost::Message;
+//! use proptest::prelude::*;
+//!
+//! proptest! {
+//!     #[test]
+//!     fn request_round_trips(request in strategies::request()) {
+//!         let decoded = Request::decode(request.encode_to_vec().as_slice()).unwrap();
+//!         prop_assert_eq!(decoded, request);
+//!     }
+//! }
+//! ```
+
+use proptest::{collection::vec, option, prelude::*};
+use prost_wkt_types::{Any, Timestamp};
+
+use crate::{
+    firehose_v2::single_block_request::{Cursor, Reference},
+    hex_bytes, BlockMetadata, ForkStep, Request, Response, SingleBlockRequest,
+};
+
+/// Type URLs of the blocks and transforms in [`any_message`].
+const TYPE_URLS: &[&str] = &[
+    "type.googleapis.com/sf.ethereum.type.v2.Block",
+    "type.googleapis.com/sf.solana.type.v1.Block",
+    "type.googleapis.com/sf.near.type.v1.Block",
+    "type.googleapis.com/sf.cosmos.type.v1.Block",
+    "type.googleapis.com/sf.ethereum.transform.v1.CombinedFilter",
+    "type.googleapis.com/sf.ethereum.transform.v1.HeaderOnly",
+];
+
+/// Block numbers: mostly those of live chains, sometimes anything.
+pub fn block_number() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        4 => 0..=400_000_000u64,
+        1 => any::<u64>(),
+    ]
+}
+
+/// Start blocks of a [`Request`]: absolute, relative to the head, or
+/// anything.
+pub fn start_block_num() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        4 => 0..=400_000_000i64,
+        2 => -10_000..0i64,
+        1 => any::<i64>(),
+    ]
+}
+
+/// Cursors: opaque URL-safe base64 like providers send, sometimes with
+/// surrounding whitespace or arbitrary text.
+pub fn cursor() -> impl Strategy<Value = String> {
+    prop_oneof![
+        6 => "[A-Za-z0-9_-]{40,200}",
+        1 => "[ \t]{0,2}[A-Za-z0-9_-]{40,200}[ \t\n]{0,2}",
+        1 => ".{0,64}",
+    ]
+}
+
+/// Block hashes: `0x`-prefixed or bare hex of 32 bytes, in either case.
+pub fn block_hash() -> impl Strategy<Value = String> {
+    (any::<[u8; 32]>(), any::<bool>(), any::<bool>()).prop_map(|(hash, prefixed, upper)| {
+        let hex = hex_bytes::encode(&hash);
+        let hex = if prefixed { hex } else { hex[2..].to_string() };
+        if upper {
+            hex.to_ascii_uppercase().replacen("0X", "0x", 1)
+        } else {
+            hex
+        }
+    })
+}
+
+/// Fork steps, the unset one included.
+pub fn fork_step() -> impl Strategy<Value = ForkStep> {
+    prop_oneof![
+        Just(ForkStep::StepUnset),
+        Just(ForkStep::StepNew),
+        Just(ForkStep::StepUndo),
+        Just(ForkStep::StepFinal),
+    ]
+}
+
+/// `Any` messages of block and transform types, with arbitrary payloads.
+pub fn any_message() -> impl Strategy<Value = Any> {
+    (prop::sample::select(TYPE_URLS), vec(any::<u8>(), 0..512)).prop_map(|(type_url, value)| Any {
+        type_url: type_url.to_string(),
+        value,
+    })
+}
+
+/// [`Request`]s from a block or cursor, with up to three transforms.
+pub fn request() -> impl Strategy<Value = Request> {
+    (
+        start_block_num(),
+        prop_oneof![3 => Just(String::new()), 1 => cursor()],
+        prop_oneof![2 => Just(0u64), 1 => block_number()],
+        any::<bool>(),
+        vec(any_message(), 0..=3),
+    )
+        .prop_map(
+            |(start_block_num, cursor, stop_block_num, final_blocks_only, transforms)| Request {
+                start_block_num,
+                cursor,
+                stop_block_num,
+                final_blocks_only,
+                transforms,
+            },
+        )
+}
+
+/// [`SingleBlockRequest`]s by number, by hash and number, or by cursor, with
+/// up to three transforms.
+pub fn single_block_request() -> impl Strategy<Value = SingleBlockRequest> {
+    let request = prop_oneof![
+        block_number().prop_map(SingleBlockRequest::new_by_block_number),
+        (block_hash(), block_number())
+            .prop_map(|(hash, num)| SingleBlockRequest::new_by_block_hash_and_number(hash, num)),
+        cursor().prop_map(|cursor| SingleBlockRequest {
+            reference: Some(Reference::Cursor(Cursor { cursor })),
+            ..Default::default()
+        }),
+    ];
+    (request, vec(any_message(), 0..=3)).prop_map(|(request, transforms)| SingleBlockRequest {
+        transforms,
+        ..request
+    })
+}
+
+/// Timestamps from the Unix epoch to the year 2100, with nanoseconds.
+pub fn timestamp() -> impl Strategy<Value = Timestamp> {
+    (0..4_102_444_800i64, 0..1_000_000_000i32)
+        .prop_map(|(seconds, nanos)| Timestamp { seconds, nanos })
+}
+
+/// [`BlockMetadata`] of a block following its parent, with the last
+/// irreversible block at or before it.
+pub fn block_metadata() -> impl Strategy<Value = BlockMetadata> {
+    (
+        block_number(),
+        block_hash(),
+        block_hash(),
+        0..=1_000u64,
+        option::of(timestamp()),
+    )
+        .prop_map(|(num, id, parent_id, finality, time)| BlockMetadata {
+            num,
+            id,
+            parent_num: num.saturating_sub(1),
+            parent_id,
+            lib_num: num.saturating_sub(finality),
+            time,
+        })
+}
+
+/// [`Response`] envelopes, sometimes without block or metadata as older
+/// servers send them.
+pub fn response() -> impl Strategy<Value = Response> {
+    (
+        option::weighted(0.9, any_message()),
+        fork_step(),
+        cursor(),
+        option::weighted(0.9, block_metadata()),
+    )
+        .prop_map(|(block, step, cursor, metadata)| Response {
+            block,
+            step: step.into(),
+            cursor,
+            metadata,
+        })
+}
@@ -0,0 +1,389 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Registry of request transforms, built by name from string arguments.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    sync::Arc,
+};
+
+use prost::{Message, Name};
+use prost_wkt_types::Any;
+use tonic::codegen::Bytes;
+
+use crate::{adapters, Request};
+
+/// Arguments of a registered transform, such as `addresses` or `filter`.
+pub type TransformArgs = BTreeMap<String, String>;
+
+/// Builds the encoded message of a transform from its arguments.
+pub type TransformBuilder =
+    Arc<dyn Fn(&TransformArgs) -> Result<Vec<u8>, String> + Send + Sync + 'static>;
+
+/// Transforms known by type URL, with a short name each, that requests can
+/// add by name.
+///
+/// Transforms are provider- and chain-specific messages sent with a
+/// [`Request`] to filter or reshape blocks server-side. Register a builder
+/// for each transform message, keyed by its type URL, and add it to requests
+/// with [`Request::with_transform`] by name or type URL, so new or
+/// provider-specific transforms need no changes to this crate. Builders take
+/// string arguments, so transforms can come from config files or the command
+/// line.
+///
+/// Providers apply some transforms exclusively of others, or only one of a
+/// kind, silently ignoring the rest. Declare such combinations with
+/// [`incompatible`](TransformRegistry::incompatible), so requests combining
+/// them fail client-side with [`TransformError::Incompatible`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use firehose_rs::{Request, TransformArgs, TransformRegistry};
+///
+/// # fn example(encode_filter: fn(&[&str]) -> Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+/// let mut registry = TransformRegistry::new();
+/// registry.register(
+///     "type.googleapis.com/sf.ethereum.transform.v1.CombinedFilter",
+///     "eth-logs",
+///     move |args: &TransformArgs| {
+///         let addresses = args.get("addresses").ok_or("missing `addresses`")?;
+///         Ok(encode_filter(&addresses.split(',').collect::<Vec<_>>()))
+///     },
+/// );
+///
+/// let args = TransformArgs::from([("addresses".to_string(), "0xabc,0xdef".to_string())]);
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     ..Default::default()
+/// }
+/// .with_transform(&registry, "eth-logs", &args)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct TransformRegistry {
+    /// Builders by type URL.
+    builders: HashMap<String, TransformBuilder>,
+    /// Type URLs by name.
+    names: HashMap<String, String>,
+    /// Why pairs of type URLs, ordered, cannot be combined.
+    incompatible: HashMap<(String, String), String>,
+}
+
+impl TransformRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `build` for the transform message `type_url`, also known as
+    /// `name`.
+    ///
+    /// Replaces an earlier registration of the same type URL or name, so the
+    /// type URL's previous name and the name's previous type URL are no
+    /// longer known. `build` returns the encoded message, or why its
+    /// arguments are invalid.
+    pub fn register<F>(&mut self, type_url: &str, name: &str, build: F) -> &mut Self
+    where
+        F: Fn(&TransformArgs) -> Result<Vec<u8>, String> + Send + Sync + 'static,
+    {
+        self.names.retain(|_, registered| registered != type_url);
+        if let Some(previous) = self.names.insert(name.to_string(), type_url.to_string()) {
+            if previous != type_url {
+                self.builders.remove(&previous);
+            }
+        }
+        self.builders.insert(type_url.to_string(), Arc::new(build));
+        self
+    }
+
+    /// Register the fixed transform `message`, taking no arguments, as
+    /// `name`.
+    pub fn register_message<M>(&mut self, name: &str, message: M) -> &mut Self
+    where
+        M: Message + Name + Send + Sync + 'static,
+    {
+        let encoded = message.encode_to_vec();
+        let type_url = format!("type.googleapis.com/{}", M::full_name());
+        self.register(&type_url, name, move |args| {
+            if args.is_empty() {
+                Ok(encoded.clone())
+            } else {
+                Err("takes no arguments".to_string())
+            }
+        })
+    }
+
+    /// Register the fixed transform `type_url`, already encoded as `value`,
+    /// as `name`.
+    ///
+    /// Unlike [`register_message`](TransformRegistry::register_message), the
+    /// message can be encoded with any `prost` version, or another protobuf
+    /// library.
+    pub fn register_encoded(
+        &mut self,
+        name: &str,
+        type_url: &str,
+        value: impl Into<Bytes>,
+    ) -> &mut Self {
+        let encoded = adapters::payload(value);
+        self.register(type_url, name, move |args| {
+            if args.is_empty() {
+                Ok(encoded.clone())
+            } else {
+                Err("takes no arguments".to_string())
+            }
+        })
+    }
+
+    /// Declare that the transforms `first` and `second`, by name or type
+    /// URL, cannot be sent in the same request, for `reason`.
+    ///
+    /// Declaring a transform incompatible with itself allows it only once
+    /// per request.
+    pub fn incompatible(&mut self, first: &str, second: &str, reason: &str) -> &mut Self {
+        let first = self.type_url(first).unwrap_or(first).to_string();
+        let second = self.type_url(second).unwrap_or(second).to_string();
+        self.incompatible
+            .insert(ordered(first, second), reason.to_string());
+        self
+    }
+
+    /// Check that no two of `transforms` are
+    /// [incompatible](TransformRegistry::incompatible).
+    ///
+    /// Transforms of unregistered types are only checked against declared
+    /// rules, so requests can still carry transforms unknown to the registry.
+    pub fn validate(&self, transforms: &[Any]) -> Result<(), TransformError> {
+        for (i, first) in transforms.iter().enumerate() {
+            for second in &transforms[i + 1..] {
+                let key = ordered(first.type_url.clone(), second.type_url.clone());
+                if let Some(reason) = self.incompatible.get(&key) {
+                    return Err(TransformError::Incompatible {
+                        first: first.type_url.clone(),
+                        second: second.type_url.clone(),
+                        reason: reason.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The type URL of the transform called `name`, or `name` itself if it is
+    /// a registered type URL.
+    pub fn type_url(&self, name: &str) -> Option<&str> {
+        match self.names.get(name) {
+            Some(type_url) => Some(type_url),
+            None => self
+                .builders
+                .get_key_value(name)
+                .map(|(type_url, _)| type_url.as_str()),
+        }
+    }
+
+    /// Names of the registered transforms, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.names.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Build the transform called `name`, or registered under the type URL
+    /// `name`, from `args`.
+    pub fn build(&self, name: &str, args: &TransformArgs) -> Result<Any, TransformError> {
+        let type_url = self
+            .type_url(name)
+            .ok_or_else(|| TransformError::Unknown(name.to_string()))?;
+        let build = &self.builders[type_url];
+        let value = build(args).map_err(|message| TransformError::InvalidArguments {
+            name: name.to_string(),
+            message,
+        })?;
+
+        Ok(Any {
+            type_url: type_url.to_string(),
+            value,
+        })
+    }
+}
+
+impl fmt::Debug for TransformRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransformRegistry")
+            .field("names", &self.names)
+            .field("incompatible", &self.incompatible)
+            .finish_non_exhaustive()
+    }
+}
+
+/// `(first, second)` sorted, as incompatibility is symmetric.
+fn ordered(first: String, second: String) -> (String, String) {
+    if first <= second {
+        (first, second)
+    } else {
+        (second, first)
+    }
+}
+
+impl Request {
+    /// Add the transform called `name` in `registry`, built from `args`.
+    ///
+    /// Fails with [`TransformError::Incompatible`] if the request already
+    /// has a transform that `registry` declares incompatible with it.
+    pub fn with_transform(
+        mut self,
+        registry: &TransformRegistry,
+        name: &str,
+        args: &TransformArgs,
+    ) -> Result<Self, TransformError> {
+        self.transforms.push(registry.build(name, args)?);
+        registry.validate(&self.transforms)?;
+        Ok(self)
+    }
+
+    /// Add the transform `type_url`, already encoded as `value` with any
+    /// `prost` version or protobuf library, bypassing registries.
+    pub fn with_encoded_transform(mut self, type_url: &str, value: impl Into<Bytes>) -> Self {
+        self.transforms.push(Any {
+            type_url: type_url.to_string(),
+            value: adapters::payload(value),
+        });
+        self
+    }
+
+    /// Add several transforms of `registry`, by name with their arguments,
+    /// checking their combination as [`with_transform`](Request::with_transform)
+    /// does.
+    pub fn with_transforms<'a, I>(
+        self,
+        registry: &TransformRegistry,
+        transforms: I,
+    ) -> Result<Self, TransformError>
+    where
+        I: IntoIterator<Item = (&'a str, &'a TransformArgs)>,
+    {
+        transforms
+            .into_iter()
+            .try_fold(self, |request, (name, args)| {
+                request.with_transform(registry, name, args)
+            })
+    }
+}
+
+/// Errors returned when building a transform from a [`TransformRegistry`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransformError {
+    /// No transform is registered under this name or type URL.
+    Unknown(String),
+    /// The transform's builder rejected its arguments.
+    InvalidArguments {
+        /// Name or type URL the transform was requested by.
+        name: String,
+        /// Why the arguments are invalid.
+        message: String,
+    },
+    /// Two transforms of the request cannot be combined.
+    Incompatible {
+        /// Type URL of the first transform.
+        first: String,
+        /// Type URL of the second transform, possibly the same.
+        second: String,
+        /// Why they cannot be combined.
+        reason: String,
+    },
+}
+
+impl Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransformError::Unknown(name) => write!(f, "no transform registered as `{name}`"),
+            TransformError::InvalidArguments { name, message } => {
+                write!(f, "invalid arguments for transform `{name}`: {message}")
+            }
+            TransformError::Incompatible {
+                first,
+                second,
+                reason,
+            } if first == second => {
+                write!(f, "transform `{first}` is given more than once: {reason}")
+            }
+            TransformError::Incompatible {
+                first,
+                second,
+                reason,
+            } => write!(
+                f,
+                "transforms `{first}` and `{second}` cannot be combined: {reason}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGS: &str = "type.googleapis.com/sf.ethereum.transform.v1.CombinedFilter";
+    const HEADERS: &str = "type.googleapis.com/sf.ethereum.transform.v1.HeaderOnly";
+
+    fn encoding(value: &'static [u8]) -> impl Fn(&TransformArgs) -> Result<Vec<u8>, String> {
+        move |_| Ok(value.to_vec())
+    }
+
+    #[test]
+    fn renaming_a_type_url_forgets_its_old_name() {
+        let mut registry = TransformRegistry::new();
+        registry.register(LOGS, "logs", encoding(b"old"));
+        registry.register(LOGS, "eth-logs", encoding(b"new"));
+
+        assert_eq!(registry.names(), ["eth-logs"]);
+        assert_eq!(registry.type_url("logs"), None);
+        assert!(matches!(
+            registry.build("logs", &TransformArgs::new()),
+            Err(TransformError::Unknown(_))
+        ));
+        let built = registry.build("eth-logs", &TransformArgs::new()).unwrap();
+        assert_eq!(
+            (built.type_url.as_str(), built.value),
+            (LOGS, b"new".to_vec())
+        );
+    }
+
+    #[test]
+    fn rebinding_a_name_forgets_its_old_type_url() {
+        let mut registry = TransformRegistry::new();
+        registry.register(LOGS, "filter", encoding(b"logs"));
+        registry.register(HEADERS, "filter", encoding(b"headers"));
+
+        assert_eq!(registry.names(), ["filter"]);
+        assert_eq!(registry.type_url("filter"), Some(HEADERS));
+        assert_eq!(registry.type_url(LOGS), None);
+        assert!(matches!(
+            registry.build(LOGS, &TransformArgs::new()),
+            Err(TransformError::Unknown(_))
+        ));
+        let built = registry.build("filter", &TransformArgs::new()).unwrap();
+        assert_eq!(
+            (built.type_url.as_str(), built.value),
+            (HEADERS, b"headers".to_vec())
+        );
+    }
+
+    #[test]
+    fn registering_again_replaces_the_builder() {
+        let mut registry = TransformRegistry::new();
+        registry.register(LOGS, "logs", encoding(b"old"));
+        registry.register(LOGS, "logs", encoding(b"new"));
+
+        assert_eq!(registry.names(), ["logs"]);
+        let built = registry.build(LOGS, &TransformArgs::new()).unwrap();
+        assert_eq!(built.value, b"new".to_vec());
+    }
+}
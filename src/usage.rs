@@ -0,0 +1,89 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+
+/// Usage recorded for one endpoint of an [`EndpointPool`](crate::EndpointPool)
+/// since the last reset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EndpointUsage {
+    /// The endpoint URI.
+    pub uri: String,
+    /// Fetch calls and stream openings sent to the endpoint.
+    pub requests: u64,
+    /// Encoded size of the responses received from the endpoint.
+    pub bytes: u64,
+    /// Blocks received from the endpoint, fetched or streamed.
+    pub blocks: u64,
+}
+
+/// Usage of an [`EndpointPool`](crate::EndpointPool), for attributing and
+/// capping the costs of metered Firehose providers.
+///
+/// Returned by [`EndpointPool::usage`](crate::EndpointPool::usage) and
+/// [`EndpointPool::reset_usage`](crate::EndpointPool::reset_usage).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UsageReport {
+    /// When recording started, at pool creation or the last reset.
+    pub since: SystemTime,
+    /// Usage of each endpoint, in priority order.
+    pub endpoints: Vec<EndpointUsage>,
+}
+
+impl UsageReport {
+    /// Requests sent to all endpoints.
+    pub fn requests(&self) -> u64 {
+        self.endpoints.iter().map(|usage| usage.requests).sum()
+    }
+
+    /// Bytes received from all endpoints.
+    pub fn bytes(&self) -> u64 {
+        self.endpoints.iter().map(|usage| usage.bytes).sum()
+    }
+
+    /// Blocks received from all endpoints.
+    pub fn blocks(&self) -> u64 {
+        self.endpoints.iter().map(|usage| usage.blocks).sum()
+    }
+}
+
+/// Counters shared by all clones of a pool member.
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    requests: AtomicU64,
+    bytes: AtomicU64,
+    blocks: AtomicU64,
+}
+
+impl Usage {
+    pub(crate) fn record_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_block(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Read the counters, zeroing them if `reset` is set.
+    pub(crate) fn report(&self, uri: String, reset: bool) -> EndpointUsage {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+
+        EndpointUsage {
+            uri,
+            requests: read(&self.requests),
+            bytes: read(&self.bytes),
+            blocks: read(&self.blocks),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+// SPDX-FileCopyrightText: 2024 Semiotic AI, Inc.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{FirehoseError, ResilientStream, Response};
+
+/// Which clock a [`TimeChunked`] stream buckets blocks by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WindowClock {
+    /// The timestamp of each block, so a backfill of last year's blocks
+    /// still produces one window per hour of chain time.
+    #[default]
+    BlockTime,
+    /// The time each block is received. Windows also close when the clock
+    /// passes their end while no block arrives.
+    WallClock,
+}
+
+/// A half-open time range `[start, end)` of a [`TimeChunked`] stream.
+///
+/// Windows are aligned to multiples of their length since the Unix epoch, so
+/// hourly windows start on the hour in UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimeWindow {
+    /// First instant of the window.
+    pub start: SystemTime,
+    /// First instant after the window.
+    pub end: SystemTime,
+}
+
+impl TimeWindow {
+    /// The window of length `length` that contains `time`.
+    ///
+    /// Times before the Unix epoch fall into the window starting at the
+    /// epoch.
+    pub fn containing(time: SystemTime, length: Duration) -> Self {
+        let length_nanos = length.as_nanos().max(1);
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let index = since_epoch.as_nanos() / length_nanos;
+
+        let start = UNIX_EPOCH + nanos(index * length_nanos);
+        TimeWindow {
+            start,
+            end: start + nanos(length_nanos),
+        }
+    }
+
+    /// Whether `time` is within the window.
+    pub fn contains(&self, time: SystemTime) -> bool {
+        self.start <= time && time < self.end
+    }
+
+    /// Length of the window.
+    pub fn length(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+/// What a [`TimeChunked`] stream produces.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WindowEvent {
+    /// A block, and the window it belongs to.
+    Block {
+        /// The window the block was assigned to.
+        window: TimeWindow,
+        /// The block.
+        response: Response,
+    },
+    /// No more blocks will be assigned to `window`, so file sinks can finish
+    /// its partition.
+    Closed {
+        /// The closed window.
+        window: TimeWindow,
+        /// Blocks emitted in the window.
+        blocks: u64,
+    },
+}
+
+/// A stream grouping blocks into fixed wall-clock or chain-time windows,
+/// such as hourly buckets by block timestamp, and announcing when each
+/// window closes.
+///
+/// Created with [`ResilientStream::chunked_by_time`]. Windows only move
+/// forward: a block whose timestamp falls into an earlier window than the
+/// current one, such as an undo step or a block with a skewed clock, and
+/// blocks without a timestamp, stay in the current window. Windows without
+/// blocks are skipped. The last window is closed when a bounded stream
+/// completes.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use firehose_rs::{FirehoseEndpoint, Request, ResilientStream, WindowClock, WindowEvent};
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let request = Request {
+///     start_block_num: 17_000_000,
+///     stop_block_num: 17_099_999,
+///     final_blocks_only: true,
+///     ..Default::default()
+/// };
+/// let mut hourly = ResilientStream::from_endpoint(FirehoseEndpoint::from_env()?, request)?
+///     .chunked_by_time(Duration::from_secs(3600), WindowClock::BlockTime);
+///
+/// while let Some(event) = hourly.message().await? {
+///     match event {
+///         WindowEvent::Block { window, response } => {
+///             // Append `response` to the file of `window`
+///         }
+///         WindowEvent::Closed { window, blocks } => {
+///             // Finish the file of `window`
+///         }
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TimeChunked {
+    stream: ResilientStream,
+    length: Duration,
+    clock: WindowClock,
+    /// The open window and the blocks emitted in it.
+    current: Option<(TimeWindow, u64)>,
+    /// A block of the next window, held back while the current one closes.
+    next: Option<(TimeWindow, Response)>,
+    completed: bool,
+}
+
+impl ResilientStream {
+    /// Group blocks into windows of `length`, by block timestamp or by
+    /// arrival time according to `clock`.
+    pub fn chunked_by_time(self, length: Duration, clock: WindowClock) -> TimeChunked {
+        TimeChunked {
+            stream: self,
+            length,
+            clock,
+            current: None,
+            next: None,
+            completed: false,
+        }
+    }
+}
+
+impl TimeChunked {
+    /// The wrapped stream.
+    pub fn stream(&self) -> &ResilientStream {
+        &self.stream
+    }
+
+    /// The open window, if any.
+    pub fn current_window(&self) -> Option<TimeWindow> {
+        self.current.map(|(window, _)| window)
+    }
+
+    /// Receive the next block or window closure.
+    ///
+    /// Returns `Ok(None)` once a bounded stream has completed and its last
+    /// window was closed.
+    pub async fn message(&mut self) -> Result<Option<WindowEvent>, FirehoseError> {
+        if let Some((window, response)) = self.next.take() {
+            return Ok(Some(self.open(window, response)));
+        }
+        if self.completed {
+            return Ok(self.close());
+        }
+
+        let received = match (self.clock, self.current) {
+            (WindowClock::WallClock, Some((window, _))) => {
+                let closes_in = window
+                    .end
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default();
+                tokio::select! {
+                    received = self.stream.message() => received?,
+                    _ = tokio::time::sleep(closes_in) => return Ok(self.close()),
+                }
+            }
+            _ => self.stream.message().await?,
+        };
+
+        let Some(response) = received else {
+            self.completed = true;
+            return Ok(self.close());
+        };
+
+        let time = match self.clock {
+            WindowClock::BlockTime => response.timestamp(),
+            WindowClock::WallClock => Some(SystemTime::now()),
+        };
+        let window = match (self.current, time) {
+            (Some((current, _)), Some(time)) if time >= current.end => {
+                TimeWindow::containing(time, self.length)
+            }
+            (Some((current, _)), _) => current,
+            (None, time) => {
+                TimeWindow::containing(time.unwrap_or_else(SystemTime::now), self.length)
+            }
+        };
+
+        if self
+            .current_window()
+            .is_some_and(|current| current != window)
+        {
+            self.next = Some((window, response));
+            return Ok(self.close());
+        }
+        Ok(Some(self.open(window, response)))
+    }
+
+    /// Emit `response` in `window`, opening it if needed.
+    fn open(&mut self, window: TimeWindow, response: Response) -> WindowEvent {
+        let (_, blocks) = self.current.get_or_insert((window, 0));
+        *blocks += 1;
+        WindowEvent::Block { window, response }
+    }
+
+    fn close(&mut self) -> Option<WindowEvent> {
+        let (window, blocks) = self.current.take()?;
+        Some(WindowEvent::Closed { window, blocks })
+    }
+}
+
+fn nanos(nanos: u128) -> Duration {
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}